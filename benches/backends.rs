@@ -0,0 +1,176 @@
+// the same programs on the tree-walker and the vm side by side, run with
+// `cargo bench --bench backends`. Each backend has to print the same output for a program or
+// the comparison stops there, a faster backend giving wrong answers isn't faster. With
+// `--features register-vm` the register vm gets a column too, compared against the stack vm,
+// for the programs it can compile
+
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use rslox::compiler::Compiler;
+use rslox::interpreter::Interpreter;
+use rslox::lexer::{Lexer, SourceMap};
+use rslox::parser::Parser;
+use rslox::resolver::Resolver;
+use rslox::vm::Vm;
+
+const PROGRAMS: [(&str, &str); 4] = [
+  (
+    "fib",
+    "fun fib(n) {
+  if (n < 2) return n;
+  return fib(n - 2) + fib(n - 1);
+}
+print fib(22);",
+  ),
+  (
+    "binary_trees",
+    "class Tree {
+  init(depth) {
+    if (depth > 0) {
+      this.left = Tree(depth - 1);
+      this.right = Tree(depth - 1);
+    } else {
+      this.left = nil;
+      this.right = nil;
+    }
+  }
+  check() {
+    if (this.left == nil) return 1;
+    return 1 + this.left.check() + this.right.check();
+  }
+}
+var total = 0;
+for (var i = 0; i < 8; i = i + 1) total = total + Tree(10).check();
+print total;",
+  ),
+  (
+    "string_churn",
+    "var s = \"\";
+for (var i = 0; i < 2000; i = i + 1) {
+  s = s + \"x\";
+  var t = \"${i}:\" + s;
+  if (t == s) print \"never\";
+}
+print s == s;",
+  ),
+  (
+    "method_calls",
+    "class Counter {
+  init() { this.count = 0; }
+  add(n) { this.count = this.count + n; return this; }
+  get() { return this.count; }
+}
+var counter = Counter();
+for (var i = 0; i < 50000; i = i + 1) counter.add(1).add(2);
+print counter.get();",
+  ),
+];
+
+const MIN_DURATION: Duration = Duration::from_secs(1);
+
+// what print wrote, so the backends' outputs can be compared
+#[derive(Clone, Default)]
+struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Output {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.0.borrow_mut().write(buf)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+fn tree_walker(code: &str, out: Box<dyn Write>) {
+  let ast = Parser::new(Lexer::new(code).lex().unwrap()).parse().unwrap();
+  let locals = Resolver::new().resolve(&ast).unwrap();
+  let mut interpreter = Interpreter::with_output(out);
+  interpreter.resolve(locals);
+  interpreter.interpret(&ast).unwrap();
+}
+
+fn vm(code: &str, out: Box<dyn Write>) {
+  let ast = Parser::new(Lexer::new(code).lex().unwrap()).parse().unwrap();
+  Resolver::new().resolve(&ast).unwrap();
+  let script = Compiler::new(&SourceMap::new(code)).compile(&ast).unwrap();
+  Vm::with_output(out).interpret(script).unwrap();
+}
+
+#[cfg(feature = "register-vm")]
+fn register_vm(code: &str, out: Box<dyn Write>) {
+  let ast = Parser::new(Lexer::new(code).lex().unwrap()).parse().unwrap();
+  Resolver::new().resolve(&ast).unwrap();
+  let script = rslox::register::RegisterCompiler::new(&SourceMap::new(code)).compile(&ast).unwrap();
+  rslox::register::RegisterVm::with_output(out).interpret(script).unwrap();
+}
+
+// how the register vm does against the stack vm, or why it can't run the program
+#[cfg(feature = "register-vm")]
+fn register_column(name: &str, code: &str, vm: Duration, expected: &str) -> String {
+  let ast = Parser::new(Lexer::new(code).lex().unwrap()).parse().unwrap();
+
+  if rslox::register::RegisterCompiler::new(&SourceMap::new(code)).compile(&ast).is_err() {
+    return format!(" {:>14} {:>8}", "unsupported", "-");
+  }
+
+  let (register_vm, printed) = time(register_vm, code);
+  assert_eq!(printed, expected, "{} printed something else on the register vm", name);
+
+  format!(
+    " {:>11.2} ms {:>7.1}x",
+    register_vm.as_secs_f64() * 1000.0,
+    vm.as_secs_f64() / register_vm.as_secs_f64()
+  )
+}
+
+#[cfg(not(feature = "register-vm"))]
+fn register_column(_: &str, _: &str, _: Duration, _: &str) -> String {
+  String::new()
+}
+
+// the fastest of as many runs as fit in MIN_DURATION, every stage from lexing on included,
+// and what the program printed
+fn time(backend: fn(&str, Box<dyn Write>), code: &str) -> (Duration, String) {
+  let output = Output::default();
+  backend(code, Box::new(output.clone()));
+
+  let mut fastest = Duration::MAX;
+  let started = Instant::now();
+
+  while fastest == Duration::MAX || started.elapsed() < MIN_DURATION {
+    let run = Instant::now();
+    backend(code, Box::new(io::sink()));
+    fastest = fastest.min(run.elapsed());
+  }
+
+  let printed = String::from_utf8(output.0.take()).unwrap();
+  (fastest, printed)
+}
+
+fn main() {
+  let register_header = if cfg!(feature = "register-vm") {
+    format!(" {:>14} {:>8}", "register vm", "vs vm")
+  } else {
+    String::new()
+  };
+  println!("{:<16} {:>14} {:>14} {:>8}{}", "program", "tree-walker", "vm", "speedup", register_header);
+
+  for (name, code) in PROGRAMS {
+    let (tree_walker, expected) = time(tree_walker, code);
+    let (vm, printed) = time(vm, code);
+    assert_eq!(printed, expected, "{} printed something else on the vm", name);
+
+    println!(
+      "{:<16} {:>11.2} ms {:>11.2} ms {:>7.1}x{}",
+      name,
+      tree_walker.as_secs_f64() * 1000.0,
+      vm.as_secs_f64() * 1000.0,
+      tree_walker.as_secs_f64() / vm.as_secs_f64(),
+      register_column(name, code, vm, &expected)
+    );
+  }
+}
@@ -0,0 +1,57 @@
+// throughput of the lexer over a few megabytes of generated source, run with `cargo bench`
+// or `cargo bench -- <path>` to lex a file of your own
+
+use std::env;
+use std::fs;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use rslox::lexer;
+
+const SAMPLE: &str = "// compute a running total
+fun total(items, scale) {
+  /* block comments /* nest */ too */
+  var sum = 0;
+  for (var i = 0; i < items.length; i += 1) {
+    sum = sum + items.get(i) * scale - 0x1F / 2.5e3;
+  }
+  print \"total: ${sum} for \\\"${items.name}\\\"\";
+  return sum >= 1_000_000 and !(sum == 0 or sum != 1);
+}
+";
+
+const TARGET_SIZE: usize = 8 * 1024 * 1024;
+const MIN_DURATION: Duration = Duration::from_secs(2);
+
+fn generated_source() -> String {
+  SAMPLE.repeat(TARGET_SIZE / SAMPLE.len() + 1)
+}
+
+fn main() {
+  // cargo passes --bench along with any filter, the first other argument is taken as a path
+  let code = match env::args().skip(1).find(|arg| !arg.starts_with("--")) {
+    Some(path) => fs::read_to_string(&path).unwrap_or_else(|error| panic!("could not read {}: {}", path, error)),
+    None => generated_source(),
+  };
+
+  let mut runs = 0;
+  let mut tokens = 0;
+  let started = Instant::now();
+
+  while runs == 0 || started.elapsed() < MIN_DURATION {
+    tokens = black_box(lexer::Lexer::new(black_box(&code))).count();
+    runs += 1;
+  }
+
+  let per_run = started.elapsed().as_secs_f64() / runs as f64;
+  let megabytes = code.len() as f64 / (1024.0 * 1024.0);
+
+  println!(
+    "lexed {:.1} MiB into {} tokens in {:.2} ms, {:.1} MiB/s over {} runs",
+    megabytes,
+    tokens,
+    per_run * 1000.0,
+    megabytes / per_run,
+    runs
+  );
+}
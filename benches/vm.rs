@@ -0,0 +1,89 @@
+// time the vm takes over some of the standard Lox benchmark programs. Run it once with
+// `cargo bench --bench vm` and once with `cargo bench --bench vm --features nan-boxing` to
+// compare the two value representations, or pass a path to time a script of your own
+
+use std::env;
+use std::fs;
+use std::io;
+use std::mem;
+use std::time::{Duration, Instant};
+
+use rslox::compiler::Compiler;
+use rslox::lexer::{Lexer, SourceMap};
+use rslox::parser::Parser;
+use rslox::vm::{Value, Vm};
+
+const PROGRAMS: [(&str, &str); 3] = [
+  (
+    "fib",
+    "fun fib(n) {
+  if (n < 2) return n;
+  return fib(n - 2) + fib(n - 1);
+}
+print fib(27) == 196418;",
+  ),
+  (
+    "equality",
+    "var i = 0;
+while (i < 2000000) {
+  i = i + 1;
+  1 == 1; 1 == 2; 1 == nil; 1 == \"str\"; 1 == true;
+  nil == nil; nil == 1; nil == \"str\"; nil == true;
+  true == true; true == 1; true == false; true == \"str\"; true == nil;
+  \"str\" == \"str\"; \"str\" == \"stru\"; \"str\" == 1; \"str\" == nil; \"str\" == true;
+}",
+  ),
+  (
+    "string_equality",
+    "var a1 = \"a1\"; var a2 = \"a2\"; var a3 = \"a3\"; var a4 = \"a4\";
+var i = 0;
+while (i < 1000000) {
+  i = i + 1;
+  a1 == a1; a1 == a2; a1 == a3; a1 == a4;
+  a2 == a1; a2 == a2; a2 == a3; a2 == a4;
+  a3 == a1; a3 == a2; a3 == a3; a3 == a4;
+  a4 == a1; a4 == a2; a4 == a3; a4 == a4;
+  var joined = a1 + a2;
+}",
+  ),
+];
+
+const MIN_DURATION: Duration = Duration::from_secs(2);
+
+// the fastest of as many runs as fit in MIN_DURATION
+fn time(code: &str) -> (Duration, usize) {
+  let ast = Parser::new(Lexer::new(code).lex().unwrap()).parse().unwrap();
+  let source_map = SourceMap::new(code);
+  let mut fastest = Duration::MAX;
+  let mut runs = 0;
+  let started = Instant::now();
+
+  while runs == 0 || started.elapsed() < MIN_DURATION {
+    let script = Compiler::new(&source_map).compile(&ast).unwrap();
+    let run = Instant::now();
+    Vm::with_output(Box::new(io::sink())).interpret(script).unwrap();
+    fastest = fastest.min(run.elapsed());
+    runs += 1;
+  }
+
+  (fastest, runs)
+}
+
+fn main() {
+  let representation = if cfg!(feature = "nan-boxing") { "nan-boxed" } else { "enum" };
+  println!("{} values, {} bytes each", representation, mem::size_of::<Value>());
+
+  // cargo passes --bench along with any filter, the first other argument is taken as a path
+  let programs = match env::args().skip(1).find(|arg| !arg.starts_with("--")) {
+    Some(path) => {
+      let code = fs::read_to_string(&path).unwrap_or_else(|error| panic!("could not read {}: {}", path, error));
+      vec![(path, code)]
+    }
+    None => PROGRAMS.iter().map(|(name, code)| (name.to_string(), code.to_string())).collect(),
+  };
+
+  for (name, code) in programs {
+    let (fastest, runs) = time(&code);
+    println!("{:<16} {:>10.2} ms, fastest of {} runs", name, fastest.as_secs_f64() * 1000.0, runs);
+  }
+}
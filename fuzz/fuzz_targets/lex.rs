@@ -0,0 +1,46 @@
+// run with `cargo +nightly fuzz run lex` from the repository root
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use rslox::lexer::{Lexer, Span, Token};
+
+fn check_span(code: &str, span: Span) {
+  assert!(span.start <= span.end, "span {:?} is reversed", span);
+  assert!(span.end <= code.len(), "span {:?} is past the end of {} bytes", span, code.len());
+  assert!(
+    code.is_char_boundary(span.start) && code.is_char_boundary(span.end),
+    "span {:?} splits a character",
+    span
+  );
+}
+
+fn check(code: &str) {
+  let mut last = None;
+
+  for result in Lexer::new(code) {
+    let span = match &result {
+      Ok(token) => token.span(),
+      Err(error) => error.span(),
+    };
+
+    check_span(code, span);
+    last = Some(result);
+  }
+
+  assert!(matches!(last, Some(Ok(Token::Eof(_)))), "the lexer has to finish with Eof");
+
+  if let Ok(tokens) = Lexer::new(code).lex_with_trivia() {
+    for token in tokens {
+      for trivia in token.leading.iter().chain(token.trailing.iter()) {
+        check_span(code, trivia.span);
+      }
+    }
+  }
+}
+
+// lossy decoding passes valid UTF-8 through untouched, so one target covers both arbitrary
+// text and arbitrary bytes, the latter showing up as replacement characters
+fuzz_target!(|data: &[u8]| {
+  check(&String::from_utf8_lossy(data));
+});
@@ -0,0 +1,348 @@
+use super::{Ast, ExprId, ExprKind, Literal, StmtId, StmtKind};
+use crate::json::Json;
+use crate::lexer::Span;
+
+// every node becomes an object tagged with its "type" and carrying its "span", child nodes
+// keep their field names from the Rust types so the schema is easy to guess
+
+fn span_json(span: Span) -> Json {
+  Json::object(vec![
+    ("start", Json::Number(span.start as f64)),
+    ("end", Json::Number(span.end as f64)),
+  ])
+}
+
+fn node(kind: &str, span: Span, mut fields: Vec<(&str, Json)>) -> Json {
+  fields.insert(0, ("type", Json::String(String::from(kind))));
+  fields.insert(1, ("span", span_json(span)));
+
+  Json::object(fields)
+}
+
+fn literal_json(literal: &Literal) -> Json {
+  match literal {
+    Literal::Number(value) => Json::Number(*value),
+    Literal::String(value) => Json::String(value.clone()),
+    Literal::Bool(value) => Json::Bool(*value),
+    Literal::Nil => Json::Null,
+  }
+}
+
+impl Ast {
+  // the whole program, one element per top-level statement
+  pub fn to_json(&self) -> Json {
+    Json::Array(self.program().iter().map(|statement| self.stmt_json(*statement)).collect())
+  }
+
+  pub fn expr_json(&self, id: ExprId) -> Json {
+    let span = self[id].span;
+
+    match &self[id].kind {
+      ExprKind::Literal(literal) => node("Literal", span, vec![("value", literal_json(literal))]),
+      ExprKind::Interpolation(parts) => node(
+        "Interpolation",
+        span,
+        vec![("parts", Json::Array(parts.iter().map(|part| self.expr_json(*part)).collect()))],
+      ),
+      ExprKind::Grouping(inner) => node("Grouping", span, vec![("expression", self.expr_json(*inner))]),
+      ExprKind::Unary { operator, operand } => node(
+        "Unary",
+        span,
+        vec![
+          ("operator", Json::String(operator.to_string())),
+          ("operand", self.expr_json(*operand)),
+        ],
+      ),
+      ExprKind::Binary { left, operator, right } => node(
+        "Binary",
+        span,
+        vec![
+          ("operator", Json::String(operator.to_string())),
+          ("left", self.expr_json(*left)),
+          ("right", self.expr_json(*right)),
+        ],
+      ),
+      ExprKind::Logical { left, operator, right } => node(
+        "Logical",
+        span,
+        vec![
+          ("operator", Json::String(operator.to_string())),
+          ("left", self.expr_json(*left)),
+          ("right", self.expr_json(*right)),
+        ],
+      ),
+      ExprKind::Variable(name) => node("Variable", span, vec![("name", Json::String(name.clone()))]),
+      ExprKind::Assign { name, value } => node(
+        "Assign",
+        span,
+        vec![("name", Json::String(name.clone())), ("value", self.expr_json(*value))],
+      ),
+      ExprKind::Call { callee, arguments } => node(
+        "Call",
+        span,
+        vec![
+          ("callee", self.expr_json(*callee)),
+          (
+            "arguments",
+            Json::Array(arguments.iter().map(|argument| self.expr_json(*argument)).collect()),
+          ),
+        ],
+      ),
+      ExprKind::Get { object, name } => node(
+        "Get",
+        span,
+        vec![("object", self.expr_json(*object)), ("name", Json::String(name.clone()))],
+      ),
+      ExprKind::Set {
+        object,
+        name,
+        operator,
+        value,
+      } => node(
+        "Set",
+        span,
+        vec![
+          ("object", self.expr_json(*object)),
+          ("name", Json::String(name.clone())),
+          ("operator", operator.map_or(Json::Null, |operator| Json::String(operator.to_string()))),
+          ("value", self.expr_json(*value)),
+        ],
+      ),
+      ExprKind::This => node("This", span, vec![]),
+      ExprKind::Super { method } => node("Super", span, vec![("method", Json::String(method.clone()))]),
+      ExprKind::List(elements) => node(
+        "List",
+        span,
+        vec![(
+          "elements",
+          Json::Array(elements.iter().map(|element| self.expr_json(*element)).collect()),
+        )],
+      ),
+      ExprKind::Map(entries) => node(
+        "Map",
+        span,
+        vec![(
+          "entries",
+          Json::Array(
+            entries
+              .iter()
+              .map(|(key, value)| Json::Array(vec![self.expr_json(*key), self.expr_json(*value)]))
+              .collect(),
+          ),
+        )],
+      ),
+      ExprKind::Index { object, index } => node(
+        "Index",
+        span,
+        vec![("object", self.expr_json(*object)), ("index", self.expr_json(*index))],
+      ),
+      ExprKind::SetIndex {
+        object,
+        index,
+        operator,
+        value,
+      } => node(
+        "SetIndex",
+        span,
+        vec![
+          ("object", self.expr_json(*object)),
+          ("index", self.expr_json(*index)),
+          ("operator", operator.map_or(Json::Null, |operator| Json::String(operator.to_string()))),
+          ("value", self.expr_json(*value)),
+        ],
+      ),
+      ExprKind::Slice { object, start, end } => node(
+        "Slice",
+        span,
+        vec![
+          ("object", self.expr_json(*object)),
+          ("start", start.map_or(Json::Null, |start| self.expr_json(start))),
+          ("end", end.map_or(Json::Null, |end| self.expr_json(end))),
+        ],
+      ),
+      ExprKind::Lambda(declaration) => node("Lambda", span, vec![("function", self.stmt_json(*declaration))]),
+    }
+  }
+
+  pub fn stmt_json(&self, id: StmtId) -> Json {
+    let span = self[id].span;
+
+    match &self[id].kind {
+      StmtKind::Expression(expr) => node("Expression", span, vec![("expression", self.expr_json(*expr))]),
+      StmtKind::Print(expr) => node("Print", span, vec![("expression", self.expr_json(*expr))]),
+      StmtKind::Var { name, initializer } => node(
+        "Var",
+        span,
+        vec![
+          ("name", Json::String(name.clone())),
+          ("initializer", initializer.map_or(Json::Null, |initializer| self.expr_json(initializer))),
+        ],
+      ),
+      StmtKind::Block(statements) => node(
+        "Block",
+        span,
+        vec![(
+          "statements",
+          Json::Array(statements.iter().map(|statement| self.stmt_json(*statement)).collect()),
+        )],
+      ),
+      StmtKind::If {
+        condition,
+        then_branch,
+        else_branch,
+      } => node(
+        "If",
+        span,
+        vec![
+          ("condition", self.expr_json(*condition)),
+          ("then_branch", self.stmt_json(*then_branch)),
+          ("else_branch", else_branch.map_or(Json::Null, |else_branch| self.stmt_json(else_branch))),
+        ],
+      ),
+      StmtKind::While {
+        condition,
+        body,
+        increment,
+      } => node(
+        "While",
+        span,
+        vec![
+          ("condition", self.expr_json(*condition)),
+          ("body", self.stmt_json(*body)),
+          ("increment", increment.map_or(Json::Null, |increment| self.expr_json(increment))),
+        ],
+      ),
+      StmtKind::Function { name, params, body } => node(
+        "Function",
+        span,
+        vec![
+          ("name", Json::String(name.clone())),
+          ("params", Json::Array(params.iter().cloned().map(Json::String).collect())),
+          ("body", Json::Array(body.iter().map(|statement| self.stmt_json(*statement)).collect())),
+        ],
+      ),
+      StmtKind::Return(value) => node(
+        "Return",
+        span,
+        vec![("value", value.map_or(Json::Null, |value| self.expr_json(value)))],
+      ),
+      StmtKind::ForIn { name, iterable, body } => node(
+        "ForIn",
+        span,
+        vec![
+          ("name", Json::String(name.clone())),
+          ("iterable", self.expr_json(*iterable)),
+          ("body", self.stmt_json(*body)),
+        ],
+      ),
+      StmtKind::Switch { value, cases, default } => node(
+        "Switch",
+        span,
+        vec![
+          ("value", self.expr_json(*value)),
+          (
+            "cases",
+            Json::Array(
+              cases
+                .iter()
+                .map(|case| {
+                  Json::object(vec![
+                    ("values", Json::Array(case.values.iter().map(|value| self.expr_json(*value)).collect())),
+                    ("body", self.stmt_json(case.body)),
+                  ])
+                })
+                .collect(),
+            ),
+          ),
+          ("default", default.map_or(Json::Null, |default| self.stmt_json(default))),
+        ],
+      ),
+      StmtKind::Break => node("Break", span, vec![]),
+      StmtKind::Continue => node("Continue", span, vec![]),
+      StmtKind::Throw(value) => node("Throw", span, vec![("value", self.expr_json(*value))]),
+      StmtKind::Try { body, name, handler } => node(
+        "Try",
+        span,
+        vec![
+          ("body", self.stmt_json(*body)),
+          ("name", Json::String(name.clone())),
+          ("handler", self.stmt_json(*handler)),
+        ],
+      ),
+      StmtKind::Import { path, names } => node(
+        "Import",
+        span,
+        vec![
+          ("path", Json::String(path.clone())),
+          (
+            "names",
+            names.as_ref().map_or(Json::Null, |names| Json::Array(names.iter().cloned().map(Json::String).collect())),
+          ),
+        ],
+      ),
+      StmtKind::Class {
+        name,
+        superclass,
+        methods,
+      } => node(
+        "Class",
+        span,
+        vec![
+          ("name", Json::String(name.clone())),
+          ("superclass", superclass.map_or(Json::Null, |superclass| self.expr_json(superclass))),
+          ("methods", Json::Array(methods.iter().map(|method| self.stmt_json(*method)).collect())),
+        ],
+      ),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::lexer::Lexer;
+  use crate::parser::Parser;
+
+  fn program_json(code: &str) -> String {
+    let ast = Parser::new(Lexer::new(code).lex().unwrap()).parse().unwrap();
+
+    ast
+      .program()
+      .iter()
+      .map(|statement| ast.stmt_json(*statement).to_string())
+      .collect::<Vec<_>>()
+      .join("\n")
+  }
+
+  #[test]
+  fn statements_and_expressions() {
+    assert_eq!(
+      program_json("var a = -1 + x;"),
+      concat!(
+        r#"{"type":"Var","span":{"start":0,"end":15},"name":"a","initializer":"#,
+        r#"{"type":"Binary","span":{"start":8,"end":14},"operator":"+","left":"#,
+        r#"{"type":"Unary","span":{"start":8,"end":10},"operator":"-","operand":"#,
+        r#"{"type":"Literal","span":{"start":9,"end":10},"value":1}},"#,
+        r#""right":{"type":"Variable","span":{"start":13,"end":14},"name":"x"}}}"#
+      )
+    );
+  }
+
+  #[test]
+  fn literals_map_onto_json_values() {
+    assert_eq!(
+      program_json("var a; print nil; print true; print \"q\\\"\";"),
+      concat!(
+        r#"{"type":"Var","span":{"start":0,"end":6},"name":"a","initializer":null}"#,
+        "\n",
+        r#"{"type":"Print","span":{"start":7,"end":17},"expression":"#,
+        r#"{"type":"Literal","span":{"start":13,"end":16},"value":null}}"#,
+        "\n",
+        r#"{"type":"Print","span":{"start":18,"end":29},"expression":"#,
+        r#"{"type":"Literal","span":{"start":24,"end":28},"value":true}}"#,
+        "\n",
+        r#"{"type":"Print","span":{"start":30,"end":42},"expression":"#,
+        r#"{"type":"Literal","span":{"start":36,"end":41},"value":"q\""}}"#
+      )
+    );
+  }
+}
@@ -0,0 +1,406 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Index;
+use std::path::{Path, PathBuf};
+
+use crate::lexer::Span;
+
+mod json;
+mod printer;
+mod visitor;
+
+pub use printer::AstPrinter;
+pub use visitor::{ExprVisitor, StmtVisitor};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOperator {
+  Add,
+  Subtract,
+  Multiply,
+  Divide,
+  Remainder,
+  Equal,
+  NotEqual,
+  Less,
+  LessEqual,
+  Greater,
+  GreaterEqual,
+  BitAnd,
+  BitOr,
+  BitXor,
+  ShiftLeft,
+  ShiftRight,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOperator {
+  Negate,
+  Not,
+  BitNot,
+}
+
+// `and` and `or` only evaluate their right side when the left doesn't decide the result
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogicalOperator {
+  And,
+  Or,
+  // the left side unless it's nil
+  Coalesce,
+}
+
+// operators display the way they are written in source
+impl fmt::Display for BinaryOperator {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let symbol = match self {
+      BinaryOperator::Add => "+",
+      BinaryOperator::Subtract => "-",
+      BinaryOperator::Multiply => "*",
+      BinaryOperator::Divide => "/",
+      BinaryOperator::Remainder => "%",
+      BinaryOperator::Equal => "==",
+      BinaryOperator::NotEqual => "!=",
+      BinaryOperator::Less => "<",
+      BinaryOperator::LessEqual => "<=",
+      BinaryOperator::Greater => ">",
+      BinaryOperator::GreaterEqual => ">=",
+      BinaryOperator::BitAnd => "&",
+      BinaryOperator::BitOr => "|",
+      BinaryOperator::BitXor => "^",
+      BinaryOperator::ShiftLeft => "<<",
+      BinaryOperator::ShiftRight => ">>",
+    };
+
+    write!(f, "{}", symbol)
+  }
+}
+
+impl fmt::Display for UnaryOperator {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let symbol = match self {
+      UnaryOperator::Negate => "-",
+      UnaryOperator::Not => "!",
+      UnaryOperator::BitNot => "~",
+    };
+
+    write!(f, "{}", symbol)
+  }
+}
+
+impl fmt::Display for LogicalOperator {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      LogicalOperator::And => write!(f, "and"),
+      LogicalOperator::Or => write!(f, "or"),
+      LogicalOperator::Coalesce => write!(f, "??"),
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+  Number(f64),
+  String(String),
+  Bool(bool),
+  Nil,
+}
+
+// handles into the Ast that owns the nodes, cheap to copy and to use as keys in side tables
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ExprId(u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StmtId(u32);
+
+#[derive(Debug, Clone)]
+pub enum ExprKind {
+  Literal(Literal),
+  // the string pieces and embedded expressions of "a ${b} c" in source order
+  Interpolation(Vec<ExprId>),
+  Grouping(ExprId),
+  Unary {
+    operator: UnaryOperator,
+    operand: ExprId,
+  },
+  Binary {
+    left: ExprId,
+    operator: BinaryOperator,
+    right: ExprId,
+  },
+  Logical {
+    left: ExprId,
+    operator: LogicalOperator,
+    right: ExprId,
+  },
+  Variable(String),
+  Assign {
+    name: String,
+    value: ExprId,
+  },
+  // spans of calls run through the closing paren
+  Call {
+    callee: ExprId,
+    arguments: Vec<ExprId>,
+  },
+  Get {
+    object: ExprId,
+    name: String,
+  },
+  // the operator of a compound assignment like `a.b += 1`, which is applied to what the
+  // property held so the object is only evaluated once
+  Set {
+    object: ExprId,
+    name: String,
+    operator: Option<BinaryOperator>,
+    value: ExprId,
+  },
+  This,
+  // `super.method`, always followed by the method name
+  Super {
+    method: String,
+  },
+  // `[a, b, c]`
+  List(Vec<ExprId>),
+  // `{key: value}`, the keys are expressions evaluated like any other
+  Map(Vec<(ExprId, ExprId)>),
+  // on a list or a map
+  Index {
+    object: ExprId,
+    index: ExprId,
+  },
+  // the operator of a compound assignment as on Set, the object and index are evaluated once
+  SetIndex {
+    object: ExprId,
+    index: ExprId,
+    operator: Option<BinaryOperator>,
+    value: ExprId,
+  },
+  // `a[start:end]`, either bound can be left out to run from the start or to the end
+  Slice {
+    object: ExprId,
+    start: Option<ExprId>,
+    end: Option<ExprId>,
+  },
+  // `fun (a, b) { ... }`, a Function statement named <lambda> that's a value rather than a
+  // declaration
+  Lambda(StmtId),
+}
+
+#[derive(Debug, Clone)]
+pub struct Expr {
+  pub kind: ExprKind,
+  pub span: Span,
+}
+
+impl Expr {
+  pub fn new(kind: ExprKind, span: Span) -> Self {
+    Self { kind, span }
+  }
+}
+
+#[derive(Debug, Clone)]
+pub enum StmtKind {
+  Expression(ExprId),
+  Print(ExprId),
+  Var {
+    name: String,
+    initializer: Option<ExprId>,
+  },
+  Block(Vec<StmtId>),
+  If {
+    condition: ExprId,
+    then_branch: StmtId,
+    else_branch: Option<StmtId>,
+  },
+  // a for loop is one of these, its increment runs after the body and after every continue
+  While {
+    condition: ExprId,
+    body: StmtId,
+    increment: Option<ExprId>,
+  },
+  Function {
+    name: String,
+    params: Vec<String>,
+    body: Vec<StmtId>,
+  },
+  Return(Option<ExprId>),
+  // `for (var name in iterable) body`, the variable is a new one for every element
+  ForIn {
+    name: String,
+    iterable: ExprId,
+    body: StmtId,
+  },
+  // `switch (value) { case a, b: ... default: ... }`, the first case with a value equal to it
+  // runs and nothing after it does. The default is there for when none is, wherever it's written
+  Switch {
+    value: ExprId,
+    cases: Vec<Case>,
+    default: Option<StmtId>,
+  },
+  // only ever inside a loop, and not in a function nested in one
+  Break,
+  Continue,
+  // any value can be thrown
+  Throw(ExprId),
+  // `try body catch (name) handler`, both blocks. The variable holds what was thrown, or the
+  // message of the runtime error
+  Try {
+    body: StmtId,
+    name: String,
+    handler: StmtId,
+  },
+  // `import "path";` brings in every name the file declares at its top level, `import a, b
+  // from "path";` just those. The file itself is found by the loader, see Ast::imported
+  Import {
+    path: String,
+    names: Option<Vec<String>>,
+  },
+  // every method is a Function statement, the superclass is a Variable expression
+  Class {
+    name: String,
+    superclass: Option<ExprId>,
+    methods: Vec<StmtId>,
+  },
+}
+
+// an arm of a switch, its values are tried in order and its statements are a Block
+#[derive(Debug, Clone)]
+pub struct Case {
+  pub values: Vec<ExprId>,
+  pub body: StmtId,
+}
+
+// spans run from the first token of the statement through its semicolon or closing brace
+#[derive(Debug, Clone)]
+pub struct Stmt {
+  pub kind: StmtKind,
+  pub span: Span,
+}
+
+impl Stmt {
+  pub fn new(kind: StmtKind, span: Span) -> Self {
+    Self { kind, span }
+  }
+}
+
+// a file brought in by an import. Its statements are nodes of the same tree as the program's,
+// so a function declared in it can be called from anywhere
+#[derive(Debug, Clone, PartialEq)]
+pub struct Module {
+  // where the importer's import led, for messages
+  pub path: String,
+  // the file it names once links and `..` are resolved, no two modules have the same one
+  pub canonical: PathBuf,
+  pub body: Vec<StmtId>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ModuleId(u32);
+
+// every node of a parse lives in one of two vectors and refers to its children by id, the
+// AST owns its names and strings so it can outlive the source it was parsed from
+#[derive(Debug, Clone, Default)]
+pub struct Ast {
+  exprs: Vec<Expr>,
+  stmts: Vec<Stmt>,
+  program: Vec<StmtId>,
+  modules: Vec<Module>,
+  // the module each import statement loads, for the imports the loader has been through
+  imports: HashMap<StmtId, ModuleId>,
+}
+
+impl Ast {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn add_expr(&mut self, expr: Expr) -> ExprId {
+    self.exprs.push(expr);
+    ExprId(self.exprs.len() as u32 - 1)
+  }
+
+  pub fn add_stmt(&mut self, stmt: Stmt) -> StmtId {
+    self.stmts.push(stmt);
+    StmtId(self.stmts.len() as u32 - 1)
+  }
+
+  // top level statements in source order, nested ones are reached through their parents
+  pub fn program(&self) -> &[StmtId] {
+    &self.program
+  }
+
+  pub fn push_top_level(&mut self, stmt: StmtId) {
+    self.program.push(stmt);
+  }
+
+  // forgets the top level statements but keeps their nodes, so ids handed out before stay
+  // valid for whatever still refers to them
+  pub fn clear_program(&mut self) {
+    self.program.clear();
+  }
+
+  pub fn add_module(&mut self, module: Module) -> ModuleId {
+    self.modules.push(module);
+    ModuleId(self.modules.len() as u32 - 1)
+  }
+
+  pub fn module(&self, id: ModuleId) -> &Module {
+    &self.modules[id.0 as usize]
+  }
+
+  // the module loaded from the file, if one was
+  pub fn find_module(&self, canonical: &Path) -> Option<ModuleId> {
+    let index = self.modules.iter().position(|module| module.canonical == canonical)?;
+
+    Some(ModuleId(index as u32))
+  }
+
+  pub fn link_import(&mut self, import: StmtId, module: ModuleId) {
+    self.imports.insert(import, module);
+  }
+
+  // None for an import that was only parsed, never loaded
+  pub fn imported(&self, import: StmtId) -> Option<ModuleId> {
+    self.imports.get(&import).copied()
+  }
+
+  // the names the module declares at its top level and the ones its own imports brought in,
+  // in the order they first come up. They're what importing all of it brings in
+  pub fn exports(&self, module: ModuleId) -> Vec<String> {
+    let mut exports = vec![];
+
+    for statement in &self.module(module).body {
+      let names = match &self[*statement].kind {
+        StmtKind::Var { name, .. } | StmtKind::Function { name, .. } | StmtKind::Class { name, .. } => {
+          vec![name.clone()]
+        }
+        StmtKind::Import { names: Some(names), .. } => names.clone(),
+        StmtKind::Import { names: None, .. } => self.imported(*statement).map_or_else(Vec::new, |module| self.exports(module)),
+        _ => vec![],
+      };
+
+      for name in names {
+        if !exports.contains(&name) {
+          exports.push(name);
+        }
+      }
+    }
+
+    exports
+  }
+}
+
+impl Index<ExprId> for Ast {
+  type Output = Expr;
+
+  fn index(&self, id: ExprId) -> &Expr {
+    &self.exprs[id.0 as usize]
+  }
+}
+
+impl Index<StmtId> for Ast {
+  type Output = Stmt;
+
+  fn index(&self, id: StmtId) -> &Stmt {
+    &self.stmts[id.0 as usize]
+  }
+}
@@ -0,0 +1,454 @@
+use super::{
+  Ast, BinaryOperator, Case, ExprId, ExprVisitor, Literal, LogicalOperator, StmtId, StmtKind, StmtVisitor, UnaryOperator,
+};
+
+// renders the tree as s-expressions, `-123 * (45.67)` comes out as (* (- 123) (group 45.67)),
+// which makes the shape the parser settled on easy to read and to compare in tests
+pub struct AstPrinter;
+
+impl AstPrinter {
+  pub fn print_expr(&mut self, ast: &Ast, expr: ExprId) -> String {
+    ast.visit_expr(expr, self)
+  }
+
+  pub fn print_stmt(&mut self, ast: &Ast, stmt: StmtId) -> String {
+    ast.visit_stmt(stmt, self)
+  }
+
+  // one top level statement per line
+  pub fn print_program(&mut self, ast: &Ast) -> String {
+    ast
+      .program()
+      .iter()
+      .map(|statement| self.print_stmt(ast, *statement) + "\n")
+      .collect()
+  }
+
+  fn parenthesize(&mut self, ast: &Ast, name: &str, exprs: impl IntoIterator<Item = ExprId>) -> String {
+    let mut out = format!("({}", name);
+
+    for expr in exprs {
+      out.push(' ');
+      out.push_str(&self.print_expr(ast, expr));
+    }
+
+    out.push(')');
+    out
+  }
+
+  // (head (params) body...)
+  fn function(&mut self, ast: &Ast, head: &str, params: &[String], body: &[StmtId]) -> String {
+    let mut out = format!("({} ({})", head, params.join(" "));
+
+    for statement in body {
+      out.push(' ');
+      out.push_str(&self.print_stmt(ast, *statement));
+    }
+
+    out.push(')');
+    out
+  }
+}
+
+impl ExprVisitor<String> for AstPrinter {
+  fn visit_literal(&mut self, _: &Ast, _: ExprId, literal: &Literal) -> String {
+    match literal {
+      Literal::Number(value) => value.to_string(),
+      Literal::String(value) => format!("{:?}", value),
+      Literal::Bool(value) => value.to_string(),
+      Literal::Nil => String::from("nil"),
+    }
+  }
+
+  fn visit_interpolation(&mut self, ast: &Ast, _: ExprId, parts: &[ExprId]) -> String {
+    self.parenthesize(ast, "interpolate", parts.iter().copied())
+  }
+
+  fn visit_grouping(&mut self, ast: &Ast, _: ExprId, inner: ExprId) -> String {
+    self.parenthesize(ast, "group", [inner])
+  }
+
+  fn visit_unary(&mut self, ast: &Ast, _: ExprId, operator: UnaryOperator, operand: ExprId) -> String {
+    self.parenthesize(ast, &operator.to_string(), [operand])
+  }
+
+  fn visit_binary(
+    &mut self,
+    ast: &Ast,
+    _: ExprId,
+    left: ExprId,
+    operator: BinaryOperator,
+    right: ExprId,
+  ) -> String {
+    self.parenthesize(ast, &operator.to_string(), [left, right])
+  }
+
+  fn visit_logical(
+    &mut self,
+    ast: &Ast,
+    _: ExprId,
+    left: ExprId,
+    operator: LogicalOperator,
+    right: ExprId,
+  ) -> String {
+    self.parenthesize(ast, &operator.to_string(), [left, right])
+  }
+
+  fn visit_variable(&mut self, _: &Ast, _: ExprId, name: &str) -> String {
+    String::from(name)
+  }
+
+  fn visit_assign(&mut self, ast: &Ast, _: ExprId, name: &str, value: ExprId) -> String {
+    format!("(= {} {})", name, self.print_expr(ast, value))
+  }
+
+  fn visit_call(&mut self, ast: &Ast, _: ExprId, callee: ExprId, arguments: &[ExprId]) -> String {
+    self.parenthesize(ast, "call", [callee].into_iter().chain(arguments.iter().copied()))
+  }
+
+  fn visit_get(&mut self, ast: &Ast, _: ExprId, object: ExprId, name: &str) -> String {
+    format!("(. {} {})", self.print_expr(ast, object), name)
+  }
+
+  // a compound assignment shows as its operator, `(+= (. a b) 1)`
+  fn visit_set(
+    &mut self,
+    ast: &Ast,
+    _: ExprId,
+    object: ExprId,
+    name: &str,
+    operator: Option<BinaryOperator>,
+    value: ExprId,
+  ) -> String {
+    let target = format!("(. {} {})", self.print_expr(ast, object), name);
+    let operator = operator.map_or(String::new(), |operator| operator.to_string());
+    format!("({}= {} {})", operator, target, self.print_expr(ast, value))
+  }
+
+  fn visit_this(&mut self, _: &Ast, _: ExprId) -> String {
+    String::from("this")
+  }
+
+  fn visit_super(&mut self, _: &Ast, _: ExprId, method: &str) -> String {
+    format!("(super {})", method)
+  }
+
+  fn visit_list(&mut self, ast: &Ast, _: ExprId, elements: &[ExprId]) -> String {
+    self.parenthesize(ast, "list", elements.iter().copied())
+  }
+
+  fn visit_map(&mut self, ast: &Ast, _: ExprId, entries: &[(ExprId, ExprId)]) -> String {
+    self.parenthesize(ast, "map", entries.iter().flat_map(|(key, value)| [*key, *value]))
+  }
+
+  fn visit_index(&mut self, ast: &Ast, _: ExprId, object: ExprId, index: ExprId) -> String {
+    self.parenthesize(ast, "[]", [object, index])
+  }
+
+  fn visit_set_index(
+    &mut self,
+    ast: &Ast,
+    _: ExprId,
+    object: ExprId,
+    index: ExprId,
+    operator: Option<BinaryOperator>,
+    value: ExprId,
+  ) -> String {
+    let target = self.parenthesize(ast, "[]", [object, index]);
+    let operator = operator.map_or(String::new(), |operator| operator.to_string());
+    format!("({}= {} {})", operator, target, self.print_expr(ast, value))
+  }
+
+  // a bound that's left out shows as _
+  fn visit_slice(
+    &mut self,
+    ast: &Ast,
+    _: ExprId,
+    object: ExprId,
+    start: Option<ExprId>,
+    end: Option<ExprId>,
+  ) -> String {
+    let mut bound = |bound: Option<ExprId>| bound.map_or(String::from("_"), |bound| self.print_expr(ast, bound));
+    let (start, end) = (bound(start), bound(end));
+
+    format!("([:] {} {} {})", self.print_expr(ast, object), start, end)
+  }
+
+  fn visit_lambda(&mut self, ast: &Ast, _: ExprId, declaration: StmtId) -> String {
+    match &ast[declaration].kind {
+      StmtKind::Function { params, body, .. } => self.function(ast, "lambda", params, body),
+      kind => unreachable!("lambdas wrap function declarations, not {:?}", kind),
+    }
+  }
+}
+
+impl StmtVisitor<String> for AstPrinter {
+  fn visit_expression_stmt(&mut self, ast: &Ast, _: StmtId, expr: ExprId) -> String {
+    self.parenthesize(ast, "expr", [expr])
+  }
+
+  fn visit_print(&mut self, ast: &Ast, _: StmtId, expr: ExprId) -> String {
+    self.parenthesize(ast, "print", [expr])
+  }
+
+  fn visit_var(&mut self, ast: &Ast, _: StmtId, name: &str, initializer: Option<ExprId>) -> String {
+    match initializer {
+      Some(initializer) => format!("(var {} {})", name, self.print_expr(ast, initializer)),
+      None => format!("(var {})", name),
+    }
+  }
+
+  fn visit_block(&mut self, ast: &Ast, _: StmtId, statements: &[StmtId]) -> String {
+    let mut out = String::from("(block");
+
+    for statement in statements {
+      out.push(' ');
+      out.push_str(&self.print_stmt(ast, *statement));
+    }
+
+    out.push(')');
+    out
+  }
+
+  fn visit_if(
+    &mut self,
+    ast: &Ast,
+    _: StmtId,
+    condition: ExprId,
+    then_branch: StmtId,
+    else_branch: Option<StmtId>,
+  ) -> String {
+    let condition = self.print_expr(ast, condition);
+    let then_branch = self.print_stmt(ast, then_branch);
+
+    match else_branch {
+      Some(else_branch) => {
+        format!("(if {} {} {})", condition, then_branch, self.print_stmt(ast, else_branch))
+      }
+      None => format!("(if {} {})", condition, then_branch),
+    }
+  }
+
+  // a for loop's increment is shown at the end of its body, where it runs
+  fn visit_while(
+    &mut self,
+    ast: &Ast,
+    _: StmtId,
+    condition: ExprId,
+    body: StmtId,
+    increment: Option<ExprId>,
+  ) -> String {
+    let body = self.print_stmt(ast, body);
+    let body = match increment {
+      Some(increment) => format!("(block {} {})", body, self.parenthesize(ast, "expr", [increment])),
+      None => body,
+    };
+
+    format!("(while {} {})", self.print_expr(ast, condition), body)
+  }
+
+  fn visit_function(
+    &mut self,
+    ast: &Ast,
+    _: StmtId,
+    name: &str,
+    params: &[String],
+    body: &[StmtId],
+  ) -> String {
+    self.function(ast, &format!("fun {}", name), params, body)
+  }
+
+  fn visit_return(&mut self, ast: &Ast, _: StmtId, value: Option<ExprId>) -> String {
+    match value {
+      Some(value) => self.parenthesize(ast, "return", [value]),
+      None => String::from("(return)"),
+    }
+  }
+
+  fn visit_for_in(&mut self, ast: &Ast, _: StmtId, name: &str, iterable: ExprId, body: StmtId) -> String {
+    format!("(for {} {} {})", name, self.print_expr(ast, iterable), self.print_stmt(ast, body))
+  }
+
+  fn visit_switch(
+    &mut self,
+    ast: &Ast,
+    _: StmtId,
+    value: ExprId,
+    cases: &[Case],
+    default: Option<StmtId>,
+  ) -> String {
+    let mut out = format!("(switch {}", self.print_expr(ast, value));
+
+    for case in cases {
+      let values = case.values.iter().map(|value| self.print_expr(ast, *value)).collect::<Vec<_>>();
+      out.push_str(&format!(" (case ({}) {})", values.join(" "), self.print_stmt(ast, case.body)));
+    }
+
+    if let Some(default) = default {
+      out.push_str(&format!(" (default {})", self.print_stmt(ast, default)));
+    }
+
+    out.push(')');
+    out
+  }
+
+  fn visit_break(&mut self, _: &Ast, _: StmtId) -> String {
+    String::from("(break)")
+  }
+
+  fn visit_continue(&mut self, _: &Ast, _: StmtId) -> String {
+    String::from("(continue)")
+  }
+
+  fn visit_throw(&mut self, ast: &Ast, _: StmtId, value: ExprId) -> String {
+    self.parenthesize(ast, "throw", [value])
+  }
+
+  fn visit_try(&mut self, ast: &Ast, _: StmtId, body: StmtId, name: &str, handler: StmtId) -> String {
+    format!("(try {} (catch {} {}))", self.print_stmt(ast, body), name, self.print_stmt(ast, handler))
+  }
+
+  fn visit_import(&mut self, _: &Ast, _: StmtId, path: &str, names: Option<&[String]>) -> String {
+    match names {
+      Some(names) => format!("(import ({}) {:?})", names.join(" "), path),
+      None => format!("(import {:?})", path),
+    }
+  }
+
+  fn visit_class(
+    &mut self,
+    ast: &Ast,
+    _: StmtId,
+    name: &str,
+    superclass: Option<ExprId>,
+    methods: &[StmtId],
+  ) -> String {
+    let mut out = format!("(class {}", name);
+
+    if let Some(superclass) = superclass {
+      out.push_str(&format!(" (< {})", self.print_expr(ast, superclass)));
+    }
+
+    for method in methods {
+      out.push(' ');
+      out.push_str(&self.print_stmt(ast, *method));
+    }
+
+    out.push(')');
+    out
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::lexer::Lexer;
+  use crate::parser::Parser;
+
+  fn print(code: &str) -> String {
+    let tokens = Lexer::new(code).lex().unwrap();
+
+    let (ast, expr) = Parser::new(tokens).parse_expression().unwrap();
+
+    AstPrinter.print_expr(&ast, expr)
+  }
+
+  fn print_program(code: &str) -> String {
+    let tokens = Lexer::new(code).lex().unwrap();
+
+    AstPrinter.print_program(&Parser::new(tokens).parse().unwrap())
+  }
+
+  #[test]
+  fn expressions() {
+    assert_eq!(print("-123 * (45.67)"), "(* (- 123) (group 45.67))");
+    assert_eq!(print("1 + 2 * 3 - 4"), "(- (+ 1 (* 2 3)) 4)");
+    assert_eq!(print("!true == false or nil and x"), "(or (== (! true) false) (and nil x))");
+    assert_eq!(print("a ?? b or c ?? d"), "(?? (?? a (or b c)) d)");
+    assert_eq!(print("a = b += 1"), "(= a (= b (+ b 1)))");
+    assert_eq!(print("~x & 0xF << 2 | y % 3"), "(| (& (~ x) (<< 15 2)) (% y 3))");
+    assert_eq!(print("\"a ${x} \\\"b\\\"\""), "(interpolate \"a \" x \" \\\"b\\\"\")");
+    assert_eq!(print("-f(1)(g(), 2)"), "(- (call (call f 1) (call g) 2))");
+    assert_eq!(print("[1, [a[0]], b[1:], c[:2]]"), "(list 1 (list ([] a 0)) ([:] b 1 _) ([:] c _ 2))");
+    assert_eq!(print("{\"a\": 1, b: {}}[\"a\"]"), "([] (map \"a\" 1 b (map)) \"a\")");
+    assert_eq!(print("a[i] += f()[0]"), "(+= ([] a i) ([] (call f) 0))");
+  }
+
+  #[test]
+  fn statements() {
+    assert_eq!(
+      print_program("var a = 1; var b; { print a; b = a; }"),
+      "(var a 1)\n(var b)\n(block (print a) (expr (= b a)))\n"
+    );
+    assert_eq!(
+      print_program("if (a) print 1; else if (b) {} while (a < 3) a += 1;"),
+      "(if a (print 1) (if b (block)))\n(while (< a 3) (expr (= a (+ a 1))))\n"
+    );
+  }
+
+  #[test]
+  fn for_loops_come_out_as_while_loops() {
+    assert_eq!(
+      print_program("for (var i = 0; i < 3; i += 1) print i;"),
+      "(block (var i 0) (while (< i 3) (block (print i) (expr (= i (+ i 1))))))\n"
+    );
+    assert_eq!(print_program("for (;;) print 1;"), "(while true (print 1))\n");
+    assert_eq!(
+      print_program("while (a) { if (b) break; continue; }"),
+      "(while a (block (if b (break)) (continue)))\n"
+    );
+    assert_eq!(print_program("for (var x in [1]) print x;"), "(for x (list 1) (print x))\n");
+  }
+
+  #[test]
+  fn switches() {
+    assert_eq!(
+      print_program("switch (a) { case 1, \"b\": print 1; case c: default: print 2; }"),
+      "(switch a (case (1 \"b\") (block (print 1))) (case (c) (block)) (default (block (print 2))))\n"
+    );
+  }
+
+  #[test]
+  fn exceptions() {
+    assert_eq!(
+      print_program("try { throw 1; } catch (e) { print e; }"),
+      "(try (block (throw 1)) (catch e (block (print e))))\n"
+    );
+  }
+
+  #[test]
+  fn imports() {
+    assert_eq!(
+      print_program("import \"lib/m.lox\"; import a, from from \"m.lox\";"),
+      "(import \"lib/m.lox\")\n(import (a from) \"m.lox\")\n"
+    );
+  }
+
+  #[test]
+  fn functions() {
+    assert_eq!(
+      print_program("fun add(a, b) { return a + b; } fun nothing() { return; }"),
+      "(fun add (a b) (return (+ a b)))\n(fun nothing () (return))\n"
+    );
+    assert_eq!(
+      print("map(xs, fun (x) { return x * 2; })"),
+      "(call map xs (lambda (x) (return (* x 2))))"
+    );
+    assert_eq!(print("(fun () {})()"), "(call (group (lambda ())))");
+  }
+
+  #[test]
+  fn classes() {
+    assert_eq!(
+      print_program("class A { init(x) { this.x = x; } get() { return this.x; } } a.b.c += 1;"),
+      concat!(
+        "(class A (fun init (x) (expr (= (. this x) x))) (fun get () (return (. this x))))\n",
+        "(expr (+= (. (. a b) c) 1))\n"
+      )
+    );
+    assert_eq!(
+      print_program("class B < A { f() { return super.f; } }"),
+      "(class B (< A) (fun f () (return (super f))))\n"
+    );
+  }
+}
@@ -0,0 +1,260 @@
+use super::{Ast, BinaryOperator, Case, ExprId, ExprKind, Literal, LogicalOperator, StmtId, StmtKind, UnaryOperator};
+
+// one method per kind of node, Ast::visit_expr and Ast::visit_stmt pick the right one so passes
+// over the tree don't each match on every kind themselves. The node's own id comes along with
+// its parts, for its span or as a key into the pass's own tables
+pub trait ExprVisitor<T> {
+  fn visit_literal(&mut self, ast: &Ast, id: ExprId, literal: &Literal) -> T;
+  fn visit_interpolation(&mut self, ast: &Ast, id: ExprId, parts: &[ExprId]) -> T;
+  fn visit_grouping(&mut self, ast: &Ast, id: ExprId, inner: ExprId) -> T;
+  fn visit_unary(&mut self, ast: &Ast, id: ExprId, operator: UnaryOperator, operand: ExprId) -> T;
+  fn visit_binary(&mut self, ast: &Ast, id: ExprId, left: ExprId, operator: BinaryOperator, right: ExprId)
+    -> T;
+  fn visit_logical(&mut self, ast: &Ast, id: ExprId, left: ExprId, operator: LogicalOperator, right: ExprId)
+    -> T;
+  fn visit_variable(&mut self, ast: &Ast, id: ExprId, name: &str) -> T;
+  fn visit_assign(&mut self, ast: &Ast, id: ExprId, name: &str, value: ExprId) -> T;
+  fn visit_call(&mut self, ast: &Ast, id: ExprId, callee: ExprId, arguments: &[ExprId]) -> T;
+  fn visit_get(&mut self, ast: &Ast, id: ExprId, object: ExprId, name: &str) -> T;
+  fn visit_set(
+    &mut self,
+    ast: &Ast,
+    id: ExprId,
+    object: ExprId,
+    name: &str,
+    operator: Option<BinaryOperator>,
+    value: ExprId,
+  ) -> T;
+  fn visit_this(&mut self, ast: &Ast, id: ExprId) -> T;
+  fn visit_super(&mut self, ast: &Ast, id: ExprId, method: &str) -> T;
+  fn visit_list(&mut self, ast: &Ast, id: ExprId, elements: &[ExprId]) -> T;
+  fn visit_map(&mut self, ast: &Ast, id: ExprId, entries: &[(ExprId, ExprId)]) -> T;
+  fn visit_index(&mut self, ast: &Ast, id: ExprId, object: ExprId, index: ExprId) -> T;
+  fn visit_set_index(
+    &mut self,
+    ast: &Ast,
+    id: ExprId,
+    object: ExprId,
+    index: ExprId,
+    operator: Option<BinaryOperator>,
+    value: ExprId,
+  ) -> T;
+  fn visit_slice(&mut self, ast: &Ast, id: ExprId, object: ExprId, start: Option<ExprId>, end: Option<ExprId>)
+    -> T;
+  fn visit_lambda(&mut self, ast: &Ast, id: ExprId, declaration: StmtId) -> T;
+}
+
+pub trait StmtVisitor<T> {
+  fn visit_expression_stmt(&mut self, ast: &Ast, id: StmtId, expr: ExprId) -> T;
+  fn visit_print(&mut self, ast: &Ast, id: StmtId, expr: ExprId) -> T;
+  fn visit_var(&mut self, ast: &Ast, id: StmtId, name: &str, initializer: Option<ExprId>) -> T;
+  fn visit_block(&mut self, ast: &Ast, id: StmtId, statements: &[StmtId]) -> T;
+  fn visit_if(
+    &mut self,
+    ast: &Ast,
+    id: StmtId,
+    condition: ExprId,
+    then_branch: StmtId,
+    else_branch: Option<StmtId>,
+  ) -> T;
+  fn visit_while(&mut self, ast: &Ast, id: StmtId, condition: ExprId, body: StmtId, increment: Option<ExprId>) -> T;
+  fn visit_function(&mut self, ast: &Ast, id: StmtId, name: &str, params: &[String], body: &[StmtId]) -> T;
+  fn visit_return(&mut self, ast: &Ast, id: StmtId, value: Option<ExprId>) -> T;
+  fn visit_for_in(&mut self, ast: &Ast, id: StmtId, name: &str, iterable: ExprId, body: StmtId) -> T;
+  fn visit_switch(&mut self, ast: &Ast, id: StmtId, value: ExprId, cases: &[Case], default: Option<StmtId>) -> T;
+  fn visit_break(&mut self, ast: &Ast, id: StmtId) -> T;
+  fn visit_continue(&mut self, ast: &Ast, id: StmtId) -> T;
+  fn visit_throw(&mut self, ast: &Ast, id: StmtId, value: ExprId) -> T;
+  fn visit_try(&mut self, ast: &Ast, id: StmtId, body: StmtId, name: &str, handler: StmtId) -> T;
+  fn visit_import(&mut self, ast: &Ast, id: StmtId, path: &str, names: Option<&[String]>) -> T;
+  fn visit_class(
+    &mut self,
+    ast: &Ast,
+    id: StmtId,
+    name: &str,
+    superclass: Option<ExprId>,
+    methods: &[StmtId],
+  ) -> T;
+}
+
+impl Ast {
+  pub fn visit_expr<T>(&self, id: ExprId, visitor: &mut impl ExprVisitor<T>) -> T {
+    match &self[id].kind {
+      ExprKind::Literal(literal) => visitor.visit_literal(self, id, literal),
+      ExprKind::Interpolation(parts) => visitor.visit_interpolation(self, id, parts),
+      ExprKind::Grouping(inner) => visitor.visit_grouping(self, id, *inner),
+      ExprKind::Unary { operator, operand } => visitor.visit_unary(self, id, *operator, *operand),
+      ExprKind::Binary { left, operator, right } => visitor.visit_binary(self, id, *left, *operator, *right),
+      ExprKind::Logical { left, operator, right } => {
+        visitor.visit_logical(self, id, *left, *operator, *right)
+      }
+      ExprKind::Variable(name) => visitor.visit_variable(self, id, name),
+      ExprKind::Assign { name, value } => visitor.visit_assign(self, id, name, *value),
+      ExprKind::Call { callee, arguments } => visitor.visit_call(self, id, *callee, arguments),
+      ExprKind::Get { object, name } => visitor.visit_get(self, id, *object, name),
+      ExprKind::Set {
+        object,
+        name,
+        operator,
+        value,
+      } => visitor.visit_set(self, id, *object, name, *operator, *value),
+      ExprKind::This => visitor.visit_this(self, id),
+      ExprKind::Super { method } => visitor.visit_super(self, id, method),
+      ExprKind::List(elements) => visitor.visit_list(self, id, elements),
+      ExprKind::Map(entries) => visitor.visit_map(self, id, entries),
+      ExprKind::Index { object, index } => visitor.visit_index(self, id, *object, *index),
+      ExprKind::SetIndex {
+        object,
+        index,
+        operator,
+        value,
+      } => visitor.visit_set_index(self, id, *object, *index, *operator, *value),
+      ExprKind::Slice { object, start, end } => visitor.visit_slice(self, id, *object, *start, *end),
+      ExprKind::Lambda(declaration) => visitor.visit_lambda(self, id, *declaration),
+    }
+  }
+
+  pub fn visit_stmt<T>(&self, id: StmtId, visitor: &mut impl StmtVisitor<T>) -> T {
+    match &self[id].kind {
+      StmtKind::Expression(expr) => visitor.visit_expression_stmt(self, id, *expr),
+      StmtKind::Print(expr) => visitor.visit_print(self, id, *expr),
+      StmtKind::Var { name, initializer } => visitor.visit_var(self, id, name, *initializer),
+      StmtKind::Block(statements) => visitor.visit_block(self, id, statements),
+      StmtKind::If {
+        condition,
+        then_branch,
+        else_branch,
+      } => visitor.visit_if(self, id, *condition, *then_branch, *else_branch),
+      StmtKind::While {
+        condition,
+        body,
+        increment,
+      } => visitor.visit_while(self, id, *condition, *body, *increment),
+      StmtKind::Function { name, params, body } => visitor.visit_function(self, id, name, params, body),
+      StmtKind::Return(value) => visitor.visit_return(self, id, *value),
+      StmtKind::ForIn { name, iterable, body } => visitor.visit_for_in(self, id, name, *iterable, *body),
+      StmtKind::Switch { value, cases, default } => visitor.visit_switch(self, id, *value, cases, *default),
+      StmtKind::Break => visitor.visit_break(self, id),
+      StmtKind::Continue => visitor.visit_continue(self, id),
+      StmtKind::Throw(value) => visitor.visit_throw(self, id, *value),
+      StmtKind::Try { body, name, handler } => visitor.visit_try(self, id, *body, name, *handler),
+      StmtKind::Import { path, names } => visitor.visit_import(self, id, path, names.as_deref()),
+      StmtKind::Class {
+        name,
+        superclass,
+        methods,
+      } => visitor.visit_class(self, id, name, *superclass, methods),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::lexer::Lexer;
+  use crate::parser::Parser;
+
+  // every variable read, in evaluation order
+  struct Reads(Vec<String>);
+
+  impl ExprVisitor<()> for Reads {
+    fn visit_literal(&mut self, _: &Ast, _: ExprId, _: &Literal) {}
+
+    fn visit_interpolation(&mut self, ast: &Ast, _: ExprId, parts: &[ExprId]) {
+      parts.iter().for_each(|part| ast.visit_expr(*part, self));
+    }
+
+    fn visit_grouping(&mut self, ast: &Ast, _: ExprId, inner: ExprId) {
+      ast.visit_expr(inner, self)
+    }
+
+    fn visit_unary(&mut self, ast: &Ast, _: ExprId, _: UnaryOperator, operand: ExprId) {
+      ast.visit_expr(operand, self)
+    }
+
+    fn visit_binary(&mut self, ast: &Ast, _: ExprId, left: ExprId, _: BinaryOperator, right: ExprId) {
+      ast.visit_expr(left, self);
+      ast.visit_expr(right, self);
+    }
+
+    fn visit_logical(&mut self, ast: &Ast, _: ExprId, left: ExprId, _: LogicalOperator, right: ExprId) {
+      ast.visit_expr(left, self);
+      ast.visit_expr(right, self);
+    }
+
+    fn visit_variable(&mut self, _: &Ast, _: ExprId, name: &str) {
+      self.0.push(String::from(name));
+    }
+
+    fn visit_assign(&mut self, ast: &Ast, _: ExprId, _: &str, value: ExprId) {
+      ast.visit_expr(value, self)
+    }
+
+    fn visit_call(&mut self, ast: &Ast, _: ExprId, callee: ExprId, arguments: &[ExprId]) {
+      ast.visit_expr(callee, self);
+      arguments.iter().for_each(|argument| ast.visit_expr(*argument, self));
+    }
+
+    fn visit_get(&mut self, ast: &Ast, _: ExprId, object: ExprId, _: &str) {
+      ast.visit_expr(object, self)
+    }
+
+    fn visit_set(&mut self, ast: &Ast, _: ExprId, object: ExprId, _: &str, _: Option<BinaryOperator>, value: ExprId) {
+      ast.visit_expr(object, self);
+      ast.visit_expr(value, self);
+    }
+
+    fn visit_this(&mut self, _: &Ast, _: ExprId) {}
+
+    fn visit_super(&mut self, _: &Ast, _: ExprId, _: &str) {}
+
+    fn visit_list(&mut self, ast: &Ast, _: ExprId, elements: &[ExprId]) {
+      elements.iter().for_each(|element| ast.visit_expr(*element, self));
+    }
+
+    fn visit_map(&mut self, ast: &Ast, _: ExprId, entries: &[(ExprId, ExprId)]) {
+      for (key, value) in entries {
+        ast.visit_expr(*key, self);
+        ast.visit_expr(*value, self);
+      }
+    }
+
+    fn visit_index(&mut self, ast: &Ast, _: ExprId, object: ExprId, index: ExprId) {
+      ast.visit_expr(object, self);
+      ast.visit_expr(index, self);
+    }
+
+    fn visit_set_index(
+      &mut self,
+      ast: &Ast,
+      _: ExprId,
+      object: ExprId,
+      index: ExprId,
+      _: Option<BinaryOperator>,
+      value: ExprId,
+    ) {
+      ast.visit_expr(object, self);
+      ast.visit_expr(index, self);
+      ast.visit_expr(value, self);
+    }
+
+    fn visit_slice(&mut self, ast: &Ast, _: ExprId, object: ExprId, start: Option<ExprId>, end: Option<ExprId>) {
+      ast.visit_expr(object, self);
+      start.into_iter().chain(end).for_each(|bound| ast.visit_expr(bound, self));
+    }
+
+    // the body is statements, which aren't reads until the lambda is called
+    fn visit_lambda(&mut self, _: &Ast, _: ExprId, _: StmtId) {}
+  }
+
+  #[test]
+  fn visit_dispatches_on_the_node_kind() {
+    let tokens = Lexer::new("a = -b + (c or \"${d}\") + e(f) + [g[h], i[j:]] + {k: l}").lex().unwrap();
+    let (ast, expr) = Parser::new(tokens).parse_expression().unwrap();
+    let mut reads = Reads(vec![]);
+
+    ast.visit_expr(expr, &mut reads);
+
+    assert_eq!(reads.0, vec!["b", "c", "d", "e", "f", "g", "h", "i", "j", "k", "l"]);
+  }
+}
@@ -0,0 +1,44 @@
+use std::error;
+use std::fmt;
+
+// why bytes couldn't be read back as a compiled script. Offsets are into the file, except for
+// code problems where they're into the code of the function named
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoadError {
+  NotCompiled,
+  UnsupportedVersion { found: u16 },
+  Truncated { offset: usize },
+  InvalidUtf8 { offset: usize },
+  UnknownConstant { tag: u8, offset: usize },
+  NestedTooDeeply { offset: usize },
+  InvalidLines { function: String },
+  InvalidCode { function: String, offset: usize, problem: &'static str },
+  TrailingBytes { offset: usize },
+}
+
+impl fmt::Display for LoadError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      LoadError::NotCompiled => write!(f, "not a compiled Lox script"),
+      LoadError::UnsupportedVersion { found } => write!(
+        f,
+        "compiled for format version {}, this rslox reads version {}",
+        found,
+        super::FORMAT_VERSION
+      ),
+      LoadError::Truncated { offset } => write!(f, "file ends early, at byte {}", offset),
+      LoadError::InvalidUtf8 { offset } => write!(f, "string at byte {} isn't valid UTF-8", offset),
+      LoadError::UnknownConstant { tag, offset } => write!(f, "unknown constant kind {} at byte {}", tag, offset),
+      LoadError::NestedTooDeeply { offset } => write!(f, "functions nested too deeply, at byte {}", offset),
+      LoadError::InvalidLines { function } => write!(f, "line information of {} doesn't cover its code", function),
+      LoadError::InvalidCode {
+        function,
+        offset,
+        problem,
+      } => write!(f, "{} in {} at {:04}", problem, function, offset),
+      LoadError::TrailingBytes { offset } => write!(f, "unexpected data after the script, at byte {}", offset),
+    }
+  }
+}
+
+impl error::Error for LoadError {}
@@ -0,0 +1,633 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use super::{Chunk, ConstantKey, Function, LineRun, LoadError, OpCode, Value};
+
+// a .loxc file is the magic and the format version, followed by the script. A function is its
+// name, arity, upvalue count, code, line runs and constants, with the functions among its
+// constants written out in full where they appear. Numbers are big endian, as operands are
+//
+//   "LOXC" u16 version
+//   function: string name, u32 arity, u32 upvalue_count, u32 len + code,
+//             u32 runs + (u32 start, u32 line) each, u32 count + constants
+//   constant: u8 tag, then f64 bits, string, u8 bool, nothing for nil, or a function
+//   string:   u32 len + UTF-8
+pub const MAGIC: &[u8; 4] = b"LOXC";
+
+// bumped whenever the instructions or the layout change, files of any other version are
+// rejected rather than run as something they weren't compiled for
+pub const FORMAT_VERSION: u16 = 9;
+
+const NUMBER: u8 = 0;
+const STRING: u8 = 1;
+const BOOL: u8 = 2;
+const NIL: u8 = 3;
+const FUNCTION: u8 = 4;
+
+// functions inside functions deeper than this are rejected rather than overflowing the stack
+const MAX_NESTING: usize = 256;
+
+pub fn to_bytes(script: &Function) -> Vec<u8> {
+  let mut out = MAGIC.to_vec();
+  out.extend(FORMAT_VERSION.to_be_bytes());
+  write_function(&mut out, script);
+
+  out
+}
+
+fn write_u32(out: &mut Vec<u8>, value: usize) {
+  out.extend((value as u32).to_be_bytes());
+}
+
+fn write_string(out: &mut Vec<u8>, value: &str) {
+  write_u32(out, value.len());
+  out.extend(value.as_bytes());
+}
+
+fn write_function(out: &mut Vec<u8>, function: &Function) {
+  let chunk = &function.chunk;
+  write_string(out, &function.name);
+  write_u32(out, function.arity);
+  write_u32(out, function.upvalue_count);
+  write_u32(out, chunk.code.len());
+  out.extend(&chunk.code);
+  write_u32(out, chunk.lines.len());
+
+  for run in &chunk.lines {
+    write_u32(out, run.start);
+    write_u32(out, run.line);
+  }
+
+  write_u32(out, chunk.constants.len());
+
+  for constant in &chunk.constants {
+    match constant {
+      Value::Number(value) => {
+        out.push(NUMBER);
+        out.extend(value.to_bits().to_be_bytes());
+      }
+      Value::String(value) => {
+        out.push(STRING);
+        write_string(out, value);
+      }
+      Value::Bool(value) => out.extend([BOOL, *value as u8]),
+      Value::Nil => out.push(NIL),
+      Value::Function(function) => {
+        out.push(FUNCTION);
+        write_function(out, function);
+      }
+    }
+  }
+}
+
+// the script the bytes hold, once every function in it has been checked the way the compiler's
+// output would pass: known opcodes, operands inside the code, constants of the right kind, jumps
+// that land on an instruction, and locals, calls and pops that stay inside the call's slots.
+// What kind of value each slot holds isn't followed, so a file written by hand can still give an
+// instruction a value of a kind the compiler never would
+pub fn from_bytes(bytes: &[u8]) -> Result<Function, LoadError> {
+  if !bytes.starts_with(MAGIC) {
+    return Err(LoadError::NotCompiled);
+  }
+
+  let mut reader = Reader {
+    bytes,
+    offset: MAGIC.len(),
+  };
+  let version = u16::from_be_bytes([reader.byte()?, reader.byte()?]);
+
+  if version != FORMAT_VERSION {
+    return Err(LoadError::UnsupportedVersion { found: version });
+  }
+
+  let script = reader.function(0)?;
+
+  if reader.offset != bytes.len() {
+    return Err(LoadError::TrailingBytes { offset: reader.offset });
+  }
+
+  Ok(script)
+}
+
+struct Reader<'b> {
+  bytes: &'b [u8],
+  offset: usize,
+}
+
+impl Reader<'_> {
+  fn take(&mut self, len: usize) -> Result<&[u8], LoadError> {
+    let end = self.offset.checked_add(len).filter(|end| *end <= self.bytes.len());
+    let end = end.ok_or(LoadError::Truncated {
+      offset: self.bytes.len(),
+    })?;
+    let taken = &self.bytes[self.offset..end];
+    self.offset = end;
+
+    Ok(taken)
+  }
+
+  fn byte(&mut self) -> Result<u8, LoadError> {
+    Ok(self.take(1)?[0])
+  }
+
+  fn u32(&mut self) -> Result<usize, LoadError> {
+    let bytes = self.take(4)?;
+
+    Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+  }
+
+  fn string(&mut self) -> Result<String, LoadError> {
+    let len = self.u32()?;
+    let offset = self.offset;
+    let bytes = self.take(len)?;
+
+    String::from_utf8(bytes.to_vec()).map_err(|_| LoadError::InvalidUtf8 { offset })
+  }
+
+  // depth is how many functions this one is nested in, which is limited so a file that nests
+  // them without end is an error instead of a stack overflow
+  fn function(&mut self, depth: usize) -> Result<Function, LoadError> {
+    let name = self.string()?;
+    let arity = self.u32()?;
+    let upvalue_count = self.u32()?;
+    let len = self.u32()?;
+    let code = self.take(len)?.to_vec();
+
+    let mut lines = vec![];
+    for _ in 0..self.u32()? {
+      lines.push(LineRun {
+        start: self.u32()?,
+        line: self.u32()?,
+      });
+    }
+
+    let mut constants = vec![];
+    for _ in 0..self.u32()? {
+      let offset = self.offset;
+      let constant = match self.byte()? {
+        NUMBER => {
+          let bytes = self.take(8)?;
+          let number = f64::from_bits(u64::from_be_bytes(bytes.try_into().expect("took 8 bytes")));
+          // with nan-boxing some NaNs are other values, so every NaN loads as the one arithmetic makes
+          Value::Number(if number.is_nan() { f64::NAN } else { number })
+        }
+        STRING => Value::String(Rc::from(self.string()?)),
+        BOOL => Value::Bool(self.byte()? != 0),
+        NIL => Value::Nil,
+        FUNCTION if depth == MAX_NESTING => return Err(LoadError::NestedTooDeeply { offset }),
+        FUNCTION => Value::Function(Rc::new(self.function(depth + 1)?)),
+        tag => return Err(LoadError::UnknownConstant { tag, offset }),
+      };
+      constants.push(constant);
+    }
+
+    // the first of each number and string is the one the compiler would have found
+    let mut indices = HashMap::new();
+    for (index, constant) in constants.iter().enumerate() {
+      if let Some(key) = ConstantKey::of(constant) {
+        indices.entry(key).or_insert(index);
+      }
+    }
+
+    let function = Function {
+      name,
+      arity,
+      upvalue_count,
+      chunk: Chunk {
+        code,
+        lines,
+        constants,
+        indices,
+      },
+    };
+    verify(&function)?;
+
+    Ok(function)
+  }
+}
+
+// every byte needs a line for errors to report, so the runs start at the code and go forward
+fn verify_lines(function: &Function) -> Result<(), LoadError> {
+  let chunk = &function.chunk;
+  let starts_at_the_code = chunk.code.is_empty() || chunk.lines.first().map(|run| run.start) == Some(0);
+  let in_order = chunk.lines.windows(2).all(|runs| runs[0].start < runs[1].start);
+  let inside = chunk.lines.last().is_none_or(|run| run.start < chunk.code.len());
+
+  if starts_at_the_code && in_order && inside {
+    Ok(())
+  } else {
+    Err(LoadError::InvalidLines {
+      function: function.to_string(),
+    })
+  }
+}
+
+fn verify(function: &Function) -> Result<(), LoadError> {
+  verify_lines(function)?;
+
+  let chunk = &function.chunk;
+  let invalid = |offset: usize, problem: &'static str| LoadError::InvalidCode {
+    function: function.to_string(),
+    offset,
+    problem,
+  };
+  let operand = |offset: usize, len: usize| -> Result<&[u8], LoadError> {
+    chunk
+      .code
+      .get(offset + 1..offset + 1 + len)
+      .ok_or_else(|| invalid(offset, "operand runs past the end of the code"))
+  };
+  let constant = |offset: usize, long: bool| -> Result<&Value, LoadError> {
+    let index = match operand(offset, if long { 3 } else { 1 })? {
+      [index] => *index as usize,
+      bytes => u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]) as usize,
+    };
+
+    chunk.constants.get(index).ok_or_else(|| invalid(offset, "constant index out of range"))
+  };
+  let name = |offset: usize, long: bool| -> Result<(), LoadError> {
+    match constant(offset, long)? {
+      Value::String(_) => Ok(()),
+      _ => Err(invalid(offset, "expected a name constant")),
+    }
+  };
+  let index_width = |long: bool| if long { 3 } else { 1 };
+
+  // the length of the instruction starting at each offset, zero inside one
+  let mut lens = vec![0; chunk.code.len()];
+  let mut jumps = vec![];
+  let mut offset = 0;
+  let mut last = None;
+
+  while offset < chunk.code.len() {
+    let op = OpCode::try_from(chunk.code[offset]).map_err(|_| invalid(offset, "unknown instruction"))?;
+    let long = op.is_long();
+
+    let len = match op {
+      OpCode::Constant | OpCode::ConstantLong => {
+        constant(offset, long)?;
+        index_width(long)
+      }
+      OpCode::GetGlobal
+      | OpCode::GetGlobalLong
+      | OpCode::DefineGlobal
+      | OpCode::DefineGlobalLong
+      | OpCode::SetGlobal
+      | OpCode::SetGlobalLong
+      | OpCode::GetProperty
+      | OpCode::GetPropertyLong
+      | OpCode::SetProperty
+      | OpCode::SetPropertyLong
+      | OpCode::GetSuper
+      | OpCode::GetSuperLong
+      | OpCode::Class
+      | OpCode::ClassLong
+      | OpCode::Method
+      | OpCode::MethodLong
+      | OpCode::ImportName
+      | OpCode::ImportNameLong => {
+        name(offset, long)?;
+        index_width(long)
+      }
+      OpCode::Import | OpCode::ImportLong => match constant(offset, long)? {
+        Value::Function(_) => index_width(long),
+        _ => return Err(invalid(offset, "expected a function constant")),
+      },
+      OpCode::Invoke | OpCode::InvokeLong | OpCode::SuperInvoke | OpCode::SuperInvokeLong => {
+        name(offset, long)?;
+        operand(offset, index_width(long) + 1)?;
+        index_width(long) + 1
+      }
+      // each captured variable is a local of this function, or one of its own upvalues
+      OpCode::Closure | OpCode::ClosureLong => {
+        let captures = match constant(offset, long)? {
+          Value::Function(function) => function.upvalue_count,
+          _ => return Err(invalid(offset, "expected a function constant")),
+        };
+        let pairs = operand(offset, index_width(long) + 2 * captures)?;
+
+        for pair in pairs[index_width(long)..].chunks(2) {
+          match pair {
+            [1, _] => (),
+            [0, index] if (*index as usize) < function.upvalue_count => (),
+            [0, _] => return Err(invalid(offset, "upvalue index out of range")),
+            _ => return Err(invalid(offset, "captures must be locals or upvalues")),
+          }
+        }
+
+        index_width(long) + 2 * captures
+      }
+      OpCode::GetUpvalue | OpCode::SetUpvalue => {
+        if operand(offset, 1)?[0] as usize >= function.upvalue_count {
+          return Err(invalid(offset, "upvalue index out of range"));
+        }
+
+        1
+      }
+      OpCode::GetLocal | OpCode::SetLocal | OpCode::Call | OpCode::Dup | OpCode::Interpolate => {
+        operand(offset, 1)?;
+        1
+      }
+      OpCode::AddLocals | OpCode::BuildList | OpCode::BuildMap => {
+        operand(offset, 2)?;
+        2
+      }
+      OpCode::Jump | OpCode::JumpIfFalse | OpCode::JumpIfNotNil | OpCode::Case | OpCode::Loop | OpCode::Try => {
+        let bytes = operand(offset, 2)?;
+        let distance = u16::from_be_bytes([bytes[0], bytes[1]]) as usize;
+
+        match jump_target(op, offset, distance) {
+          Some(target) if target < chunk.code.len() => jumps.push((offset, target)),
+          _ => return Err(invalid(offset, "jump lands outside the code")),
+        }
+
+        2
+      }
+      _ => 0,
+    };
+
+    lens[offset] = 1 + len;
+    last = Some(op);
+    offset += 1 + len;
+  }
+
+  // running off the end of the code would read past it
+  if last != Some(OpCode::Return) {
+    return Err(invalid(chunk.code.len(), "code doesn't end with a return"));
+  }
+
+  // one into the middle of an instruction would run its operands as code
+  if let Some((offset, _)) = jumps.iter().find(|(_, target)| lens[*target] == 0) {
+    return Err(invalid(*offset, "jump lands inside an instruction"));
+  }
+
+  verify_stack(function, &lens).map_err(|(offset, problem)| invalid(offset, problem))
+}
+
+fn jump_target(op: OpCode, offset: usize, distance: usize) -> Option<usize> {
+  match op {
+    OpCode::Loop => (offset + 3).checked_sub(distance),
+    _ => Some(offset + 3 + distance),
+  }
+}
+
+// follows every path through the code from its start, with the stack as high as the callee and
+// its arguments. Each instruction has to find as many values as it takes and see the same height
+// whichever way it was reached, the way the compiler's locals do, so the slots of locals and
+// the callee of a call are always inside the stack
+fn verify_stack(function: &Function, lens: &[usize]) -> Result<(), (usize, &'static str)> {
+  let code = &function.chunk.code;
+  let mut heights = vec![None; code.len()];
+  let mut pending = vec![(0, function.arity + 1)];
+
+  while let Some((offset, height)) = pending.pop() {
+    match heights[offset] {
+      Some(seen) if seen == height => continue,
+      Some(_) => return Err((offset, "stack heights differ where paths meet")),
+      None => heights[offset] = Some(height),
+    }
+
+    let op = OpCode::try_from(code[offset]).expect("decoded already");
+    let width = if op.is_long() { 3 } else { 1 };
+    let byte = |index: usize| code[offset + 1 + index] as usize;
+    let short = |index: usize| byte(index) << 8 | byte(index + 1);
+    let local = |index: usize| match byte(index) < height {
+      true => Ok(()),
+      false => Err((offset, "local slot out of range")),
+    };
+
+    // how many values the instruction takes off the stack, and how many it leaves
+    let (pops, pushes) = match op {
+      OpCode::Constant
+      | OpCode::ConstantLong
+      | OpCode::Nil
+      | OpCode::True
+      | OpCode::False
+      | OpCode::GetGlobal
+      | OpCode::GetGlobalLong
+      | OpCode::GetUpvalue
+      | OpCode::Class
+      | OpCode::ClassLong => (0, 1),
+      OpCode::Try | OpCode::EndTry | OpCode::Jump | OpCode::Loop => (0, 0),
+      OpCode::GetLocal => {
+        local(0)?;
+        (0, 1)
+      }
+      OpCode::SetLocal => {
+        local(0)?;
+        (1, 1)
+      }
+      OpCode::AddLocals => {
+        local(0)?;
+        local(1)?;
+        (0, 1)
+      }
+      OpCode::Pop
+      | OpCode::DefineGlobal
+      | OpCode::DefineGlobalLong
+      | OpCode::Print
+      | OpCode::CloseUpvalue
+      | OpCode::Return
+      | OpCode::Throw => (1, 0),
+      OpCode::SetGlobal
+      | OpCode::SetGlobalLong
+      | OpCode::SetUpvalue
+      | OpCode::GetProperty
+      | OpCode::GetPropertyLong
+      | OpCode::Not
+      | OpCode::Negate
+      | OpCode::BitNot
+      | OpCode::JumpIfFalse
+      | OpCode::JumpIfNotNil
+      | OpCode::ImportName
+      | OpCode::ImportNameLong => (1, 1),
+      OpCode::Inherit | OpCode::Method | OpCode::MethodLong => (2, 1),
+      OpCode::SetProperty
+      | OpCode::SetPropertyLong
+      | OpCode::GetSuper
+      | OpCode::GetSuperLong
+      | OpCode::Equal
+      | OpCode::Greater
+      | OpCode::GreaterEqual
+      | OpCode::Less
+      | OpCode::LessEqual
+      | OpCode::Add
+      | OpCode::Subtract
+      | OpCode::Multiply
+      | OpCode::Divide
+      | OpCode::Remainder
+      | OpCode::BitAnd
+      | OpCode::BitOr
+      | OpCode::BitXor
+      | OpCode::ShiftLeft
+      | OpCode::ShiftRight
+      | OpCode::GetIndex
+      | OpCode::HasElement => (2, 1),
+      // the case value and the switch's, the switch's stays unless the jump is taken
+      OpCode::Case => (2, 1),
+      OpCode::SetIndex | OpCode::Slice => (3, 1),
+      OpCode::Interpolate => (byte(0), 1),
+      OpCode::Dup => (byte(0), 2 * byte(0)),
+      OpCode::BuildList => (short(0), 1),
+      OpCode::BuildMap => (2 * short(0), 1),
+      OpCode::Call => (byte(0) + 1, 1),
+      OpCode::Invoke | OpCode::InvokeLong => (byte(width) + 1, 1),
+      // the superclass on top of the arguments and the receiver
+      OpCode::SuperInvoke | OpCode::SuperInvokeLong => (byte(width) + 2, 1),
+      OpCode::Closure | OpCode::ClosureLong => {
+        let captures = (lens[offset] - 1 - width) / 2;
+
+        // the closure may capture the slot it's about to be pushed into, a local function
+        // calling itself does
+        for capture in 0..captures {
+          if byte(width + 2 * capture) == 1 && byte(width + 2 * capture + 1) > height {
+            return Err((offset, "local slot out of range"));
+          }
+        }
+
+        (0, 1)
+      }
+      OpCode::Import | OpCode::ImportLong => (0, 2),
+    };
+
+    let Some(left) = height.checked_sub(pops) else {
+      return Err((offset, "takes more values than the stack holds"));
+    };
+    let after = left + pushes;
+    let next = offset + lens[offset];
+    let target = || jump_target(op, offset, short(0)).expect("checked while decoding");
+
+    // the last instruction is a return, so the next one of any other is inside the code
+    match op {
+      OpCode::Return | OpCode::Throw => (),
+      OpCode::Jump | OpCode::Loop => pending.push((target(), after)),
+      OpCode::Case => pending.extend([(target(), left), (next, after)]),
+      OpCode::Try => pending.extend([(target(), after + 1), (next, after)]),
+      OpCode::JumpIfFalse | OpCode::JumpIfNotNil => pending.extend([(target(), after), (next, after)]),
+      _ => pending.push((next, after)),
+    }
+  }
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::compiler::Compiler;
+  use crate::debug;
+  use crate::lexer::{Lexer, SourceMap};
+  use crate::parser::Parser;
+  use crate::vm::Vm;
+
+  fn compile(code: &str) -> Function {
+    let ast = Parser::new(Lexer::new(code).lex().unwrap()).parse().unwrap();
+
+    Compiler::new(&SourceMap::new(code)).compile(&ast).unwrap()
+  }
+
+  #[test]
+  fn scripts_come_back_as_they_were_written() {
+    let script = compile(
+      "var a = 1.5;\nfun f(b) {\n  fun g() { return b + \"!\"; }\n  return g;\n}\nclass A < B { m() { super.m(); } }\nprint nil == true;",
+    );
+    let mut loaded = from_bytes(&to_bytes(&script)).unwrap();
+
+    assert_eq!(debug::disassemble_function(&loaded), debug::disassemble_function(&script));
+    assert_eq!(loaded.chunk.add_constant(Value::Number(1.5)), 0);
+  }
+
+  #[test]
+  fn headers_are_checked() {
+    let mut bytes = to_bytes(&compile("print 1;"));
+
+    assert_eq!(from_bytes(b"print 1;").unwrap_err(), LoadError::NotCompiled);
+    assert_eq!(from_bytes(&bytes[..bytes.len() - 1]).unwrap_err(), LoadError::Truncated { offset: bytes.len() - 1 });
+
+    bytes.push(0);
+    assert_eq!(from_bytes(&bytes).unwrap_err(), LoadError::TrailingBytes { offset: bytes.len() - 1 });
+
+    bytes[5] = 99;
+    assert_eq!(from_bytes(&bytes).unwrap_err(), LoadError::UnsupportedVersion { found: 99 });
+  }
+
+  #[test]
+  fn code_the_vm_cannot_run_is_rejected() {
+    let script = compile("print 1;");
+    let broken = |patch: &dyn Fn(&mut Chunk)| {
+      let mut script = Function {
+        name: script.name.clone(),
+        arity: script.arity,
+        upvalue_count: script.upvalue_count,
+        chunk: script.chunk.clone(),
+      };
+      patch(&mut script.chunk);
+
+      match from_bytes(&to_bytes(&script)).unwrap_err() {
+        LoadError::InvalidCode { problem, .. } => problem,
+        error => panic!("expected invalid code, found {:?}", error),
+      }
+    };
+
+    assert_eq!(broken(&|chunk| chunk.code[1] = 7), "constant index out of range");
+    assert_eq!(broken(&|chunk| chunk.code[0] = 255), "unknown instruction");
+    assert_eq!(broken(&|chunk| chunk.code[0] = OpCode::GetGlobal as u8), "expected a name constant");
+    assert_eq!(broken(&|chunk| chunk.code[0] = OpCode::GetUpvalue as u8), "upvalue index out of range");
+    assert_eq!(broken(&|chunk| chunk.code.truncate(3)), "code doesn't end with a return");
+    assert_eq!(broken(&|chunk| chunk.code[0] = OpCode::Loop as u8), "jump lands outside the code");
+
+    let jump_into_operand = [OpCode::Jump as u8, 0, 1, OpCode::Constant as u8, 0, OpCode::Return as u8];
+    assert_eq!(broken(&|chunk| chunk.code = jump_into_operand.to_vec()), "jump lands inside an instruction");
+    let uneven = [OpCode::Constant as u8, 0, OpCode::JumpIfFalse as u8, 0, 1, OpCode::Pop as u8, OpCode::Return as u8];
+    assert_eq!(broken(&|chunk| chunk.code = uneven.to_vec()), "stack heights differ where paths meet");
+    assert_eq!(broken(&|chunk| chunk.code[3] = OpCode::Pop as u8), "takes more values than the stack holds");
+    let call = [OpCode::Call as u8, 5];
+    assert_eq!(broken(&|chunk| chunk.code[..2].copy_from_slice(&call)), "takes more values than the stack holds");
+    let get_local = [OpCode::GetLocal as u8, 1];
+    assert_eq!(broken(&|chunk| chunk.code[..2].copy_from_slice(&get_local)), "local slot out of range");
+  }
+
+  #[test]
+  fn functions_nested_too_deeply_are_rejected() {
+    let nested = |depth: usize| to_bytes(&compile(&format!("{}{}", "fun f() {".repeat(depth), "}".repeat(depth))));
+
+    assert!(from_bytes(&nested(MAX_NESTING)).is_ok());
+    assert!(matches!(from_bytes(&nested(MAX_NESTING + 1)), Err(LoadError::NestedTooDeeply { .. })));
+  }
+
+  // a NaN's payload could be anything in a file, which nan-boxing would take for another value
+  #[test]
+  fn every_nan_loads_as_the_same_number() {
+    let mut script = compile("print 1;");
+    script.chunk.constants[0] = Value::Number(f64::from_bits(0xfffc_0000_0000_0001));
+    let loaded = from_bytes(&to_bytes(&script)).unwrap();
+
+    assert!(matches!(loaded.chunk.constants[0], Value::Number(number) if number.to_bits() == f64::NAN.to_bits()));
+    assert!(Vm::with_output(Box::new(std::io::sink())).interpret(loaded).is_ok());
+  }
+
+  // every value of the operands that index the stack is either rejected or runs without
+  // panicking. The code has no loops, so none of them can make it run forever. A byte that only
+  // looks like one of those instructions, in a constant say, is corrupted too, which does no harm
+  #[test]
+  fn corrupted_stack_operands_never_reach_the_vm() {
+    let code = "fun f(a, b) { var c = a + b; a = c; return a; } print f(1, 2); { var d = f(3, 4); print d; }";
+    let bytes = to_bytes(&compile(code));
+    let stack_ops = [OpCode::GetLocal as u8, OpCode::SetLocal as u8, OpCode::Call as u8];
+    let mut loaded = 0;
+
+    for offset in (MAGIC.len()..bytes.len() - 1).filter(|offset| stack_ops.contains(&bytes[*offset])) {
+      for value in 0..=u8::MAX {
+        let mut corrupted = bytes.clone();
+        corrupted[offset + 1] = value;
+
+        if let Ok(script) = from_bytes(&corrupted) {
+          let _ = Vm::with_output(Box::new(std::io::sink())).interpret(script);
+          loaded += 1;
+        }
+      }
+    }
+
+    assert!(loaded > 0);
+  }
+}
@@ -0,0 +1,477 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+mod error;
+mod file;
+mod value;
+
+pub use error::LoadError;
+pub use file::{from_bytes, to_bytes, FORMAT_VERSION, MAGIC};
+pub use value::{Function, Value};
+
+// how many constants a chunk can hold, the long forms of the instructions indexing them have
+// three byte operands
+pub const MAX_CONSTANTS: usize = 1 << 24;
+
+// one byte instructions, the operands of those that take any follow them in the code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum OpCode {
+  // index into the constants
+  Constant,
+  // three byte index into the constants, for any past the first 256
+  ConstantLong,
+  Nil,
+  True,
+  False,
+  Pop,
+  // number of values on top of the stack to push copies of, in the same order
+  Dup,
+  // slot of the local in the current call
+  GetLocal,
+  SetLocal,
+  // index of the constant holding the name
+  GetGlobal,
+  GetGlobalLong,
+  DefineGlobal,
+  DefineGlobalLong,
+  SetGlobal,
+  SetGlobalLong,
+  // index into the current closure's captured variables
+  GetUpvalue,
+  SetUpvalue,
+  // index of the constant holding the property's name
+  GetProperty,
+  GetPropertyLong,
+  SetProperty,
+  SetPropertyLong,
+  // index of the constant holding the method's name, looked up on the superclass on top of
+  // the stack and bound to the instance under it
+  GetSuper,
+  GetSuperLong,
+  Equal,
+  Greater,
+  GreaterEqual,
+  Less,
+  LessEqual,
+  Add,
+  // the slots of two locals, pushes their sum. Only the optimizer emits it, for a get-local,
+  // get-local, add sequence
+  AddLocals,
+  Subtract,
+  Multiply,
+  Divide,
+  Remainder,
+  BitAnd,
+  BitOr,
+  BitXor,
+  ShiftLeft,
+  ShiftRight,
+  Not,
+  Negate,
+  BitNot,
+  // number of parts to stringify and join, they are on the stack in order
+  Interpolate,
+  Print,
+  // two byte offset forward from the end of the operand
+  Jump,
+  JumpIfFalse,
+  // the `??` of the value on top of the stack, which is left there either way
+  JumpIfNotNil,
+  // a switch's case, with a two byte offset forward to its statements. Pops the case value and
+  // compares it with the switch's value under it, which is popped too when they're equal and the
+  // jump taken, and left for the next case when they aren't
+  Case,
+  // two byte offset backward from the end of the operand
+  Loop,
+  // number of arguments
+  Call,
+  // index of the constant holding the method's name, then the number of arguments
+  Invoke,
+  InvokeLong,
+  SuperInvoke,
+  SuperInvokeLong,
+  // index of the function constant, then a pair of bytes for each variable it captures: 1 and
+  // a slot for a local of the enclosing function, 0 and an index for one of its upvalues
+  Closure,
+  ClosureLong,
+  // moves the local on top of the stack off it and into the closures that captured it
+  CloseUpvalue,
+  Return,
+  // index of the constant holding the class's name
+  Class,
+  ClassLong,
+  // copies the methods of the superclass under the subclass on top of the stack into it
+  Inherit,
+  // index of the constant holding the method's name, the method is added to the class under it
+  Method,
+  MethodLong,
+  // two byte number of elements, they are on the stack in order
+  BuildList,
+  // the list or map under the index
+  GetIndex,
+  // the list or map, the index and the value, the value is left as the result
+  SetIndex,
+  // the list and the two bounds, nil for one that was left out
+  Slice,
+  // the list under the index of the next element a for-in loop visits, pushes whether there is one
+  HasElement,
+  // two byte number of entries, each key is on the stack under its value
+  BuildMap,
+  // two byte distance forward to the catch clause, a runtime error or throw from here on goes
+  // there with the stack as it is now and the exception pushed on it
+  Try,
+  // the try block finished without throwing
+  EndTry,
+  // the value on top of the stack, to the innermost try
+  Throw,
+  // index of the constant holding the module's top level, a function named after its path in
+  // angle brackets. Leaves the module's number and nil on the stack, running the top level
+  // first when no module of that name has run yet, with globals of its own. The nil is what
+  // the top level returned when it did
+  Import,
+  ImportLong,
+  // index of the constant holding the name, the global of the module whose number is on top
+  // of the stack is defined as a global of the importer
+  ImportName,
+  ImportNameLong,
+}
+
+impl OpCode {
+  const ALL: [OpCode; 76] = [
+    OpCode::Constant,
+    OpCode::ConstantLong,
+    OpCode::Nil,
+    OpCode::True,
+    OpCode::False,
+    OpCode::Pop,
+    OpCode::Dup,
+    OpCode::GetLocal,
+    OpCode::SetLocal,
+    OpCode::GetGlobal,
+    OpCode::GetGlobalLong,
+    OpCode::DefineGlobal,
+    OpCode::DefineGlobalLong,
+    OpCode::SetGlobal,
+    OpCode::SetGlobalLong,
+    OpCode::GetUpvalue,
+    OpCode::SetUpvalue,
+    OpCode::GetProperty,
+    OpCode::GetPropertyLong,
+    OpCode::SetProperty,
+    OpCode::SetPropertyLong,
+    OpCode::GetSuper,
+    OpCode::GetSuperLong,
+    OpCode::Equal,
+    OpCode::Greater,
+    OpCode::GreaterEqual,
+    OpCode::Less,
+    OpCode::LessEqual,
+    OpCode::Add,
+    OpCode::AddLocals,
+    OpCode::Subtract,
+    OpCode::Multiply,
+    OpCode::Divide,
+    OpCode::Remainder,
+    OpCode::BitAnd,
+    OpCode::BitOr,
+    OpCode::BitXor,
+    OpCode::ShiftLeft,
+    OpCode::ShiftRight,
+    OpCode::Not,
+    OpCode::Negate,
+    OpCode::BitNot,
+    OpCode::Interpolate,
+    OpCode::Print,
+    OpCode::Jump,
+    OpCode::JumpIfFalse,
+    OpCode::JumpIfNotNil,
+    OpCode::Case,
+    OpCode::Loop,
+    OpCode::Call,
+    OpCode::Invoke,
+    OpCode::InvokeLong,
+    OpCode::SuperInvoke,
+    OpCode::SuperInvokeLong,
+    OpCode::Closure,
+    OpCode::ClosureLong,
+    OpCode::CloseUpvalue,
+    OpCode::Return,
+    OpCode::Class,
+    OpCode::ClassLong,
+    OpCode::Inherit,
+    OpCode::Method,
+    OpCode::MethodLong,
+    OpCode::BuildList,
+    OpCode::GetIndex,
+    OpCode::SetIndex,
+    OpCode::Slice,
+    OpCode::HasElement,
+    OpCode::BuildMap,
+    OpCode::Try,
+    OpCode::EndTry,
+    OpCode::Throw,
+    OpCode::Import,
+    OpCode::ImportLong,
+    OpCode::ImportName,
+    OpCode::ImportNameLong,
+  ];
+
+  // the form of an instruction indexing the constants that takes a three byte index
+  pub fn long(self) -> Option<Self> {
+    match self {
+      OpCode::Constant => Some(OpCode::ConstantLong),
+      OpCode::GetGlobal => Some(OpCode::GetGlobalLong),
+      OpCode::DefineGlobal => Some(OpCode::DefineGlobalLong),
+      OpCode::SetGlobal => Some(OpCode::SetGlobalLong),
+      OpCode::GetProperty => Some(OpCode::GetPropertyLong),
+      OpCode::SetProperty => Some(OpCode::SetPropertyLong),
+      OpCode::GetSuper => Some(OpCode::GetSuperLong),
+      OpCode::Invoke => Some(OpCode::InvokeLong),
+      OpCode::SuperInvoke => Some(OpCode::SuperInvokeLong),
+      OpCode::Closure => Some(OpCode::ClosureLong),
+      OpCode::Class => Some(OpCode::ClassLong),
+      OpCode::Method => Some(OpCode::MethodLong),
+      OpCode::Import => Some(OpCode::ImportLong),
+      OpCode::ImportName => Some(OpCode::ImportNameLong),
+      _ => None,
+    }
+  }
+
+  // whether the instruction is the long form of one
+  pub fn is_long(self) -> bool {
+    OpCode::ALL.iter().any(|short| short.long() == Some(self))
+  }
+
+  // how many bytes of operands follow the instruction, not counting the pairs after a closure
+  // saying where to find what it captures
+  pub fn operand_len(self) -> usize {
+    match self {
+      OpCode::Constant
+      | OpCode::GetGlobal
+      | OpCode::DefineGlobal
+      | OpCode::SetGlobal
+      | OpCode::GetProperty
+      | OpCode::SetProperty
+      | OpCode::GetSuper
+      | OpCode::Class
+      | OpCode::Method
+      | OpCode::Closure
+      | OpCode::Import
+      | OpCode::ImportName
+      | OpCode::GetLocal
+      | OpCode::SetLocal
+      | OpCode::GetUpvalue
+      | OpCode::SetUpvalue
+      | OpCode::Call
+      | OpCode::Dup
+      | OpCode::Interpolate => 1,
+      OpCode::Invoke
+      | OpCode::SuperInvoke
+      | OpCode::AddLocals
+      | OpCode::BuildList
+      | OpCode::BuildMap
+      | OpCode::Try
+      | OpCode::Jump
+      | OpCode::JumpIfFalse
+      | OpCode::JumpIfNotNil
+      | OpCode::Case
+      | OpCode::Loop => 2,
+      OpCode::ConstantLong
+      | OpCode::GetGlobalLong
+      | OpCode::DefineGlobalLong
+      | OpCode::SetGlobalLong
+      | OpCode::GetPropertyLong
+      | OpCode::SetPropertyLong
+      | OpCode::GetSuperLong
+      | OpCode::ClassLong
+      | OpCode::MethodLong
+      | OpCode::ClosureLong
+      | OpCode::ImportLong
+      | OpCode::ImportNameLong => 3,
+      OpCode::InvokeLong | OpCode::SuperInvokeLong => 4,
+      _ => 0,
+    }
+  }
+}
+
+// the byte isn't an instruction, which only happens when reading past an operand by mistake
+impl TryFrom<u8> for OpCode {
+  type Error = u8;
+
+  fn try_from(byte: u8) -> Result<Self, Self::Error> {
+    OpCode::ALL.get(byte as usize).copied().ok_or(byte)
+  }
+}
+
+// the constants that are stored once however many times they're used, numbers by their bits so
+// 0 and -0 stay apart
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum ConstantKey {
+  Number(u64),
+  String(Rc<str>),
+}
+
+impl ConstantKey {
+  fn of(value: &Value) -> Option<Self> {
+    match value {
+      Value::Number(value) => Some(ConstantKey::Number(value.to_bits())),
+      Value::String(value) => Some(ConstantKey::String(Rc::clone(value))),
+      _ => None,
+    }
+  }
+}
+
+// consecutive bytes compiled from the same line, starting at offset start
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct LineRun {
+  start: usize,
+  line: usize,
+}
+
+// a run of bytecode together with the constants it refers to and the source line of every
+// byte, for errors to point at. Lines are stored once per run of bytes rather than per byte,
+// as a line usually compiles to several instructions
+#[derive(Debug, Clone, Default)]
+pub struct Chunk {
+  code: Vec<u8>,
+  lines: Vec<LineRun>,
+  constants: Vec<Value>,
+  // where each number and string already in the constants is
+  indices: HashMap<ConstantKey, usize>,
+}
+
+impl Chunk {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn code(&self) -> &[u8] {
+    &self.code
+  }
+
+  pub fn constants(&self) -> &[Value] {
+    &self.constants
+  }
+
+  pub fn write(&mut self, byte: u8, line: usize) {
+    if self.lines.last().map(|run| run.line) != Some(line) {
+      self.lines.push(LineRun {
+        start: self.code.len(),
+        line,
+      });
+    }
+
+    self.code.push(byte);
+  }
+
+  pub fn write_op(&mut self, op: OpCode, line: usize) {
+    self.write(op as u8, line);
+  }
+
+  // the index the constant was stored at, or of the same number or string added before it
+  pub fn add_constant(&mut self, value: Value) -> usize {
+    let key = ConstantKey::of(&value);
+
+    if let Some(index) = key.as_ref().and_then(|key| self.indices.get(key)) {
+      return *index;
+    }
+
+    self.constants.push(value);
+    let index = self.constants.len() - 1;
+
+    if let Some(key) = key {
+      self.indices.insert(key, index);
+    }
+
+    index
+  }
+
+  // the line of the code the byte at offset was compiled from
+  pub fn get_line(&self, offset: usize) -> usize {
+    let run = self.lines.partition_point(|run| run.start <= offset);
+
+    self.lines[run - 1].line
+  }
+
+  // how many bytes the instruction at offset takes up, its operands included
+  pub fn instruction_len(&self, offset: usize) -> usize {
+    let op = match OpCode::try_from(self.code[offset]) {
+      Ok(op) => op,
+      Err(_) => return 1,
+    };
+    let captures = match op {
+      OpCode::Closure | OpCode::ClosureLong => {
+        let index = match op {
+          OpCode::Closure => self.code[offset + 1] as usize,
+          _ => u32::from_be_bytes([0, self.code[offset + 1], self.code[offset + 2], self.code[offset + 3]]) as usize,
+        };
+
+        match &self.constants[index] {
+          Value::Function(function) => function.upvalue_count,
+          value => unreachable!("closures are made from functions, not {}", value),
+        }
+      }
+      _ => 0,
+    };
+
+    1 + op.operand_len() + 2 * captures
+  }
+
+  // overwrites a byte already written, for jumps whose target wasn't known when they were
+  pub fn patch(&mut self, offset: usize, byte: u8) {
+    self.code[offset] = byte;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn every_opcode_round_trips_through_its_byte() {
+    for op in OpCode::ALL {
+      assert_eq!(OpCode::try_from(op as u8), Ok(op));
+    }
+
+    assert_eq!(OpCode::try_from(OpCode::ALL.len() as u8), Err(OpCode::ALL.len() as u8));
+  }
+
+  #[test]
+  fn bytes_remember_their_line() {
+    let mut chunk = Chunk::new();
+    let constant = chunk.add_constant(Value::Number(1.2));
+    chunk.write_op(OpCode::Constant, 1);
+    chunk.write(constant as u8, 1);
+    chunk.write_op(OpCode::Return, 2);
+
+    assert_eq!(chunk.code(), &[OpCode::Constant as u8, 0, OpCode::Return as u8]);
+    assert_eq!((chunk.get_line(1), chunk.get_line(2)), (1, 2));
+    assert_eq!(chunk.constants(), &[Value::Number(1.2)]);
+  }
+
+  #[test]
+  fn lines_are_stored_once_per_run() {
+    let mut chunk = Chunk::new();
+
+    for line in [1, 1, 1, 2, 2, 1, 5] {
+      chunk.write_op(OpCode::Nil, line);
+    }
+
+    assert_eq!(chunk.lines.len(), 4);
+    assert_eq!((0..7).map(|offset| chunk.get_line(offset)).collect::<Vec<_>>(), vec![1, 1, 1, 2, 2, 1, 5]);
+  }
+
+  #[test]
+  fn numbers_and_strings_are_stored_once() {
+    let mut chunk = Chunk::new();
+    let number = chunk.add_constant(Value::Number(1.0));
+    let string = chunk.add_constant(Value::String(Rc::from("a")));
+    let function = chunk.add_constant(Value::Function(Rc::new(Function::default())));
+
+    assert_eq!(chunk.add_constant(Value::Number(1.0)), number);
+    assert_eq!(chunk.add_constant(Value::String(Rc::from("a"))), string);
+    assert_ne!(chunk.add_constant(Value::Number(-0.0)), chunk.add_constant(Value::Number(0.0)));
+    assert_ne!(chunk.add_constant(Value::Function(Rc::new(Function::default()))), function);
+  }
+}
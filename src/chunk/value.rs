@@ -0,0 +1,63 @@
+use std::fmt;
+use std::rc::Rc;
+
+use super::Chunk;
+
+// a compiled function, the top level code of a script is one without a name
+#[derive(Debug, Default)]
+pub struct Function {
+  pub name: String,
+  pub arity: usize,
+  // how many variables of the functions around it it captures, the closure instruction
+  // creating it is followed by where to find each of them
+  pub upvalue_count: usize,
+  pub chunk: Chunk,
+}
+
+impl fmt::Display for Function {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    if self.name.is_empty() {
+      write!(f, "<script>")
+    } else {
+      write!(f, "<fn {}>", self.name)
+    }
+  }
+}
+
+// the values a chunk can hold as constants, strings are shared so copying a value around never
+// copies the text. The vm loads them onto its own heap before running them
+#[derive(Debug, Clone)]
+pub enum Value {
+  Number(f64),
+  String(Rc<str>),
+  Bool(bool),
+  Nil,
+  Function(Rc<Function>),
+}
+
+// functions are only ever equal to themselves
+impl PartialEq for Value {
+  fn eq(&self, other: &Self) -> bool {
+    match (self, other) {
+      (Value::Number(left), Value::Number(right)) => left == right,
+      (Value::String(left), Value::String(right)) => left == right,
+      (Value::Bool(left), Value::Bool(right)) => left == right,
+      (Value::Nil, Value::Nil) => true,
+      (Value::Function(left), Value::Function(right)) => Rc::ptr_eq(left, right),
+      _ => false,
+    }
+  }
+}
+
+// shown the way the tree-walker prints them so both backends give the same output
+impl fmt::Display for Value {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Value::Number(value) => write!(f, "{}", value),
+      Value::String(value) => write!(f, "{}", value),
+      Value::Bool(value) => write!(f, "{}", value),
+      Value::Nil => write!(f, "nil"),
+      Value::Function(function) => write!(f, "{}", function),
+    }
+  }
+}
@@ -0,0 +1,56 @@
+use std::error;
+use std::fmt;
+
+use crate::lexer::Span;
+
+#[derive(Debug, Clone)]
+pub enum CompileError {
+  TooManyConstants {
+    span: Span,
+  },
+  TooManyLocals {
+    span: Span,
+  },
+  TooManyUpvalues {
+    span: Span,
+  },
+  JumpTooLarge {
+    span: Span,
+  },
+  // the instruction building a list counts its elements in two bytes
+  TooManyElements {
+    span: Span,
+  },
+  // and the one building a map its entries
+  TooManyEntries {
+    span: Span,
+  },
+}
+
+impl CompileError {
+  pub fn span(&self) -> Span {
+    match self {
+      CompileError::TooManyConstants { span }
+      | CompileError::TooManyLocals { span }
+      | CompileError::TooManyUpvalues { span }
+      | CompileError::JumpTooLarge { span }
+      | CompileError::TooManyElements { span }
+      | CompileError::TooManyEntries { span } => *span,
+    }
+  }
+}
+
+impl fmt::Display for CompileError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      CompileError::TooManyConstants { .. } => write!(f, "too many constants in one chunk"),
+      CompileError::TooManyLocals { .. } => write!(f, "too many local variables in function"),
+      CompileError::TooManyUpvalues { .. } => write!(f, "too many closure variables in function"),
+      CompileError::JumpTooLarge { .. } => write!(f, "too much code to jump over"),
+      CompileError::TooManyElements { .. } => write!(f, "too many elements in list literal"),
+      CompileError::TooManyEntries { .. } => write!(f, "too many entries in map literal"),
+    }
+  }
+}
+
+impl error::Error for CompileError {}
@@ -0,0 +1,1259 @@
+use std::collections::HashMap;
+use std::mem;
+use std::rc::Rc;
+
+use crate::ast::{
+  Ast, BinaryOperator, Case, ExprId, ExprKind, ExprVisitor, Literal, LogicalOperator, ModuleId, StmtId, StmtKind,
+  StmtVisitor, UnaryOperator,
+};
+use crate::chunk::{Chunk, Function, OpCode, Value, MAX_CONSTANTS};
+use crate::lexer::{SourceMap, Span};
+
+mod error;
+
+pub use error::CompileError;
+
+// locals live in stack slots and captured variables in a closure's list of them, so one byte
+// is enough to address every one of either
+const MAX_LOCALS: usize = u8::MAX as usize + 1;
+const MAX_UPVALUES: usize = u8::MAX as usize + 1;
+
+#[derive(Debug)]
+struct Local {
+  name: String,
+  // how many blocks deep it was declared, its slot is freed when that block ends
+  depth: usize,
+  // a closure refers to it, so it has to outlive its slot once the block ends
+  captured: bool,
+}
+
+// a variable of an enclosing function that a closure captures
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Upvalue {
+  // the slot of the local when it's one of the immediately enclosing function's, otherwise the
+  // index of that function's own upvalue for it
+  index: u8,
+  is_local: bool,
+}
+
+// where a name was found
+enum Variable {
+  Local(u8),
+  Upvalue(u8),
+  Global,
+}
+
+// a loop partway through being compiled. Its breaks and continues are jumps forward, patched
+// once the code they go to is written
+#[derive(Debug)]
+struct Loop {
+  // the scope the loop started in, the locals of any deeper one are popped on the way out
+  scope_depth: usize,
+  // try blocks open when it started, the handlers of ones opened since are dropped
+  tries: usize,
+  breaks: Vec<usize>,
+  continues: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FunctionKind {
+  Script,
+  Function,
+  // slot 0 holds the instance the method was called on, as `this`
+  Method,
+  // a method that always returns its instance
+  Initializer,
+}
+
+// a function partway through being compiled
+#[derive(Debug)]
+struct FunctionState {
+  function: Function,
+  kind: FunctionKind,
+  // slot 0 holds the function being called, the parameters and locals come after it
+  locals: Vec<Local>,
+  upvalues: Vec<Upvalue>,
+  scope_depth: usize,
+  // innermost last
+  loops: Vec<Loop>,
+  // try blocks the code being compiled is in, not counting their catch clauses
+  tries: usize,
+}
+
+impl FunctionState {
+  fn new(name: &str, arity: usize, kind: FunctionKind) -> Self {
+    let receiver = match kind {
+      FunctionKind::Method | FunctionKind::Initializer => "this",
+      FunctionKind::Script | FunctionKind::Function => "",
+    };
+
+    Self {
+      function: Function {
+        name: String::from(name),
+        arity,
+        upvalue_count: 0,
+        chunk: Chunk::new(),
+      },
+      kind,
+      locals: vec![Local {
+        name: String::from(receiver),
+        depth: 0,
+        captured: false,
+      }],
+      upvalues: vec![],
+      scope_depth: 0,
+      loops: vec![],
+      tries: 0,
+    }
+  }
+}
+
+// walks the tree once, emitting bytecode for each node as it goes. Locals are given stack
+// slots in the order they are declared, anything not found among them is a global
+pub struct Compiler<'a> {
+  source_map: &'a SourceMap<'a>,
+  // the function being compiled and the ones it is nested in, innermost last
+  functions: Vec<FunctionState>,
+  // the top level of each imported file, compiled the first time an import of it comes up
+  modules: HashMap<ModuleId, Rc<Function>>,
+  errors: Vec<CompileError>,
+}
+
+impl<'a> Compiler<'a> {
+  // the source map turns the spans of the tree into the line numbers the chunk keeps
+  pub fn new(source_map: &'a SourceMap<'a>) -> Self {
+    Self {
+      source_map,
+      functions: vec![FunctionState::new("", 0, FunctionKind::Script)],
+      modules: HashMap::new(),
+      errors: vec![],
+    }
+  }
+
+  // the whole program as a function taking no arguments, or every error found compiling it.
+  // The tree should have been through the resolver, which catches the mistakes the compiler
+  // doesn't look for
+  pub fn compile(mut self, ast: &Ast) -> Result<Function, Vec<CompileError>> {
+    for statement in ast.program() {
+      ast.visit_stmt(*statement, &mut self);
+    }
+
+    let end = ast.program().last().map_or(0, |statement| ast[*statement].span.end);
+    self.emit_return(Span::new(end, end));
+    let script = self.functions.pop().expect("the script is compiled last");
+
+    if self.errors.is_empty() {
+      Ok(script.function)
+    } else {
+      Err(self.errors)
+    }
+  }
+
+  fn current(&mut self) -> &mut FunctionState {
+    self.functions.last_mut().expect("there is always a function being compiled")
+  }
+
+  fn chunk(&mut self) -> &mut Chunk {
+    &mut self.current().function.chunk
+  }
+
+  fn compile_expr(&mut self, ast: &Ast, expr: ExprId) {
+    ast.visit_expr(expr, self)
+  }
+
+  fn compile_stmt(&mut self, ast: &Ast, stmt: StmtId) {
+    ast.visit_stmt(stmt, self)
+  }
+
+  fn emit_byte(&mut self, byte: u8, span: Span) {
+    let line = self.source_map.location(span.start).line;
+    self.chunk().write(byte, line);
+  }
+
+  // the instructions of a binary operator, its operands are on the stack
+  fn emit_binary(&mut self, operator: BinaryOperator, span: Span) {
+    let op = match operator {
+      BinaryOperator::Add => OpCode::Add,
+      BinaryOperator::Subtract => OpCode::Subtract,
+      BinaryOperator::Multiply => OpCode::Multiply,
+      BinaryOperator::Divide => OpCode::Divide,
+      BinaryOperator::Remainder => OpCode::Remainder,
+      BinaryOperator::Equal => OpCode::Equal,
+      BinaryOperator::NotEqual => {
+        self.emit_op(OpCode::Equal, span);
+        OpCode::Not
+      }
+      BinaryOperator::Less => OpCode::Less,
+      BinaryOperator::LessEqual => OpCode::LessEqual,
+      BinaryOperator::Greater => OpCode::Greater,
+      BinaryOperator::GreaterEqual => OpCode::GreaterEqual,
+      BinaryOperator::BitAnd => OpCode::BitAnd,
+      BinaryOperator::BitOr => OpCode::BitOr,
+      BinaryOperator::BitXor => OpCode::BitXor,
+      BinaryOperator::ShiftLeft => OpCode::ShiftLeft,
+      BinaryOperator::ShiftRight => OpCode::ShiftRight,
+    };
+    self.emit_op(op, span);
+  }
+
+  fn emit_op(&mut self, op: OpCode, span: Span) {
+    self.emit_byte(op as u8, span);
+  }
+
+  fn emit_with_operand(&mut self, op: OpCode, operand: u8, span: Span) {
+    self.emit_op(op, span);
+    self.emit_byte(operand, span);
+  }
+
+  fn make_constant(&mut self, value: Value, span: Span) -> usize {
+    let index = self.chunk().add_constant(value);
+
+    if index >= MAX_CONSTANTS {
+      self.errors.push(CompileError::TooManyConstants { span });
+    }
+
+    index
+  }
+
+  // the first 256 constants fit in a byte, the rest need the long form of the instruction
+  fn emit_with_constant(&mut self, op: OpCode, index: usize, span: Span) {
+    match (u8::try_from(index), op.long()) {
+      (Ok(index), _) => self.emit_with_operand(op, index, span),
+      (Err(_), Some(long)) => {
+        let [_, high, middle, low] = (index as u32).to_be_bytes();
+        self.emit_op(long, span);
+        self.emit_byte(high, span);
+        self.emit_byte(middle, span);
+        self.emit_byte(low, span);
+      }
+      (Err(_), None) => unreachable!("{:?} doesn't index the constants", op),
+    }
+  }
+
+  fn emit_constant(&mut self, value: Value, span: Span) {
+    let index = self.make_constant(value, span);
+    self.emit_with_constant(OpCode::Constant, index, span);
+  }
+
+  // globals are looked up by name at runtime, the name goes in the constants
+  fn name_constant(&mut self, name: &str, span: Span) -> usize {
+    self.make_constant(Value::String(Rc::from(name)), span)
+  }
+
+  // the offset of the jump's operand, to patch once the code it jumps over is written
+  fn emit_jump(&mut self, op: OpCode, span: Span) -> usize {
+    self.emit_op(op, span);
+    self.emit_byte(0xff, span);
+    self.emit_byte(0xff, span);
+
+    self.chunk().code().len() - 2
+  }
+
+  fn patch_jump(&mut self, operand: usize, span: Span) {
+    let distance = self.chunk().code().len() - operand - 2;
+    let distance = u16::try_from(distance).unwrap_or_else(|_| {
+      self.errors.push(CompileError::JumpTooLarge { span });
+      0
+    });
+
+    let [high, low] = distance.to_be_bytes();
+    self.chunk().patch(operand, high);
+    self.chunk().patch(operand + 1, low);
+  }
+
+  fn emit_loop(&mut self, loop_start: usize, span: Span) {
+    self.emit_op(OpCode::Loop, span);
+
+    let distance = self.chunk().code().len() - loop_start + 2;
+    let distance = u16::try_from(distance).unwrap_or_else(|_| {
+      self.errors.push(CompileError::JumpTooLarge { span });
+      0
+    });
+
+    let [high, low] = distance.to_be_bytes();
+    self.emit_byte(high, span);
+    self.emit_byte(low, span);
+  }
+
+  // a function that runs off its end returns nil, and an initializer its instance
+  fn emit_return(&mut self, span: Span) {
+    if self.current().kind == FunctionKind::Initializer {
+      self.emit_with_operand(OpCode::GetLocal, 0, span);
+    } else {
+      self.emit_op(OpCode::Nil, span);
+    }
+
+    self.emit_op(OpCode::Return, span);
+  }
+
+  fn begin_scope(&mut self) {
+    self.current().scope_depth += 1;
+  }
+
+  // the locals of the block are popped off the stack along with it, the ones closures captured
+  // are moved into them on the way
+  fn end_scope(&mut self, span: Span) {
+    self.current().scope_depth -= 1;
+
+    loop {
+      let current = self.current();
+
+      match current.locals.last() {
+        Some(local) if local.depth > current.scope_depth => {
+          let op = if local.captured { OpCode::CloseUpvalue } else { OpCode::Pop };
+          current.locals.pop();
+          self.emit_op(op, span);
+        }
+        _ => break,
+      }
+    }
+  }
+
+  fn is_global_scope(&self) -> bool {
+    self.functions.len() == 1 && self.functions[0].scope_depth == 0
+  }
+
+  // the body of the loop is compiled with it as the one its breaks and continues leave
+  fn begin_loop(&mut self) {
+    let current = self.current();
+    let (scope_depth, tries) = (current.scope_depth, current.tries);
+
+    current.loops.push(Loop {
+      scope_depth,
+      tries,
+      breaks: vec![],
+      continues: vec![],
+    });
+  }
+
+  // the continues go to the next instruction, the code of a loop after its body that moves
+  // on to the next time round
+  fn patch_continues(&mut self, span: Span) {
+    let continues = mem::take(&mut self.current().loops.last_mut().expect("inside a loop").continues);
+
+    for jump in continues {
+      self.patch_jump(jump, span);
+    }
+  }
+
+  // the breaks go to the next instruction, once the loop is done
+  fn end_loop(&mut self, span: Span) {
+    let breaks = self.current().loops.pop().expect("inside a loop").breaks;
+
+    for jump in breaks {
+      self.patch_jump(jump, span);
+    }
+  }
+
+  // the stack is left the way it was when the loop started, with the locals declared inside it
+  // popped and the handlers of the try blocks opened inside it dropped. The compiler still
+  // knows about the locals, the code after the jump is in their scope
+  fn emit_loop_exit(&mut self, span: Span) {
+    let current = self.current();
+    let current_loop = current.loops.last().expect("the parser only allows break and continue in loops");
+    let ends = current.tries - current_loop.tries;
+    let pops = current
+      .locals
+      .iter()
+      .rev()
+      .take_while(|local| local.depth > current_loop.scope_depth)
+      .map(|local| if local.captured { OpCode::CloseUpvalue } else { OpCode::Pop })
+      .collect::<Vec<_>>();
+
+    for op in pops {
+      self.emit_op(op, span);
+    }
+
+    for _ in 0..ends {
+      self.emit_op(OpCode::EndTry, span);
+    }
+  }
+
+  // the value the local starts with is already on top of the stack, or is about to be, its
+  // position there becomes the local's slot
+  fn add_local(&mut self, name: &str, span: Span) {
+    if self.current().locals.len() == MAX_LOCALS {
+      self.errors.push(CompileError::TooManyLocals { span });
+      return;
+    }
+
+    let depth = self.current().scope_depth;
+    self.current().locals.push(Local {
+      name: String::from(name),
+      depth,
+      captured: false,
+    });
+  }
+
+  // the name as a local of the function being compiled, then as one of a function around it,
+  // and otherwise as a global
+  fn resolve(&mut self, name: &str, span: Span) -> Variable {
+    let current = self.functions.len() - 1;
+
+    if let Some(slot) = self.resolve_local(current, name) {
+      return Variable::Local(slot);
+    }
+
+    match self.resolve_upvalue(current, name, span) {
+      Some(index) => Variable::Upvalue(index),
+      None => Variable::Global,
+    }
+  }
+
+  // the slot of the innermost local with the name in the function
+  fn resolve_local(&self, function: usize, name: &str) -> Option<u8> {
+    let locals = &self.functions[function].locals;
+
+    locals.iter().rposition(|local| local.name == name).map(|slot| slot as u8)
+  }
+
+  // the index of the function's upvalue for a local of a function around it. Every function in
+  // between captures it too, so each closure can hand it on to the next one in
+  fn resolve_upvalue(&mut self, function: usize, name: &str, span: Span) -> Option<u8> {
+    if function == 0 {
+      return None;
+    }
+
+    if let Some(slot) = self.resolve_local(function - 1, name) {
+      self.functions[function - 1].locals[slot as usize].captured = true;
+      return Some(self.add_upvalue(function, slot, true, span));
+    }
+
+    let index = self.resolve_upvalue(function - 1, name, span)?;
+
+    Some(self.add_upvalue(function, index, false, span))
+  }
+
+  // a variable captured more than once is only stored once
+  fn add_upvalue(&mut self, function: usize, index: u8, is_local: bool, span: Span) -> u8 {
+    let upvalue = Upvalue { index, is_local };
+    let upvalues = &mut self.functions[function].upvalues;
+
+    if let Some(existing) = upvalues.iter().position(|existing| *existing == upvalue) {
+      return existing as u8;
+    }
+
+    if upvalues.len() == MAX_UPVALUES {
+      self.errors.push(CompileError::TooManyUpvalues { span });
+      return 0;
+    }
+
+    upvalues.push(upvalue);
+    (upvalues.len() - 1) as u8
+  }
+
+  fn load_variable(&mut self, name: &str, span: Span) {
+    match self.resolve(name, span) {
+      Variable::Local(slot) => self.emit_with_operand(OpCode::GetLocal, slot, span),
+      Variable::Upvalue(index) => self.emit_with_operand(OpCode::GetUpvalue, index, span),
+      Variable::Global => {
+        let name = self.name_constant(name, span);
+        self.emit_with_constant(OpCode::GetGlobal, name, span);
+      }
+    }
+  }
+
+  // parameters are the first locals after the function itself, the body shares their scope
+  fn function(
+    &mut self,
+    ast: &Ast,
+    id: StmtId,
+    name: &str,
+    params: &[String],
+    body: &[StmtId],
+    kind: FunctionKind,
+  ) {
+    let span = ast[id].span;
+    self.functions.push(FunctionState::new(name, params.len(), kind));
+    self.begin_scope();
+
+    for param in params {
+      self.add_local(param, span);
+    }
+
+    body.iter().for_each(|statement| self.compile_stmt(ast, *statement));
+    self.emit_return(Span::new(span.end, span.end));
+
+    let FunctionState {
+      mut function, upvalues, ..
+    } = self.functions.pop().expect("the function was pushed above");
+    function.upvalue_count = upvalues.len();
+
+    let index = self.make_constant(Value::Function(Rc::new(function)), span);
+    self.emit_with_constant(OpCode::Closure, index, span);
+
+    for upvalue in upvalues {
+      self.emit_byte(upvalue.is_local as u8, span);
+      self.emit_byte(upvalue.index, span);
+    }
+  }
+
+  // a module's statements as a script of their own, named after its file in angle brackets so
+  // a stack trace shows which file's top level was running
+  fn module_function(&mut self, ast: &Ast, module: ModuleId) -> Rc<Function> {
+    if let Some(function) = self.modules.get(&module) {
+      return Rc::clone(function);
+    }
+
+    let name = format!("<{}>", ast.module(module).path);
+    let enclosing = mem::replace(&mut self.functions, vec![FunctionState::new(&name, 0, FunctionKind::Script)]);
+
+    let body = &ast.module(module).body;
+    body.iter().for_each(|statement| self.compile_stmt(ast, *statement));
+
+    let end = body.last().map_or(0, |statement| ast[*statement].span.end);
+    self.emit_return(Span::new(end, end));
+
+    let script = mem::replace(&mut self.functions, enclosing).pop().expect("the module is compiled last");
+    let function = Rc::new(script.function);
+    self.modules.insert(module, Rc::clone(&function));
+
+    function
+  }
+}
+
+impl<'a> ExprVisitor<()> for Compiler<'a> {
+  fn visit_literal(&mut self, ast: &Ast, id: ExprId, literal: &Literal) {
+    let span = ast[id].span;
+
+    match literal {
+      Literal::Number(value) => self.emit_constant(Value::Number(*value), span),
+      Literal::String(value) => self.emit_constant(Value::String(Rc::from(value.as_str())), span),
+      Literal::Bool(true) => self.emit_op(OpCode::True, span),
+      Literal::Bool(false) => self.emit_op(OpCode::False, span),
+      Literal::Nil => self.emit_op(OpCode::Nil, span),
+    }
+  }
+
+  // the parts are joined as they pile up, an instruction only has room to join 255 at once
+  fn visit_interpolation(&mut self, ast: &Ast, id: ExprId, parts: &[ExprId]) {
+    let mut on_stack = 0;
+
+    for part in parts {
+      if on_stack == u8::MAX {
+        self.emit_with_operand(OpCode::Interpolate, on_stack, ast[id].span);
+        on_stack = 1;
+      }
+
+      self.compile_expr(ast, *part);
+      on_stack += 1;
+    }
+
+    self.emit_with_operand(OpCode::Interpolate, on_stack, ast[id].span);
+  }
+
+  fn visit_grouping(&mut self, ast: &Ast, _: ExprId, inner: ExprId) {
+    self.compile_expr(ast, inner);
+  }
+
+  fn visit_unary(&mut self, ast: &Ast, id: ExprId, operator: UnaryOperator, operand: ExprId) {
+    self.compile_expr(ast, operand);
+
+    let op = match operator {
+      UnaryOperator::Negate => OpCode::Negate,
+      UnaryOperator::Not => OpCode::Not,
+      UnaryOperator::BitNot => OpCode::BitNot,
+    };
+    self.emit_op(op, ast[id].span);
+  }
+
+  fn visit_binary(&mut self, ast: &Ast, id: ExprId, left: ExprId, operator: BinaryOperator, right: ExprId) {
+    self.compile_expr(ast, left);
+    self.compile_expr(ast, right);
+    self.emit_binary(operator, ast[id].span);
+  }
+
+  // the left operand stays on the stack as the result when it decides it
+  fn visit_logical(&mut self, ast: &Ast, id: ExprId, left: ExprId, operator: LogicalOperator, right: ExprId) {
+    let span = ast[id].span;
+    self.compile_expr(ast, left);
+
+    let end = match operator {
+      LogicalOperator::And => self.emit_jump(OpCode::JumpIfFalse, span),
+      LogicalOperator::Coalesce => self.emit_jump(OpCode::JumpIfNotNil, span),
+      LogicalOperator::Or => {
+        let right_side = self.emit_jump(OpCode::JumpIfFalse, span);
+        let end = self.emit_jump(OpCode::Jump, span);
+        self.patch_jump(right_side, span);
+
+        end
+      }
+    };
+
+    self.emit_op(OpCode::Pop, span);
+    self.compile_expr(ast, right);
+    self.patch_jump(end, span);
+  }
+
+  fn visit_variable(&mut self, ast: &Ast, id: ExprId, name: &str) {
+    self.load_variable(name, ast[id].span);
+  }
+
+  fn visit_assign(&mut self, ast: &Ast, id: ExprId, name: &str, value: ExprId) {
+    let span = ast[id].span;
+    self.compile_expr(ast, value);
+
+    match self.resolve(name, span) {
+      Variable::Local(slot) => self.emit_with_operand(OpCode::SetLocal, slot, span),
+      Variable::Upvalue(index) => self.emit_with_operand(OpCode::SetUpvalue, index, span),
+      Variable::Global => {
+        let name = self.name_constant(name, span);
+        self.emit_with_constant(OpCode::SetGlobal, name, span);
+      }
+    }
+  }
+
+  // the callee sits below its arguments, where the call's slot 0 will be. Calling a method
+  // straight away is done in one instruction, without making a bound method for it first
+  fn visit_call(&mut self, ast: &Ast, id: ExprId, callee: ExprId, arguments: &[ExprId]) {
+    let span = ast[id].span;
+    // the parser already rejects calls with more arguments than fit in the operand
+    let argument_count = arguments.len() as u8;
+
+    match &ast[callee].kind {
+      ExprKind::Get { object, name } => {
+        self.compile_expr(ast, *object);
+        arguments.iter().for_each(|argument| self.compile_expr(ast, *argument));
+
+        let name = self.name_constant(name, span);
+        self.emit_with_constant(OpCode::Invoke, name, span);
+        self.emit_byte(argument_count, span);
+      }
+      ExprKind::Super { method } => {
+        self.load_variable("this", span);
+        arguments.iter().for_each(|argument| self.compile_expr(ast, *argument));
+        self.load_variable("super", span);
+
+        let method = self.name_constant(method, span);
+        self.emit_with_constant(OpCode::SuperInvoke, method, span);
+        self.emit_byte(argument_count, span);
+      }
+      _ => {
+        self.compile_expr(ast, callee);
+        arguments.iter().for_each(|argument| self.compile_expr(ast, *argument));
+        self.emit_with_operand(OpCode::Call, argument_count, span);
+      }
+    }
+  }
+
+  fn visit_get(&mut self, ast: &Ast, id: ExprId, object: ExprId, name: &str) {
+    let span = ast[id].span;
+    self.compile_expr(ast, object);
+
+    let name = self.name_constant(name, span);
+    self.emit_with_constant(OpCode::GetProperty, name, span);
+  }
+
+  // a compound assignment copies the object to read the property from, so it's only evaluated once
+  fn visit_set(
+    &mut self,
+    ast: &Ast,
+    id: ExprId,
+    object: ExprId,
+    name: &str,
+    operator: Option<BinaryOperator>,
+    value: ExprId,
+  ) {
+    let span = ast[id].span;
+    self.compile_expr(ast, object);
+
+    if let Some(operator) = operator {
+      self.emit_with_operand(OpCode::Dup, 1, span);
+      let name = self.name_constant(name, span);
+      self.emit_with_constant(OpCode::GetProperty, name, span);
+      self.compile_expr(ast, value);
+      self.emit_binary(operator, span);
+    } else {
+      self.compile_expr(ast, value);
+    }
+
+    let name = self.name_constant(name, span);
+    self.emit_with_constant(OpCode::SetProperty, name, span);
+  }
+
+  // a local of every method, in slot 0
+  fn visit_this(&mut self, ast: &Ast, id: ExprId) {
+    self.load_variable("this", ast[id].span);
+  }
+
+  // the superclass is a local of a scope around the methods, which they capture
+  fn visit_super(&mut self, ast: &Ast, id: ExprId, method: &str) {
+    let span = ast[id].span;
+    self.load_variable("this", span);
+    self.load_variable("super", span);
+
+    let method = self.name_constant(method, span);
+    self.emit_with_constant(OpCode::GetSuper, method, span);
+  }
+
+  fn visit_list(&mut self, ast: &Ast, id: ExprId, elements: &[ExprId]) {
+    let span = ast[id].span;
+    elements.iter().for_each(|element| self.compile_expr(ast, *element));
+
+    let count = u16::try_from(elements.len()).unwrap_or_else(|_| {
+      self.errors.push(CompileError::TooManyElements { span });
+      0
+    });
+
+    let [high, low] = count.to_be_bytes();
+    self.emit_op(OpCode::BuildList, span);
+    self.emit_byte(high, span);
+    self.emit_byte(low, span);
+  }
+
+  fn visit_map(&mut self, ast: &Ast, id: ExprId, entries: &[(ExprId, ExprId)]) {
+    let span = ast[id].span;
+
+    for (key, value) in entries {
+      self.compile_expr(ast, *key);
+      self.compile_expr(ast, *value);
+    }
+
+    let count = u16::try_from(entries.len()).unwrap_or_else(|_| {
+      self.errors.push(CompileError::TooManyEntries { span });
+      0
+    });
+
+    let [high, low] = count.to_be_bytes();
+    self.emit_op(OpCode::BuildMap, span);
+    self.emit_byte(high, span);
+    self.emit_byte(low, span);
+  }
+
+  fn visit_index(&mut self, ast: &Ast, id: ExprId, object: ExprId, index: ExprId) {
+    self.compile_expr(ast, object);
+    self.compile_expr(ast, index);
+    self.emit_op(OpCode::GetIndex, ast[id].span);
+  }
+
+  // a compound assignment copies the object and the index to read the element with
+  fn visit_set_index(
+    &mut self,
+    ast: &Ast,
+    id: ExprId,
+    object: ExprId,
+    index: ExprId,
+    operator: Option<BinaryOperator>,
+    value: ExprId,
+  ) {
+    let span = ast[id].span;
+    self.compile_expr(ast, object);
+    self.compile_expr(ast, index);
+
+    if let Some(operator) = operator {
+      self.emit_with_operand(OpCode::Dup, 2, span);
+      self.emit_op(OpCode::GetIndex, span);
+      self.compile_expr(ast, value);
+      self.emit_binary(operator, span);
+    } else {
+      self.compile_expr(ast, value);
+    }
+
+    self.emit_op(OpCode::SetIndex, span);
+  }
+
+  fn visit_slice(&mut self, ast: &Ast, id: ExprId, object: ExprId, start: Option<ExprId>, end: Option<ExprId>) {
+    let span = ast[id].span;
+    self.compile_expr(ast, object);
+
+    for bound in [start, end] {
+      match bound {
+        Some(bound) => self.compile_expr(ast, bound),
+        None => self.emit_op(OpCode::Nil, span),
+      }
+    }
+
+    self.emit_op(OpCode::Slice, span);
+  }
+
+  // the closure is left on the stack like any other value
+  fn visit_lambda(&mut self, ast: &Ast, _: ExprId, declaration: StmtId) {
+    let (name, params, body) = match &ast[declaration].kind {
+      StmtKind::Function { name, params, body } => (name, params, body),
+      kind => unreachable!("lambdas wrap function declarations, not {:?}", kind),
+    };
+
+    self.function(ast, declaration, name, params, body, FunctionKind::Function);
+  }
+}
+
+impl<'a> StmtVisitor<()> for Compiler<'a> {
+  fn visit_expression_stmt(&mut self, ast: &Ast, id: StmtId, expr: ExprId) {
+    self.compile_expr(ast, expr);
+    self.emit_op(OpCode::Pop, ast[id].span);
+  }
+
+  fn visit_print(&mut self, ast: &Ast, id: StmtId, expr: ExprId) {
+    self.compile_expr(ast, expr);
+    self.emit_op(OpCode::Print, ast[id].span);
+  }
+
+  // a local is only added once its initializer is compiled, so the initializer can't see it
+  fn visit_var(&mut self, ast: &Ast, id: StmtId, name: &str, initializer: Option<ExprId>) {
+    let span = ast[id].span;
+
+    match initializer {
+      Some(initializer) => self.compile_expr(ast, initializer),
+      None => self.emit_op(OpCode::Nil, span),
+    }
+
+    if self.is_global_scope() {
+      let name = self.name_constant(name, span);
+      self.emit_with_constant(OpCode::DefineGlobal, name, span);
+    } else {
+      self.add_local(name, span);
+    }
+  }
+
+  fn visit_block(&mut self, ast: &Ast, id: StmtId, statements: &[StmtId]) {
+    self.begin_scope();
+    statements.iter().for_each(|statement| self.compile_stmt(ast, *statement));
+    self.end_scope(ast[id].span);
+  }
+
+  // the condition is popped on whichever branch is taken
+  fn visit_if(
+    &mut self,
+    ast: &Ast,
+    id: StmtId,
+    condition: ExprId,
+    then_branch: StmtId,
+    else_branch: Option<StmtId>,
+  ) {
+    let span = ast[id].span;
+    self.compile_expr(ast, condition);
+
+    let else_jump = self.emit_jump(OpCode::JumpIfFalse, span);
+    self.emit_op(OpCode::Pop, span);
+    self.compile_stmt(ast, then_branch);
+
+    let end_jump = self.emit_jump(OpCode::Jump, span);
+    self.patch_jump(else_jump, span);
+    self.emit_op(OpCode::Pop, span);
+
+    if let Some(else_branch) = else_branch {
+      self.compile_stmt(ast, else_branch);
+    }
+
+    self.patch_jump(end_jump, span);
+  }
+
+  // the condition was popped before a break, so it jumps past the pop at the exit
+  //
+  //   loop:     condition, jump-if-false exit, pop
+  //             body
+  //   continue: increment, pop, loop
+  //   exit:     pop
+  //   break:
+  fn visit_while(&mut self, ast: &Ast, id: StmtId, condition: ExprId, body: StmtId, increment: Option<ExprId>) {
+    let span = ast[id].span;
+    let loop_start = self.chunk().code().len();
+    self.compile_expr(ast, condition);
+
+    let exit_jump = self.emit_jump(OpCode::JumpIfFalse, span);
+    self.emit_op(OpCode::Pop, span);
+    self.begin_loop();
+    self.compile_stmt(ast, body);
+    self.patch_continues(span);
+
+    if let Some(increment) = increment {
+      self.compile_expr(ast, increment);
+      self.emit_op(OpCode::Pop, span);
+    }
+
+    self.emit_loop(loop_start, span);
+
+    self.patch_jump(exit_jump, span);
+    self.emit_op(OpCode::Pop, span);
+    self.end_loop(span);
+  }
+
+  // the list and the index of the next element are kept in two locals no name can refer to, in a
+  // scope around the loop. The variable is a local of a scope inside it, so every element gets a
+  // new one that closures in the body can capture on their own
+  //
+  //   list, 0
+  //   loop:     get list, get index, has-element, jump-if-false exit, pop
+  //             get list, get index, get-index    the variable
+  //             body, end of the variable's scope
+  //   continue: get index, 1, add, set index, pop, loop
+  //   exit:     pop
+  //   break:    end of the loop's scope
+  fn visit_for_in(&mut self, ast: &Ast, id: StmtId, name: &str, iterable: ExprId, body: StmtId) {
+    let span = ast[id].span;
+    self.begin_scope();
+
+    self.compile_expr(ast, iterable);
+    self.add_local("", span);
+    let list = (self.current().locals.len() - 1) as u8;
+
+    self.emit_constant(Value::Number(0.0), span);
+    self.add_local("", span);
+    let index = (self.current().locals.len() - 1) as u8;
+
+    let loop_start = self.chunk().code().len();
+    self.emit_with_operand(OpCode::GetLocal, list, span);
+    self.emit_with_operand(OpCode::GetLocal, index, span);
+    self.emit_op(OpCode::HasElement, span);
+    let exit_jump = self.emit_jump(OpCode::JumpIfFalse, span);
+    self.emit_op(OpCode::Pop, span);
+
+    self.begin_loop();
+    self.begin_scope();
+    self.emit_with_operand(OpCode::GetLocal, list, span);
+    self.emit_with_operand(OpCode::GetLocal, index, span);
+    self.emit_op(OpCode::GetIndex, span);
+    self.add_local(name, span);
+    self.compile_stmt(ast, body);
+    self.end_scope(span);
+    self.patch_continues(span);
+
+    self.emit_with_operand(OpCode::GetLocal, index, span);
+    self.emit_constant(Value::Number(1.0), span);
+    self.emit_op(OpCode::Add, span);
+    self.emit_with_operand(OpCode::SetLocal, index, span);
+    self.emit_op(OpCode::Pop, span);
+    self.emit_loop(loop_start, span);
+
+    self.patch_jump(exit_jump, span);
+    self.emit_op(OpCode::Pop, span);
+    self.end_loop(span);
+    self.end_scope(span);
+  }
+
+  // the comparisons come first, a Case for every value jumping to the statements of its case,
+  // and the default's statements right after them. The switch's value is off the stack by the
+  // time any of them run, so a break in one has nothing of the switch's to pop
+  fn visit_switch(&mut self, ast: &Ast, id: StmtId, value: ExprId, cases: &[Case], default: Option<StmtId>) {
+    let span = ast[id].span;
+    self.compile_expr(ast, value);
+
+    let mut case_jumps = vec![];
+
+    for case in cases {
+      let jumps = case
+        .values
+        .iter()
+        .map(|value| {
+          self.compile_expr(ast, *value);
+          self.emit_jump(OpCode::Case, ast[*value].span)
+        })
+        .collect::<Vec<_>>();
+      case_jumps.push(jumps);
+    }
+
+    self.emit_op(OpCode::Pop, span);
+
+    if let Some(default) = default {
+      self.compile_stmt(ast, default);
+    }
+
+    // each case starts with the jump that ends the one before it
+    let mut end_jumps = vec![];
+
+    for (case, jumps) in cases.iter().zip(case_jumps) {
+      end_jumps.push(self.emit_jump(OpCode::Jump, span));
+      jumps.into_iter().for_each(|jump| self.patch_jump(jump, span));
+      self.compile_stmt(ast, case.body);
+    }
+
+    end_jumps.into_iter().for_each(|jump| self.patch_jump(jump, span));
+  }
+
+  fn visit_break(&mut self, ast: &Ast, id: StmtId) {
+    let span = ast[id].span;
+    self.emit_loop_exit(span);
+    let jump = self.emit_jump(OpCode::Jump, span);
+    self.current().loops.last_mut().expect("inside a loop").breaks.push(jump);
+  }
+
+  fn visit_continue(&mut self, ast: &Ast, id: StmtId) {
+    let span = ast[id].span;
+    self.emit_loop_exit(span);
+    let jump = self.emit_jump(OpCode::Jump, span);
+    self.current().loops.last_mut().expect("inside a loop").continues.push(jump);
+  }
+
+  fn visit_throw(&mut self, ast: &Ast, id: StmtId, value: ExprId) {
+    self.compile_expr(ast, value);
+    self.emit_op(OpCode::Throw, ast[id].span);
+  }
+
+  // the vm pushes the exception where the stack was when the try started, which makes it the
+  // catch variable's slot
+  //
+  //   try catch
+  //          body, end-try, jump end
+  //   catch: handler, end of the variable's scope
+  //   end:
+  fn visit_try(&mut self, ast: &Ast, id: StmtId, body: StmtId, name: &str, handler: StmtId) {
+    let span = ast[id].span;
+    let catch_jump = self.emit_jump(OpCode::Try, span);
+    self.current().tries += 1;
+    self.compile_stmt(ast, body);
+    self.current().tries -= 1;
+    self.emit_op(OpCode::EndTry, span);
+    let end_jump = self.emit_jump(OpCode::Jump, span);
+
+    self.patch_jump(catch_jump, span);
+    self.begin_scope();
+    self.add_local(name, span);
+    self.compile_stmt(ast, handler);
+    self.end_scope(span);
+    self.patch_jump(end_jump, span);
+  }
+
+  // the module is left on the stack while the names are copied out of its globals. Imports the
+  // loader never linked were reported by the resolver, there's nothing to compile for them
+  fn visit_import(&mut self, ast: &Ast, id: StmtId, _: &str, names: Option<&[String]>) {
+    let span = ast[id].span;
+    let module = match ast.imported(id) {
+      Some(module) => module,
+      None => return,
+    };
+
+    let function = self.module_function(ast, module);
+    let index = self.make_constant(Value::Function(function), span);
+    self.emit_with_constant(OpCode::Import, index, span);
+    self.emit_op(OpCode::Pop, span);
+
+    for name in names.map_or_else(|| ast.exports(module), <[String]>::to_vec) {
+      let name = self.name_constant(&name, span);
+      self.emit_with_constant(OpCode::ImportName, name, span);
+    }
+
+    self.emit_op(OpCode::Pop, span);
+  }
+
+  // a local function gets its slot before its body is compiled, as the resolver defines it
+  fn visit_function(&mut self, ast: &Ast, id: StmtId, name: &str, params: &[String], body: &[StmtId]) {
+    let span = ast[id].span;
+
+    if self.is_global_scope() {
+      self.function(ast, id, name, params, body, FunctionKind::Function);
+      let name = self.name_constant(name, span);
+      self.emit_with_constant(OpCode::DefineGlobal, name, span);
+    } else {
+      self.add_local(name, span);
+      self.function(ast, id, name, params, body, FunctionKind::Function);
+    }
+  }
+
+  fn visit_return(&mut self, ast: &Ast, id: StmtId, value: Option<ExprId>) {
+    let span = ast[id].span;
+
+    match value {
+      Some(value) => {
+        self.compile_expr(ast, value);
+        self.emit_op(OpCode::Return, span);
+      }
+      None => self.emit_return(span),
+    }
+  }
+
+  // the class is defined before its methods are added to it, so they can refer to it. A
+  // subclass starts with its superclass's methods copied into it, and its methods close over
+  // the superclass as `super`
+  fn visit_class(
+    &mut self,
+    ast: &Ast,
+    id: StmtId,
+    name: &str,
+    superclass: Option<ExprId>,
+    methods: &[StmtId],
+  ) {
+    let span = ast[id].span;
+    let name_constant = self.name_constant(name, span);
+    self.emit_with_constant(OpCode::Class, name_constant, span);
+
+    if self.is_global_scope() {
+      self.emit_with_constant(OpCode::DefineGlobal, name_constant, span);
+    } else {
+      self.add_local(name, span);
+    }
+
+    if let Some(superclass) = superclass {
+      self.compile_expr(ast, superclass);
+      self.begin_scope();
+      self.add_local("super", span);
+      self.load_variable(name, span);
+      self.emit_op(OpCode::Inherit, span);
+    }
+
+    self.load_variable(name, span);
+
+    for method in methods {
+      let (method_name, params, body) = match &ast[*method].kind {
+        StmtKind::Function { name, params, body } => (name, params, body),
+        kind => unreachable!("methods are function declarations, not {:?}", kind),
+      };
+      let kind = if method_name == "init" {
+        FunctionKind::Initializer
+      } else {
+        FunctionKind::Method
+      };
+
+      self.function(ast, *method, method_name, params, body, kind);
+      let method_name = self.name_constant(method_name, ast[*method].span);
+      self.emit_with_constant(OpCode::Method, method_name, ast[*method].span);
+    }
+
+    self.emit_op(OpCode::Pop, span);
+
+    if superclass.is_some() {
+      self.end_scope(span);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::lexer::Lexer;
+  use crate::parser::Parser;
+  use OpCode::*;
+
+  fn compile(code: &str) -> Result<Chunk, Vec<String>> {
+    let ast = Parser::new(Lexer::new(code).lex().unwrap()).parse().unwrap();
+    let source_map = SourceMap::new(code);
+
+    Compiler::new(&source_map)
+      .compile(&ast)
+      .map(|script| script.chunk)
+      .map_err(|errors| errors.iter().map(|error| error.to_string()).collect())
+  }
+
+  fn ops(code: &str) -> Vec<u8> {
+    compile(code).unwrap().code().to_vec()
+  }
+
+  #[test]
+  fn expressions_leave_their_value_on_the_stack() {
+    assert_eq!(
+      ops("print !(1 < 2);"),
+      vec![Constant as u8, 0, Constant as u8, 1, Less as u8, Not as u8, Print as u8, Nil as u8, Return as u8]
+    );
+  }
+
+  #[test]
+  fn locals_live_in_stack_slots() {
+    let chunk = compile("{ var a = 1; var b = a; b = 2; }").unwrap();
+
+    assert_eq!(
+      chunk.code(),
+      &[
+        Constant as u8,
+        0,
+        GetLocal as u8,
+        1,
+        Constant as u8,
+        1,
+        SetLocal as u8,
+        2,
+        Pop as u8,
+        Pop as u8,
+        Pop as u8,
+        Nil as u8,
+        Return as u8,
+      ]
+    );
+  }
+
+  #[test]
+  fn globals_are_looked_up_by_name() {
+    let chunk = compile("var a = 1;\nprint a;").unwrap();
+
+    assert_eq!(
+      chunk.code(),
+      &[Constant as u8, 0, DefineGlobal as u8, 1, GetGlobal as u8, 1, Print as u8, Nil as u8, Return as u8]
+    );
+    assert_eq!(chunk.constants()[1], Value::String(Rc::from("a")));
+    assert_eq!((chunk.get_line(0), chunk.get_line(4)), (1, 2));
+  }
+
+  #[test]
+  fn jumps_land_after_the_code_they_skip() {
+    let code = ops("while (true) print 1;");
+
+    // True, JumpIfFalse +7, Pop, Constant 0, Print, Loop -11, Pop
+    assert_eq!(code[1..4], [JumpIfFalse as u8, 0, 7]);
+    assert_eq!(code[8..11], [Loop as u8, 0, 11]);
+    assert_eq!(code[11], Pop as u8);
+  }
+
+  #[test]
+  fn switches_compare_before_running_a_case() {
+    let code = ops("switch (1) { case 2: print 3; }");
+
+    // Constant 0, Constant 1, Case +4 to the print, Pop, Jump +3 over it, Constant 2, Print
+    assert_eq!(code[4..11], [Case as u8, 0, 4, Pop as u8, Jump as u8, 0, 3]);
+    assert_eq!(code[11..14], [Constant as u8, 2, Print as u8]);
+  }
+
+  #[test]
+  fn constants_past_the_first_256_use_long_instructions() {
+    let many_constants = (0..300).map(|n| format!("{};", n)).collect::<String>() + "0;";
+    let chunk = compile(&many_constants).unwrap();
+
+    assert_eq!(chunk.constants().len(), 300);
+    assert_eq!(chunk.code()[255 * 3..256 * 3], [Constant as u8, 255, Pop as u8]);
+    assert_eq!(chunk.code()[256 * 3..256 * 3 + 5], [ConstantLong as u8, 0, 1, 0, Pop as u8]);
+    // the 0 at the end is the one already stored
+    assert_eq!(chunk.code()[256 * 3 + 44 * 5..256 * 3 + 44 * 5 + 3], [Constant as u8, 0, Pop as u8]);
+  }
+
+  #[test]
+  fn closures_capture_the_variables_they_use() {
+    let script = compile("fun f() { var a; var b; fun g() { fun h() { b; a; } a; } }").unwrap();
+    let f = match &script.constants()[0] {
+      Value::Function(f) => f,
+      value => panic!("expected f, found {}", value),
+    };
+    let g = match &f.chunk.constants()[0] {
+      Value::Function(g) => g,
+      value => panic!("expected g, found {}", value),
+    };
+
+    assert_eq!(script.code()[..2], [Closure as u8, 0]);
+    // g captures b and a from slots 2 and 1 of f, b only so h can capture it from g in turn
+    assert_eq!(g.upvalue_count, 2);
+    assert_eq!(f.chunk.code()[2..8], [Closure as u8, 0, 1, 2, 1, 1]);
+    assert_eq!(g.chunk.code()[..6], [Closure as u8, 0, 0, 0, 0, 1]);
+    assert_eq!(g.chunk.code()[6..8], [GetUpvalue as u8, 1]);
+  }
+
+  #[test]
+  fn captured_locals_are_closed_when_their_block_ends() {
+    let code = ops("{ var a; var b; fun f() { a; } }");
+
+    assert_eq!(code[code.len() - 5..], [Pop as u8, Pop as u8, CloseUpvalue as u8, Nil as u8, Return as u8]);
+  }
+
+  #[test]
+  fn methods_are_added_to_the_class_after_it_is_defined() {
+    let chunk = compile("class A { f() {} }").unwrap();
+
+    assert_eq!(
+      chunk.code()[..9],
+      [Class as u8, 0, DefineGlobal as u8, 0, GetGlobal as u8, 0, Closure as u8, 1, Method as u8]
+    );
+    assert_eq!(chunk.code()[10], Pop as u8);
+  }
+
+  #[test]
+  fn initializers_return_their_instance() {
+    let chunk = compile("class A { init() { return; } }").unwrap();
+    let init = match &chunk.constants()[1] {
+      Value::Function(init) => init,
+      value => panic!("expected init, found {}", value),
+    };
+
+    assert_eq!(init.chunk.code(), &[GetLocal as u8, 0, Return as u8, GetLocal as u8, 0, Return as u8]);
+  }
+
+  #[test]
+  fn calling_a_method_straight_away_invokes_it() {
+    assert_eq!(ops("a.f(1);")[2..9], [Constant as u8, 1, Invoke as u8, 2, 1, Pop as u8, Nil as u8]);
+    assert!(ops("(a.f)(1);").contains(&(GetProperty as u8)));
+  }
+}
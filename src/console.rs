@@ -0,0 +1,16 @@
+// reading what the user types, for the console natives of every backend
+use std::io::{self, BufRead};
+
+// the next line of stdin without its line ending, None once there are no more
+pub fn read_line() -> Option<String> {
+  let mut line = String::new();
+
+  match io::stdin().lock().read_line(&mut line) {
+    Ok(0) | Err(_) => None,
+    Ok(_) => {
+      let len = line.trim_end_matches(['\n', '\r']).len();
+      line.truncate(len);
+      Some(line)
+    }
+  }
+}
@@ -0,0 +1,253 @@
+use std::fmt::Write;
+
+use crate::chunk::{Chunk, Function, OpCode, Value};
+
+// the name clox gives the instruction, OP_JUMP_IF_FALSE for JumpIfFalse
+fn op_name(op: OpCode) -> String {
+  let mut name = String::from("OP");
+
+  for character in format!("{:?}", op).chars() {
+    if character.is_uppercase() {
+      name.push('_');
+    }
+    name.push(character.to_ascii_uppercase());
+  }
+
+  name
+}
+
+// the whole chunk, one instruction per line under a header with its name
+//
+//   == script ==
+//   0000    1 OP_CONSTANT         0 '1.2'
+//   0002    | OP_PRINT
+pub fn disassemble_chunk(chunk: &Chunk, name: &str) -> String {
+  let mut out = format!("== {} ==\n", name);
+  let mut offset = 0;
+
+  while offset < chunk.code().len() {
+    let (instruction, next) = disassemble_instruction(chunk, offset);
+    out.push_str(&instruction);
+    out.push('\n');
+    offset = next;
+  }
+
+  out
+}
+
+// the function's chunk followed by those of the functions declared in it, each under its own
+// header
+pub fn disassemble_function(function: &Function) -> String {
+  let name = if function.name.is_empty() { "script" } else { &function.name };
+  let mut out = disassemble_chunk(&function.chunk, name);
+
+  for constant in function.chunk.constants() {
+    if let Value::Function(function) = constant {
+      out.push('\n');
+      out.push_str(&disassemble_function(function));
+    }
+  }
+
+  out
+}
+
+// the instruction at offset and the offset of the one after it. The line is left out, shown
+// as |, when it's the same as the previous instruction's
+pub fn disassemble_instruction(chunk: &Chunk, offset: usize) -> (String, usize) {
+  let mut out = format!("{:04} ", offset);
+
+  if offset > 0 && chunk.get_line(offset) == chunk.get_line(offset - 1) {
+    out.push_str("   | ");
+  } else {
+    let _ = write!(out, "{:4} ", chunk.get_line(offset));
+  }
+
+  let op = match OpCode::try_from(chunk.code()[offset]) {
+    Ok(op) => op,
+    Err(byte) => {
+      let _ = write!(out, "Unknown opcode {}", byte);
+      return (out, offset + 1);
+    }
+  };
+  let name = op_name(op);
+  let operand = |index: usize| chunk.code().get(offset + index).copied().unwrap_or_default();
+
+  let next = match op {
+    OpCode::Constant
+    | OpCode::GetGlobal
+    | OpCode::DefineGlobal
+    | OpCode::SetGlobal
+    | OpCode::GetProperty
+    | OpCode::SetProperty
+    | OpCode::GetSuper
+    | OpCode::Class
+    | OpCode::Method
+    | OpCode::Import
+    | OpCode::ImportName => {
+      let index = operand(1) as usize;
+      let constant = chunk.constants().get(index).map(|value| value.to_string()).unwrap_or_default();
+      let _ = write!(out, "{:<16} {:4} '{}'", name, index, constant);
+      offset + 2
+    }
+    OpCode::ConstantLong
+    | OpCode::GetGlobalLong
+    | OpCode::DefineGlobalLong
+    | OpCode::SetGlobalLong
+    | OpCode::GetPropertyLong
+    | OpCode::SetPropertyLong
+    | OpCode::GetSuperLong
+    | OpCode::ClassLong
+    | OpCode::MethodLong
+    | OpCode::ImportLong
+    | OpCode::ImportNameLong => {
+      let index = u32::from_be_bytes([0, operand(1), operand(2), operand(3)]) as usize;
+      let constant = chunk.constants().get(index).map(|value| value.to_string()).unwrap_or_default();
+      let _ = write!(out, "{:<16} {:4} '{}'", name, index, constant);
+      offset + 4
+    }
+    // the method's name, then the number of arguments
+    OpCode::Invoke | OpCode::InvokeLong | OpCode::SuperInvoke | OpCode::SuperInvokeLong => {
+      let (index, next) = match op {
+        OpCode::Invoke | OpCode::SuperInvoke => (operand(1) as usize, offset + 2),
+        _ => (u32::from_be_bytes([0, operand(1), operand(2), operand(3)]) as usize, offset + 4),
+      };
+      let constant = chunk.constants().get(index).map(|value| value.to_string()).unwrap_or_default();
+      let arguments = chunk.code().get(next).copied().unwrap_or_default();
+      let _ = write!(out, "{:<16} ({} args) {:4} '{}'", name, arguments, index, constant);
+      next + 1
+    }
+    // the function, then a line for each variable it captures
+    OpCode::Closure | OpCode::ClosureLong => {
+      let (index, mut next) = match op {
+        OpCode::Closure => (operand(1) as usize, offset + 2),
+        _ => (u32::from_be_bytes([0, operand(1), operand(2), operand(3)]) as usize, offset + 4),
+      };
+      let function = chunk.constants().get(index);
+      let _ = write!(out, "{:<16} {:4} '{}'", name, index, function.map(|value| value.to_string()).unwrap_or_default());
+
+      if let Some(Value::Function(function)) = function {
+        for _ in 0..function.upvalue_count {
+          let byte = |offset: usize| chunk.code().get(offset).copied().unwrap_or_default();
+          let kind = if byte(next) == 1 { "local" } else { "upvalue" };
+          let _ = write!(out, "\n{:04}    |                     {} {}", next, kind, byte(next + 1));
+          next += 2;
+        }
+      }
+
+      next
+    }
+    OpCode::GetLocal
+    | OpCode::SetLocal
+    | OpCode::GetUpvalue
+    | OpCode::SetUpvalue
+    | OpCode::Call
+    | OpCode::Dup
+    | OpCode::Interpolate => {
+      let _ = write!(out, "{:<16} {:4}", name, operand(1));
+      offset + 2
+    }
+    OpCode::AddLocals => {
+      let _ = write!(out, "{:<16} {:4} {:4}", name, operand(1), operand(2));
+      offset + 3
+    }
+    OpCode::BuildList | OpCode::BuildMap => {
+      let _ = write!(out, "{:<16} {:4}", name, u16::from_be_bytes([operand(1), operand(2)]));
+      offset + 3
+    }
+    OpCode::Jump | OpCode::JumpIfFalse | OpCode::JumpIfNotNil | OpCode::Case | OpCode::Loop | OpCode::Try => {
+      let distance = u16::from_be_bytes([operand(1), operand(2)]) as usize;
+      let target = match op {
+        OpCode::Loop => (offset + 3).wrapping_sub(distance),
+        _ => offset + 3 + distance,
+      };
+      let _ = write!(out, "{:<16} {:4} -> {}", name, offset, target);
+      offset + 3
+    }
+    _ => {
+      out.push_str(&name);
+      offset + 1
+    }
+  };
+
+  (out, next)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::compiler::Compiler;
+  use crate::lexer::{Lexer, SourceMap};
+  use crate::parser::Parser;
+
+  fn disassemble(code: &str) -> String {
+    let ast = Parser::new(Lexer::new(code).lex().unwrap()).parse().unwrap();
+    let script = Compiler::new(&SourceMap::new(code)).compile(&ast).unwrap();
+
+    disassemble_function(&script)
+  }
+
+  #[test]
+  fn instructions_show_their_operands() {
+    assert_eq!(
+      disassemble("var a = 1.2;\nprint a;"),
+      "\
+== script ==
+0000    1 OP_CONSTANT         0 '1.2'
+0002    | OP_DEFINE_GLOBAL    1 'a'
+0004    2 OP_GET_GLOBAL       1 'a'
+0006    | OP_PRINT
+0007    | OP_NIL
+0008    | OP_RETURN
+"
+    );
+  }
+
+  #[test]
+  fn jumps_show_where_they_land() {
+    let listing = disassemble("while (false) {}");
+
+    assert!(listing.contains("0001    | OP_JUMP_IF_FALSE    1 -> 8"));
+    assert!(listing.contains("0005    | OP_LOOP             5 -> 0"));
+  }
+
+  #[test]
+  fn functions_follow_the_code_declaring_them() {
+    let listing = disassemble("fun f(a) { return a; }");
+
+    assert!(listing.contains("0000    1 OP_CLOSURE          0 '<fn f>'"));
+    assert!(listing.ends_with("== f ==\n0000    1 OP_GET_LOCAL        1\n0002    | OP_RETURN\n0003    | OP_NIL\n0004    | OP_RETURN\n"));
+  }
+
+  #[test]
+  fn long_constants_show_their_whole_index() {
+    let code = (0..257).map(|n| format!("{};", n)).collect::<String>();
+
+    assert!(disassemble(&code).contains("0768    | OP_CONSTANT_LONG  256 '256'"));
+  }
+
+  #[test]
+  fn closures_list_what_they_capture() {
+    let listing = disassemble("{ var a; var b; fun f() { b; a; } }");
+
+    assert!(listing.contains(
+      "0002    | OP_CLOSURE          0 '<fn f>'\n0004    |                     local 2\n0006    |                     local 1\n"
+    ));
+    assert!(listing.contains("0000    1 OP_GET_UPVALUE      0"));
+  }
+
+  #[test]
+  fn invokes_show_their_argument_count() {
+    let listing = disassemble("a.f(1, 2);");
+
+    assert!(listing.contains("0006    | OP_INVOKE        (2 args)    3 'f'"));
+    assert!(listing.contains("0009    | OP_POP"));
+  }
+
+  #[test]
+  fn bytes_that_are_not_instructions() {
+    let mut chunk = Chunk::new();
+    chunk.write(u8::MAX, 1);
+
+    assert_eq!(disassemble_instruction(&chunk, 0), (String::from("0000    1 Unknown opcode 255"), 1));
+  }
+}
@@ -0,0 +1,206 @@
+use std::fmt;
+
+use crate::compiler::CompileError;
+use crate::interpreter::RuntimeError;
+use crate::lexer::{LexError, SourceMap, Span};
+use crate::modules::ModuleError;
+use crate::parser::ParseError;
+use crate::resolver::ResolveError;
+
+const RED: &str = "\x1b[1;31m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+// an error message pinned to the code it is about, every stage turns its own errors into these
+// so they are all reported the same way
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+  pub message: String,
+  pub span: Span,
+}
+
+impl Diagnostic {
+  pub fn error(message: impl fmt::Display, span: Span) -> Self {
+    Self {
+      message: message.to_string(),
+      span,
+    }
+  }
+}
+
+impl From<&LexError> for Diagnostic {
+  fn from(error: &LexError) -> Self {
+    Diagnostic::error(error, error.span())
+  }
+}
+
+impl From<&ParseError> for Diagnostic {
+  fn from(error: &ParseError) -> Self {
+    Diagnostic::error(error, error.span())
+  }
+}
+
+impl From<&ModuleError> for Diagnostic {
+  fn from(error: &ModuleError) -> Self {
+    Diagnostic::error(error, error.span())
+  }
+}
+
+impl From<&ResolveError> for Diagnostic {
+  fn from(error: &ResolveError) -> Self {
+    Diagnostic::error(error, error.span())
+  }
+}
+
+impl From<&CompileError> for Diagnostic {
+  fn from(error: &CompileError) -> Self {
+    Diagnostic::error(error, error.span())
+  }
+}
+
+#[cfg(feature = "register-vm")]
+impl From<&crate::register::CompileError> for Diagnostic {
+  fn from(error: &crate::register::CompileError) -> Self {
+    Diagnostic::error(error, error.span())
+  }
+}
+
+impl From<&RuntimeError> for Diagnostic {
+  fn from(error: &RuntimeError) -> Self {
+    Diagnostic::error(error, error.span())
+  }
+}
+
+// renders diagnostics for one file the way compilers usually do, the message, where it is and
+// the offending line with the span underlined
+//
+//   error: expected ';' after value
+//    --> main.lox:2:8
+//     |
+//   2 | print a
+//     |        ^
+pub struct Emitter<'a> {
+  file_name: &'a str,
+  source_map: &'a SourceMap<'a>,
+  color: bool,
+}
+
+impl<'a> Emitter<'a> {
+  // color should be off whenever the output isn't a terminal, escape codes only garble files and pipes
+  pub fn new(file_name: &'a str, source_map: &'a SourceMap<'a>, color: bool) -> Self {
+    Self {
+      file_name,
+      source_map,
+      color,
+    }
+  }
+
+  fn paint(&self, style: &str, text: &str) -> String {
+    if self.color {
+      format!("{}{}{}", style, text, RESET)
+    } else {
+      String::from(text)
+    }
+  }
+
+  pub fn render(&self, diagnostic: &Diagnostic) -> String {
+    let start = self.source_map.location(diagnostic.span.start);
+    let end = self.source_map.location(diagnostic.span.end);
+    let line = self.expand_tabs(self.source_map.line_text(start.line));
+
+    // a span running onto later lines is underlined up to the end of its first one
+    let end_col = if end.line == start.line {
+      end.col
+    } else {
+      line.chars().count() + 1
+    };
+    let underline = format!(
+      "{}{}",
+      " ".repeat(start.col - 1),
+      "^".repeat(end_col.saturating_sub(start.col).max(1))
+    );
+
+    let line_number = start.line.to_string();
+    let gutter = " ".repeat(line_number.len());
+
+    [
+      format!("{}{}", self.paint(RED, "error"), self.paint(BOLD, &format!(": {}", diagnostic.message))),
+      format!("{}{} {}:{}", gutter, self.paint(BLUE, "-->"), self.file_name, start),
+      format!("{} {}", gutter, self.paint(BLUE, "|")),
+      format!("{} {}", self.paint(BLUE, &format!("{} |", line_number)), line).trim_end().to_string(),
+      format!("{} {} {}", gutter, self.paint(BLUE, "|"), self.paint(RED, &underline)),
+    ]
+    .join("\n")
+  }
+
+  // tabs become the spaces they stand for so the underline lines up with the columns in the source map
+  fn expand_tabs(&self, line: &str) -> String {
+    let tab_width = self.source_map.tab_width();
+    let mut expanded = String::new();
+    let mut col = 0;
+
+    for character in line.chars() {
+      match character {
+        '\t' => {
+          let next = (col / tab_width + 1) * tab_width;
+          expanded.push_str(&" ".repeat(next - col));
+          col = next;
+        }
+        _ => {
+          expanded.push(character);
+          col += 1;
+        }
+      }
+    }
+
+    expanded
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn render(code: &str, span: Span) -> String {
+    let source_map = SourceMap::with_tab_width(code, 4);
+
+    Emitter::new("main.lox", &source_map, false).render(&Diagnostic::error("oops", span))
+  }
+
+  #[test]
+  fn underlines_the_span_on_its_line() {
+    assert_eq!(
+      render("var a = 1;\nprint a +;\n", Span::new(19, 20)),
+      "error: oops\n --> main.lox:2:9\n  |\n2 | print a +;\n  |         ^"
+    );
+  }
+
+  #[test]
+  fn empty_spans_still_get_a_caret() {
+    assert_eq!(render("print 1", Span::new(7, 7)).lines().last(), Some("  |        ^"));
+  }
+
+  #[test]
+  fn spans_over_several_lines_stop_at_the_first_line_end() {
+    assert_eq!(render("x = \"ab\ncd\";", Span::new(4, 11)).lines().last(), Some("  |     ^^^"));
+  }
+
+  #[test]
+  fn tabs_are_expanded_to_match_the_columns() {
+    let rendered = render("\tfoo bar", Span::new(5, 8));
+
+    assert_eq!(rendered.lines().nth(1), Some(" --> main.lox:1:9"));
+    assert_eq!(rendered.lines().nth(3), Some("1 |     foo bar"));
+    assert_eq!(rendered.lines().nth(4), Some("  |         ^^^"));
+  }
+
+  #[test]
+  fn color_is_optional() {
+    let source_map = SourceMap::new("1");
+    let diagnostic = Diagnostic::error("oops", Span::new(0, 1));
+
+    assert!(Emitter::new("a", &source_map, true).render(&diagnostic).contains(RED));
+    assert!(!Emitter::new("a", &source_map, false).render(&diagnostic).contains('\x1b'));
+  }
+}
@@ -0,0 +1,360 @@
+use std::io::{self, IsTerminal, Read, Write};
+use std::process::{Command, Stdio};
+
+// what reading a line came back with
+#[derive(Debug, PartialEq)]
+pub enum Input {
+    Line(String),
+    // Ctrl-C, the line typed so far is thrown away
+    Interrupted,
+    // Ctrl-D on an empty line, or the end of piped input
+    Eof,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Home,
+    End,
+    Up,
+    Down,
+    KillToEnd,
+    KillToStart,
+    Interrupt,
+    EndOfInput,
+    // anything else, ignored rather than inserted
+    Unknown,
+}
+
+fn read_byte(input: &mut impl Read) -> io::Result<Option<u8>> {
+    let mut byte = [0];
+
+    match input.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+// the keys a terminal sends as escape sequences, after the escape itself has been read
+fn read_escape(input: &mut impl Read) -> io::Result<Key> {
+    let key = match read_byte(input)? {
+        Some(b'[') | Some(b'O') => match read_byte(input)? {
+            Some(b'A') => Key::Up,
+            Some(b'B') => Key::Down,
+            Some(b'C') => Key::Right,
+            Some(b'D') => Key::Left,
+            Some(b'H') => Key::Home,
+            Some(b'F') => Key::End,
+            // `ESC [ 3 ~` and friends, the number says which key
+            Some(digit @ b'0'..=b'9') => {
+                let mut code = vec![digit];
+
+                while let Some(byte) = read_byte(input)? {
+                    match byte {
+                        b'~' => break,
+                        byte if byte.is_ascii_digit() || byte == b';' => code.push(byte),
+                        _ => return Ok(Key::Unknown),
+                    }
+                }
+
+                match code.as_slice() {
+                    b"1" | b"7" => Key::Home,
+                    b"4" | b"8" => Key::End,
+                    b"3" => Key::Delete,
+                    _ => Key::Unknown,
+                }
+            }
+            _ => Key::Unknown,
+        },
+        _ => Key::Unknown,
+    };
+
+    Ok(key)
+}
+
+// the rest of a character whose first byte has been read
+fn read_char(input: &mut impl Read, first: u8) -> io::Result<Key> {
+    let length = match first {
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => 1,
+    };
+    let mut bytes = vec![first];
+
+    for _ in 1..length {
+        match read_byte(input)? {
+            Some(byte) => bytes.push(byte),
+            None => break,
+        }
+    }
+
+    let key = match std::str::from_utf8(&bytes).ok().and_then(|text| text.chars().next()) {
+        Some(character) if !character.is_control() => Key::Char(character),
+        _ => Key::Unknown,
+    };
+
+    Ok(key)
+}
+
+// None once the input has ended
+fn read_key(input: &mut impl Read) -> io::Result<Option<Key>> {
+    let byte = match read_byte(input)? {
+        Some(byte) => byte,
+        None => return Ok(None),
+    };
+
+    let key = match byte {
+        0x01 => Key::Home,
+        0x02 => Key::Left,
+        0x03 => Key::Interrupt,
+        0x04 => Key::EndOfInput,
+        0x05 => Key::End,
+        0x06 => Key::Right,
+        0x08 | 0x7f => Key::Backspace,
+        0x0a | 0x0d => Key::Enter,
+        0x0b => Key::KillToEnd,
+        0x0e => Key::Down,
+        0x10 => Key::Up,
+        0x15 => Key::KillToStart,
+        0x1b => read_escape(input)?,
+        byte => read_char(input, byte)?,
+    };
+
+    Ok(Some(key))
+}
+
+// the line being edited, the cursor counts characters rather than bytes
+#[derive(Debug, Default)]
+struct LineBuffer {
+    chars: Vec<char>,
+    cursor: usize,
+}
+
+impl LineBuffer {
+    fn text(&self) -> String {
+        self.chars.iter().collect()
+    }
+
+    fn replace(&mut self, text: &str) {
+        self.chars = text.chars().collect();
+        self.cursor = self.chars.len();
+    }
+
+    fn edit(&mut self, key: Key) {
+        match key {
+            Key::Char(character) => {
+                self.chars.insert(self.cursor, character);
+                self.cursor += 1;
+            }
+            Key::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.chars.remove(self.cursor);
+            }
+            Key::Delete if self.cursor < self.chars.len() => {
+                self.chars.remove(self.cursor);
+            }
+            Key::Left => self.cursor = self.cursor.saturating_sub(1),
+            Key::Right => self.cursor = (self.cursor + 1).min(self.chars.len()),
+            Key::Home => self.cursor = 0,
+            Key::End => self.cursor = self.chars.len(),
+            Key::KillToEnd => self.chars.truncate(self.cursor),
+            Key::KillToStart => {
+                self.chars.drain(..self.cursor);
+                self.cursor = 0;
+            }
+            _ => (),
+        }
+    }
+}
+
+// puts the terminal into raw mode for as long as it lives, so keys arrive one at a time and
+// aren't echoed. stty does the work so no platform specific terminal calls are needed
+struct RawMode {
+    saved: String,
+}
+
+impl RawMode {
+    fn stty(args: &[&str]) -> io::Result<String> {
+        let output = Command::new("stty").args(args).stdin(Stdio::inherit()).output()?;
+
+        if output.status.success() {
+            Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+        } else {
+            Err(io::Error::other("stty could not set up the terminal"))
+        }
+    }
+
+    fn enable() -> io::Result<Self> {
+        let saved = Self::stty(&["-g"])?;
+        Self::stty(&["raw", "-echo"])?;
+
+        Ok(Self { saved })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = Self::stty(&[&self.saved]);
+    }
+}
+
+// reads lines with arrow key history and the usual emacs style movement when stdin is a
+// terminal, and plain lines when it's a pipe or raw mode isn't available
+#[derive(Default)]
+pub struct Editor {
+    history: Vec<String>,
+}
+
+impl Editor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn read_line(&mut self, prompt: &str) -> io::Result<Input> {
+        let input = match io::stdin().is_terminal().then(RawMode::enable) {
+            Some(Ok(_raw_mode)) => self.edit_line(prompt)?,
+            _ => Self::read_plain_line(prompt)?,
+        };
+
+        if let Input::Line(line) = &input {
+            if !line.trim().is_empty() && self.history.last() != Some(line) {
+                self.history.push(line.clone());
+            }
+        }
+
+        Ok(input)
+    }
+
+    fn read_plain_line(prompt: &str) -> io::Result<Input> {
+        print!("{}", prompt);
+        io::stdout().flush()?;
+
+        let mut line = String::new();
+
+        match io::stdin().read_line(&mut line)? {
+            0 => {
+                println!();
+                Ok(Input::Eof)
+            }
+            _ => Ok(Input::Line(line.trim_end_matches(['\n', '\r']).to_string())),
+        }
+    }
+
+    // raw mode turns off the terminal's own newline handling, so lines end in \r\n here
+    fn edit_line(&mut self, prompt: &str) -> io::Result<Input> {
+        let mut stdin = io::stdin().lock();
+        let mut stdout = io::stdout().lock();
+        let mut buffer = LineBuffer::default();
+        // where Up and Down are in the history, and the line that was being typed before
+        let mut position = self.history.len();
+        let mut draft = String::new();
+
+        loop {
+            let prompt_width = prompt.chars().count();
+            write!(stdout, "\r{}{}\x1b[K\r", prompt, buffer.text())?;
+
+            if prompt_width + buffer.cursor > 0 {
+                write!(stdout, "\x1b[{}C", prompt_width + buffer.cursor)?;
+            }
+            stdout.flush()?;
+
+            let key = match read_key(&mut stdin)? {
+                Some(key) => key,
+                None => return Ok(Input::Eof),
+            };
+
+            match key {
+                Key::Enter => {
+                    write!(stdout, "\r\n")?;
+                    return Ok(Input::Line(buffer.text()));
+                }
+                Key::Interrupt => {
+                    write!(stdout, "^C\r\n")?;
+                    return Ok(Input::Interrupted);
+                }
+                Key::EndOfInput if buffer.chars.is_empty() => {
+                    write!(stdout, "\r\n")?;
+                    return Ok(Input::Eof);
+                }
+                Key::EndOfInput => buffer.edit(Key::Delete),
+                Key::Up if position > 0 => {
+                    if position == self.history.len() {
+                        draft = buffer.text();
+                    }
+
+                    position -= 1;
+                    buffer.replace(&self.history[position]);
+                }
+                Key::Down if position < self.history.len() => {
+                    position += 1;
+
+                    match self.history.get(position) {
+                        Some(line) => buffer.replace(line),
+                        None => buffer.replace(&draft),
+                    }
+                }
+                key => buffer.edit(key),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(input: &str) -> Vec<Key> {
+        let mut input = input.as_bytes();
+        let mut keys = vec![];
+
+        while let Some(key) = read_key(&mut input).unwrap() {
+            keys.push(key);
+        }
+
+        keys
+    }
+
+    #[test]
+    fn escape_sequences_and_control_keys() {
+        assert_eq!(
+            keys("\x1b[A\x1b[D\x1bOH\x1b[3~\x01\x05\x03\x7fé"),
+            vec![
+                Key::Up,
+                Key::Left,
+                Key::Home,
+                Key::Delete,
+                Key::Home,
+                Key::End,
+                Key::Interrupt,
+                Key::Backspace,
+                Key::Char('é'),
+            ]
+        );
+    }
+
+    #[test]
+    fn editing_moves_around_the_cursor() {
+        let mut buffer = LineBuffer::default();
+
+        for key in keys("prnt 1\x01\x1b[C\x1b[Ci\x05\x7f2") {
+            buffer.edit(key);
+        }
+        assert_eq!(buffer.text(), "print 2");
+
+        for key in keys("\x1b[D\x1b[D\x0b;") {
+            buffer.edit(key);
+        }
+        assert_eq!(buffer.text(), "print;");
+
+        for key in keys("\x1b[D\x15") {
+            buffer.edit(key);
+        }
+        assert_eq!((buffer.text().as_str(), buffer.cursor), (";", 0));
+    }
+}
@@ -0,0 +1,44 @@
+// the file natives of every backend. They're behind the file-io feature, which is on by
+// default, so a program embedding rslox can leave scripts without access to the disk
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+
+pub fn read(path: &str) -> Result<String, String> {
+  fs::read_to_string(path).map_err(|error| format!("Could not read '{}': {}.", path, error))
+}
+
+// the file is created if it isn't there and replaced if it is
+pub fn write(path: &str, contents: &str) -> Result<(), String> {
+  fs::write(path, contents).map_err(|error| format!("Could not write '{}': {}.", path, error))
+}
+
+// the file is created if it isn't there
+pub fn append(path: &str, contents: &str) -> Result<(), String> {
+  OpenOptions::new()
+    .create(true)
+    .append(true)
+    .open(path)
+    .and_then(|mut file| file.write_all(contents.as_bytes()))
+    .map_err(|error| format!("Could not write '{}': {}.", path, error))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn writes_and_appends_are_read_back() {
+    let path = std::env::temp_dir().join(format!("rslox-files-{}.txt", std::process::id()));
+    let path = path.to_str().unwrap();
+
+    write(path, "one\n").unwrap();
+    append(path, "two\n").unwrap();
+    assert_eq!(read(path), Ok(String::from("one\ntwo\n")));
+
+    write(path, "three").unwrap();
+    assert_eq!(read(path), Ok(String::from("three")));
+
+    fs::remove_file(path).unwrap();
+    assert!(read(path).unwrap_err().starts_with(&format!("Could not read '{}': ", path)));
+  }
+}
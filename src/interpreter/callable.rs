@@ -0,0 +1,188 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::mem;
+use std::rc::Rc;
+
+use super::{Environment, Instance, Interpreter, RuntimeError, RuntimeErrorKind, Unwind, Value};
+use crate::ast::{Ast, StmtId, StmtKind};
+use crate::lexer::Span;
+use crate::system::NativeError;
+
+// anything a call expression can call, Lox functions and the natives the interpreter provides
+pub trait Callable: fmt::Debug + fmt::Display {
+  // how the call shows up in stack traces
+  fn name(&self) -> &str;
+
+  fn arity(&self) -> usize;
+
+  // the arguments have already been checked against arity. A call that fails adds itself to
+  // the error's trace at call_site
+  fn call(self: Rc<Self>, interpreter: &mut Interpreter, ast: &Ast, call_site: Span, arguments: Vec<Value>)
+    -> Result<Value, RuntimeError>;
+}
+
+// a function declared in Lox, its parameters and body are read from the declaration on every call
+#[derive(Debug)]
+pub struct Function {
+  name: String,
+  arity: usize,
+  declaration: StmtId,
+  // the scope the declaration ran in, a function stored in a variable of that scope keeps it
+  // and itself alive through a reference cycle that is never collected
+  closure: Rc<RefCell<Environment>>,
+  // the top level scope of the file the declaration is in, where the names the resolver didn't
+  // find in any block are looked up while it runs
+  globals: Rc<RefCell<Environment>>,
+  // init always hands back the instance it initialized
+  is_initializer: bool,
+}
+
+impl Function {
+  pub fn new(
+    ast: &Ast,
+    declaration: StmtId,
+    closure: Rc<RefCell<Environment>>,
+    globals: Rc<RefCell<Environment>>,
+  ) -> Self {
+    match &ast[declaration].kind {
+      StmtKind::Function { name, params, .. } => Self {
+        name: name.clone(),
+        arity: params.len(),
+        declaration,
+        closure,
+        globals,
+        is_initializer: false,
+      },
+      kind => unreachable!("functions are only made from function declarations, not {:?}", kind),
+    }
+  }
+
+  pub fn method(
+    ast: &Ast,
+    declaration: StmtId,
+    closure: Rc<RefCell<Environment>>,
+    globals: Rc<RefCell<Environment>>,
+  ) -> Self {
+    let method = Self::new(ast, declaration, closure, globals);
+
+    Self {
+      is_initializer: method.name == "init",
+      ..method
+    }
+  }
+
+  // the method with `this` defined in a scope of its own between it and its closure
+  pub fn bind(&self, instance: Rc<Instance>) -> Self {
+    let mut environment = Environment::with_enclosing(Rc::clone(&self.closure));
+    environment.define("this", Value::Instance(instance));
+
+    Self {
+      name: self.name.clone(),
+      closure: Rc::new(RefCell::new(environment)),
+      globals: Rc::clone(&self.globals),
+      ..*self
+    }
+  }
+}
+
+impl fmt::Display for Function {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "<fn {}>", self.name)
+  }
+}
+
+impl Callable for Function {
+  fn name(&self) -> &str {
+    &self.name
+  }
+
+  fn arity(&self) -> usize {
+    self.arity
+  }
+
+  fn call(
+    self: Rc<Self>,
+    interpreter: &mut Interpreter,
+    ast: &Ast,
+    call_site: Span,
+    arguments: Vec<Value>,
+  ) -> Result<Value, RuntimeError> {
+    let (params, body) = match &ast[self.declaration].kind {
+      StmtKind::Function { params, body, .. } => (params, body),
+      kind => unreachable!("functions are only made from function declarations, not {:?}", kind),
+    };
+
+    // every call gets a fresh scope for its parameters, enclosed by the one the function was
+    // declared in so it sees the variables around it rather than those around the call
+    let mut environment = Environment::with_enclosing(Rc::clone(&self.closure));
+
+    for (param, argument) in params.iter().zip(arguments) {
+      environment.define(param, argument);
+    }
+
+    let globals = mem::replace(&mut interpreter.globals, Rc::clone(&self.globals));
+    let result = interpreter.execute_block(ast, body, environment);
+    interpreter.globals = globals;
+
+    match result {
+      Err(Unwind::Error(mut error)) => {
+        error.push_frame(&self.name, call_site);
+        Err(error)
+      }
+      Ok(()) | Err(Unwind::Return(_)) if self.is_initializer => {
+        Ok(self.closure.borrow().get("this").unwrap_or(Value::Nil))
+      }
+      Ok(()) => Ok(Value::Nil),
+      Err(Unwind::Return(value)) => Ok(value),
+      Err(Unwind::Break | Unwind::Continue) => unreachable!("the parser keeps break and continue inside loops"),
+    }
+  }
+}
+
+// a function implemented in Rust, errors are the message to report. It has no frame of its own,
+// they point at the call the way the vm's do
+pub struct NativeFunction {
+  pub(super) name: &'static str,
+  pub(super) arity: usize,
+  pub(super) function: fn(&[Value]) -> Result<Value, NativeError>,
+}
+
+impl NativeFunction {
+  // the arity of a native that takes any number of arguments and checks them itself
+  pub(super) const VARIADIC: usize = usize::MAX;
+}
+
+impl fmt::Debug for NativeFunction {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "NativeFunction({})", self.name)
+  }
+}
+
+impl fmt::Display for NativeFunction {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "<native fn>")
+  }
+}
+
+impl Callable for NativeFunction {
+  fn name(&self) -> &str {
+    self.name
+  }
+
+  fn arity(&self) -> usize {
+    self.arity
+  }
+
+  fn call(
+    self: Rc<Self>,
+    _: &mut Interpreter,
+    _: &Ast,
+    call_site: Span,
+    arguments: Vec<Value>,
+  ) -> Result<Value, RuntimeError> {
+    (self.function)(&arguments).map_err(|error| match error {
+      NativeError::Exit(status) => RuntimeErrorKind::Exit { span: call_site, status }.into(),
+      NativeError::Message(message) => RuntimeErrorKind::Native { span: call_site, message }.into(),
+    })
+  }
+}
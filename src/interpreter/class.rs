@@ -0,0 +1,107 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use super::{Callable, Function, Interpreter, RuntimeError, Value};
+use crate::ast::Ast;
+use crate::lexer::Span;
+
+#[derive(Debug)]
+pub struct Class {
+  name: String,
+  superclass: Option<Rc<Class>>,
+  methods: HashMap<String, Rc<Function>>,
+}
+
+impl Class {
+  pub fn new(name: &str, superclass: Option<Rc<Class>>, methods: HashMap<String, Rc<Function>>) -> Self {
+    Self {
+      name: String::from(name),
+      superclass,
+      methods,
+    }
+  }
+
+  // methods of the class itself override the ones it inherits
+  pub fn find_method(&self, name: &str) -> Option<Rc<Function>> {
+    match self.methods.get(name) {
+      Some(method) => Some(Rc::clone(method)),
+      None => self.superclass.as_ref()?.find_method(name),
+    }
+  }
+}
+
+impl fmt::Display for Class {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.name)
+  }
+}
+
+// calling a class makes an instance and runs init on it, if the class has one
+impl Callable for Class {
+  fn name(&self) -> &str {
+    &self.name
+  }
+
+  fn arity(&self) -> usize {
+    self.find_method("init").map_or(0, |init| init.arity())
+  }
+
+  fn call(
+    self: Rc<Self>,
+    interpreter: &mut Interpreter,
+    ast: &Ast,
+    call_site: Span,
+    arguments: Vec<Value>,
+  ) -> Result<Value, RuntimeError> {
+    let instance = Rc::new(Instance::new(Rc::clone(&self)));
+
+    if let Some(init) = self.find_method("init") {
+      Rc::new(init.bind(Rc::clone(&instance))).call(interpreter, ast, call_site, arguments)?;
+    }
+
+    Ok(Value::Instance(instance))
+  }
+}
+
+#[derive(Debug)]
+pub struct Instance {
+  class: Rc<Class>,
+  fields: RefCell<HashMap<String, Value>>,
+}
+
+impl Instance {
+  pub fn new(class: Rc<Class>) -> Self {
+    Self {
+      class,
+      fields: RefCell::new(HashMap::new()),
+    }
+  }
+
+  // fields shadow methods, a method comes back bound to the instance it was read from
+  pub fn get(instance: &Rc<Instance>, name: &str) -> Option<Value> {
+    if let Some(value) = instance.fields.borrow().get(name) {
+      return Some(value.clone());
+    }
+
+    let method = instance.class.find_method(name)?;
+
+    Some(Value::Callable(Rc::new(method.bind(Rc::clone(instance)))))
+  }
+
+  pub fn class_name(&self) -> &str {
+    &self.class.name
+  }
+
+  // any field can be added to any instance
+  pub fn set(&self, name: &str, value: Value) {
+    self.fields.borrow_mut().insert(String::from(name), value);
+  }
+}
+
+impl fmt::Display for Instance {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{} instance", self.class)
+  }
+}
@@ -0,0 +1,79 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use super::Value;
+
+// the variables of one scope, names not found here are looked up in the scopes around it
+#[derive(Debug, Default)]
+pub struct Environment {
+  values: HashMap<String, Value>,
+  enclosing: Option<Rc<RefCell<Environment>>>,
+}
+
+impl Environment {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn with_enclosing(enclosing: Rc<RefCell<Environment>>) -> Self {
+    Self {
+      values: HashMap::new(),
+      enclosing: Some(enclosing),
+    }
+  }
+
+  // redefining a name in the same scope replaces it, as Lox allows at the top level
+  pub fn define(&mut self, name: &str, value: Value) {
+    self.values.insert(String::from(name), value);
+  }
+
+  // the names defined in this scope alone, in no particular order
+  pub fn bindings(&self) -> impl Iterator<Item = (&str, &Value)> {
+    self.values.iter().map(|(name, value)| (name.as_str(), value))
+  }
+
+  pub fn get(&self, name: &str) -> Option<Value> {
+    match self.values.get(name) {
+      Some(value) => Some(value.clone()),
+      None => self.enclosing.as_ref()?.borrow().get(name),
+    }
+  }
+
+  // false when no scope defines the name, assigning never creates a variable
+  pub fn assign(&mut self, name: &str, value: Value) -> bool {
+    match self.values.get_mut(name) {
+      Some(slot) => {
+        *slot = value;
+        true
+      }
+      None => match &self.enclosing {
+        Some(enclosing) => enclosing.borrow_mut().assign(name, value),
+        None => false,
+      },
+    }
+  }
+
+  // the scope depth scopes out from this one, as the resolver counted them
+  fn ancestor(environment: &Rc<RefCell<Environment>>, depth: usize) -> Rc<RefCell<Environment>> {
+    (0..depth).fold(Rc::clone(environment), |environment, _| {
+      let enclosing = environment.borrow().enclosing.clone();
+      enclosing.expect("the resolver never counts past the outermost scope")
+    })
+  }
+
+  // reads a name straight from the scope the resolver found it in, skipping any that shadow it
+  pub fn get_at(environment: &Rc<RefCell<Environment>>, depth: usize, name: &str) -> Option<Value> {
+    Self::ancestor(environment, depth).borrow().values.get(name).cloned()
+  }
+
+  pub fn assign_at(environment: &Rc<RefCell<Environment>>, depth: usize, name: &str, value: Value) -> bool {
+    match Self::ancestor(environment, depth).borrow_mut().values.get_mut(name) {
+      Some(slot) => {
+        *slot = value;
+        true
+      }
+      None => false,
+    }
+  }
+}
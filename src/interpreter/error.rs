@@ -0,0 +1,208 @@
+use std::error;
+use std::fmt;
+
+use super::Value;
+use crate::lexer::{SourceMap, Span};
+
+// the messages follow the book so output can be compared with other Lox implementations
+#[derive(Debug, Clone)]
+pub enum RuntimeErrorKind {
+  OperandMustBeNumber {
+    span: Span,
+  },
+  OperandsMustBeNumbers {
+    span: Span,
+  },
+  OperandsMustBeNumbersOrStrings {
+    span: Span,
+  },
+  UndefinedVariable {
+    span: Span,
+    name: String,
+  },
+  NotCallable {
+    span: Span,
+  },
+  ArityMismatch {
+    span: Span,
+    expected: usize,
+    got: usize,
+  },
+  OnlyInstancesHaveProperties {
+    span: Span,
+  },
+  OnlyInstancesHaveFields {
+    span: Span,
+  },
+  UndefinedProperty {
+    span: Span,
+    name: String,
+  },
+  SuperclassMustBeClass {
+    span: Span,
+  },
+  OnlyListsAndMapsCanBeIndexed {
+    span: Span,
+  },
+  OnlyListsCanBeSliced {
+    span: Span,
+  },
+  IndexMustBeNumber {
+    span: Span,
+  },
+  SliceBoundsMustBeNumbers {
+    span: Span,
+  },
+  OnlyListsCanBeIterated {
+    span: Span,
+  },
+  // an index or slice range outside the list, or a key that isn't in the map
+  InvalidIndex {
+    span: Span,
+    message: String,
+  },
+  // a native was given something it can't work with
+  Native {
+    span: Span,
+    message: String,
+  },
+  // a throw no try caught, the value is what a catch clause would have got
+  Thrown {
+    span: Span,
+    value: Value,
+  },
+  // exit() stopping the script, which no try catches
+  Exit {
+    span: Span,
+    status: i32,
+  },
+}
+
+impl RuntimeErrorKind {
+  pub fn span(&self) -> Span {
+    match self {
+      RuntimeErrorKind::OperandMustBeNumber { span }
+      | RuntimeErrorKind::OperandsMustBeNumbers { span }
+      | RuntimeErrorKind::OperandsMustBeNumbersOrStrings { span }
+      | RuntimeErrorKind::UndefinedVariable { span, .. }
+      | RuntimeErrorKind::NotCallable { span }
+      | RuntimeErrorKind::ArityMismatch { span, .. }
+      | RuntimeErrorKind::OnlyInstancesHaveProperties { span }
+      | RuntimeErrorKind::OnlyInstancesHaveFields { span }
+      | RuntimeErrorKind::UndefinedProperty { span, .. }
+      | RuntimeErrorKind::SuperclassMustBeClass { span }
+      | RuntimeErrorKind::OnlyListsAndMapsCanBeIndexed { span }
+      | RuntimeErrorKind::OnlyListsCanBeSliced { span }
+      | RuntimeErrorKind::IndexMustBeNumber { span }
+      | RuntimeErrorKind::SliceBoundsMustBeNumbers { span }
+      | RuntimeErrorKind::OnlyListsCanBeIterated { span }
+      | RuntimeErrorKind::InvalidIndex { span, .. }
+      | RuntimeErrorKind::Native { span, .. }
+      | RuntimeErrorKind::Thrown { span, .. }
+      | RuntimeErrorKind::Exit { span, .. } => *span,
+    }
+  }
+}
+
+impl fmt::Display for RuntimeErrorKind {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      RuntimeErrorKind::OperandMustBeNumber { .. } => write!(f, "Operand must be a number."),
+      RuntimeErrorKind::OperandsMustBeNumbers { .. } => write!(f, "Operands must be numbers."),
+      RuntimeErrorKind::OperandsMustBeNumbersOrStrings { .. } => {
+        write!(f, "Operands must be two numbers or two strings.")
+      }
+      RuntimeErrorKind::UndefinedVariable { name, .. } => write!(f, "Undefined variable '{}'.", name),
+      RuntimeErrorKind::NotCallable { .. } => write!(f, "Can only call functions and classes."),
+      RuntimeErrorKind::ArityMismatch { expected, got, .. } => {
+        write!(f, "Expected {} arguments but got {}.", expected, got)
+      }
+      RuntimeErrorKind::OnlyInstancesHaveProperties { .. } => write!(f, "Only instances have properties."),
+      RuntimeErrorKind::OnlyInstancesHaveFields { .. } => write!(f, "Only instances have fields."),
+      RuntimeErrorKind::UndefinedProperty { name, .. } => write!(f, "Undefined property '{}'.", name),
+      RuntimeErrorKind::SuperclassMustBeClass { .. } => write!(f, "Superclass must be a class."),
+      RuntimeErrorKind::OnlyListsAndMapsCanBeIndexed { .. } => write!(f, "Only lists and maps can be indexed."),
+      RuntimeErrorKind::OnlyListsCanBeSliced { .. } => write!(f, "Only lists can be sliced."),
+      RuntimeErrorKind::IndexMustBeNumber { .. } => write!(f, "List index must be a number."),
+      RuntimeErrorKind::SliceBoundsMustBeNumbers { .. } => write!(f, "Slice bounds must be numbers."),
+      RuntimeErrorKind::OnlyListsCanBeIterated { .. } => write!(f, "Can only iterate over lists."),
+      RuntimeErrorKind::InvalidIndex { message, .. } => write!(f, "{}", message),
+      RuntimeErrorKind::Native { message, .. } => write!(f, "{}", message),
+      RuntimeErrorKind::Thrown { value, .. } => write!(f, "Uncaught exception: {}", value),
+      RuntimeErrorKind::Exit { status, .. } => write!(f, "{}", crate::system::exited(*status)),
+    }
+  }
+}
+
+// a call that was still running when the error happened
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackFrame {
+  pub function: String,
+  pub call_site: Span,
+}
+
+// what went wrong, and the calls it happened under, innermost first
+#[derive(Debug, Clone)]
+pub struct RuntimeError {
+  pub kind: RuntimeErrorKind,
+  pub trace: Vec<StackFrame>,
+}
+
+impl RuntimeError {
+  pub fn span(&self) -> Span {
+    self.kind.span()
+  }
+
+  // the status to exit with when this is exit() rather than an error
+  pub fn exit_status(&self) -> Option<i32> {
+    match self.kind {
+      RuntimeErrorKind::Exit { status, .. } => Some(status),
+      _ => None,
+    }
+  }
+
+  // called on the way out of each call the error passes through
+  pub fn push_frame(&mut self, function: &str, call_site: Span) {
+    self.trace.push(StackFrame {
+      function: String::from(function),
+      call_site,
+    });
+  }
+
+  // one line per frame in the style of clox, where each function was when the error happened.
+  // The top level of an imported file is named after it in angle brackets
+  //
+  //   [line 3] in fibonacci()
+  //   [line 1] in <lib/math.lox>
+  //   [line 7] in script
+  pub fn stack_trace(&self, source_map: &SourceMap) -> Vec<String> {
+    let lines = std::iter::once(self.span()).chain(self.trace.iter().map(|frame| frame.call_site));
+    let functions = self
+      .trace
+      .iter()
+      .map(|frame| match frame.function.starts_with('<') {
+        true => frame.function.clone(),
+        false => format!("{}()", frame.function),
+      })
+      .chain(std::iter::once(String::from("script")));
+
+    lines
+      .zip(functions)
+      .map(|(span, function)| format!("[line {}] in {}", source_map.location(span.start).line, function))
+      .collect()
+  }
+}
+
+impl From<RuntimeErrorKind> for RuntimeError {
+  fn from(kind: RuntimeErrorKind) -> Self {
+    Self { kind, trace: vec![] }
+  }
+}
+
+impl fmt::Display for RuntimeError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.kind)
+  }
+}
+
+impl error::Error for RuntimeError {}
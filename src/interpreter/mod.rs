@@ -0,0 +1,1489 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::mem;
+use std::rc::Rc;
+
+use crate::ast::{
+  Ast, BinaryOperator, Case, ExprId, ExprVisitor, Literal, LogicalOperator, ModuleId, StmtId, StmtKind, StmtVisitor,
+  UnaryOperator,
+};
+use crate::lists;
+use crate::maps;
+use crate::resolver::Locals;
+
+mod callable;
+mod class;
+mod environment;
+mod error;
+mod natives;
+mod value;
+
+pub use callable::{Callable, Function, NativeFunction};
+pub use class::{Class, Instance};
+pub use environment::Environment;
+pub use error::{RuntimeError, RuntimeErrorKind, StackFrame};
+pub use value::Value;
+use value::{Map, MapKey};
+
+type EvalResult = Result<Value, RuntimeError>;
+type ExecResult = Result<(), Unwind>;
+
+// why a statement stopped early, return travels up to its call as an error does without panicking
+// and break and continue up to their loop
+#[derive(Debug)]
+enum Unwind {
+  Return(Value),
+  Break,
+  Continue,
+  Error(RuntimeError),
+}
+
+impl From<RuntimeError> for Unwind {
+  fn from(error: RuntimeError) -> Self {
+    Unwind::Error(error)
+  }
+}
+
+fn number_operand(ast: &Ast, id: ExprId, operand: Value) -> Result<f64, RuntimeError> {
+  match operand {
+    Value::Number(value) => Ok(value),
+    _ => Err(RuntimeErrorKind::OperandMustBeNumber { span: ast[id].span }.into()),
+  }
+}
+
+// what's being sliced, and the index into a list
+fn list_operand(ast: &Ast, id: ExprId, operand: Value) -> Result<Rc<RefCell<Vec<Value>>>, RuntimeError> {
+  match operand {
+    Value::List(list) => Ok(list),
+    _ => Err(RuntimeErrorKind::OnlyListsCanBeSliced { span: ast[id].span }.into()),
+  }
+}
+
+fn index_operand(ast: &Ast, id: ExprId, operand: Value) -> Result<f64, RuntimeError> {
+  match operand {
+    Value::Number(value) => Ok(value),
+    _ => Err(RuntimeErrorKind::IndexMustBeNumber { span: ast[id].span }.into()),
+  }
+}
+
+fn key_operand(ast: &Ast, id: ExprId, operand: &Value) -> Result<MapKey, RuntimeError> {
+  operand.to_key().ok_or_else(|| invalid_index(ast, id)(maps::invalid_key()))
+}
+
+fn not_indexable(ast: &Ast, id: ExprId) -> RuntimeError {
+  RuntimeErrorKind::OnlyListsAndMapsCanBeIndexed { span: ast[id].span }.into()
+}
+
+fn slice_bound(ast: &Ast, id: ExprId, bound: Option<Value>) -> Result<Option<f64>, RuntimeError> {
+  match bound {
+    None => Ok(None),
+    Some(Value::Number(value)) => Ok(Some(value)),
+    Some(_) => Err(RuntimeErrorKind::SliceBoundsMustBeNumbers { span: ast[id].span }.into()),
+  }
+}
+
+fn invalid_index(ast: &Ast, id: ExprId) -> impl FnOnce(String) -> RuntimeError {
+  let span = ast[id].span;
+
+  move |message| RuntimeErrorKind::InvalidIndex { span, message }.into()
+}
+
+// bitwise operators work on the integer part of their operands
+fn integer(value: f64) -> i64 {
+  value as i64
+}
+
+fn element(ast: &Ast, id: ExprId, object: Value, index: Value) -> EvalResult {
+  match object {
+    Value::List(list) => {
+      let element = lists::get(&list.borrow(), index_operand(ast, id, index)?);
+      element.map_err(invalid_index(ast, id))
+    }
+    Value::Map(map) => {
+      let value = map.borrow().get(&key_operand(ast, id, &index)?).cloned();
+      value.ok_or_else(|| invalid_index(ast, id)(maps::undefined_key(index)))
+    }
+    _ => Err(not_indexable(ast, id)),
+  }
+}
+
+// the operator applied to two values, for binary expressions and compound assignments
+fn binary(ast: &Ast, id: ExprId, left: Value, operator: BinaryOperator, right: Value) -> EvalResult {
+  let (left, right) = match (operator, left, right) {
+    (BinaryOperator::Equal, left, right) => return Ok(Value::Bool(left == right)),
+    (BinaryOperator::NotEqual, left, right) => return Ok(Value::Bool(left != right)),
+    (BinaryOperator::Add, Value::String(left), Value::String(right)) => {
+      return Ok(Value::String(Rc::from(format!("{}{}", left, right))));
+    }
+    (_, Value::Number(left), Value::Number(right)) => (left, right),
+    (BinaryOperator::Add, _, _) => {
+      return Err(RuntimeErrorKind::OperandsMustBeNumbersOrStrings { span: ast[id].span }.into());
+    }
+    _ => return Err(RuntimeErrorKind::OperandsMustBeNumbers { span: ast[id].span }.into()),
+  };
+
+  let value = match operator {
+    BinaryOperator::Add => Value::Number(left + right),
+    BinaryOperator::Subtract => Value::Number(left - right),
+    BinaryOperator::Multiply => Value::Number(left * right),
+    BinaryOperator::Divide => Value::Number(left / right),
+    BinaryOperator::Remainder => Value::Number(left % right),
+    BinaryOperator::Less => Value::Bool(left < right),
+    BinaryOperator::LessEqual => Value::Bool(left <= right),
+    BinaryOperator::Greater => Value::Bool(left > right),
+    BinaryOperator::GreaterEqual => Value::Bool(left >= right),
+    BinaryOperator::BitAnd => Value::Number((integer(left) & integer(right)) as f64),
+    BinaryOperator::BitOr => Value::Number((integer(left) | integer(right)) as f64),
+    BinaryOperator::BitXor => Value::Number((integer(left) ^ integer(right)) as f64),
+    // shift counts wrap at 64 the way the hardware does
+    BinaryOperator::ShiftLeft => Value::Number(integer(left).wrapping_shl(integer(right) as u32) as f64),
+    BinaryOperator::ShiftRight => Value::Number(integer(left).wrapping_shr(integer(right) as u32) as f64),
+    BinaryOperator::Equal | BinaryOperator::NotEqual => unreachable!("equality is decided above"),
+  };
+
+  Ok(value)
+}
+
+// evaluates the tree directly, walking it with the visitor
+pub struct Interpreter {
+  // the innermost scope, blocks push a new one and pop it again when they end
+  environment: Rc<RefCell<Environment>>,
+  // the top level scope, where names the resolver didn't find in any block are looked up.
+  // It's the imported file's own while its code runs
+  globals: Rc<RefCell<Environment>>,
+  // the top level scope of each module that has run, an import of one of them again only
+  // copies names out of it
+  modules: HashMap<ModuleId, Rc<RefCell<Environment>>>,
+  locals: Locals,
+  out: Box<dyn Write>,
+}
+
+impl Default for Interpreter {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Interpreter {
+  pub fn new() -> Self {
+    Self::with_output(Box::new(io::stdout()))
+  }
+
+  // print writes to out instead of stdout
+  pub fn with_output(out: Box<dyn Write>) -> Self {
+    let globals = Rc::new(RefCell::new(Environment::new()));
+    natives::define_natives(&globals);
+
+    Self {
+      environment: Rc::clone(&globals),
+      globals,
+      modules: HashMap::new(),
+      locals: Locals::new(),
+      out,
+    }
+  }
+
+  // every global and its value sorted by name, natives included
+  pub fn globals(&self) -> Vec<(String, Value)> {
+    let mut globals = self
+      .globals
+      .borrow()
+      .bindings()
+      .map(|(name, value)| (String::from(name), value.clone()))
+      .collect::<Vec<_>>();
+    globals.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    globals
+  }
+
+  // the depths the resolver found, they have to be added before the code they came from runs
+  pub fn resolve(&mut self, locals: Locals) {
+    self.locals.extend(locals);
+  }
+
+  // runs the top level statements in order, stopping at the first runtime error, a return
+  // outside of any function ends the program
+  pub fn interpret(&mut self, ast: &Ast) -> Result<(), RuntimeError> {
+    match ast.program().iter().try_for_each(|statement| self.execute(ast, *statement)) {
+      Ok(()) | Err(Unwind::Return(_)) => Ok(()),
+      Err(Unwind::Error(error)) => Err(error),
+      Err(Unwind::Break | Unwind::Continue) => unreachable!("the parser only allows break and continue in loops"),
+    }
+  }
+
+  fn execute(&mut self, ast: &Ast, stmt: StmtId) -> ExecResult {
+    ast.visit_stmt(stmt, self)
+  }
+
+  pub fn evaluate(&mut self, ast: &Ast, expr: ExprId) -> EvalResult {
+    ast.visit_expr(expr, self)
+  }
+
+  fn execute_block(&mut self, ast: &Ast, statements: &[StmtId], environment: Environment) -> ExecResult {
+    let enclosing = mem::replace(&mut self.environment, Rc::new(RefCell::new(environment)));
+    let result = statements.iter().try_for_each(|statement| self.execute(ast, *statement));
+
+    // the scope is left even when a statement in it failed
+    self.environment = enclosing;
+    result
+  }
+
+  // the module's statements with a top level scope of its own, natives and all, as if it
+  // were a program by itself. The scope is kept once they've all run
+  fn run_module(&mut self, ast: &Ast, import: StmtId, module: ModuleId) -> Result<Rc<RefCell<Environment>>, Unwind> {
+    let globals = Rc::new(RefCell::new(Environment::new()));
+    natives::define_natives(&globals);
+
+    let importer = mem::replace(&mut self.globals, Rc::clone(&globals));
+    let environment = mem::replace(&mut self.environment, Rc::clone(&globals));
+    let result = ast.module(module).body.iter().try_for_each(|statement| self.execute(ast, *statement));
+    self.globals = importer;
+    self.environment = environment;
+
+    if let Err(Unwind::Error(mut error)) = result {
+      error.push_frame(&format!("<{}>", ast.module(module).path), ast[import].span);
+      return Err(error.into());
+    }
+
+    self.modules.insert(module, Rc::clone(&globals));
+    Ok(globals)
+  }
+
+  fn look_up_variable(&self, ast: &Ast, id: ExprId, name: &str) -> EvalResult {
+    let value = match self.locals.get(&id) {
+      Some(depth) => Environment::get_at(&self.environment, *depth, name),
+      None => self.globals.borrow().get(name),
+    };
+
+    value.ok_or_else(|| {
+      RuntimeErrorKind::UndefinedVariable {
+        span: ast[id].span,
+        name: String::from(name),
+      }
+      .into()
+    })
+  }
+}
+
+impl ExprVisitor<EvalResult> for Interpreter {
+  fn visit_literal(&mut self, _: &Ast, _: ExprId, literal: &Literal) -> EvalResult {
+    let value = match literal {
+      Literal::Number(value) => Value::Number(*value),
+      Literal::String(value) => Value::String(Rc::from(value.as_str())),
+      Literal::Bool(value) => Value::Bool(*value),
+      Literal::Nil => Value::Nil,
+    };
+
+    Ok(value)
+  }
+
+  // every part is stringified the way print would show it
+  fn visit_interpolation(&mut self, ast: &Ast, _: ExprId, parts: &[ExprId]) -> EvalResult {
+    let mut text = String::new();
+
+    for part in parts {
+      text.push_str(&self.evaluate(ast, *part)?.to_string());
+    }
+
+    Ok(Value::String(Rc::from(text)))
+  }
+
+  fn visit_grouping(&mut self, ast: &Ast, _: ExprId, inner: ExprId) -> EvalResult {
+    self.evaluate(ast, inner)
+  }
+
+  fn visit_unary(&mut self, ast: &Ast, id: ExprId, operator: UnaryOperator, operand: ExprId) -> EvalResult {
+    let operand = self.evaluate(ast, operand)?;
+
+    let value = match operator {
+      UnaryOperator::Not => Value::Bool(!operand.is_truthy()),
+      UnaryOperator::Negate => Value::Number(-number_operand(ast, id, operand)?),
+      UnaryOperator::BitNot => Value::Number(!integer(number_operand(ast, id, operand)?) as f64),
+    };
+
+    Ok(value)
+  }
+
+  fn visit_binary(
+    &mut self,
+    ast: &Ast,
+    id: ExprId,
+    left: ExprId,
+    operator: BinaryOperator,
+    right: ExprId,
+  ) -> EvalResult {
+    let left = self.evaluate(ast, left)?;
+    let right = self.evaluate(ast, right)?;
+
+    binary(ast, id, left, operator, right)
+  }
+
+  // the result is whichever operand decided it, not necessarily a boolean
+  fn visit_logical(
+    &mut self,
+    ast: &Ast,
+    _: ExprId,
+    left: ExprId,
+    operator: LogicalOperator,
+    right: ExprId,
+  ) -> EvalResult {
+    let left = self.evaluate(ast, left)?;
+
+    match (operator, left.is_truthy()) {
+      (LogicalOperator::Or, true) | (LogicalOperator::And, false) => Ok(left),
+      (LogicalOperator::Coalesce, _) if left != Value::Nil => Ok(left),
+      _ => self.evaluate(ast, right),
+    }
+  }
+
+  fn visit_variable(&mut self, ast: &Ast, id: ExprId, name: &str) -> EvalResult {
+    self.look_up_variable(ast, id, name)
+  }
+
+  // an assignment evaluates to the value assigned so `a = b = 1` sets both
+  fn visit_assign(&mut self, ast: &Ast, id: ExprId, name: &str, value: ExprId) -> EvalResult {
+    let value = self.evaluate(ast, value)?;
+
+    let assigned = match self.locals.get(&id) {
+      Some(depth) => Environment::assign_at(&self.environment, *depth, name, value.clone()),
+      None => self.globals.borrow_mut().assign(name, value.clone()),
+    };
+
+    if assigned {
+      Ok(value)
+    } else {
+      Err(
+        RuntimeErrorKind::UndefinedVariable {
+          span: ast[id].span,
+          name: String::from(name),
+        }
+        .into(),
+      )
+    }
+  }
+
+  fn visit_call(&mut self, ast: &Ast, id: ExprId, callee: ExprId, arguments: &[ExprId]) -> EvalResult {
+    let callee = self.evaluate(ast, callee)?;
+    let arguments = arguments
+      .iter()
+      .map(|argument| self.evaluate(ast, *argument))
+      .collect::<Result<Vec<_>, _>>()?;
+
+    let callable: Rc<dyn Callable> = match callee {
+      Value::Callable(callable) => callable,
+      Value::Class(class) => class,
+      _ => return Err(RuntimeErrorKind::NotCallable { span: ast[id].span }.into()),
+    };
+
+    if callable.arity() != NativeFunction::VARIADIC && arguments.len() != callable.arity() {
+      return Err(
+        RuntimeErrorKind::ArityMismatch {
+          span: ast[id].span,
+          expected: callable.arity(),
+          got: arguments.len(),
+        }
+        .into(),
+      );
+    }
+
+    callable.call(self, ast, ast[id].span, arguments)
+  }
+
+  fn visit_get(&mut self, ast: &Ast, id: ExprId, object: ExprId, name: &str) -> EvalResult {
+    let instance = match self.evaluate(ast, object)? {
+      Value::Instance(instance) => instance,
+      _ => return Err(RuntimeErrorKind::OnlyInstancesHaveProperties { span: ast[id].span }.into()),
+    };
+
+    Instance::get(&instance, name).ok_or_else(|| {
+      RuntimeErrorKind::UndefinedProperty {
+        span: ast[id].span,
+        name: String::from(name),
+      }
+      .into()
+    })
+  }
+
+  // a compound assignment reads the property from the instance the object evaluated to, as on
+  // the vm, without evaluating the object again
+  fn visit_set(
+    &mut self,
+    ast: &Ast,
+    id: ExprId,
+    object: ExprId,
+    name: &str,
+    operator: Option<BinaryOperator>,
+    value: ExprId,
+  ) -> EvalResult {
+    let instance = match self.evaluate(ast, object)? {
+      Value::Instance(instance) => instance,
+      _ => return Err(RuntimeErrorKind::OnlyInstancesHaveFields { span: ast[id].span }.into()),
+    };
+
+    let value = match operator {
+      Some(operator) => {
+        let current = Instance::get(&instance, name).ok_or_else(|| RuntimeErrorKind::UndefinedProperty {
+          span: ast[id].span,
+          name: String::from(name),
+        })?;
+        let value = self.evaluate(ast, value)?;
+        binary(ast, id, current, operator, value)?
+      }
+      None => self.evaluate(ast, value)?,
+    };
+    instance.set(name, value.clone());
+
+    Ok(value)
+  }
+
+  // methods see `this` as a variable in the scope binding put around them
+  fn visit_this(&mut self, ast: &Ast, id: ExprId) -> EvalResult {
+    self.look_up_variable(ast, id, "this")
+  }
+
+  // the method is looked up on the superclass of the class `super` appears in, which the
+  // class declaration put in a scope around its methods, and bound to the current `this` found
+  // in the scope just inside that one
+  fn visit_super(&mut self, ast: &Ast, id: ExprId, method: &str) -> EvalResult {
+    let depth = *self.locals.get(&id).expect("the resolver rejects super outside of a subclass");
+
+    let superclass = match Environment::get_at(&self.environment, depth, "super") {
+      Some(Value::Class(superclass)) => superclass,
+      value => unreachable!("super is always bound to a class, not {:?}", value),
+    };
+    let instance = match Environment::get_at(&self.environment, depth - 1, "this") {
+      Some(Value::Instance(instance)) => instance,
+      value => unreachable!("this is always bound to an instance, not {:?}", value),
+    };
+
+    match superclass.find_method(method) {
+      Some(method) => Ok(Value::Callable(Rc::new(method.bind(instance)))),
+      None => Err(
+        RuntimeErrorKind::UndefinedProperty {
+          span: ast[id].span,
+          name: String::from(method),
+        }
+        .into(),
+      ),
+    }
+  }
+
+  fn visit_list(&mut self, ast: &Ast, _: ExprId, elements: &[ExprId]) -> EvalResult {
+    let elements = elements
+      .iter()
+      .map(|element| self.evaluate(ast, *element))
+      .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Value::List(Rc::new(RefCell::new(elements))))
+  }
+
+  // every key and value is evaluated before the keys are checked, as on the vm
+  fn visit_map(&mut self, ast: &Ast, id: ExprId, entries: &[(ExprId, ExprId)]) -> EvalResult {
+    let entries = entries
+      .iter()
+      .map(|(key, value)| Ok((self.evaluate(ast, *key)?, self.evaluate(ast, *value)?)))
+      .collect::<Result<Vec<_>, RuntimeError>>()?;
+    let mut map = Map::new();
+
+    for (key, value) in entries {
+      map.set(key_operand(ast, id, &key)?, value);
+    }
+
+    Ok(Value::Map(Rc::new(RefCell::new(map))))
+  }
+
+  // the object and the index are both evaluated before either is checked, as on the vm
+  fn visit_index(&mut self, ast: &Ast, id: ExprId, object: ExprId, index: ExprId) -> EvalResult {
+    let object = self.evaluate(ast, object)?;
+    let index = self.evaluate(ast, index)?;
+
+    element(ast, id, object, index)
+  }
+
+  // a compound assignment reads the element of the object and index it evaluated, as on the vm
+  fn visit_set_index(
+    &mut self,
+    ast: &Ast,
+    id: ExprId,
+    object: ExprId,
+    index: ExprId,
+    operator: Option<BinaryOperator>,
+    value: ExprId,
+  ) -> EvalResult {
+    let object = self.evaluate(ast, object)?;
+    let index = self.evaluate(ast, index)?;
+    let value = match operator {
+      Some(operator) => {
+        let current = element(ast, id, object.clone(), index.clone())?;
+        let value = self.evaluate(ast, value)?;
+        binary(ast, id, current, operator, value)?
+      }
+      None => self.evaluate(ast, value)?,
+    };
+
+    match object {
+      Value::List(list) => {
+        let set = lists::set(&mut list.borrow_mut(), index_operand(ast, id, index)?, value.clone());
+        set.map_err(invalid_index(ast, id))?;
+      }
+      Value::Map(map) => map.borrow_mut().set(key_operand(ast, id, &index)?, value.clone()),
+      _ => return Err(not_indexable(ast, id)),
+    }
+
+    Ok(value)
+  }
+
+  // a new list, changing it leaves the one it was sliced from alone
+  fn visit_slice(
+    &mut self,
+    ast: &Ast,
+    id: ExprId,
+    object: ExprId,
+    start: Option<ExprId>,
+    end: Option<ExprId>,
+  ) -> EvalResult {
+    let object = self.evaluate(ast, object)?;
+    let start = start.map(|start| self.evaluate(ast, start)).transpose()?;
+    let end = end.map(|end| self.evaluate(ast, end)).transpose()?;
+    let list = list_operand(ast, id, object)?;
+    let (start, end) = (slice_bound(ast, id, start)?, slice_bound(ast, id, end)?);
+
+    let slice = lists::slice(&list.borrow(), start, end);
+    slice.map(|elements| Value::List(Rc::new(RefCell::new(elements)))).map_err(invalid_index(ast, id))
+  }
+
+  fn visit_lambda(&mut self, ast: &Ast, _: ExprId, declaration: StmtId) -> EvalResult {
+    let function = Function::new(ast, declaration, Rc::clone(&self.environment), Rc::clone(&self.globals));
+
+    Ok(Value::Callable(Rc::new(function)))
+  }
+}
+
+impl StmtVisitor<ExecResult> for Interpreter {
+  fn visit_expression_stmt(&mut self, ast: &Ast, _: StmtId, expr: ExprId) -> ExecResult {
+    self.evaluate(ast, expr)?;
+
+    Ok(())
+  }
+
+  fn visit_print(&mut self, ast: &Ast, _: StmtId, expr: ExprId) -> ExecResult {
+    let value = self.evaluate(ast, expr)?;
+    // a closed stdout is no reason to stop the script
+    let _ = writeln!(self.out, "{}", value);
+
+    Ok(())
+  }
+
+  // a variable declared without an initializer starts out as nil
+  fn visit_var(&mut self, ast: &Ast, _: StmtId, name: &str, initializer: Option<ExprId>) -> ExecResult {
+    let value = match initializer {
+      Some(initializer) => self.evaluate(ast, initializer)?,
+      None => Value::Nil,
+    };
+
+    self.environment.borrow_mut().define(name, value);
+    Ok(())
+  }
+
+  fn visit_block(&mut self, ast: &Ast, _: StmtId, statements: &[StmtId]) -> ExecResult {
+    let environment = Environment::with_enclosing(Rc::clone(&self.environment));
+
+    self.execute_block(ast, statements, environment)
+  }
+
+  fn visit_if(
+    &mut self,
+    ast: &Ast,
+    _: StmtId,
+    condition: ExprId,
+    then_branch: StmtId,
+    else_branch: Option<StmtId>,
+  ) -> ExecResult {
+    if self.evaluate(ast, condition)?.is_truthy() {
+      self.execute(ast, then_branch)
+    } else if let Some(else_branch) = else_branch {
+      self.execute(ast, else_branch)
+    } else {
+      Ok(())
+    }
+  }
+
+  // a continue still runs the increment of a for loop
+  fn visit_while(
+    &mut self,
+    ast: &Ast,
+    _: StmtId,
+    condition: ExprId,
+    body: StmtId,
+    increment: Option<ExprId>,
+  ) -> ExecResult {
+    while self.evaluate(ast, condition)?.is_truthy() {
+      match self.execute(ast, body) {
+        Ok(()) | Err(Unwind::Continue) => (),
+        Err(Unwind::Break) => break,
+        Err(unwind) => return Err(unwind),
+      }
+
+      if let Some(increment) = increment {
+        self.evaluate(ast, increment)?;
+      }
+    }
+
+    Ok(())
+  }
+
+  // the length is checked before every element, so elements the body adds are visited too
+  fn visit_for_in(&mut self, ast: &Ast, _: StmtId, name: &str, iterable: ExprId, body: StmtId) -> ExecResult {
+    let list = match self.evaluate(ast, iterable)? {
+      Value::List(list) => list,
+      _ => return Err(RuntimeError::from(RuntimeErrorKind::OnlyListsCanBeIterated { span: ast[iterable].span }).into()),
+    };
+    let mut index = 0;
+
+    loop {
+      let element = match list.borrow().get(index) {
+        Some(element) => element.clone(),
+        None => return Ok(()),
+      };
+
+      let mut environment = Environment::with_enclosing(Rc::clone(&self.environment));
+      environment.define(name, element);
+
+      match self.execute_block(ast, &[body], environment) {
+        Ok(()) | Err(Unwind::Continue) => (),
+        Err(Unwind::Break) => return Ok(()),
+        Err(unwind) => return Err(unwind),
+      }
+
+      index += 1;
+    }
+  }
+
+  // case values are evaluated in order until one is equal, the ones after it aren't evaluated at all
+  fn visit_switch(
+    &mut self,
+    ast: &Ast,
+    _: StmtId,
+    value: ExprId,
+    cases: &[Case],
+    default: Option<StmtId>,
+  ) -> ExecResult {
+    let value = self.evaluate(ast, value)?;
+
+    for case in cases {
+      for candidate in &case.values {
+        if self.evaluate(ast, *candidate)? == value {
+          return self.execute(ast, case.body);
+        }
+      }
+    }
+
+    match default {
+      Some(default) => self.execute(ast, default),
+      None => Ok(()),
+    }
+  }
+
+  fn visit_break(&mut self, _: &Ast, _: StmtId) -> ExecResult {
+    Err(Unwind::Break)
+  }
+
+  fn visit_continue(&mut self, _: &Ast, _: StmtId) -> ExecResult {
+    Err(Unwind::Continue)
+  }
+
+  // thrown values travel up as errors so they unwind through calls the same way
+  fn visit_throw(&mut self, ast: &Ast, id: StmtId, value: ExprId) -> ExecResult {
+    let value = self.evaluate(ast, value)?;
+
+    Err(RuntimeError::from(RuntimeErrorKind::Thrown { span: ast[id].span, value }).into())
+  }
+
+  // runtime errors are caught as their message, a return or exit() passes straight through
+  fn visit_try(&mut self, ast: &Ast, _: StmtId, body: StmtId, name: &str, handler: StmtId) -> ExecResult {
+    let exception = match self.execute(ast, body) {
+      Err(Unwind::Error(error)) if error.exit_status().is_none() => match error.kind {
+        RuntimeErrorKind::Thrown { value, .. } => value,
+        kind => Value::String(Rc::from(kind.to_string())),
+      },
+      result => return result,
+    };
+
+    let mut environment = Environment::with_enclosing(Rc::clone(&self.environment));
+    environment.define(name, exception);
+    self.execute_block(ast, &[handler], environment)
+  }
+
+  // each import copies the names it brings in out of the module's top level scope, running
+  // the module first the one time
+  fn visit_import(&mut self, ast: &Ast, id: StmtId, _: &str, names: Option<&[String]>) -> ExecResult {
+    let module = ast.imported(id).expect("the resolver reports imports that were never loaded");
+    let globals = match self.modules.get(&module) {
+      Some(globals) => Rc::clone(globals),
+      None => self.run_module(ast, id, module)?,
+    };
+
+    for name in names.map_or_else(|| ast.exports(module), <[String]>::to_vec) {
+      let value = globals.borrow().get(&name).unwrap_or(Value::Nil);
+      self.environment.borrow_mut().define(&name, value);
+    }
+
+    Ok(())
+  }
+
+  fn visit_function(&mut self, ast: &Ast, id: StmtId, name: &str, _: &[String], _: &[StmtId]) -> ExecResult {
+    let function = Function::new(ast, id, Rc::clone(&self.environment), Rc::clone(&self.globals));
+    self.environment.borrow_mut().define(name, Value::Callable(Rc::new(function)));
+
+    Ok(())
+  }
+
+  fn visit_class(
+    &mut self,
+    ast: &Ast,
+    _: StmtId,
+    name: &str,
+    superclass: Option<ExprId>,
+    methods: &[StmtId],
+  ) -> ExecResult {
+    let superclass = match superclass {
+      Some(superclass) => match self.evaluate(ast, superclass)? {
+        Value::Class(class) => Some(class),
+        _ => {
+          let error = RuntimeError::from(RuntimeErrorKind::SuperclassMustBeClass { span: ast[superclass].span });
+          return Err(error.into());
+        }
+      },
+      None => None,
+    };
+
+    // methods of a subclass close over a scope holding `super`
+    let closure = match &superclass {
+      Some(superclass) => {
+        let mut environment = Environment::with_enclosing(Rc::clone(&self.environment));
+        environment.define("super", Value::Class(Rc::clone(superclass)));
+
+        Rc::new(RefCell::new(environment))
+      }
+      None => Rc::clone(&self.environment),
+    };
+
+    let methods = methods
+      .iter()
+      .map(|method| {
+        let name = match &ast[*method].kind {
+          StmtKind::Function { name, .. } => name.clone(),
+          kind => unreachable!("methods are function declarations, not {:?}", kind),
+        };
+
+        let method = Function::method(ast, *method, Rc::clone(&closure), Rc::clone(&self.globals));
+
+        (name, Rc::new(method))
+      })
+      .collect::<HashMap<_, _>>();
+
+    let class = Class::new(name, superclass, methods);
+    self.environment.borrow_mut().define(name, Value::Class(Rc::new(class)));
+
+    Ok(())
+  }
+
+  fn visit_return(&mut self, ast: &Ast, _: StmtId, value: Option<ExprId>) -> ExecResult {
+    let value = match value {
+      Some(value) => self.evaluate(ast, value)?,
+      None => Value::Nil,
+    };
+
+    Err(Unwind::Return(value))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::lexer::Lexer;
+  use crate::modules::{load_imports, Sources};
+  use crate::parser::Parser;
+  use crate::resolver::Resolver;
+
+  fn evaluate(code: &str) -> EvalResult {
+    let (ast, expr) = Parser::new(Lexer::new(code).lex().unwrap()).parse_expression().unwrap();
+
+    Interpreter::new().evaluate(&ast, expr)
+  }
+
+  fn show(code: &str) -> String {
+    evaluate(code).unwrap().to_string()
+  }
+
+  // collects what print writes so tests can look at it
+  #[derive(Clone, Default)]
+  struct Output(Rc<RefCell<Vec<u8>>>);
+
+  impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+      self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+      Ok(())
+    }
+  }
+
+  fn run(code: &str) -> Result<String, String> {
+    let ast = Parser::new(Lexer::new(code).lex().unwrap()).parse().unwrap();
+    let locals = Resolver::new().resolve(&ast).unwrap();
+    let output = Output::default();
+    let mut interpreter = Interpreter::with_output(Box::new(output.clone()));
+    interpreter.resolve(locals);
+    let result = interpreter.interpret(&ast);
+    let printed = String::from_utf8(output.0.take()).unwrap();
+
+    result.map(|_| printed).map_err(|error| error.to_string())
+  }
+
+  #[test]
+  fn arithmetic() {
+    assert_eq!(show("1 + 2 * 3 - 4 / 2"), "5");
+    assert_eq!(show("-(1.5 + 1)"), "-2.5");
+    assert_eq!(show("7 % 3"), "1");
+    assert_eq!(show("1 / 0"), "inf");
+  }
+
+  #[test]
+  fn remainder_takes_the_sign_of_the_dividend() {
+    assert_eq!(show("-7 % 3"), "-1");
+    assert_eq!(show("7 % -3"), "1");
+    assert_eq!(show("-7 % -3"), "-1");
+    assert_eq!(show("5.5 % 2"), "1.5");
+    assert_eq!(show("1 % 0"), "NaN");
+    assert_eq!(run("print \"7\" % 3;"), Err(String::from("Operands must be numbers.")));
+  }
+
+  #[test]
+  fn comparison_and_equality() {
+    assert_eq!(show("1 < 2 == !(2 <= 1)"), "true");
+    assert_eq!(show("nil == nil"), "true");
+    assert_eq!(show("1 == \"1\""), "false");
+    assert_eq!(show("\"a\" != \"b\""), "true");
+  }
+
+  #[test]
+  fn strings_concatenate() {
+    assert_eq!(show("\"foo\" + \"bar\""), "foobar");
+    assert_eq!(show("\"1 + 1 = ${1 + 1}, ${nil}\""), "1 + 1 = 2, nil");
+  }
+
+  #[test]
+  fn bitwise_operators_use_the_integer_part() {
+    assert_eq!(show("6.9 & 3"), "2");
+    assert_eq!(show("1 << 4 | 1 ^ 3"), "18");
+    assert_eq!(show("~0"), "-1");
+    assert_eq!(show("-16 >> 2"), "-4");
+  }
+
+  #[test]
+  fn bitwise_operators_truncate_fractions_towards_zero() {
+    assert_eq!(show("-2.7 & -1"), "-2");
+    assert_eq!(show("5.9 >> 0.9"), "5");
+    assert_eq!(show("~2.5"), "-3");
+    assert_eq!(show("0.5 | 0.5"), "0");
+    assert_eq!(show("(0 / 0) | 1"), "1");
+    assert_eq!(run("print \"1\" & 1;"), Err(String::from("Operands must be numbers.")));
+    assert_eq!(run("print ~nil;"), Err(String::from("Operand must be a number.")));
+  }
+
+  #[test]
+  fn logical_operators_short_circuit() {
+    assert_eq!(show("nil or \"default\""), "default");
+    assert_eq!(show("false and -nil"), "false");
+    assert_eq!(show("1 and 2"), "2");
+    assert_eq!(show("nil ?? \"default\""), "default");
+    assert_eq!(show("false ?? -nil"), "false");
+    assert_eq!(show("nil ?? nil ?? 0"), "0");
+  }
+
+  #[test]
+  fn type_errors() {
+    let message = |code| evaluate(code).unwrap_err().to_string();
+
+    assert_eq!(message("-\"a\""), "Operand must be a number.");
+    assert_eq!(message("1 < true"), "Operands must be numbers.");
+    assert_eq!(message("1 + \"a\""), "Operands must be two numbers or two strings.");
+    assert_eq!(message("x"), "Undefined variable 'x'.");
+  }
+
+  #[test]
+  fn variables() {
+    let code = "var a = 1; var b; print a; print b; a = b = 2; print a + b;";
+
+    assert_eq!(run(code), Ok(String::from("1\nnil\n4\n")));
+    assert_eq!(run("var a = 1; var a = \"again\"; print a;"), Ok(String::from("again\n")));
+  }
+
+  #[test]
+  fn blocks_shadow_and_assign_through_scopes() {
+    let code = "var a = \"global\"; var b = 1; { var a = \"inner\"; b = 2; print a; } print a; print b;";
+
+    assert_eq!(run(code), Ok(String::from("inner\nglobal\n2\n")));
+  }
+
+  #[test]
+  fn control_flow() {
+    assert_eq!(run("if (nil) print 1; else print 2; if (0) print 3;"), Ok(String::from("2\n3\n")));
+
+    let code = "var i = 0; var sum = 0; while (i < 5) { sum += i; i += 1; } print sum;";
+    assert_eq!(run(code), Ok(String::from("10\n")));
+
+    let code = "var a = 0; var b = 1; for (var i = 0; i < 10; i += 1) { var t = a; a = b; b += t; } print a;";
+    assert_eq!(run(code), Ok(String::from("55\n")));
+  }
+
+  #[test]
+  fn switches() {
+    let code = "fun name(n) { switch (n) { case 1: return \"one\"; case 2, 3: return \"few\"; default: return \"many\"; } }
+      print name(1); print name(3); print name(7);
+      switch (\"b\") { case \"a\": print \"a\"; case \"b\": var x = \"bee\"; print x; case \"b\": print \"again\"; }
+      switch (nil) { default: print \"default\"; case nil: print \"nil\"; } switch (1) { case 2: print 2; }";
+    assert_eq!(run(code), Ok(String::from("one\nfew\nmany\nbee\nnil\n")));
+
+    // the values after the one that matched aren't evaluated, a break or continue is the loop's
+    let code = "fun two() { print \"two\"; return 2; } switch (1) { case 1: print 1; case two(): print 2; }
+      for (var i = 0; i < 5; i += 1) { var a = i; switch (a) { case 1: continue; case 3: var b = a; break; } print a; }";
+    assert_eq!(run(code), Ok(String::from("1\n0\n2\n")));
+  }
+
+  #[test]
+  fn break_and_continue() {
+    let code = "for (var i = 0; i < 6; i += 1) { if (i == 1) continue; if (i == 4) break; print i; }
+      var n = 0; while (true) { n += 1; if (n < 3) continue; break; } print n;
+      for (var x in [1, 2, 3, 4]) { if (x == 2) continue; if (x == 4) break; print x; }";
+    assert_eq!(run(code), Ok(String::from("0\n2\n3\n3\n1\n3\n")));
+
+    // only the innermost loop is left, with the locals declared in it gone
+    let code = "fun f() { var total = 0; for (var i = 0; i < 3; i += 1) { var a = i; for (var j = 0; j < 3; j += 1) {
+      var b = a * 10 + j; if (j == 2) break; { var c = b; if (c % 2 == 1) continue; total += c; } } } var after = total; return after; }
+      print f();";
+    assert_eq!(run(code), Ok(String::from("30\n")));
+
+    // closures keep the variable of the time round they were made in
+    let code = "var fs = []; for (var x in [1, 2, 3]) { fun f() { return x; } push(fs, f); if (x == 2) continue; }
+      var i = 0; while (i < 3) { var j = i; fun g() { return j; } push(fs, g); i += 1; if (j == 1) break; }
+      for (var f in fs) print f();";
+    assert_eq!(run(code), Ok(String::from("1\n2\n3\n0\n1\n")));
+
+    // a try left by break or continue doesn't catch what's thrown after it
+    let code = "for (var i = 0; i < 2; i += 1) { try { if (i == 0) continue; break; } catch (e) { print \"stale\"; } }
+      throw \"after\";";
+    assert_eq!(run(code), Err(String::from("Uncaught exception: after")));
+  }
+
+  #[test]
+  fn conditions_short_circuit() {
+    let code = "var a = 0; if (true or (a = 1)) print a; if (false and (a = 2)) {} print a; if (0 ?? (a = 3)) print a;";
+
+    assert_eq!(run(code), Ok(String::from("0\n0\n0\n")));
+  }
+
+  #[test]
+  fn functions() {
+    let code = "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); } print fib(10);";
+    assert_eq!(run(code), Ok(String::from("55\n")));
+
+    let code = "fun greet(name) { print \"hi ${name}\"; } print greet(\"bob\"); print greet; print clock;";
+    assert_eq!(run(code), Ok(String::from("hi bob\nnil\n<fn greet>\n<native fn>\n")));
+  }
+
+  #[test]
+  fn closures_capture_their_environment() {
+    let code = "
+      fun makeCounter() {
+        var i = 0;
+        fun count() {
+          i += 1;
+          return i;
+        }
+        return count;
+      }
+      var counter = makeCounter();
+      var other = makeCounter();
+      print counter();
+      print counter();
+      print other();
+    ";
+    assert_eq!(run(code), Ok(String::from("1\n2\n1\n")));
+
+    let code = "fun adder(n) { fun add(x) { return x + n; } return add; } print adder(2)(3);";
+    assert_eq!(run(code), Ok(String::from("5\n")));
+  }
+
+  #[test]
+  fn lambdas_are_closures_without_a_name() {
+    let code = "
+      fun apply(f, x) {
+        return f(x);
+      }
+      var n = 10;
+      var add = fun (x) { return x + n; };
+      n = 20;
+      print apply(add, 1);
+      print apply(fun (x) { return x * 2; }, 4);
+      print (fun (a, b) { return a - b; })(5, 3);
+      print add;
+    ";
+    assert_eq!(run(code), Ok(String::from("21\n8\n2\n<fn <lambda>>\n")));
+
+    let code = "fun counter() { var i = 0; return fun () { i += 1; return i; }; } var c = counter(); c(); print c();";
+    assert_eq!(run(code), Ok(String::from("2\n")));
+  }
+
+  #[test]
+  fn closures_see_the_variables_they_were_declared_with() {
+    let code = "
+      var a = \"global\";
+      {
+        fun show() {
+          print a;
+        }
+        show();
+        var a = \"block\";
+        show();
+      }
+    ";
+
+    assert_eq!(run(code), Ok(String::from("global\nglobal\n")));
+  }
+
+  #[test]
+  fn lists() {
+    let code = "var a = [1, \"two\", [3]]; print a; print a[1]; print a[2][0]; a[0] = a[0] + 10; print a[0];
+      print len(a); print a[1:]; print a[:1]; print a[:]; print [];";
+    assert_eq!(run(code), Ok(String::from("[1, two, [3]]\ntwo\n3\n11\n3\n[two, [3]]\n[11]\n[11, two, [3]]\n[]\n")));
+
+    let code = "var a = [3]; push(a, 4); insert(a, 0, 1); insert(a, 1, 2); print a; print pop(a); print remove(a, 0);
+      print a; print split(\"a,b\", \",\"); print type(a); print [1] == [1]; var b = a; print a == b;";
+    assert_eq!(run(code), Ok(String::from("[1, 2, 3, 4]\n4\n1\n[2, 3]\n[a, b]\nlist\nfalse\ntrue\n")));
+
+    // each iteration has a variable of its own for closures to capture
+    let code = "var fs = []; for (var x in [1, 2, 3]) { fun f() { return x; } push(fs, f); }
+      for (var f in fs) print f(); var a = [1]; a[0] = a; print a;";
+    assert_eq!(run(code), Ok(String::from("1\n2\n3\n[[...]]\n")));
+
+    assert_eq!(run("1[0];"), Err(String::from("Only lists and maps can be indexed.")));
+    assert_eq!(run("[1][\"a\"];"), Err(String::from("List index must be a number.")));
+    assert_eq!(run("[1][0.5];"), Err(String::from("List index must be a whole number.")));
+    assert_eq!(run("[1][1] = 2;"), Err(String::from("List index 1 is out of bounds for a list of length 1.")));
+    assert_eq!(run("[1][0:\"a\"];"), Err(String::from("Slice bounds must be numbers.")));
+    assert_eq!(run("[1][:2];"), Err(String::from("Slice range 0..2 is out of bounds for a list of length 1.")));
+    assert_eq!(run("for (var x in \"ab\") {}"), Err(String::from("Can only iterate over lists.")));
+    assert_eq!(run("pop([]);"), Err(String::from("Can't pop from an empty list.")));
+  }
+
+  #[test]
+  fn compound_assignments_evaluate_their_target_once() {
+    let code = "var calls = 0; var a = [1, 2]; var m = {\"k\": 2};
+      fun i() { calls += 1; return 1; } fun k() { calls += 1; return \"k\"; }
+      class P {} var p = P(); p.x = 1; fun getP() { calls += 1; return p; }
+      a[i()] += 5; m[k()] *= 3; getP().x += 1; print a; print m; print p.x; print calls;";
+    assert_eq!(run(code), Ok(String::from("[1, 7]\n{k: 6}\n2\n3\n")));
+
+    assert_eq!(run("class P {} P().x += 1;"), Err(String::from("Undefined property 'x'.")));
+    let out_of_bounds = "List index 1 is out of bounds for a list of length 1.";
+    assert_eq!(run("var a = [1]; a[1] -= 1;"), Err(String::from(out_of_bounds)));
+    assert_eq!(run("var a = [nil]; a[0] += 1;"), Err(String::from("Operands must be two numbers or two strings.")));
+  }
+
+  #[test]
+  fn maps() {
+    let code = "var m = {\"a\": 1, 2: [3]}; print m; print m[\"a\"] + m[2][0]; m[\"b\"] = m; m[\"a\"] = 4; print m;
+      print keys(m)[2]; print values(m)[0]; print has(m, 2) and !has(m, \"c\"); print type(m); print {} == {};";
+    assert_eq!(run(code), Ok(String::from("{a: 1, 2: [3]}\n4\n{a: 4, 2: [3], b: {...}}\nb\n4\ntrue\nmap\nfalse\n")));
+
+    let code = "var m = {1: \"one\", -0: \"zero\"}; print delete(m, 1); print delete(m, 1); m[0] = \"nought\"; print m;";
+    assert_eq!(run(code), Ok(String::from("true\nfalse\n{0: nought}\n")));
+
+    assert_eq!(run("print {}[\"a\"];"), Err(String::from("Undefined key 'a'.")));
+    assert_eq!(run("print {nil: 1};"), Err(String::from("Map keys must be strings or numbers.")));
+    assert_eq!(run("var m = {}; m[true] = 1;"), Err(String::from("Map keys must be strings or numbers.")));
+    assert_eq!(run("has({}, [1]);"), Err(String::from("Map keys must be strings or numbers.")));
+    assert_eq!(run("keys([1]);"), Err(String::from("Argument to keys() must be a map.")));
+    assert_eq!(run("print {}[0:1];"), Err(String::from("Only lists can be sliced.")));
+  }
+
+  #[test]
+  fn exceptions() {
+    let code = "try { throw \"oops\"; print \"skipped\"; } catch (e) { print \"caught \" + e; } print \"after\";";
+    assert_eq!(run(code), Ok(String::from("caught oops\nafter\n")));
+
+    // thrown through calls, and runtime errors caught as their message
+    let code = "fun f(n) { if (n == 0) throw {\"code\": 42}; return f(n - 1); } try { f(3); } catch (e) { print e[\"code\"]; }
+      try { var x = 1 + nil; } catch (e) { print e; } try { [1][5]; } catch (e) { print e; }";
+    assert_eq!(
+      run(code),
+      Ok(String::from(
+        "42\nOperands must be two numbers or two strings.\nList index 5 is out of bounds for a list of length 1.\n"
+      ))
+    );
+
+    let code = "fun g() { try { return \"returned\"; } catch (e) {} } print g();
+      try { try { throw 1; } catch (e) { throw e + 1; } } catch (e) { print e; }
+      var h; try { var local = \"captured\"; fun c() { return local; } h = c; throw nil; } catch (e) { print h(); print e; }
+      var a = 1; { var b = 2; try { var c = 3; throw c; } catch (e) { print a + b + e; } var d = 4; print d + b; }";
+    assert_eq!(run(code), Ok(String::from("returned\n2\ncaptured\nnil\n6\n6\n")));
+
+    assert_eq!(run("throw [1, 2];"), Err(String::from("Uncaught exception: [1, 2]")));
+    // the try g returned out of is over by the time of the second throw
+    let code = "fun g() { try { return 1; } catch (e) { print \"wrong\"; } } g(); throw \"late\";";
+    assert_eq!(run(code), Err(String::from("Uncaught exception: late")));
+  }
+
+  #[test]
+  fn classes() {
+    let code = "
+      class Point {
+        init(x, y) {
+          this.x = x;
+          this.y = y;
+        }
+        sum() {
+          return this.x + this.y;
+        }
+      }
+      var p = Point(1, 2);
+      p.x += 10;
+      print p.sum();
+      print p;
+      print Point;
+      var sum = p.sum;
+      p.y = 0;
+      print sum();
+    ";
+
+    assert_eq!(run(code), Ok(String::from("13\nPoint instance\nPoint\n11\n")));
+  }
+
+  #[test]
+  fn init_returns_the_instance() {
+    let code = "class A { init() { this.a = 1; return; } } var a = A(); print a.init() == a; print a.a;";
+
+    assert_eq!(run(code), Ok(String::from("true\n1\n")));
+  }
+
+  #[test]
+  fn fields_shadow_methods() {
+    let code = "class A { f() { return 1; } } var a = A(); a.f = \"field\"; print a.f;";
+
+    assert_eq!(run(code), Ok(String::from("field\n")));
+  }
+
+  #[test]
+  fn inheritance() {
+    let code = "
+      class Animal {
+        init(name) { this.name = name; }
+        speak() { return this.name + \" makes a sound\"; }
+        kind() { return \"animal\"; }
+      }
+      class Dog < Animal {
+        speak() { return super.speak() + \", woof\"; }
+      }
+      class Puppy < Dog {
+        speak() { return super.speak() + \"!\"; }
+      }
+      var puppy = Puppy(\"rex\");
+      print puppy.speak();
+      print puppy.kind();
+    ";
+
+    assert_eq!(run(code), Ok(String::from("rex makes a sound, woof!\nanimal\n")));
+  }
+
+  #[test]
+  fn inheritance_errors() {
+    assert_eq!(run("var A = 1; class B < A {}"), Err(String::from("Superclass must be a class.")));
+    assert_eq!(
+      run("class A {} class B < A { f() { return super.g; } } B().f();"),
+      Err(String::from("Undefined property 'g'."))
+    );
+  }
+
+  #[test]
+  fn property_errors() {
+    assert_eq!(run("class A {} A().b;"), Err(String::from("Undefined property 'b'.")));
+    assert_eq!(run("var a = 1; a.b;"), Err(String::from("Only instances have properties.")));
+    assert_eq!(run("true.b = 1;"), Err(String::from("Only instances have fields.")));
+    assert_eq!(run("class A { init(a) {} } A();"), Err(String::from("Expected 1 arguments but got 0.")));
+  }
+
+  #[test]
+  fn return_unwinds_through_loops_and_blocks() {
+    let code = "fun find() { var i = 0; while (true) { { if (i == 3) return i; } i += 1; } } print find();";
+
+    assert_eq!(run(code), Ok(String::from("3\n")));
+  }
+
+  #[test]
+  fn call_errors() {
+    assert_eq!(run("\"not a function\"();"), Err(String::from("Can only call functions and classes.")));
+    assert_eq!(run("fun f(a, b) {} f(1);"), Err(String::from("Expected 2 arguments but got 1.")));
+    assert_eq!(run("clock(1);"), Err(String::from("Expected 0 arguments but got 1.")));
+  }
+
+  #[test]
+  fn globals_are_listed_by_name() {
+    let ast = Parser::new(Lexer::new("var b = 2; var a = 1; { var c = 3; }").lex().unwrap()).parse().unwrap();
+    let mut interpreter = Interpreter::with_output(Box::new(io::sink()));
+    interpreter.interpret(&ast).unwrap();
+
+    let globals = interpreter.globals();
+    let names = globals.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>();
+    assert!(names.windows(2).all(|pair| pair[0] < pair[1]));
+    assert!(names.contains(&"a") && names.contains(&"b") && names.contains(&"clock"));
+    assert!(!names.contains(&"c"));
+  }
+
+  #[test]
+  fn natives() {
+    assert_eq!(run("var t = clock(); print t > 0 and t == t;"), Ok(String::from("true\n")));
+
+    // nothing is ever collected on the tree-walker, but scripts reading the counts still run
+    let code = "var s = gcStats(); print keys(s); print s[\"collections\"] + s[\"bytesAllocated\"];";
+    let keys = "[collections, minor, major, bytesAllocated, bytesFreed, longestPause, totalPause]\n0\n";
+    assert_eq!(run(code), Ok(String::from(keys)));
+
+    let code = "var s = \"Hello, World\"; print len(s); print substring(s, 7, len(s)); print indexOf(s, \"o\");
+      print indexOf(s, \"x\"); print toUpper(s); print toLower(s);";
+    assert_eq!(run(code), Ok(String::from("12\nWorld\n4\n-1\nHELLO, WORLD\nhello, world\n")));
+    assert_eq!(run("len(1);"), Err(String::from("Argument to len() must be a string or a list.")));
+
+    let code = "print sqrt(16); print abs(-2.5); print floor(1.7); print ceil(1.2); print pow(2, 10);
+      print min(3, -1); print max(3, -1); print floor(PI * 100); print floor(E * 100);";
+    assert_eq!(run(code), Ok(String::from("4\n2.5\n1\n2\n1024\n-1\n3\n314\n271\n")));
+    assert_eq!(run("sqrt(\"4\");"), Err(String::from("Argument to sqrt() must be a number.")));
+    assert_eq!(run("pow(2, nil);"), Err(String::from("Arguments to pow() must be numbers.")));
+
+    let code = "seedRandom(42); var a = random(); var b = randomInt(1, 6); seedRandom(42);
+      print a == random() and b == randomInt(1, 6); print a >= 0 and a < 1 and b >= 1 and b <= 6 and b == floor(b);";
+    assert_eq!(run(code), Ok(String::from("true\ntrue\n")));
+    let code = "print str(1.5) + str(nil) + str(clock); print num(\" 42 \") + 1; print num(\"4x\");
+      print chr(72) + chr(105); print ord(\"A\");";
+    assert_eq!(run(code), Ok(String::from("1.5nil<native fn>\n43\nnil\nHi\n65\n")));
+    assert_eq!(run("ord(\"ab\");"), Err(String::from("Argument to ord() must be a string of one character.")));
+    assert_eq!(run("chr(-1);"), Err(String::from("Argument to chr() must be a character code.")));
+
+    let code = "class Point { m() {} } fun f() {} var p = Point();
+      print type(1) + type(\"a\") + type(true) + type(nil); print type(f) + type(clock) + type(p.m);
+      print type(Point) + type(p);";
+    assert_eq!(run(code), Ok(String::from("numberstringboolnil\nfunctionfunctionfunction\nclassPoint\n")));
+
+    assert_eq!(run("assert(1 < 2, \"unused\"); print \"ok\";"), Ok(String::from("ok\n")));
+    assert_eq!(run("var x = 2; assert(x == 1, x);"), Err(String::from("Assertion failed: 2")));
+
+    let code = "print format(\"x = {}, y = {:.2}\", 1, 2); print format(\"{1}{0}{{}}\", nil, [1]) + format(\"\");";
+    assert_eq!(run(code), Ok(String::from("x = 1, y = 2.00\n[1]nil{}\n")));
+    assert_eq!(run("format(1);"), Err(String::from("First argument to format() must be a string.")));
+    assert_eq!(run("format();"), Err(String::from("First argument to format() must be a string.")));
+    assert_eq!(run("format(\"{} {}\", 1);"), Err(String::from("Format string needs at least 2 arguments but got 1.")));
+    assert_eq!(run("format(\"{:.1}\", \"a\");"), Err(String::from("Only numbers can be formatted with a precision.")));
+
+    let code = "var data = jsonParse(\"{\\\"a\\\": [1, true, null], \\\"b\\\": {\\\"c\\\": \\\"d\\\"}}\"); print data[\"a\"][0] + 1;
+      print data[\"b\"][\"c\"]; print data; print jsonStringify(data); print jsonStringify({1: [], \"x\": 0.5}, true);";
+    let printed = "2\nd\n{a: [1, true, nil], b: {c: d}}\n{\"a\":[1,true,null],\"b\":{\"c\":\"d\"}}\n{\n  \"1\": [],\n  \"x\": 0.5\n}\n";
+    assert_eq!(run(code), Ok(String::from(printed)));
+    assert_eq!(run("jsonParse(\"[1,\");"), Err(String::from("Invalid JSON: expected a value at the end.")));
+    assert_eq!(run("var l = [1]; push(l, l); jsonStringify(l);"), Err(String::from("Can't convert a list or map that contains itself to JSON.")));
+    assert_eq!(run("jsonStringify(clock);"), Err(String::from("Only nil, bools, numbers, strings, lists and maps can be converted to JSON.")));
+    assert_eq!(run("jsonStringify(1, 2);"), Err(String::from("Second argument to jsonStringify() must be a bool.")));
+    assert_eq!(run("jsonStringify();"), Err(String::from("Expected 1 or 2 arguments but got 0.")));
+    assert_eq!(run("jsonStringify([1, 1/0]);"), Err(String::from("Can't convert infinity or NaN to JSON.")));
+    assert_eq!(run("jsonStringify(0/0);"), Err(String::from("Can't convert infinity or NaN to JSON.")));
+
+    let nested = |depth| {
+      format!("var l = []; for (var i = 1; i < {}; i += 1) l = [l]; print len(jsonStringify(l));", depth)
+    };
+    assert_eq!(run(&nested(512)), Ok(String::from("1024\n")));
+    let too_deep = "Can't convert lists and maps nested more than 512 deep to JSON.";
+    assert_eq!(run(&nested(513)), Err(String::from(too_deep)));
+
+    crate::system::set_args(vec![String::from("-v"), String::from("in.txt")]);
+    let code = "print args(); print len(args()[1]); print getEnv(\"RSLOX_NOT_A_VARIABLE\"); print type(platform());";
+    assert_eq!(run(code), Ok(String::from("[-v, in.txt]\n6\nnil\nstring\n")));
+    assert_eq!(run("getEnv(1);"), Err(String::from("Argument to getEnv() must be a string.")));
+
+    // exit() goes past the try it's called in, an invalid status is an error like any other
+    let code = "fun f() { try { exit(3); } catch (e) { print \"caught\"; } } f(); print \"after\";";
+    assert_eq!(run(code), Err(String::from("Exited with status 3.")));
+    let code = "try { exit(-1); } catch (e) { print e; }";
+    assert_eq!(run(code), Ok(String::from("Argument to exit() must be a whole number from 0 to 255.\n")));
+
+    assert_eq!(
+      run("randomInt(2, 1);"),
+      Err(String::from("Arguments to randomInt() must be whole numbers, the first no greater than the second."))
+    );
+    assert_eq!(
+      run("substring(\"abc\", 2, 1);"),
+      Err(String::from("Substring range 2..1 is out of bounds for a string of length 3."))
+    );
+  }
+
+  #[test]
+  fn math_natives() {
+    let code = "print floor(1.7); print floor(-1.5); print ceil(1.2); print ceil(-1.5); print sqrt(2.25);
+      print sqrt(-1); print pow(2, 10); print pow(2, -1); print pow(9, 0.5); print abs(-2.5); print abs(3);
+      print min(3, -1); print max(3, -1); print min(2, 2);";
+    let printed = "1\n-2\n2\n-1\n1.5\nNaN\n1024\n0.5\n3\n2.5\n3\n-1\n3\n2\n";
+    assert_eq!(run(code), Ok(String::from(printed)));
+
+    for name in ["floor", "ceil", "sqrt", "abs"] {
+      let error = format!("Argument to {}() must be a number.", name);
+      assert_eq!(run(&format!("{}(\"1\");", name)), Err(error.clone()));
+      assert_eq!(run(&format!("{}(nil);", name)), Err(error));
+      assert_eq!(run(&format!("{}();", name)), Err(String::from("Expected 1 arguments but got 0.")));
+      assert_eq!(run(&format!("{}(1, 2);", name)), Err(String::from("Expected 1 arguments but got 2.")));
+    }
+
+    for name in ["pow", "min", "max"] {
+      let error = format!("Arguments to {}() must be numbers.", name);
+      assert_eq!(run(&format!("{}(\"1\", 2);", name)), Err(error.clone()));
+      assert_eq!(run(&format!("{}(1, true);", name)), Err(error));
+      assert_eq!(run(&format!("{}(1);", name)), Err(String::from("Expected 2 arguments but got 1.")));
+      assert_eq!(run(&format!("{}(1, 2, 3);", name)), Err(String::from("Expected 2 arguments but got 3.")));
+    }
+  }
+
+  #[test]
+  fn type_native() {
+    let code = "print type(1.5); print type(\"a\"); print type(\"\"); print type(false); print type(nil);
+      print type([1]); print type({\"a\": 1});";
+    assert_eq!(run(code), Ok(String::from("number\nstring\nstring\nbool\nnil\nlist\nmap\n")));
+
+    // closures, natives, lambdas and bound methods are all functions
+    let code = "fun f() { var x = 1; fun g() { return x; } return g; } class A { m() {} }
+      print type(f); print type(f()); print type(clock); print type(fun () {}); print type(A().m);";
+    assert_eq!(run(code), Ok(String::from("function\nfunction\nfunction\nfunction\nfunction\n")));
+
+    // an instance is named after its own class, not the one it inherits from
+    let code = "class A {} class B < A {} print type(A); print type(B); print type(A()); print type(B());
+      print type(type(A()));";
+    assert_eq!(run(code), Ok(String::from("class\nclass\nA\nB\nstring\n")));
+    assert_eq!(run("type();"), Err(String::from("Expected 1 arguments but got 0.")));
+  }
+
+  #[test]
+  fn assert_native() {
+    let code = "assert(true, \"unused\"); assert(1, nil); assert(\"\", 0); assert([], 0); print \"passed\";";
+    assert_eq!(run(code), Ok(String::from("passed\n")));
+
+    // the message is shown the way print would show it, whatever kind of value it is
+    let code = "print \"before\"; assert(1 + 1 == 3, \"math is broken\"); print \"after\";";
+    assert_eq!(run(code), Err(String::from("Assertion failed: math is broken")));
+    assert_eq!(run("assert(nil, [1, \"a\"]);"), Err(String::from("Assertion failed: [1, a]")));
+    assert_eq!(run("assert(false, nil);"), Err(String::from("Assertion failed: nil")));
+
+    // the message can't be left out
+    assert_eq!(run("assert(false);"), Err(String::from("Expected 2 arguments but got 1.")));
+    assert_eq!(run("assert(true);"), Err(String::from("Expected 2 arguments but got 1.")));
+  }
+
+  #[cfg(feature = "file-io")]
+  #[test]
+  fn file_natives() {
+    let path = std::env::temp_dir().join(format!("rslox-interpreter-{}.txt", std::process::id()));
+    let path = path.to_str().unwrap();
+    let code = format!("writeFile(\"{0}\", \"a\"); appendFile(\"{0}\", \"b\"); print readFile(\"{0}\");", path);
+
+    assert_eq!(run(&code), Ok(String::from("ab\n")));
+    std::fs::remove_file(path).unwrap();
+    assert_eq!(run("writeFile(\"a\", 1);"), Err(String::from("Arguments to writeFile() must be two strings.")));
+  }
+
+  // a native has no frame of its own, its errors point at the call
+  #[test]
+  fn native_errors_point_at_the_call() {
+    let code = "fun shout(s) {\n  return toUpper(s);\n}\nshout(1);";
+    let ast = Parser::new(Lexer::new(code).lex().unwrap()).parse().unwrap();
+    let error = Interpreter::with_output(Box::new(io::sink())).interpret(&ast).unwrap_err();
+
+    assert_eq!(
+      error.stack_trace(&crate::lexer::SourceMap::new(code)),
+      vec!["[line 2] in shout()", "[line 4] in script"]
+    );
+  }
+
+  #[test]
+  fn undefined_variables() {
+    assert_eq!(run("print a;"), Err(String::from("Undefined variable 'a'.")));
+    assert_eq!(run("{ var a = 1; } a = 2;"), Err(String::from("Undefined variable 'a'.")));
+  }
+
+  #[test]
+  fn errors_carry_the_calls_they_happened_in() {
+    let code = "fun fail(n) {\n  return n + nil;\n}\nfun outer() {\n  fail(1);\n}\nouter();";
+    let ast = Parser::new(Lexer::new(code).lex().unwrap()).parse().unwrap();
+    let error = Interpreter::with_output(Box::new(io::sink())).interpret(&ast).unwrap_err();
+
+    assert_eq!(
+      error.stack_trace(&crate::lexer::SourceMap::new(code)),
+      vec!["[line 2] in fail()", "[line 5] in outer()", "[line 7] in script"]
+    );
+  }
+
+  #[test]
+  fn undefined_variable_errors_point_at_the_name() {
+    let ast = Parser::new(Lexer::new("var a;\nprint a + b;").lex().unwrap()).parse().unwrap();
+    let error = Interpreter::with_output(Box::new(io::sink())).interpret(&ast).unwrap_err();
+
+    assert_eq!(error.span(), crate::lexer::Span::new(17, 18));
+  }
+
+  // the program and the files it imports from a directory of their own
+  fn load(directory: &std::path::Path, files: &[(&str, &str)], code: &str) -> (Ast, Sources) {
+    std::fs::create_dir_all(directory).unwrap();
+
+    for (name, code) in files {
+      std::fs::write(directory.join(name), code).unwrap();
+    }
+
+    let mut sources = Sources::new(directory.join("main.lox").to_str().unwrap(), code);
+    let mut ast = Parser::new(Lexer::new(code).lex().unwrap()).parse().unwrap();
+    load_imports(&mut ast, &mut sources).unwrap();
+
+    (ast, sources)
+  }
+
+  #[test]
+  fn imported_files_run_once_with_globals_of_their_own() {
+    let directory = std::env::temp_dir().join(format!("rslox-interpreter-imports-{}", std::process::id()));
+    let counter = "var count = 0;\nfun bump() { count = count + 1; return count; }\nprint \"loaded\";";
+    let code = "import \"counter.lox\";\nimport bump from \"counter.lox\";\nvar count = 10;\nprint bump();\nprint bump();\nprint count;";
+    let (ast, _) = load(&directory, &[("counter.lox", counter)], code);
+
+    let locals = Resolver::new().resolve(&ast).unwrap();
+    let output = Output::default();
+    let mut interpreter = Interpreter::with_output(Box::new(output.clone()));
+    interpreter.resolve(locals);
+    interpreter.interpret(&ast).unwrap();
+
+    assert_eq!(String::from_utf8(output.0.take()).unwrap(), "loaded\n1\n2\n10\n");
+    std::fs::remove_dir_all(directory).unwrap();
+  }
+
+  #[test]
+  fn errors_in_imported_files_name_the_file() {
+    let directory = std::env::temp_dir().join(format!("rslox-interpreter-import-errors-{}", std::process::id()));
+    let broken = "fun fail() {\n  return -nil;\n}\nfail();";
+    let (ast, sources) = load(&directory, &[("broken.lox", broken)], "print 1;\nimport \"broken.lox\";");
+    let error = Interpreter::with_output(Box::new(io::sink())).interpret(&ast).unwrap_err();
+
+    assert_eq!(
+      error.stack_trace(&sources.source_map()),
+      vec![
+        String::from("[line 2] in fail()"),
+        format!("[line 4] in <{}>", directory.join("broken.lox").display()),
+        String::from("[line 2] in script"),
+      ]
+    );
+    std::fs::remove_dir_all(directory).unwrap();
+  }
+}
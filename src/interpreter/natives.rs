@@ -0,0 +1,688 @@
+use std::cell::RefCell;
+use std::f64::consts;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{Environment, Map, NativeFunction, Value};
+use crate::console;
+#[cfg(feature = "file-io")]
+use crate::files;
+use crate::json::{self, Json};
+use crate::lists;
+use crate::maps::{self, Key};
+use crate::random;
+use crate::strings::{self, Piece};
+use crate::system::{self, NativeError};
+use crate::vm::GcStats;
+
+fn clock(_: &[Value]) -> Result<Value, NativeError> {
+  let elapsed = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+
+  Ok(Value::Number(elapsed.as_secs_f64()))
+}
+
+// the same map as on the vm, all zeros. Values here are reference counted, nothing ever collects
+fn gc_stats(_: &[Value]) -> Result<Value, NativeError> {
+  let mut map = Map::new();
+
+  for (name, value) in GcStats::default().fields() {
+    map.set(Key::String(Rc::from(name)), Value::Number(value));
+  }
+
+  Ok(Value::Map(Rc::new(RefCell::new(map))))
+}
+
+fn len(arguments: &[Value]) -> Result<Value, NativeError> {
+  match arguments {
+    [Value::String(text)] => Ok(Value::Number(strings::len(text) as f64)),
+    [Value::List(list)] => Ok(Value::Number(list.borrow().len() as f64)),
+    _ => Err(String::from("Argument to len() must be a string or a list.").into()),
+  }
+}
+
+fn substring(arguments: &[Value]) -> Result<Value, NativeError> {
+  match arguments {
+    [Value::String(text), Value::Number(start), Value::Number(end)] => {
+      Ok(Value::String(Rc::from(strings::substring(text, *start, *end)?)))
+    }
+    _ => Err(String::from("Arguments to substring() must be a string and two numbers.").into()),
+  }
+}
+
+fn index_of(arguments: &[Value]) -> Result<Value, NativeError> {
+  match arguments {
+    [Value::String(text), Value::String(needle)] => Ok(Value::Number(strings::index_of(text, needle))),
+    _ => Err(String::from("Arguments to indexOf() must be two strings.").into()),
+  }
+}
+
+fn to_upper(arguments: &[Value]) -> Result<Value, NativeError> {
+  match arguments {
+    [Value::String(text)] => Ok(Value::String(Rc::from(text.to_uppercase()))),
+    _ => Err(String::from("Argument to toUpper() must be a string.").into()),
+  }
+}
+
+fn to_lower(arguments: &[Value]) -> Result<Value, NativeError> {
+  match arguments {
+    [Value::String(text)] => Ok(Value::String(Rc::from(text.to_lowercase()))),
+    _ => Err(String::from("Argument to toLower() must be a string.").into()),
+  }
+}
+
+// the list handed back is a new one
+fn split(arguments: &[Value]) -> Result<Value, NativeError> {
+  match arguments {
+    [Value::String(text), Value::String(separator)] => {
+      let pieces = strings::split(text, separator).into_iter().map(|piece| Value::String(Rc::from(piece)));
+
+      Ok(Value::List(Rc::new(RefCell::new(pieces.collect()))))
+    }
+    _ => Err(String::from("Arguments to split() must be two strings.").into()),
+  }
+}
+
+fn push(arguments: &[Value]) -> Result<Value, NativeError> {
+  match arguments {
+    [Value::List(list), value] => {
+      list.borrow_mut().push(value.clone());
+      Ok(Value::Nil)
+    }
+    _ => Err(String::from("First argument to push() must be a list.").into()),
+  }
+}
+
+fn pop(arguments: &[Value]) -> Result<Value, NativeError> {
+  match arguments {
+    [Value::List(list)] => lists::pop(&mut list.borrow_mut()).map_err(NativeError::from),
+    _ => Err(String::from("Argument to pop() must be a list.").into()),
+  }
+}
+
+fn insert(arguments: &[Value]) -> Result<Value, NativeError> {
+  match arguments {
+    [Value::List(list), Value::Number(index), value] => {
+      lists::insert(&mut list.borrow_mut(), *index, value.clone()).map(|_| Value::Nil).map_err(NativeError::from)
+    }
+    _ => Err(String::from("Arguments to insert() must be a list, a number and a value.").into()),
+  }
+}
+
+fn remove(arguments: &[Value]) -> Result<Value, NativeError> {
+  match arguments {
+    [Value::List(list), Value::Number(index)] => {
+      lists::remove(&mut list.borrow_mut(), *index).map_err(NativeError::from)
+    }
+    _ => Err(String::from("Arguments to remove() must be a list and a number.").into()),
+  }
+}
+
+// in the order they were first set
+fn keys(arguments: &[Value]) -> Result<Value, NativeError> {
+  match arguments {
+    [Value::Map(map)] => {
+      let keys = map.borrow().entries().iter().map(|(key, _)| Value::from_key(key)).collect();
+      Ok(Value::List(Rc::new(RefCell::new(keys))))
+    }
+    _ => Err(String::from("Argument to keys() must be a map.").into()),
+  }
+}
+
+fn values(arguments: &[Value]) -> Result<Value, NativeError> {
+  match arguments {
+    [Value::Map(map)] => {
+      let values = map.borrow().entries().iter().map(|(_, value)| value.clone()).collect();
+      Ok(Value::List(Rc::new(RefCell::new(values))))
+    }
+    _ => Err(String::from("Argument to values() must be a map.").into()),
+  }
+}
+
+fn has(arguments: &[Value]) -> Result<Value, NativeError> {
+  match arguments {
+    [Value::Map(map), key] => Ok(Value::Bool(map.borrow().contains(&key.to_key().ok_or_else(maps::invalid_key)?))),
+    _ => Err(String::from("First argument to has() must be a map.").into()),
+  }
+}
+
+// whether the key was there to delete
+fn delete(arguments: &[Value]) -> Result<Value, NativeError> {
+  match arguments {
+    [Value::Map(map), key] => {
+      let removed = map.borrow_mut().remove(&key.to_key().ok_or_else(maps::invalid_key)?);
+      Ok(Value::Bool(removed.is_some()))
+    }
+    _ => Err(String::from("First argument to delete() must be a map.").into()),
+  }
+}
+
+// the one number argument of a math native, name is for the message when it isn't one
+fn number(arguments: &[Value], name: &str) -> Result<f64, String> {
+  match arguments {
+    [Value::Number(value)] => Ok(*value),
+    _ => Err(format!("Argument to {}() must be a number.", name)),
+  }
+}
+
+fn numbers(arguments: &[Value], name: &str) -> Result<(f64, f64), String> {
+  match arguments {
+    [Value::Number(left), Value::Number(right)] => Ok((*left, *right)),
+    _ => Err(format!("Arguments to {}() must be numbers.", name)),
+  }
+}
+
+fn sqrt(arguments: &[Value]) -> Result<Value, NativeError> {
+  Ok(Value::Number(number(arguments, "sqrt")?.sqrt()))
+}
+
+fn abs(arguments: &[Value]) -> Result<Value, NativeError> {
+  Ok(Value::Number(number(arguments, "abs")?.abs()))
+}
+
+fn floor(arguments: &[Value]) -> Result<Value, NativeError> {
+  Ok(Value::Number(number(arguments, "floor")?.floor()))
+}
+
+fn ceil(arguments: &[Value]) -> Result<Value, NativeError> {
+  Ok(Value::Number(number(arguments, "ceil")?.ceil()))
+}
+
+fn pow(arguments: &[Value]) -> Result<Value, NativeError> {
+  let (base, exponent) = numbers(arguments, "pow")?;
+
+  Ok(Value::Number(base.powf(exponent)))
+}
+
+fn min(arguments: &[Value]) -> Result<Value, NativeError> {
+  let (left, right) = numbers(arguments, "min")?;
+
+  Ok(Value::Number(left.min(right)))
+}
+
+fn max(arguments: &[Value]) -> Result<Value, NativeError> {
+  let (left, right) = numbers(arguments, "max")?;
+
+  Ok(Value::Number(left.max(right)))
+}
+
+fn random(_: &[Value]) -> Result<Value, NativeError> {
+  Ok(Value::Number(random::random()))
+}
+
+fn random_int(arguments: &[Value]) -> Result<Value, NativeError> {
+  let (low, high) = numbers(arguments, "randomInt")?;
+
+  Ok(Value::Number(random::random_int(low, high)?))
+}
+
+fn seed_random(arguments: &[Value]) -> Result<Value, NativeError> {
+  random::seed(number(arguments, "seedRandom")?);
+
+  Ok(Value::Nil)
+}
+
+// nil once stdin has run out
+fn read_line(_: &[Value]) -> Result<Value, NativeError> {
+  Ok(console::read_line().map_or(Value::Nil, |line| Value::String(Rc::from(line))))
+}
+
+// nil when the line isn't a number either
+fn read_number(_: &[Value]) -> Result<Value, NativeError> {
+  let number = console::read_line().and_then(|line| strings::parse_number(&line));
+
+  Ok(number.map_or(Value::Nil, Value::Number))
+}
+
+// shown the way print would show it
+fn str(arguments: &[Value]) -> Result<Value, NativeError> {
+  Ok(Value::String(Rc::from(arguments[0].to_string())))
+}
+
+// nil when the string isn't a number spelled the way a Lox literal is
+fn num(arguments: &[Value]) -> Result<Value, NativeError> {
+  match arguments {
+    [Value::String(text)] => Ok(strings::parse_number(text).map_or(Value::Nil, Value::Number)),
+    _ => Err(String::from("Argument to num() must be a string.").into()),
+  }
+}
+
+fn chr(arguments: &[Value]) -> Result<Value, NativeError> {
+  match strings::chr(number(arguments, "chr")?) {
+    Some(char) => Ok(Value::String(Rc::from(char.to_string()))),
+    None => Err(String::from("Argument to chr() must be a character code.").into()),
+  }
+}
+
+fn ord(arguments: &[Value]) -> Result<Value, NativeError> {
+  match arguments {
+    [Value::String(text)] => match strings::ord(text) {
+      Some(code) => Ok(Value::Number(code as f64)),
+      None => Err(String::from("Argument to ord() must be a string of one character.").into()),
+    },
+    _ => Err(String::from("Argument to ord() must be a string of one character.").into()),
+  }
+}
+
+// what kind of value it is, instances give the name of their class
+fn type_of(arguments: &[Value]) -> Result<Value, NativeError> {
+  let name = match &arguments[0] {
+    Value::Number(_) => "number",
+    Value::String(_) => "string",
+    Value::Bool(_) => "bool",
+    Value::Nil => "nil",
+    Value::Callable(_) => "function",
+    Value::Class(_) => "class",
+    Value::Instance(instance) => instance.class_name(),
+    Value::List(_) => "list",
+    Value::Map(_) => "map",
+  };
+
+  Ok(Value::String(Rc::from(name)))
+}
+
+// the arguments after the template fill in its placeholders the way print would show them,
+// or with a fixed number of decimals
+fn format(arguments: &[Value]) -> Result<Value, NativeError> {
+  let (template, arguments) = match arguments {
+    [Value::String(template), arguments @ ..] => (template, arguments),
+    _ => return Err(String::from("First argument to format() must be a string.").into()),
+  };
+
+  let mut text = String::new();
+
+  for piece in strings::parse_template(template, arguments.len())? {
+    match piece {
+      Piece::Text(piece) => text.push_str(&piece),
+      Piece::Argument { index, precision: None } => text.push_str(&arguments[index].to_string()),
+      Piece::Argument { index, precision: Some(precision) } => match &arguments[index] {
+        Value::Number(value) => text.push_str(&strings::fixed(*value, precision)),
+        _ => return Err(strings::precision_needs_a_number().into()),
+      },
+    }
+  }
+
+  Ok(Value::String(Rc::from(text)))
+}
+
+// objects become maps and arrays lists, null is nil
+fn json_parse(arguments: &[Value]) -> Result<Value, NativeError> {
+  match arguments {
+    [Value::String(text)] => Ok(from_json(Json::parse(text)?)),
+    _ => Err(String::from("Argument to jsonParse() must be a string.").into()),
+  }
+}
+
+fn from_json(json: Json) -> Value {
+  match json {
+    Json::Null => Value::Nil,
+    Json::Bool(value) => Value::Bool(value),
+    Json::Number(value) => Value::Number(value),
+    Json::String(value) => Value::String(Rc::from(value)),
+    Json::Array(items) => Value::List(Rc::new(RefCell::new(items.into_iter().map(from_json).collect()))),
+    Json::Object(fields) => {
+      let mut map = Map::new();
+
+      for (key, value) in fields {
+        map.set(Key::String(Rc::from(key)), from_json(value));
+      }
+
+      Value::Map(Rc::new(RefCell::new(map)))
+    }
+  }
+}
+
+// on one line, or indented over several when pretty is true
+fn json_stringify(arguments: &[Value]) -> Result<Value, NativeError> {
+  let (value, pretty) = match arguments {
+    [value] => (value, false),
+    [value, Value::Bool(pretty)] => (value, *pretty),
+    [_, _] => return Err(String::from("Second argument to jsonStringify() must be a bool.").into()),
+    _ => return Err(format!("Expected 1 or 2 arguments but got {}.", arguments.len()).into()),
+  };
+
+  let json = to_json(value, &mut vec![])?;
+
+  Ok(Value::String(Rc::from(if pretty { json.pretty() } else { json.to_string() })))
+}
+
+// number keys are written the way print shows them, as JSON's keys are strings. open holds the
+// lists and maps the value is inside of
+fn to_json(value: &Value, open: &mut Vec<*const ()>) -> Result<Json, String> {
+  let pointer = match value {
+    Value::List(list) => Rc::as_ptr(list) as *const (),
+    Value::Map(map) => Rc::as_ptr(map) as *const (),
+    Value::Nil => return Ok(Json::Null),
+    Value::Bool(value) => return Ok(Json::Bool(*value)),
+    Value::Number(value) if !value.is_finite() => return Err(json::not_finite()),
+    Value::Number(value) => return Ok(Json::Number(*value)),
+    Value::String(value) => return Ok(Json::String(value.to_string())),
+    _ => return Err(json::unsupported()),
+  };
+
+  if open.contains(&pointer) {
+    return Err(json::contains_itself());
+  }
+
+  if open.len() == json::MAX_DEPTH {
+    return Err(json::nested_too_deeply());
+  }
+
+  open.push(pointer);
+
+  let json = match value {
+    Value::List(list) => Json::Array(list.borrow().iter().map(|element| to_json(element, open)).collect::<Result<_, _>>()?),
+    Value::Map(map) => Json::Object(
+      map
+        .borrow()
+        .entries()
+        .iter()
+        .map(|(key, value)| Ok((Value::from_key(key).to_string(), to_json(value, open)?)))
+        .collect::<Result<_, String>>()?,
+    ),
+    _ => unreachable!("only lists and maps can contain themselves"),
+  };
+
+  open.pop();
+
+  Ok(json)
+}
+
+// nil when the variable isn't set
+fn get_env(arguments: &[Value]) -> Result<Value, NativeError> {
+  match arguments {
+    [Value::String(name)] => Ok(system::get_env(name).map_or(Value::Nil, |value| Value::String(Rc::from(value)))),
+    _ => Err(String::from("Argument to getEnv() must be a string.").into()),
+  }
+}
+
+// the arguments after `--` on the command line, in a new list every call
+fn args(_: &[Value]) -> Result<Value, NativeError> {
+  let args = system::args().into_iter().map(|arg| Value::String(Rc::from(arg)));
+
+  Ok(Value::List(Rc::new(RefCell::new(args.collect()))))
+}
+
+fn platform(_: &[Value]) -> Result<Value, NativeError> {
+  Ok(Value::String(Rc::from(system::platform())))
+}
+
+// always an error, the call stops the script with the status when it's a valid one
+fn exit(arguments: &[Value]) -> Result<Value, NativeError> {
+  match arguments {
+    [Value::Number(status)] => Err(system::exit(*status)),
+    _ => Err(system::invalid_status().into()),
+  }
+}
+
+// a falsey condition fails with the message, whatever kind of value it is
+fn assert(arguments: &[Value]) -> Result<Value, NativeError> {
+  match arguments[0].is_truthy() {
+    true => Ok(Value::Nil),
+    false => Err(format!("Assertion failed: {}", arguments[1]).into()),
+  }
+}
+
+#[cfg(feature = "file-io")]
+fn read_file(arguments: &[Value]) -> Result<Value, NativeError> {
+  match arguments {
+    [Value::String(path)] => Ok(Value::String(Rc::from(files::read(path)?))),
+    _ => Err(String::from("Argument to readFile() must be a string.").into()),
+  }
+}
+
+#[cfg(feature = "file-io")]
+fn write_file(arguments: &[Value]) -> Result<Value, NativeError> {
+  match arguments {
+    [Value::String(path), Value::String(contents)] => {
+      files::write(path, contents).map(|_| Value::Nil).map_err(NativeError::from)
+    }
+    _ => Err(String::from("Arguments to writeFile() must be two strings.").into()),
+  }
+}
+
+#[cfg(feature = "file-io")]
+fn append_file(arguments: &[Value]) -> Result<Value, NativeError> {
+  match arguments {
+    [Value::String(path), Value::String(contents)] => {
+      files::append(path, contents).map(|_| Value::Nil).map_err(NativeError::from)
+    }
+    _ => Err(String::from("Arguments to appendFile() must be two strings.").into()),
+  }
+}
+
+// the natives and constants every program starts out with
+pub fn define_natives(globals: &RefCell<Environment>) {
+  let natives = [
+    NativeFunction {
+      name: "clock",
+      arity: 0,
+      function: clock,
+    },
+    NativeFunction {
+      name: "gcStats",
+      arity: 0,
+      function: gc_stats,
+    },
+    NativeFunction {
+      name: "len",
+      arity: 1,
+      function: len,
+    },
+    NativeFunction {
+      name: "substring",
+      arity: 3,
+      function: substring,
+    },
+    NativeFunction {
+      name: "indexOf",
+      arity: 2,
+      function: index_of,
+    },
+    NativeFunction {
+      name: "toUpper",
+      arity: 1,
+      function: to_upper,
+    },
+    NativeFunction {
+      name: "toLower",
+      arity: 1,
+      function: to_lower,
+    },
+    NativeFunction {
+      name: "split",
+      arity: 2,
+      function: split,
+    },
+    NativeFunction {
+      name: "push",
+      arity: 2,
+      function: push,
+    },
+    NativeFunction {
+      name: "pop",
+      arity: 1,
+      function: pop,
+    },
+    NativeFunction {
+      name: "insert",
+      arity: 3,
+      function: insert,
+    },
+    NativeFunction {
+      name: "remove",
+      arity: 2,
+      function: remove,
+    },
+    NativeFunction {
+      name: "keys",
+      arity: 1,
+      function: keys,
+    },
+    NativeFunction {
+      name: "values",
+      arity: 1,
+      function: values,
+    },
+    NativeFunction {
+      name: "has",
+      arity: 2,
+      function: has,
+    },
+    NativeFunction {
+      name: "delete",
+      arity: 2,
+      function: delete,
+    },
+    NativeFunction {
+      name: "sqrt",
+      arity: 1,
+      function: sqrt,
+    },
+    NativeFunction {
+      name: "abs",
+      arity: 1,
+      function: abs,
+    },
+    NativeFunction {
+      name: "floor",
+      arity: 1,
+      function: floor,
+    },
+    NativeFunction {
+      name: "ceil",
+      arity: 1,
+      function: ceil,
+    },
+    NativeFunction {
+      name: "pow",
+      arity: 2,
+      function: pow,
+    },
+    NativeFunction {
+      name: "min",
+      arity: 2,
+      function: min,
+    },
+    NativeFunction {
+      name: "max",
+      arity: 2,
+      function: max,
+    },
+    NativeFunction {
+      name: "random",
+      arity: 0,
+      function: random,
+    },
+    NativeFunction {
+      name: "randomInt",
+      arity: 2,
+      function: random_int,
+    },
+    NativeFunction {
+      name: "seedRandom",
+      arity: 1,
+      function: seed_random,
+    },
+    NativeFunction {
+      name: "readLine",
+      arity: 0,
+      function: read_line,
+    },
+    NativeFunction {
+      name: "readNumber",
+      arity: 0,
+      function: read_number,
+    },
+    NativeFunction {
+      name: "str",
+      arity: 1,
+      function: str,
+    },
+    NativeFunction {
+      name: "num",
+      arity: 1,
+      function: num,
+    },
+    NativeFunction {
+      name: "chr",
+      arity: 1,
+      function: chr,
+    },
+    NativeFunction {
+      name: "ord",
+      arity: 1,
+      function: ord,
+    },
+    NativeFunction {
+      name: "type",
+      arity: 1,
+      function: type_of,
+    },
+    NativeFunction {
+      name: "format",
+      arity: NativeFunction::VARIADIC,
+      function: format,
+    },
+    NativeFunction {
+      name: "jsonParse",
+      arity: 1,
+      function: json_parse,
+    },
+    NativeFunction {
+      name: "jsonStringify",
+      arity: NativeFunction::VARIADIC,
+      function: json_stringify,
+    },
+    NativeFunction {
+      name: "getEnv",
+      arity: 1,
+      function: get_env,
+    },
+    NativeFunction {
+      name: "args",
+      arity: 0,
+      function: args,
+    },
+    NativeFunction {
+      name: "platform",
+      arity: 0,
+      function: platform,
+    },
+    NativeFunction {
+      name: "exit",
+      arity: 1,
+      function: exit,
+    },
+    NativeFunction {
+      name: "assert",
+      arity: 2,
+      function: assert,
+    },
+    #[cfg(feature = "file-io")]
+    NativeFunction {
+      name: "readFile",
+      arity: 1,
+      function: read_file,
+    },
+    #[cfg(feature = "file-io")]
+    NativeFunction {
+      name: "writeFile",
+      arity: 2,
+      function: write_file,
+    },
+    #[cfg(feature = "file-io")]
+    NativeFunction {
+      name: "appendFile",
+      arity: 2,
+      function: append_file,
+    },
+  ];
+
+  for native in natives {
+    globals.borrow_mut().define(native.name, Value::Callable(Rc::new(native)));
+  }
+
+  for (name, value) in [("PI", consts::PI), ("E", consts::E)] {
+    globals.borrow_mut().define(name, Value::Number(value));
+  }
+}
@@ -0,0 +1,124 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use super::{Callable, Class, Instance};
+use crate::maps::{Key, Table};
+
+pub type MapKey = Key<Rc<str>>;
+pub type Map = Table<MapKey, Value>;
+
+// strings are shared, copying a value around never copies the text. Lists and maps are shared
+// too, a change made through one copy shows through every other
+#[derive(Debug, Clone)]
+pub enum Value {
+  Number(f64),
+  String(Rc<str>),
+  Bool(bool),
+  Nil,
+  Callable(Rc<dyn Callable>),
+  Class(Rc<Class>),
+  Instance(Rc<Instance>),
+  List(Rc<RefCell<Vec<Value>>>),
+  Map(Rc<RefCell<Map>>),
+}
+
+impl Value {
+  // false and nil are falsey, everything else is truthy
+  pub fn is_truthy(&self) -> bool {
+    !matches!(self, Value::Bool(false) | Value::Nil)
+  }
+
+  // only strings and numbers can be keys
+  pub fn to_key(&self) -> Option<MapKey> {
+    match self {
+      Value::String(text) => Some(Key::String(Rc::clone(text))),
+      Value::Number(value) => Some(Key::number(*value)),
+      _ => None,
+    }
+  }
+
+  pub fn from_key(key: &MapKey) -> Self {
+    match key {
+      Key::String(text) => Value::String(Rc::clone(text)),
+      Key::Number(bits) => Value::Number(f64::from_bits(*bits)),
+    }
+  }
+}
+
+// functions, classes, instances, lists and maps are only ever equal to themselves
+impl PartialEq for Value {
+  fn eq(&self, other: &Self) -> bool {
+    match (self, other) {
+      (Value::Number(left), Value::Number(right)) => left == right,
+      (Value::String(left), Value::String(right)) => left == right,
+      (Value::Bool(left), Value::Bool(right)) => left == right,
+      (Value::Nil, Value::Nil) => true,
+      (Value::Callable(left), Value::Callable(right)) => Rc::ptr_eq(left, right),
+      (Value::Class(left), Value::Class(right)) => Rc::ptr_eq(left, right),
+      (Value::Instance(left), Value::Instance(right)) => Rc::ptr_eq(left, right),
+      (Value::List(left), Value::List(right)) => Rc::ptr_eq(left, right),
+      (Value::Map(left), Value::Map(right)) => Rc::ptr_eq(left, right),
+      _ => false,
+    }
+  }
+}
+
+// how print shows a value, whole numbers go without a fractional part
+impl fmt::Display for Value {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Value::Number(value) => write!(f, "{}", value),
+      Value::String(value) => write!(f, "{}", value),
+      Value::Bool(value) => write!(f, "{}", value),
+      Value::Nil => write!(f, "nil"),
+      Value::Callable(callable) => write!(f, "{}", callable),
+      Value::Class(class) => write!(f, "{}", class),
+      Value::Instance(instance) => write!(f, "{}", instance),
+      Value::List(_) | Value::Map(_) => write_collection(f, self, &mut vec![]),
+    }
+  }
+}
+
+// the elements the way print shows them, a list or map met again inside itself shows as [...]
+// or {...}
+fn write_collection(f: &mut fmt::Formatter, value: &Value, open: &mut Vec<*const ()>) -> fmt::Result {
+  let (pointer, brackets) = match value {
+    Value::List(list) => (Rc::as_ptr(list) as *const (), ("[", "]")),
+    Value::Map(map) => (Rc::as_ptr(map) as *const (), ("{", "}")),
+    value => return write!(f, "{}", value),
+  };
+
+  if open.contains(&pointer) {
+    return write!(f, "{}...{}", brackets.0, brackets.1);
+  }
+
+  open.push(pointer);
+  write!(f, "{}", brackets.0)?;
+
+  match value {
+    Value::List(list) => {
+      for (index, element) in list.borrow().iter().enumerate() {
+        if index > 0 {
+          write!(f, ", ")?;
+        }
+
+        write_collection(f, element, open)?;
+      }
+    }
+    Value::Map(map) => {
+      for (index, (key, value)) in map.borrow().entries().iter().enumerate() {
+        if index > 0 {
+          write!(f, ", ")?;
+        }
+
+        write!(f, "{}: ", Value::from_key(key))?;
+        write_collection(f, value, open)?;
+      }
+    }
+    _ => {}
+  }
+
+  open.pop();
+  write!(f, "{}", brackets.1)
+}
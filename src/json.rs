@@ -0,0 +1,476 @@
+use std::fmt;
+use std::fmt::Write;
+
+// minimal JSON document model, enough for the machine readable token and AST dumps and for the
+// jsonParse and jsonStringify natives of both backends
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+  Null,
+  Bool(bool),
+  Number(f64),
+  String(String),
+  Array(Vec<Json>),
+  Object(Vec<(String, Json)>),
+}
+
+impl Json {
+  pub fn object(fields: Vec<(&str, Json)>) -> Self {
+    Json::Object(
+      fields
+        .into_iter()
+        .map(|(key, value)| (String::from(key), value))
+        .collect(),
+    )
+  }
+
+  // the document the text holds, with nothing but whitespace around it
+  pub fn parse(text: &str) -> Result<Json, String> {
+    let mut parser = JsonParser {
+      chars: text.chars().collect(),
+      current: 0,
+      depth: 0,
+    };
+
+    let document = parser.value()?;
+    parser.skip_whitespace();
+
+    match parser.peek() {
+      None => Ok(document),
+      Some(_) => Err(parser.error("expected the end of the document")),
+    }
+  }
+
+  pub fn pretty(&self) -> String {
+    let mut output = String::new();
+    self.write_pretty(&mut output, 0);
+
+    output
+  }
+
+  fn write_pretty(&self, output: &mut String, depth: usize) {
+    let indent = "  ".repeat(depth + 1);
+
+    match self {
+      Json::Array(items) if !items.is_empty() => {
+        output.push_str("[\n");
+
+        for (index, item) in items.iter().enumerate() {
+          output.push_str(&indent);
+          item.write_pretty(output, depth + 1);
+          output.push_str(if index + 1 < items.len() { ",\n" } else { "\n" });
+        }
+
+        output.push_str(&"  ".repeat(depth));
+        output.push(']');
+      }
+      Json::Object(fields) if !fields.is_empty() => {
+        output.push_str("{\n");
+
+        for (index, (key, value)) in fields.iter().enumerate() {
+          output.push_str(&indent);
+          write_string(output, key);
+          output.push_str(": ");
+          value.write_pretty(output, depth + 1);
+          output.push_str(if index + 1 < fields.len() { ",\n" } else { "\n" });
+        }
+
+        output.push_str(&"  ".repeat(depth));
+        output.push('}');
+      }
+      _ => {
+        let _ = write!(output, "{}", self);
+      }
+    }
+  }
+}
+
+// what jsonStringify says about values JSON has no way to write
+pub fn unsupported() -> String {
+  String::from("Only nil, bools, numbers, strings, lists and maps can be converted to JSON.")
+}
+
+pub fn contains_itself() -> String {
+  String::from("Can't convert a list or map that contains itself to JSON.")
+}
+
+pub fn nested_too_deeply() -> String {
+  format!("Can't convert lists and maps nested more than {} deep to JSON.", MAX_DEPTH)
+}
+
+// JSON has no infinity or NaN, which would otherwise have to become null
+pub fn not_finite() -> String {
+  String::from("Can't convert infinity or NaN to JSON.")
+}
+
+// arrays and objects nested deeper than this are an error rather than a stack overflow, when
+// parsing and when jsonStringify converts lists and maps
+pub const MAX_DEPTH: usize = 512;
+
+struct JsonParser {
+  chars: Vec<char>,
+  current: usize,
+  // how many arrays and objects the parser is inside of
+  depth: usize,
+}
+
+impl JsonParser {
+  fn peek(&self) -> Option<char> {
+    self.chars.get(self.current).copied()
+  }
+
+  fn advance(&mut self) -> Option<char> {
+    let char = self.peek();
+    self.current += 1;
+
+    char
+  }
+
+  // positions count characters from 1, the way an editor's columns do
+  fn error(&self, expected: &str) -> String {
+    match self.current < self.chars.len() {
+      true => format!("Invalid JSON: {} at character {}.", expected, self.current + 1),
+      false => format!("Invalid JSON: {} at the end.", expected),
+    }
+  }
+
+  fn skip_whitespace(&mut self) {
+    while matches!(self.peek(), Some(' ' | '\t' | '\n' | '\r')) {
+      self.current += 1;
+    }
+  }
+
+  fn expect(&mut self, char: char, expected: &str) -> Result<(), String> {
+    self.skip_whitespace();
+
+    match self.peek() == Some(char) {
+      true => {
+        self.current += 1;
+        Ok(())
+      }
+      false => Err(self.error(expected)),
+    }
+  }
+
+  fn value(&mut self) -> Result<Json, String> {
+    self.skip_whitespace();
+
+    match self.peek() {
+      Some('{') => self.nested(Self::object),
+      Some('[') => self.nested(Self::array),
+      Some('"') => self.string().map(Json::String),
+      Some('-' | '0'..='9') => self.number(),
+      Some('t') => self.literal("true", Json::Bool(true)),
+      Some('f') => self.literal("false", Json::Bool(false)),
+      Some('n') => self.literal("null", Json::Null),
+      _ => Err(self.error("expected a value")),
+    }
+  }
+
+  fn nested(&mut self, parse: fn(&mut Self) -> Result<Json, String>) -> Result<Json, String> {
+    if self.depth == MAX_DEPTH {
+      return Err(self.error("nested too deeply"));
+    }
+
+    self.depth += 1;
+    let value = parse(self);
+    self.depth -= 1;
+
+    value
+  }
+
+  fn literal(&mut self, word: &str, value: Json) -> Result<Json, String> {
+    let end = self.current + word.chars().count();
+
+    match self.chars.get(self.current..end).is_some_and(|chars| chars.iter().copied().eq(word.chars())) {
+      true => {
+        self.current = end;
+        Ok(value)
+      }
+      false => Err(self.error("expected a value")),
+    }
+  }
+
+  // the digits are checked against JSON's grammar, which is stricter than Rust's
+  fn number(&mut self) -> Result<Json, String> {
+    let start = self.current;
+    let digits = |parser: &mut Self| {
+      let first = parser.current;
+
+      while parser.peek().is_some_and(|char| char.is_ascii_digit()) {
+        parser.current += 1;
+      }
+
+      parser.current > first
+    };
+
+    if self.peek() == Some('-') {
+      self.current += 1;
+    }
+
+    let leading_zero = self.peek() == Some('0');
+
+    if !digits(self) || (leading_zero && self.current - start > 1 + (self.chars[start] == '-') as usize) {
+      self.current = start;
+      return Err(self.error("expected a number"));
+    }
+
+    if self.peek() == Some('.') {
+      self.current += 1;
+
+      if !digits(self) {
+        return Err(self.error("expected digits after '.'"));
+      }
+    }
+
+    if matches!(self.peek(), Some('e' | 'E')) {
+      self.current += 1;
+
+      if matches!(self.peek(), Some('+' | '-')) {
+        self.current += 1;
+      }
+
+      if !digits(self) {
+        return Err(self.error("expected digits in the exponent"));
+      }
+    }
+
+    let text: String = self.chars[start..self.current].iter().collect();
+
+    Ok(Json::Number(text.parse().unwrap_or(f64::NAN)))
+  }
+
+  fn string(&mut self) -> Result<String, String> {
+    self.expect('"', "expected a string")?;
+    let mut value = String::new();
+
+    loop {
+      match self.advance() {
+        Some('"') => return Ok(value),
+        Some('\\') => value.push(self.escape()?),
+        Some(char) if (char as u32) < 0x20 => {
+          self.current -= 1;
+          return Err(self.error("control characters must be escaped"));
+        }
+        Some(char) => value.push(char),
+        None => return Err(self.error("unterminated string")),
+      }
+    }
+  }
+
+  // the character after a backslash. A \u escape for half of a surrogate pair takes the other
+  // half from the escape after it
+  fn escape(&mut self) -> Result<char, String> {
+    let char = match self.advance() {
+      Some('"') => '"',
+      Some('\\') => '\\',
+      Some('/') => '/',
+      Some('b') => '\u{8}',
+      Some('f') => '\u{c}',
+      Some('n') => '\n',
+      Some('r') => '\r',
+      Some('t') => '\t',
+      Some('u') => {
+        let high = self.code_unit()?;
+
+        let code = match high {
+          0xd800..=0xdbff if self.chars.get(self.current..self.current + 2) == Some(&['\\', 'u']) => {
+            self.current += 2;
+            let low = self.code_unit()?;
+
+            match low {
+              0xdc00..=0xdfff => 0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00),
+              _ => return Err(self.error("invalid unicode escape")),
+            }
+          }
+          code => code,
+        };
+
+        return char::from_u32(code).ok_or_else(|| self.error("invalid unicode escape"));
+      }
+      _ => {
+        self.current -= 1;
+        return Err(self.error("invalid escape"));
+      }
+    };
+
+    Ok(char)
+  }
+
+  // the four hex digits of a \u escape
+  fn code_unit(&mut self) -> Result<u32, String> {
+    let digits: String = self.chars.iter().skip(self.current).take(4).collect();
+
+    match (digits.len(), u32::from_str_radix(&digits, 16)) {
+      (4, Ok(code)) if digits.chars().all(|char| char.is_ascii_hexdigit()) => {
+        self.current += 4;
+        Ok(code)
+      }
+      _ => Err(self.error("expected four hex digits")),
+    }
+  }
+
+  fn array(&mut self) -> Result<Json, String> {
+    self.expect('[', "expected '['")?;
+    let mut items = vec![];
+    self.skip_whitespace();
+
+    if self.peek() == Some(']') {
+      self.current += 1;
+      return Ok(Json::Array(items));
+    }
+
+    loop {
+      items.push(self.value()?);
+      self.skip_whitespace();
+
+      match self.advance() {
+        Some(',') => continue,
+        Some(']') => return Ok(Json::Array(items)),
+        _ => {
+          self.current -= 1;
+          return Err(self.error("expected ',' or ']'"));
+        }
+      }
+    }
+  }
+
+  fn object(&mut self) -> Result<Json, String> {
+    self.expect('{', "expected '{'")?;
+    let mut fields = vec![];
+    self.skip_whitespace();
+
+    if self.peek() == Some('}') {
+      self.current += 1;
+      return Ok(Json::Object(fields));
+    }
+
+    loop {
+      self.skip_whitespace();
+
+      if self.peek() != Some('"') {
+        return Err(self.error("expected a string key"));
+      }
+
+      let key = self.string()?;
+      self.expect(':', "expected ':' after key")?;
+      fields.push((key, self.value()?));
+      self.skip_whitespace();
+
+      match self.advance() {
+        Some(',') => continue,
+        Some('}') => return Ok(Json::Object(fields)),
+        _ => {
+          self.current -= 1;
+          return Err(self.error("expected ',' or '}'"));
+        }
+      }
+    }
+  }
+}
+
+fn write_string(output: &mut impl Write, value: &str) {
+  let _ = output.write_char('"');
+
+  for character in value.chars() {
+    let _ = match character {
+      '"' => output.write_str("\\\""),
+      '\\' => output.write_str("\\\\"),
+      '\n' => output.write_str("\\n"),
+      '\r' => output.write_str("\\r"),
+      '\t' => output.write_str("\\t"),
+      character if (character as u32) < 0x20 => write!(output, "\\u{:04x}", character as u32),
+      character => output.write_char(character),
+    };
+  }
+
+  let _ = output.write_char('"');
+}
+
+impl fmt::Display for Json {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Json::Null => write!(f, "null"),
+      Json::Bool(value) => write!(f, "{}", value),
+      // JSON has no representation for NaN or the infinities
+      Json::Number(value) if !value.is_finite() => write!(f, "null"),
+      Json::Number(value) => write!(f, "{}", value),
+      Json::String(value) => {
+        write_string(f, value);
+        Ok(())
+      }
+      Json::Array(items) => {
+        write!(f, "[")?;
+
+        for (index, item) in items.iter().enumerate() {
+          if index > 0 {
+            write!(f, ",")?;
+          }
+
+          write!(f, "{}", item)?;
+        }
+
+        write!(f, "]")
+      }
+      Json::Object(fields) => {
+        write!(f, "{{")?;
+
+        for (index, (key, value)) in fields.iter().enumerate() {
+          if index > 0 {
+            write!(f, ",")?;
+          }
+
+          write_string(f, key);
+          write!(f, ":{}", value)?;
+        }
+
+        write!(f, "}}")
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn documents_parse_into_the_model() {
+    let document = Json::parse(" {\"a\": [1, -2.5e1, true, null], \"b\": {}, \"c\": \"x\\n\\u00e9\\ud83d\\ude00\"} ");
+
+    assert_eq!(
+      document,
+      Ok(Json::Object(vec![
+        (String::from("a"), Json::Array(vec![Json::Number(1.0), Json::Number(-25.0), Json::Bool(true), Json::Null])),
+        (String::from("b"), Json::Object(vec![])),
+        (String::from("c"), Json::String(String::from("x\né😀"))),
+      ]))
+    );
+  }
+
+  #[test]
+  fn documents_round_trip() {
+    let text = "{\"list\":[1,0.5,\"q\\\"\"],\"empty\":[],\"nested\":{\"x\":false}}";
+
+    assert_eq!(Json::parse(text).unwrap().to_string(), text);
+    assert_eq!(Json::parse(&Json::parse(text).unwrap().pretty()).unwrap().to_string(), text);
+  }
+
+  #[test]
+  fn errors_say_where_the_document_went_wrong() {
+    let error = |text: &str| Json::parse(text).unwrap_err();
+
+    assert_eq!(error("[1, 2"), "Invalid JSON: expected ',' or ']' at the end.");
+    assert_eq!(error("[1; 2]"), "Invalid JSON: expected ',' or ']' at character 3.");
+    assert_eq!(error("{1: 2}"), "Invalid JSON: expected a string key at character 2.");
+    assert_eq!(error("01"), "Invalid JSON: expected a number at character 1.");
+    assert_eq!(error("1 2"), "Invalid JSON: expected the end of the document at character 3.");
+    assert_eq!(error("\"\\x\""), "Invalid JSON: invalid escape at character 3.");
+    assert_eq!(error("tru"), "Invalid JSON: expected a value at character 1.");
+    assert_eq!(error(""), "Invalid JSON: expected a value at the end.");
+    assert_eq!(error(&"[".repeat(1000)), "Invalid JSON: nested too deeply at character 513.");
+
+    for text in ["-", "1.", "1e", ".5", "+1", "\"a", "\"\t\"", "\"\\u12\"", "\"\\ud83d\"", "\"\\ud83d\\u0041\"", "[1,]", "nul"] {
+      assert!(Json::parse(text).is_err(), "{}", text);
+    }
+  }
+}
@@ -0,0 +1,65 @@
+use std::error;
+use std::fmt;
+
+use super::Span;
+
+#[derive(Debug, Clone)]
+pub enum LexError {
+  InvalidToken {
+    span: Span,
+    lexeme: String,
+  },
+  UnterminatedString {
+    span: Span,
+  },
+  UnterminatedBlockComment {
+    span: Span,
+  },
+  UnterminatedInterpolation {
+    span: Span,
+  },
+  InvalidEscape {
+    span: Span,
+    sequence: String,
+  },
+  InvalidNumber {
+    span: Span,
+    lexeme: String,
+  },
+  InvalidDigitSeparator {
+    span: Span,
+    lexeme: String,
+  },
+}
+
+impl LexError {
+  pub fn span(&self) -> Span {
+    match self {
+      LexError::InvalidToken { span, .. }
+      | LexError::UnterminatedString { span }
+      | LexError::UnterminatedBlockComment { span }
+      | LexError::UnterminatedInterpolation { span }
+      | LexError::InvalidEscape { span, .. }
+      | LexError::InvalidNumber { span, .. }
+      | LexError::InvalidDigitSeparator { span, .. } => *span,
+    }
+  }
+}
+
+impl fmt::Display for LexError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      LexError::InvalidToken { lexeme, .. } => write!(f, "invalid token {}", lexeme),
+      LexError::UnterminatedString { .. } => write!(f, "non terminated string"),
+      LexError::UnterminatedBlockComment { .. } => write!(f, "non terminated block comment"),
+      LexError::UnterminatedInterpolation { .. } => write!(f, "non terminated string interpolation"),
+      LexError::InvalidEscape { sequence, .. } => write!(f, "invalid escape sequence {}", sequence),
+      LexError::InvalidNumber { lexeme, .. } => write!(f, "invalid number literal {}", lexeme),
+      LexError::InvalidDigitSeparator { lexeme, .. } => {
+        write!(f, "misplaced digit separator `_` in number literal {}", lexeme)
+      }
+    }
+  }
+}
+
+impl error::Error for LexError {}
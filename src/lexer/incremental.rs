@@ -0,0 +1,331 @@
+// editor integrations keep a TokenBuffer around and patch it on every keystroke
+
+use std::borrow::Cow;
+
+use super::{LexError, Lexer, Literals, Span, Token};
+
+// replacement of the old source range `replaced` by `inserted_len` bytes of new text
+#[derive(Debug, Clone, Copy)]
+pub struct TextEdit {
+  pub replaced: Span,
+  pub inserted_len: usize,
+}
+
+impl TextEdit {
+  pub fn new(replaced: Span, inserted: &str) -> Self {
+    Self {
+      replaced,
+      inserted_len: inserted.len(),
+    }
+  }
+
+  fn delta(&self) -> isize {
+    self.inserted_len as isize - (self.replaced.end - self.replaced.start) as isize
+  }
+}
+
+// everything the lexer produced for a source, in order, so it can be patched after an edit
+pub struct TokenBuffer<'a> {
+  items: Vec<Result<Token<'a>, LexError>>,
+  // how many string interpolations were open when each item started, rescanning can only
+  // start and stop where none are since the lexer treats `}` differently inside them
+  depths: Vec<usize>,
+}
+
+impl<'a> TokenBuffer<'a> {
+  pub fn tokens(&self) -> impl Iterator<Item = &Token<'a>> {
+    self.items.iter().filter_map(|item| item.as_ref().ok())
+  }
+
+  pub fn errors(&self) -> impl Iterator<Item = &LexError> {
+    self.items.iter().filter_map(|item| item.as_ref().err())
+  }
+}
+
+fn item_span(item: &Result<Token, LexError>) -> Span {
+  match item {
+    Ok(token) => token.span(),
+    Err(error) => error.span(),
+  }
+}
+
+// how far into the source the lexer read to produce the item, an unterminated block comment
+// is reported at its opening `/*` but swallows everything up to the end of the input and an
+// `r` identifier was only decided after looking past the `#`s following it for a raw string
+// quote, and a number followed by a dot, one with misplaced separators too, looked at the
+// character after it for a fraction. The text before the edit is the same in both sources so
+// code can be the new one
+fn item_extent_end(item: &Result<Token, LexError>, code: &str) -> usize {
+  match item {
+    Err(LexError::UnterminatedBlockComment { .. } | LexError::UnterminatedInterpolation { .. }) => {
+      usize::MAX
+    }
+    Ok(Token::Identifier(span, "r")) => {
+      let rest = code.get(span.end..).unwrap_or_default();
+
+      span.end + rest.bytes().take_while(|byte| *byte == b'#').count()
+    }
+    Ok(Token::Literal(span, Literals::Number(_)))
+    | Err(LexError::InvalidNumber { span, .. } | LexError::InvalidDigitSeparator { span, .. })
+      if code.as_bytes().get(span.end) == Some(&b'.') =>
+    {
+      span.end + 1
+    }
+    item => item_span(item).end,
+  }
+}
+
+fn shift(span: Span, delta: isize) -> Span {
+  Span::new(
+    (span.start as isize + delta) as usize,
+    (span.end as isize + delta) as usize,
+  )
+}
+
+fn shift_error(error: &LexError, delta: isize) -> LexError {
+  let mut error = error.clone();
+  let span = match &mut error {
+    LexError::InvalidToken { span, .. }
+    | LexError::UnterminatedString { span }
+    | LexError::UnterminatedBlockComment { span }
+    | LexError::UnterminatedInterpolation { span }
+    | LexError::InvalidEscape { span, .. }
+    | LexError::InvalidNumber { span, .. }
+    | LexError::InvalidDigitSeparator { span, .. } => span,
+  };
+
+  *span = shift(*span, delta);
+
+  error
+}
+
+// text between the quotes of a string token that borrows from the source
+fn string_content(code: &str, span: Span) -> &str {
+  if code.as_bytes()[span.start] == b'r' {
+    let hashes = code[span.start + 1..].bytes().take_while(|byte| *byte == b'#').count();
+
+    &code[span.start + 2 + hashes..span.end - 1 - hashes]
+  } else {
+    &code[span.start + 1..span.end - 1]
+  }
+}
+
+// moves an item that sits outside the edited region over to the new source, borrowed
+// text is sliced again from the new source since it didn't change
+fn rebase<'b>(item: &Result<Token, LexError>, code: &'b str, delta: isize) -> Result<Token<'b>, LexError> {
+  let token = match item {
+    Ok(token) => token,
+    Err(error) => return Err(shift_error(error, delta)),
+  };
+  let span = shift(token.span(), delta);
+
+  Ok(match token {
+    Token::Operator(_, operator) => Token::Operator(span, *operator),
+    Token::OpenBrace(_) => Token::OpenBrace(span),
+    Token::CloseBrace(_) => Token::CloseBrace(span),
+    Token::OpenParen(_) => Token::OpenParen(span),
+    Token::CloseParen(_) => Token::CloseParen(span),
+    Token::OpenBracket(_) => Token::OpenBracket(span),
+    Token::CloseBracket(_) => Token::CloseBracket(span),
+    Token::Semicolon(_) => Token::Semicolon(span),
+    Token::Colon(_) => Token::Colon(span),
+    Token::Comma(_) => Token::Comma(span),
+    Token::Dot(_) => Token::Dot(span),
+    Token::Literal(_, Literals::String(Cow::Borrowed(_))) => {
+      Token::Literal(span, Literals::String(Cow::Borrowed(string_content(code, span))))
+    }
+    Token::Literal(_, Literals::String(Cow::Owned(value))) => {
+      Token::Literal(span, Literals::String(Cow::Owned(value.clone())))
+    }
+    Token::Literal(_, Literals::Number(value)) => Token::Literal(span, Literals::Number(*value)),
+    // the segment runs from the opening quote or `}` up to and including `${`
+    Token::Interpolation(_, Cow::Borrowed(_)) => {
+      Token::Interpolation(span, Cow::Borrowed(&code[span.start + 1..span.end - 2]))
+    }
+    Token::Interpolation(_, Cow::Owned(value)) => Token::Interpolation(span, Cow::Owned(value.clone())),
+    // the tail runs from the `}` up to and including the closing quote
+    Token::InterpolationEnd(_, Cow::Borrowed(_)) => {
+      Token::InterpolationEnd(span, Cow::Borrowed(&code[span.start + 1..span.end - 1]))
+    }
+    Token::InterpolationEnd(_, Cow::Owned(value)) => {
+      Token::InterpolationEnd(span, Cow::Owned(value.clone()))
+    }
+    Token::Identifier(_, _) => Token::Identifier(span, &code[span.start..span.end]),
+    Token::Keyword(_, keyword) => Token::Keyword(span, *keyword),
+    Token::Eof(_) => Token::Eof(span),
+  })
+}
+
+fn is_same_kind(old: &Result<Token, LexError>, new: &Result<Token, LexError>) -> bool {
+  match (old, new) {
+    (Ok(old), Ok(new)) => old.kind() == new.kind(),
+    _ => false,
+  }
+}
+
+impl<'a> Lexer<'a> {
+  // depth of open interpolations before the next item, an item queued behind others from the
+  // same scan reports the depth after that scan, which callers guard against by checking pending
+  fn next_with_depth(&mut self) -> Option<(Result<Token<'a>, LexError>, usize)> {
+    let depth = self.interpolations.len();
+
+    self.next().map(|item| (item, depth))
+  }
+
+  pub fn lex_buffer(mut self) -> TokenBuffer<'a> {
+    let mut items = vec![];
+    let mut depths = vec![];
+
+    while let Some((item, depth)) = self.next_with_depth() {
+      items.push(item);
+      depths.push(depth);
+    }
+
+    TokenBuffer { items, depths }
+  }
+
+  // the lexer has to be created over the source with the edit already applied, only the
+  // region between the last token before the edit and the first old token that lines up
+  // again afterwards is scanned
+  pub fn relex(mut self, edit: &TextEdit, old: &TokenBuffer) -> TokenBuffer<'a> {
+    let delta = edit.delta();
+    let code = self.code;
+
+    // a token ending right where the edit starts can still grow, `=` followed by an inserted `=`
+    let mut first_damaged = old
+      .items
+      .iter()
+      .position(|item| item_extent_end(item, code) >= edit.replaced.start)
+      .unwrap_or(old.items.len());
+
+    // bad escapes are reported from inside their string so rescanning can't start right after
+    // one, nor can it start inside an interpolation
+    while first_damaged > 0
+      && (old.depths[first_damaged] > 0
+        || matches!(old.items[first_damaged - 1], Err(LexError::InvalidEscape { .. })))
+    {
+      first_damaged -= 1;
+    }
+
+    let restart = match first_damaged {
+      0 => 0,
+      index => item_span(&old.items[index - 1]).end,
+    };
+
+    let mut items: Vec<Result<Token<'a>, LexError>> = old.items[..first_damaged]
+      .iter()
+      .map(|item| rebase(item, code, 0))
+      .collect();
+    let mut depths = old.depths[..first_damaged].to_vec();
+
+    self.current = restart;
+
+    let edit_end = edit.replaced.start + edit.inserted_len;
+    let mut old_index = first_damaged;
+
+    while let Some((item, depth)) = self.next_with_depth() {
+      let start = item_span(&item).start;
+
+      if start >= edit_end && depth == 0 {
+        let old_start = (start as isize - delta) as usize;
+
+        while old_index < old.items.len() && item_span(&old.items[old_index]).start < old_start {
+          old_index += 1;
+        }
+
+        let old_item = old.items.get(old_index);
+        let is_synchronized = old_item.is_some_and(|old_item| {
+          let old_span = item_span(old_item);
+
+          old_span.start == old_start
+            && old_span.start >= edit.replaced.end
+            && shift(old_span, delta) == item_span(&item)
+            && is_same_kind(old_item, &item)
+            && old.depths[old_index] == 0
+            && self.pending.is_empty()
+        });
+
+        if is_synchronized {
+          items.extend(old.items[old_index..].iter().map(|item| rebase(item, code, delta)));
+          depths.extend_from_slice(&old.depths[old_index..]);
+
+          return TokenBuffer { items, depths };
+        }
+      }
+
+      items.push(item);
+      depths.push(depth);
+    }
+
+    TokenBuffer { items, depths }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::random;
+
+  // pieces that open or close what changes how the rest of the source is read
+  const INSERTS: [&str; 29] = [
+    "", " ", "\n", "\r", "x", "r", "e", "#", "#!", "!", "\"", "\\", "=", "-", "+", ".", "0", "1.", "0x", "_", "}", "${",
+    "/*", "*/", "//", "r#\"", "\"#", "\"a${b}c\"", "\"${",
+  ];
+
+  // relexes the edit from the buffer of the source before it, which has to give what lexing the
+  // edited source from the start does
+  fn edit(code: &str, replaced: Span, inserted: &str) -> String {
+    let mut edited = String::from(code);
+    edited.replace_range(replaced.start..replaced.end, inserted);
+
+    let old = Lexer::new(code).lex_buffer();
+    let relexed = Lexer::new(&edited).relex(&TextEdit::new(replaced, inserted), &old);
+    let lexed = Lexer::new(&edited).lex_buffer();
+    assert_eq!(
+      format!("{:?} {:?}", relexed.items, relexed.depths),
+      format!("{:?} {:?}", lexed.items, lexed.depths),
+      "{:?} became {:?}",
+      code,
+      edited
+    );
+
+    edited
+  }
+
+  // from 0 to len, both included
+  fn pick(len: usize) -> usize {
+    random::random_int(0.0, len as f64).unwrap() as usize
+  }
+
+  #[test]
+  fn random_edits_relex_like_the_whole_source() {
+    let sources = [
+      "var a = 1; /* note */ print a == 2;",
+      "print \"x = ${x + 1}, ${\"in ${y}\"}\"; var b = r#\"raw \"quoted\" ${not}\"#;",
+      "fun f(n) {\n  // comment\n  return n.5 + 0x1F + 1_000 + 2.5e-3; /* nested /* comment */ */\n}\nr #",
+      "var s = \"bad \\q escape\"; { print \"${ { } }\"; } /* open",
+      "print \"unterminated ${1 + \n var c = r\"plain\";",
+    ];
+    random::seed(18.0);
+
+    for source in sources {
+      let mut code = String::from(source);
+
+      for _ in 0..500 {
+        let start = pick(code.len());
+        let end = start + pick((code.len() - start).min(8));
+        code = edit(&code, Span::new(start, end), INSERTS[pick(INSERTS.len() - 1)]);
+      }
+    }
+  }
+
+  // the lexer looked past the dot after a number for a digit, malformed numbers included
+  #[test]
+  fn edits_after_a_number_and_a_dot() {
+    edit("print 1.x;", Span::new(8, 8), "5");
+    edit("print 1.5;", Span::new(8, 9), "");
+    edit("print 1_.x;", Span::new(9, 9), "0");
+    edit("print 1_.5;", Span::new(9, 10), "x");
+    edit("print 1e.x;", Span::new(9, 9), "2");
+  }
+}
@@ -1,90 +1,349 @@
+use std::borrow::Cow;
+use std::collections::VecDeque;
 use std::fmt;
-use std::str;
 
-const NEW_LINE: u8 = b'\n';
-const LINE_FEED: u8 = b'\r';
+mod error;
+mod incremental;
+mod source_map;
+mod span;
+mod trivia;
 
-#[derive(Debug)]
-pub struct TokenLocation {
-  row: usize,
-  col: usize,
-}
+pub use error::LexError;
+pub use incremental::{TextEdit, TokenBuffer};
+pub use source_map::{Location, SourceMap};
+pub use span::Span;
+pub use trivia::{Trivia, TriviaKind, TriviaToken};
+
+const NEW_LINE: u8 = b'\n';
+const CARRIAGE_RETURN: u8 = b'\r';
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Operators {
   Plus,
   Minus,
   Star,
+  Slash,
+  Percent,
   Assignment,
+  PlusAssignment,
+  MinusAssignment,
+  StarAssignment,
+  SlashAssignment,
+  Equal,
+  NotEqual,
+  Less,
+  LessEqual,
+  Greater,
+  GreaterEqual,
   Increment,
   Decrement,
+  Bang,
+  BitAnd,
+  BitOr,
+  BitXor,
+  BitNot,
+  ShiftLeft,
+  ShiftRight,
+  // `??`, a lone `?` isn't a token
+  Coalesce,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Keywords {
+  And,
+  Or,
+  True,
+  False,
+  Nil,
+  Print,
+  Var,
+  If,
+  Else,
+  While,
+  For,
+  Fun,
+  Return,
+  Class,
+  This,
+  Super,
+  In,
+  Throw,
+  Try,
+  Catch,
+  Import,
+  Break,
+  Continue,
+  Switch,
+  Case,
+  Default,
+}
+
+impl Keywords {
+  fn from_identifier(identifier: &str) -> Option<Self> {
+    match identifier {
+      "and" => Some(Keywords::And),
+      "or" => Some(Keywords::Or),
+      "true" => Some(Keywords::True),
+      "false" => Some(Keywords::False),
+      "nil" => Some(Keywords::Nil),
+      "print" => Some(Keywords::Print),
+      "var" => Some(Keywords::Var),
+      "if" => Some(Keywords::If),
+      "else" => Some(Keywords::Else),
+      "while" => Some(Keywords::While),
+      "for" => Some(Keywords::For),
+      "fun" => Some(Keywords::Fun),
+      "return" => Some(Keywords::Return),
+      "class" => Some(Keywords::Class),
+      "this" => Some(Keywords::This),
+      "super" => Some(Keywords::Super),
+      "in" => Some(Keywords::In),
+      "throw" => Some(Keywords::Throw),
+      "try" => Some(Keywords::Try),
+      "catch" => Some(Keywords::Catch),
+      "import" => Some(Keywords::Import),
+      "break" => Some(Keywords::Break),
+      "continue" => Some(Keywords::Continue),
+      "switch" => Some(Keywords::Switch),
+      "case" => Some(Keywords::Case),
+      "default" => Some(Keywords::Default),
+      _ => None,
+    }
+  }
+}
+
+#[derive(Clone)]
 pub enum Literals<'a> {
-  String(&'a [u8]),
-  Number(&'a [u8]),
+  // borrowed straight from the source unless escape sequences had to be decoded
+  String(Cow<'a, str>),
+  // decoded value, the literal as written is always available through the token span
+  Number(f64),
 }
 
 impl<'a> fmt::Debug for Literals<'a> {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
     match self {
-      Literals::String(bytes) => {
-        write!(f, "\"{}\"", str::from_utf8(bytes).unwrap())
+      Literals::String(value) => {
+        write!(f, "\"{}\"", value)
       }
-      Literals::Number(bytes) => {
-        write!(f, "{}", str::from_utf8(bytes).unwrap())
+      Literals::Number(value) => {
+        write!(f, "{}", value)
       }
     }
   }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Token<'a> {
-  Operator(TokenLocation, Operators),
-  OpenBrace(TokenLocation),
-  CloseBrace(TokenLocation),
-  OpenParen(TokenLocation),
-  CloseParen(TokenLocation),
-  Literal(TokenLocation, Literals<'a>),
-  Identifier(TokenLocation, &'a str),
-  EOF(TokenLocation),
+  Operator(Span, Operators),
+  OpenBrace(Span),
+  CloseBrace(Span),
+  OpenParen(Span),
+  CloseParen(Span),
+  OpenBracket(Span),
+  CloseBracket(Span),
+  Semicolon(Span),
+  Colon(Span),
+  Comma(Span),
+  Dot(Span),
+  Literal(Span, Literals<'a>),
+  // string text leading up to an embedded `${`, the expression tokens follow and the string
+  // carries on with another segment or its end after the closing `}`
+  Interpolation(Span, Cow<'a, str>),
+  // string text after the last embedded expression, from the `}` up to the closing quote
+  InterpolationEnd(Span, Cow<'a, str>),
+  Identifier(Span, &'a str),
+  Keyword(Span, Keywords),
+  Eof(Span),
+}
+
+impl<'a> Token<'a> {
+  pub fn span(&self) -> Span {
+    match self {
+      Token::Operator(span, _)
+      | Token::OpenBrace(span)
+      | Token::CloseBrace(span)
+      | Token::OpenParen(span)
+      | Token::CloseParen(span)
+      | Token::OpenBracket(span)
+      | Token::CloseBracket(span)
+      | Token::Semicolon(span)
+      | Token::Colon(span)
+      | Token::Comma(span)
+      | Token::Dot(span)
+      | Token::Literal(span, _)
+      | Token::Interpolation(span, _)
+      | Token::InterpolationEnd(span, _)
+      | Token::Identifier(span, _)
+      | Token::Keyword(span, _)
+      | Token::Eof(span) => *span,
+    }
+  }
+
+  // name of the token kind as shown in token dumps, operators and keywords use their variant name
+  pub fn kind(&self) -> String {
+    match self {
+      Token::Operator(_, operator) => format!("{:?}", operator),
+      Token::OpenBrace(_) => String::from("OpenBrace"),
+      Token::CloseBrace(_) => String::from("CloseBrace"),
+      Token::OpenParen(_) => String::from("OpenParen"),
+      Token::CloseParen(_) => String::from("CloseParen"),
+      Token::OpenBracket(_) => String::from("OpenBracket"),
+      Token::CloseBracket(_) => String::from("CloseBracket"),
+      Token::Semicolon(_) => String::from("Semicolon"),
+      Token::Colon(_) => String::from("Colon"),
+      Token::Comma(_) => String::from("Comma"),
+      Token::Dot(_) => String::from("Dot"),
+      Token::Literal(_, Literals::String(_)) => String::from("String"),
+      Token::Literal(_, Literals::Number(_)) => String::from("Number"),
+      Token::Interpolation(_, _) => String::from("Interpolation"),
+      Token::InterpolationEnd(_, _) => String::from("InterpolationEnd"),
+      Token::Identifier(_, _) => String::from("Identifier"),
+      Token::Keyword(_, keyword) => format!("{:?}", keyword),
+      Token::Eof(_) => String::from("Eof"),
+    }
+  }
+}
+
+pub struct LexerOptions {
+  // treat `#` as the start of a single line comment, kept for scripts written before `//` was supported
+  pub hash_comments: bool,
+  // columns a tab advances to in reported locations, 1 treats it as a single column
+  pub tab_width: usize,
+}
+
+impl Default for LexerOptions {
+  fn default() -> Self {
+    Self {
+      hash_comments: true,
+      tab_width: 1,
+    }
+  }
+}
+
+// a `${` whose closing `}` hasn't been seen yet, depth counts the braces opened inside it
+struct OpenInterpolation {
+  start: usize,
+  depth: usize,
 }
 
 pub struct Lexer<'a> {
-  row: usize,
-  col: usize,
+  // where lexing began, the top of the script unless it was started partway into the code
+  start: usize,
   current: usize,
+  code: &'a str,
   code_bytes: &'a [u8],
-  tokens: Vec<Token<'a>>,
+  // tokens and errors produced by the character being scanned, waiting to be handed out by next()
+  pending: VecDeque<Result<Token<'a>, LexError>>,
+  // whitespace and comments seen since the last token, only kept when lexing with trivia
+  trivia: Vec<Trivia>,
+  is_collecting_trivia: bool,
+  interpolations: Vec<OpenInterpolation>,
+  is_finished: bool,
+  options: LexerOptions,
 }
 
 impl<'a> Lexer<'a> {
-  pub fn new() -> Self {
+  pub fn new(code: &'a str) -> Self {
+    Self::with_options(code, LexerOptions::default())
+  }
+
+  pub fn with_options(code: &'a str, options: LexerOptions) -> Self {
     Self {
-      row: 1,
-      col: 1,
+      start: 0,
       current: 0,
-      tokens: vec![],
-      code_bytes: &[],
+      code,
+      code_bytes: code.as_bytes(),
+      pending: VecDeque::new(),
+      trivia: vec![],
+      is_collecting_trivia: false,
+      interpolations: vec![],
+      is_finished: false,
+      options,
     }
   }
 
-  fn peek(&self) -> u8 {
-    self.code_bytes[self.current + 1]
+  // lexes the code from offset on, for a file that was appended to the text of others. Spans
+  // stay offsets into all of it so they can't be mistaken for ones in the files before
+  pub fn from_offset(code: &'a str, offset: usize) -> Self {
+    Self {
+      start: offset,
+      current: offset,
+      ..Self::new(code)
+    }
   }
 
-  fn lookup(&mut self, lookup_char: u8) -> bool {
-    if !self.is_eof(1) {
-      let lookup_matched = self.peek() == lookup_char;
+  // line and column lookup for the code being lexed, using the configured tab width
+  pub fn source_map(&self) -> SourceMap<'a> {
+    SourceMap::with_tab_width(self.code, self.options.tab_width)
+  }
 
-      if lookup_matched {
-        self.advance();
+  fn emit(&mut self, token: Token<'a>) {
+    self.pending.push_back(Ok(token));
+  }
+
+  fn report(&mut self, error: LexError) {
+    self.pending.push_back(Err(error));
+  }
+
+  fn record_trivia(&mut self, kind: TriviaKind, start: usize) {
+    if !self.is_collecting_trivia {
+      return;
+    }
+
+    let span = self.span_from(start);
+
+    // runs of spaces are kept as a single piece
+    match self.trivia.last_mut() {
+      Some(last) if kind == TriviaKind::Whitespace && last.kind == kind && last.span.end == start => {
+        last.span.end = span.end
       }
+      _ => self.trivia.push(Trivia { kind, span }),
+    }
+  }
 
-      return lookup_matched;
+  // width in bytes of the UTF-8 encoded character starting at offset
+  fn char_width(&self, offset: usize) -> usize {
+    match self.code_bytes.get(offset) {
+      Some(0xC0..=0xDF) => 2,
+      Some(0xE0..=0xEF) => 3,
+      Some(0xF0..=0xFF) => 4,
+      _ => 1,
     }
+  }
+
+  fn next_offset(&self) -> usize {
+    self.current + self.char_width(self.current)
+  }
+
+  // first byte of the character n places past the current one, enough to compare against
+  // ASCII since UTF-8 never reuses ASCII bytes inside multi-byte sequences
+  fn peek_n(&self, n: usize) -> Option<u8> {
+    let offset = (0..n).fold(self.current, |offset, _| offset + self.char_width(offset));
+
+    self.code_bytes.get(offset).copied()
+  }
 
-    return false;
+  fn peek(&self) -> Option<u8> {
+    self.peek_n(1)
+  }
+
+  fn get_current_char(&self) -> char {
+    self.code[self.current..].chars().next().unwrap()
+  }
+
+  fn peek_char(&self) -> Option<char> {
+    self.code[self.next_offset()..].chars().next()
+  }
+
+  fn lookup(&mut self, lookup_char: u8) -> bool {
+    let lookup_matched = self.peek() == Some(lookup_char);
+
+    if lookup_matched {
+      self.advance();
+    }
+
+    lookup_matched
   }
 
   fn get_current_char_byte(&self) -> u8 {
@@ -92,195 +351,1331 @@ impl<'a> Lexer<'a> {
   }
 
   fn advance(&mut self) {
-    if self.is_eol(0) {
-      self.row +=1;
-      self.col = 1;
-    } else {
-      self.col += 1;
+    self.current = self.next_offset();
+  }
+
+  // moves over the run of bytes after the current character that is_skipped accepts, leaving
+  // current on the last character of the run so the main loop's advance lands past it
+  fn advance_while(&mut self, is_skipped: impl Fn(u8) -> bool) {
+    let from = self.next_offset();
+    let run = self.code_bytes[from.min(self.code_bytes.len())..]
+      .iter()
+      .take_while(|byte| is_skipped(**byte))
+      .count();
+
+    if run > 0 {
+      let mut last = from + run - 1;
+
+      while !self.code.is_char_boundary(last) {
+        last -= 1;
+      }
+
+      self.current = last;
     }
+  }
 
-    self.current += 1;
+  // offset counts characters, 0 being the current one and 1 the next
+  fn offset_position(&self, offset: usize) -> usize {
+    if offset == 0 {
+      self.current
+    } else {
+      self.next_offset()
+    }
   }
 
   fn is_eof(&self, offset: usize) -> bool {
-    self.current + offset >= self.code_bytes.len()
+    self.offset_position(offset) >= self.code_bytes.len()
   }
 
-  fn is_eol(&self, offset: usize) -> bool {
-    !self.is_eof(0) && (self.code_bytes[self.current + offset] == NEW_LINE || self.code_bytes[self.current + offset] == LINE_FEED)
-  }
 
-  fn eat_string(&mut self) {
-    let str_start_col = self.col;
-    let str_start_row = self.row;
-    self.advance();
+  fn eat_string(&mut self) -> Result<(), LexError> {
     let str_start = self.current;
+    self.advance();
 
-    while !self.is_eof(0)
-      && self.get_current_char_byte() != b'"'
-      && !self.is_eol(0)
-    {
+    self.eat_string_content(str_start)
+  }
+
+  // scans string text from current up to the closing quote or the next `${`, str_start is the
+  // opening quote or the `}` that closed the previous interpolation
+  fn eat_string_content(&mut self, str_start: usize) -> Result<(), LexError> {
+    let mut segment_start = self.current;
+    let mut decoded: Option<String> = None;
+
+    // strings may span lines, the source map keeps positions after them right
+    while !self.is_eof(0) && self.get_current_char_byte() != b'"' {
+      if self.get_current_char_byte() == b'$' && self.peek() == Some(b'{') {
+        let value = Self::finish_string(decoded, &self.code[segment_start..self.current]);
+        self.advance();
+
+        self.emit(Token::Interpolation(self.span_from(str_start), value));
+        self.interpolations.push(OpenInterpolation {
+          start: self.current - 1,
+          depth: 0,
+        });
+
+        return Ok(());
+      }
+
+      if self.get_current_char_byte() == b'\\' {
+        let value = decoded.get_or_insert_with(String::new);
+        value.push_str(&self.code[segment_start..self.current]);
+
+        // a bad escape is reported but the rest of the string is still scanned for more of them
+        match self.eat_escape_sequence(str_start) {
+          Ok(character) => value.push(character),
+          Err(error @ LexError::InvalidEscape { .. }) => self.report(error),
+          Err(error) => return Err(error),
+        }
+
+        segment_start = self.next_offset();
+      }
+
+      self.advance_while(|byte| !matches!(byte, b'"' | b'\\' | b'$'));
       self.advance();
     }
 
     if self.current == self.code_bytes.len() || self.get_current_char_byte() != b'"' {
-      panic!(
-        "non terminated string found at {}:{}",
-        str_start_row, str_start_col
-      )
+      Err(LexError::UnterminatedString {
+        span: Span::new(str_start, self.current),
+      })
     } else {
-      let str_bytes = &self.code_bytes[str_start..self.current];
+      let value = Self::finish_string(decoded, &self.code[segment_start..self.current]);
+      let span = self.span_from(str_start);
+
+      if self.code_bytes[str_start] == b'}' {
+        self.emit(Token::InterpolationEnd(span, value));
+      } else {
+        self.emit(Token::Literal(span, Literals::String(value)));
+      }
+
+      Ok(())
+    }
+  }
+
+  fn finish_string(decoded: Option<String>, rest: &'a str) -> Cow<'a, str> {
+    match decoded {
+      Some(mut value) => {
+        value.push_str(rest);
+        Cow::Owned(value)
+      }
+      None => Cow::Borrowed(rest),
+    }
+  }
+
+  fn eat_close_brace(&mut self) -> Result<(), LexError> {
+    match self.interpolations.last_mut() {
+      // this brace closes `${` so the string it was embedded in carries on
+      Some(interpolation) if interpolation.depth == 0 => {
+        self.interpolations.pop();
+
+        let str_start = self.current;
+        self.advance();
 
-      self.tokens.push(Token::Literal(
-        self.get_current_token_location(),
-        Literals::String(str_bytes),
-      ))
+        self.eat_string_content(str_start)
+      }
+      Some(interpolation) => {
+        interpolation.depth -= 1;
+        self.emit(Token::CloseBrace(self.span_from(self.current)));
+
+        Ok(())
+      }
+      None => {
+        self.emit(Token::CloseBrace(self.span_from(self.current)));
+
+        Ok(())
+      }
+    }
+  }
+
+  // r"C:\path" and r#"say "hi""# style strings, as many `#` as the opening used close the string
+  fn is_raw_string_start(&self) -> bool {
+    let quote = self.code[self.current + 1..]
+      .bytes()
+      .position(|byte| byte != b'#')
+      .map(|position| self.current + 1 + position);
+
+    quote.is_some_and(|quote| self.code_bytes[quote] == b'"')
+  }
+
+  fn eat_raw_string(&mut self) -> Result<(), LexError> {
+    let str_start = self.current;
+    let mut hashes = 0;
+    self.advance();
+
+    while self.get_current_char_byte() == b'#' {
+      hashes += 1;
+      self.advance();
+    }
+
+    let content_start = self.current + 1;
+    let closing = format!("\"{}", "#".repeat(hashes));
+
+    loop {
+      self.advance_while(|byte| byte != b'"');
+
+      if self.is_eof(1) {
+        return Err(LexError::UnterminatedString {
+          span: self.span_from(str_start),
+        });
+      }
+
+      self.advance();
+
+      if self.code[self.current..].starts_with(&closing) {
+        let content_end = self.current;
+
+        for _ in 0..hashes {
+          self.advance();
+        }
+
+        self.emit(Token::Literal(
+          self.span_from(str_start),
+          Literals::String(Cow::Borrowed(&self.code[content_start..content_end])),
+        ));
+
+        return Ok(());
+      }
     }
   }
 
-  fn is_digit(&self, character: u8) -> bool {
-    (character >= b'0' && character <= b'9') || character == b'.'
+  // expects current to be at the backslash and leaves it at the last character of the escape
+  fn eat_escape_sequence(&mut self, str_start: usize) -> Result<char, LexError> {
+    let escape_start = self.current;
+
+    if self.is_eof(1) {
+      return Err(LexError::UnterminatedString {
+        span: self.span_from(str_start),
+      });
+    }
+
+    self.advance();
+
+    match self.get_current_char_byte() {
+      b'n' => Ok('\n'),
+      b't' => Ok('\t'),
+      b'"' => Ok('"'),
+      b'$' => Ok('$'),
+      b'\\' => Ok('\\'),
+      b'u' => self.eat_unicode_escape(escape_start),
+      _ => Err(LexError::InvalidEscape {
+        span: self.span_from(escape_start),
+        sequence: format!("\\{}", self.get_current_char()),
+      }),
+    }
+  }
+
+  fn eat_unicode_escape(&mut self, escape_start: usize) -> Result<char, LexError> {
+    if !self.lookup(b'{') {
+      return Err(LexError::InvalidEscape {
+        span: self.span_from(escape_start),
+        sequence: String::from("\\u"),
+      });
+    }
+
+    let mut code_point: u32 = 0;
+    let mut digits = 0;
+
+    while self.peek().is_some_and(|byte| byte.is_ascii_hexdigit()) {
+      self.advance();
+      code_point = code_point * 16 + (self.get_current_char_byte() as char).to_digit(16).unwrap();
+      digits += 1;
+
+      if digits > 6 {
+        break;
+      }
+    }
+
+    let character = if (1..=6).contains(&digits) && self.lookup(b'}') {
+      char::from_u32(code_point)
+    } else {
+      None
+    };
+
+    character.ok_or_else(|| LexError::InvalidEscape {
+      span: self.span_from(escape_start),
+      sequence: String::from(&self.code[escape_start..self.next_offset()]),
+    })
+  }
+
+  fn eat_number(&mut self) -> Result<(), LexError> {
+    let radix = match (self.get_current_char_byte(), self.peek()) {
+      (b'0', Some(b'x' | b'X')) => Some(16),
+      (b'0', Some(b'o' | b'O')) => Some(8),
+      (b'0', Some(b'b' | b'B')) => Some(2),
+      _ => None,
+    };
+
+    match radix {
+      Some(radix) => self.eat_radix_number(radix),
+      None => self.eat_decimal_number(),
+    }
   }
 
-  fn eat_number(&mut self) {
-    let num_start_col = self.col;
-    let num_start_row = self.row;
+  fn eat_decimal_number(&mut self) -> Result<(), LexError> {
     let num_start = self.current;
     let mut is_decimal_point_eaten = false;
 
-    while !self.is_eof(1) && !self.is_eol(1) && self.is_digit(self.peek())
-    {  
-      if self.peek() == b'.' {
-        if !is_decimal_point_eaten {
-          is_decimal_point_eaten = true;
-        } else {
-          break;
+    loop {
+      match self.peek() {
+        Some(b'0'..=b'9' | b'_') => (),
+        // the fraction needs a digit after the dot, otherwise the dot is a token of its own
+        Some(b'.') if !is_decimal_point_eaten && self.peek_n(2).is_some_and(|b| b.is_ascii_digit()) => {
+          is_decimal_point_eaten = true
         }
+        _ => break,
       }
 
       self.advance();
     }
 
-    let num_bytes = &self.code_bytes[num_start..self.current + 1];
+    if matches!(self.peek(), Some(b'e' | b'E')) {
+      self.eat_exponent(num_start)?;
+    }
+
+    self.check_digit_separators(num_start, num_start, |byte| byte.is_ascii_digit())?;
 
-    self.tokens.push(Token::Literal(
-      TokenLocation {
-        row: num_start_row,
-        col: num_start_col,
-      },
-      Literals::Number(num_bytes),
-    ))
+    let lexeme = &self.code[num_start..self.next_offset()];
+    // separators are rare enough that only literals using them pay for a copy without them
+    let value = if lexeme.contains('_') {
+      lexeme.replace('_', "").parse().unwrap()
+    } else {
+      lexeme.parse().unwrap()
+    };
+
+    self.emit(Token::Literal(self.span_from(num_start), Literals::Number(value)));
+
+    Ok(())
   }
 
-  fn eat_hash_single_line_comment(&mut self) {
-    while !self.is_eof(0) && !self.is_eol(0) {
+  // the `e` of 1e9, 2.5e-3 or 1E+6 is the next character
+  fn eat_exponent(&mut self, num_start: usize) -> Result<(), LexError> {
+    self.advance();
+
+    if matches!(self.peek(), Some(b'+' | b'-')) {
+      self.advance();
+    }
+
+    let mut has_digits = false;
+
+    while matches!(self.peek(), Some(b'0'..=b'9' | b'_')) {
       self.advance();
+      has_digits |= self.get_current_char_byte() != b'_';
+    }
+
+    if has_digits {
+      Ok(())
+    } else {
+      Err(LexError::InvalidNumber {
+        span: self.span_from(num_start),
+        lexeme: String::from(&self.code[num_start..self.next_offset()]),
+      })
     }
   }
 
-  fn is_alphabet(&self, character: u8) -> bool {
-    (character >= b'a' && character <= b'z') || (character >= b'A' && character <= b'Z')
+  // 0x1F, 0o755 and 0b1010 style literals, the prefix is at current
+  fn eat_radix_number(&mut self, radix: u32) -> Result<(), LexError> {
+    let num_start = self.current;
+    self.advance();
+
+    // anything alphanumeric is swallowed so a literal like 0xZZ is reported as a whole
+    while self.peek().is_some_and(|byte| byte.is_ascii_alphanumeric() || byte == b'_') {
+      self.advance();
+    }
+
+    self.check_digit_separators(num_start, num_start + 2, |byte| byte.is_ascii_alphanumeric())?;
+
+    let span = self.span_from(num_start);
+    let digits = &self.code[num_start + 2..span.end];
+    let value = if digits.is_empty() {
+      None
+    } else {
+      digits
+        .chars()
+        .filter(|digit| *digit != '_')
+        .try_fold(0.0, |value: f64, digit| {
+          digit.to_digit(radix).map(|digit| value * radix as f64 + digit as f64)
+        })
+    };
+
+    match value {
+      Some(value) => {
+        self.emit(Token::Literal(span, Literals::Number(value)));
+        Ok(())
+      }
+      None => Err(LexError::InvalidNumber {
+        span,
+        lexeme: String::from(&self.code[num_start..span.end]),
+      }),
+    }
+  }
+
+  // underscores in 1_000_000 have to sit between two digits, so leading, trailing
+  // and doubled separators are rejected
+  fn check_digit_separators(
+    &self,
+    num_start: usize,
+    digits_start: usize,
+    is_digit: fn(u8) -> bool,
+  ) -> Result<(), LexError> {
+    let end = self.next_offset();
+    let is_misplaced = (digits_start..end).any(|position| {
+      self.code_bytes[position] == b'_'
+        && (position == digits_start
+          || position + 1 == end
+          || !is_digit(self.code_bytes[position - 1])
+          || !is_digit(self.code_bytes[position + 1]))
+    });
+
+    if is_misplaced {
+      Err(LexError::InvalidDigitSeparator {
+        span: self.span_from(num_start),
+        lexeme: String::from(&self.code[num_start..end]),
+      })
+    } else {
+      Ok(())
+    }
+  }
+
+  // stops on the last character before the line break so the break itself is seen as trivia
+  fn eat_single_line_comment(&mut self, comment_start: usize) {
+    self.advance_while(|byte| byte != NEW_LINE && byte != CARRIAGE_RETURN);
+
+    self.record_trivia(TriviaKind::LineComment, comment_start);
+  }
+
+  // a `#!` interpreter line at the very top of a script, skipped whatever the comment options say
+  fn is_shebang_start(&self) -> bool {
+    self.current == self.start && self.code_bytes[self.start..].starts_with(b"#!")
+  }
+
+  fn eat_shebang(&mut self) {
+    self.advance_while(|byte| byte != NEW_LINE && byte != CARRIAGE_RETURN);
+
+    self.record_trivia(TriviaKind::Shebang, self.start);
+  }
+
+  fn eat_block_comment(&mut self) -> Result<(), LexError> {
+    // the opening `/` has already been consumed by lookup so the comment starts one byte back
+    let comment_start = self.current - 1;
+    let mut depth = 1;
+
+    while depth > 0 {
+      self.advance_while(|byte| byte != b'/' && byte != b'*');
+      self.advance();
+
+      if self.is_eof(0) {
+        return Err(LexError::UnterminatedBlockComment {
+          span: Span::new(comment_start, comment_start + 2),
+        });
+      }
+
+      match self.get_current_char_byte() {
+        b'/' if self.lookup(b'*') => depth += 1,
+        b'*' if self.lookup(b'/') => depth -= 1,
+        _ => (),
+      }
+    }
+
+    self.record_trivia(TriviaKind::BlockComment, comment_start);
+
+    Ok(())
   }
 
-  fn is_alpha_numeric(&self, character: u8) -> bool {
-    self.is_digit(character) || self.is_alphabet(character)
+  fn is_identifier_char(&self, character: char) -> bool {
+    character.is_alphanumeric() || character == '_'
   }
 
   fn eat_identifier(&mut self) {
-    let ident_start_col = self.col;
-    let ident_start_row = self.row;
     let ident_start = self.current;
 
-    while !self.is_eof(1) && (self.is_alpha_numeric(self.peek()) || self.peek() == b'_') {
-      self.advance();
+    // ASCII runs are checked on the bytes, only other text needs decoding
+    loop {
+      self.advance_while(|byte| byte.is_ascii_alphanumeric() || byte == b'_');
+
+      match self.peek_char() {
+        Some(character) if !character.is_ascii() && self.is_identifier_char(character) => self.advance(),
+        _ => break,
+      }
     }
 
-    let ident_name = str::from_utf8(&self.code_bytes[ident_start..self.current + 1]).unwrap();
+    let ident_name = &self.code[ident_start..self.next_offset()];
+
+    let span = self.span_from(ident_start);
 
-    self.tokens.push(
-      Token::Identifier(TokenLocation {
-        row: ident_start_row,
-        col: ident_start_col
-      }, ident_name)
-    )
+    match Keywords::from_identifier(ident_name) {
+      Some(keyword) => self.emit(Token::Keyword(span, keyword)),
+      None => self.emit(Token::Identifier(span, ident_name)),
+    }
   }
 
-  fn get_current_token_location(&self) -> TokenLocation {
-    TokenLocation {
-      row: self.row,
-      col: self.col,
+  // span from start up to and including the character currently being looked at
+  fn span_from(&self, start: usize) -> Span {
+    Span::new(start, self.next_offset())
+  }
+
+  fn eat_token(&mut self) -> Result<(), LexError> {
+    match self.get_current_char_byte() {
+      b' ' | b'\t' => {
+        let start = self.current;
+        self.advance_while(|byte| byte == b' ' || byte == b'\t');
+        self.record_trivia(TriviaKind::Whitespace, start)
+      }
+      NEW_LINE => self.record_trivia(TriviaKind::Newline, self.current),
+      CARRIAGE_RETURN => {
+        let start = self.current;
+        // a windows line ending is kept as one newline
+        self.lookup(NEW_LINE);
+        self.record_trivia(TriviaKind::Newline, start)
+      }
+      b'+' => {
+        let start = self.current;
+        let operator = if self.lookup(b'+') {
+          Operators::Increment
+        } else if self.lookup(b'=') {
+          Operators::PlusAssignment
+        } else {
+          Operators::Plus
+        };
+
+        self.emit(Token::Operator(self.span_from(start), operator))
+      }
+      b'-' => {
+        let start = self.current;
+        let operator = if self.lookup(b'-') {
+          Operators::Decrement
+        } else if self.lookup(b'=') {
+          Operators::MinusAssignment
+        } else {
+          Operators::Minus
+        };
+
+        self.emit(Token::Operator(self.span_from(start), operator))
+      }
+      b'*' => {
+        let start = self.current;
+        let operator = if self.lookup(b'=') {
+          Operators::StarAssignment
+        } else {
+          Operators::Star
+        };
+
+        self.emit(Token::Operator(self.span_from(start), operator))
+      }
+      b'/' => {
+        if self.lookup(b'/') {
+          self.eat_single_line_comment(self.current - 1)
+        } else if self.lookup(b'*') {
+          self.eat_block_comment()?
+        } else if self.lookup(b'=') {
+          self.emit(Token::Operator(self.span_from(self.current - 1), Operators::SlashAssignment))
+        } else {
+          self.emit(Token::Operator(self.span_from(self.current), Operators::Slash))
+        }
+      }
+      b'%' => self.emit(Token::Operator(self.span_from(self.current), Operators::Percent)),
+      b'&' => self.emit(Token::Operator(self.span_from(self.current), Operators::BitAnd)),
+      b'|' => self.emit(Token::Operator(self.span_from(self.current), Operators::BitOr)),
+      b'^' => self.emit(Token::Operator(self.span_from(self.current), Operators::BitXor)),
+      b'~' => self.emit(Token::Operator(self.span_from(self.current), Operators::BitNot)),
+      b'?' if self.peek() == Some(b'?') => {
+        self.advance();
+        self.emit(Token::Operator(self.span_from(self.current - 1), Operators::Coalesce))
+      }
+      b'{' => {
+        if let Some(interpolation) = self.interpolations.last_mut() {
+          interpolation.depth += 1;
+        }
+
+        self.emit(Token::OpenBrace(self.span_from(self.current)))
+      }
+      b'}' => self.eat_close_brace()?,
+      b'(' => self.emit(Token::OpenParen(self.span_from(self.current))),
+      b')' => self.emit(Token::CloseParen(self.span_from(self.current))),
+      b'[' => self.emit(Token::OpenBracket(self.span_from(self.current))),
+      b']' => self.emit(Token::CloseBracket(self.span_from(self.current))),
+      b';' => self.emit(Token::Semicolon(self.span_from(self.current))),
+      b':' => self.emit(Token::Colon(self.span_from(self.current))),
+      b',' => self.emit(Token::Comma(self.span_from(self.current))),
+      b'.' => self.emit(Token::Dot(self.span_from(self.current))),
+      b'=' => {
+        let start = self.current;
+        let operator = if self.lookup(b'=') {
+          Operators::Equal
+        } else {
+          Operators::Assignment
+        };
+
+        self.emit(Token::Operator(self.span_from(start), operator))
+      }
+      b'!' => {
+        let start = self.current;
+        let operator = if self.lookup(b'=') {
+          Operators::NotEqual
+        } else {
+          Operators::Bang
+        };
+
+        self.emit(Token::Operator(self.span_from(start), operator))
+      }
+      b'<' => {
+        let start = self.current;
+        let operator = if self.lookup(b'<') {
+          Operators::ShiftLeft
+        } else if self.lookup(b'=') {
+          Operators::LessEqual
+        } else {
+          Operators::Less
+        };
+
+        self.emit(Token::Operator(self.span_from(start), operator))
+      }
+      b'>' => {
+        let start = self.current;
+        let operator = if self.lookup(b'>') {
+          Operators::ShiftRight
+        } else if self.lookup(b'=') {
+          Operators::GreaterEqual
+        } else {
+          Operators::Greater
+        };
+
+        self.emit(Token::Operator(self.span_from(start), operator))
+      }
+      b'"' => self.eat_string()?,
+      b'r' if self.is_raw_string_start() => self.eat_raw_string()?,
+      b'a'..=b'z' | b'A'..=b'Z' => self.eat_identifier(),
+      b'#' if self.is_shebang_start() => self.eat_shebang(),
+      b'#' if self.options.hash_comments => self.eat_single_line_comment(self.current),
+      b'0'..=b'9' => self.eat_number()?,
+      _ if self.get_current_char().is_alphabetic() => self.eat_identifier(),
+      _ => {
+        return Err(LexError::InvalidToken {
+          span: self.span_from(self.current),
+          lexeme: self.get_current_char().to_string(),
+        })
+      }
     }
+
+    Ok(())
   }
 
-  pub fn lex(&mut self, code: &'a str) -> &Vec<Token> {
-    self.tokens = vec![];
-    self.current = 0;
-    self.code_bytes = code.as_bytes();
+  // drains the whole input, for callers that want every token or every error up front
+  pub fn lex(self) -> Result<Vec<Token<'a>>, Vec<LexError>> {
+    let mut tokens = vec![];
+    let mut errors = vec![];
 
-    while self.current < code.len() {
-      let char_string = code.get(self.current..self.current + 1).unwrap();
-      match self.get_current_char_byte() {
-        b' ' => (),
-        NEW_LINE | LINE_FEED => (),
-        b'+' => {
-          if self.lookup(b'+') {
-            self.tokens.push(Token::Operator(
-              self.get_current_token_location(),
-              Operators::Increment,
-            ))
-          } else {
-            self.tokens.push(Token::Operator(
-              self.get_current_token_location(),
-              Operators::Plus,
-            ))
-          }
+    for result in self {
+      match result {
+        Ok(token) => tokens.push(token),
+        Err(error) => errors.push(error),
+      }
+    }
+
+    if errors.is_empty() {
+      Ok(tokens)
+    } else {
+      Err(errors)
+    }
+  }
+
+  // like lex but keeps whitespace and comments attached to the tokens around them so the
+  // source can be rebuilt byte for byte
+  pub fn lex_with_trivia(mut self) -> Result<Vec<TriviaToken<'a>>, Vec<LexError>> {
+    self.is_collecting_trivia = true;
+
+    let mut tokens: Vec<TriviaToken<'a>> = vec![];
+    let mut errors = vec![];
+
+    while let Some(result) = self.next() {
+      let mut leading = std::mem::take(&mut self.trivia);
+
+      if let Some(previous) = tokens.last_mut() {
+        let line_end = leading
+          .iter()
+          .position(|trivia| trivia.kind == TriviaKind::Newline)
+          .map(|index| index + 1)
+          .unwrap_or(leading.len());
+
+        previous.trailing = leading.drain(..line_end).collect();
+      }
+
+      match result {
+        Ok(token) => tokens.push(TriviaToken {
+          leading,
+          token,
+          trailing: vec![],
+        }),
+        Err(error) => errors.push(error),
+      }
+    }
+
+    if errors.is_empty() {
+      Ok(tokens)
+    } else {
+      Err(errors)
+    }
+  }
+
+  // true when the code stops partway through a string, block comment or bracket, so a prompt
+  // reading it a line at a time should wait for more rather than report it. Code with any
+  // other error is complete, more input won't fix it
+  pub fn needs_more_input(self) -> bool {
+    let mut depth = 0;
+
+    for result in self {
+      match result {
+        Ok(Token::OpenBrace(_)) | Ok(Token::OpenParen(_)) | Ok(Token::OpenBracket(_)) => depth += 1,
+        Ok(Token::CloseBrace(_)) | Ok(Token::CloseParen(_)) | Ok(Token::CloseBracket(_)) => depth -= 1,
+        Ok(_) => (),
+        Err(LexError::UnterminatedString { .. })
+        | Err(LexError::UnterminatedBlockComment { .. })
+        | Err(LexError::UnterminatedInterpolation { .. }) => depth = depth.max(1),
+        Err(_) => return false,
+      }
+    }
+
+    depth > 0
+  }
+}
+
+impl<'a> Iterator for Lexer<'a> {
+  type Item = Result<Token<'a>, LexError>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    while self.pending.is_empty() && !self.is_finished {
+      if self.current < self.code_bytes.len() {
+        // the offending character is skipped so every bad token in the file gets reported
+        if let Err(error) = self.eat_token() {
+          self.report(error);
         }
-        b'-' => {
-          if self.lookup(b'-') {
-            self.tokens.push(Token::Operator(
-              self.get_current_token_location(),
-              Operators::Decrement,
-            ))
-          } else {
-            self.tokens.push(Token::Operator(
-              self.get_current_token_location(),
-              Operators::Minus,
-            ))
-          }
+
+        self.advance();
+      } else {
+        let end = self.code_bytes.len();
+
+        if let Some(interpolation) = self.interpolations.pop() {
+          self.interpolations.clear();
+          self.report(LexError::UnterminatedInterpolation {
+            span: Span::new(interpolation.start, interpolation.start + 2),
+          });
         }
-        b'*' => self.tokens.push(Token::Operator(
-          self.get_current_token_location(),
-          Operators::Star,
-        )),
-        b'{' => self
-          .tokens
-          .push(Token::OpenBrace(self.get_current_token_location())),
-        b'}' => self
-          .tokens
-          .push(Token::CloseBrace(self.get_current_token_location())),
-        b'(' => self
-          .tokens
-          .push(Token::OpenParen(self.get_current_token_location())),
-        b')' => self
-          .tokens
-          .push(Token::CloseParen(self.get_current_token_location())),
-        b'=' => self.tokens.push(Token::Operator(
-          self.get_current_token_location(),
-          Operators::Assignment,
-        )),
-        b'"' => self.eat_string(),
-        b'a'..=b'z' | b'A'..=b'Z' => self.eat_identifier(),
-        b'#' => self.eat_hash_single_line_comment(),
-        b'0'..=b'9' => self.eat_number(),
-        _ => panic!(
-          "invalid token {} found at {}:{}",
-          char_string, self.row, self.col
-        )
+
+        self.emit(Token::Eof(Span::new(end, end)));
+        self.is_finished = true;
       }
-      self.advance();
     }
-    self.tokens.push(Token::EOF(self.get_current_token_location()));
 
-    &self.tokens
+    self.pending.pop_front()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn token_lines(code: &str) -> Vec<usize> {
+    let source_map = SourceMap::new(code);
+
+    Lexer::new(code)
+      .lex()
+      .unwrap()
+      .iter()
+      .map(|token| source_map.location(token.span().start).line)
+      .collect()
+  }
+
+  fn token_locations(code: &str) -> Vec<(usize, usize)> {
+    let source_map = SourceMap::new(code);
+
+    Lexer::new(code)
+      .map(|result| result.map_or_else(|error| error.span(), |token| token.span()))
+      .map(|span| source_map.location(span.start))
+      .map(|location| (location.line, location.col))
+      .collect()
+  }
+
+  fn newline_spans(code: &str) -> Vec<Span> {
+    Lexer::new(code)
+      .lex_with_trivia()
+      .unwrap()
+      .iter()
+      .flat_map(|token| token.leading.iter().chain(token.trailing.iter()))
+      .filter(|trivia| trivia.kind == TriviaKind::Newline)
+      .map(|trivia| trivia.span)
+      .collect()
+  }
+
+  fn kinds(code: &str) -> Vec<String> {
+    Lexer::new(code)
+      .filter_map(|result| result.ok())
+      .map(|token| token.kind())
+      .collect()
+  }
+
+  fn error_count(code: &str) -> usize {
+    Lexer::new(code).filter(|result| result.is_err()).count()
+  }
+
+  fn number(code: &str) -> f64 {
+    match Lexer::new(code).lex().unwrap().first() {
+      Some(Token::Literal(_, Literals::Number(value))) => *value,
+      token => panic!("expected a number, found {:?}", token),
+    }
+  }
+
+  fn string(code: &str) -> String {
+    match Lexer::new(code).lex().unwrap().first() {
+      Some(Token::Literal(_, Literals::String(value))) => value.to_string(),
+      token => panic!("expected a string, found {:?}", token),
+    }
+  }
+
+  fn errors(code: &str) -> Vec<String> {
+    Lexer::new(code).lex().unwrap_err().iter().map(|error| error.to_string()).collect()
+  }
+
+  #[test]
+  fn unfinished_code_needs_more_input() {
+    let needs_more_input = |code| Lexer::new(code).needs_more_input();
+
+    assert!(needs_more_input("fun f() {"));
+    assert!(needs_more_input("print (1 +"));
+    assert!(needs_more_input("var a = [1,"));
+    assert!(needs_more_input("print \"abc"));
+    assert!(needs_more_input("print \"${1 +"));
+    assert!(needs_more_input("/* still a comment"));
+    assert!(!needs_more_input("fun f() {}"));
+    assert!(!needs_more_input("print 1 +"));
+    assert!(!needs_more_input("print }"));
+    assert!(!needs_more_input("{ @"));
+  }
+
+  #[test]
+  fn operators_at_eof() {
+    assert_eq!(kinds("a +"), vec!["Identifier", "Plus", "Eof"]);
+    assert_eq!(kinds("="), vec!["Assignment", "Eof"]);
+    assert_eq!(kinds("<"), vec!["Less", "Eof"]);
+    assert_eq!(kinds(">"), vec!["Greater", "Eof"]);
+    assert_eq!(kinds("!"), vec!["Bang", "Eof"]);
+    assert_eq!(kinds("*"), vec!["Star", "Eof"]);
+    assert_eq!(kinds("/"), vec!["Slash", "Eof"]);
+    assert_eq!(kinds("-"), vec!["Minus", "Eof"]);
+    assert_eq!(kinds("a ?? b"), vec!["Identifier", "Coalesce", "Identifier", "Eof"]);
+    assert_eq!(kinds("a ?"), vec!["Identifier", "Eof"]);
+    assert_eq!(error_count("a ? b"), 1);
+  }
+
+  #[test]
+  fn brackets_and_colons() {
+    assert_eq!(
+      kinds("a[1:] in"),
+      vec!["Identifier", "OpenBracket", "Number", "Colon", "CloseBracket", "In", "Eof"]
+    );
+  }
+
+  #[test]
+  fn numbers_at_eof() {
+    assert_eq!(kinds("1"), vec!["Number", "Eof"]);
+    assert_eq!(kinds("1.5"), vec!["Number", "Eof"]);
+    assert_eq!(kinds("1."), vec!["Number", "Dot", "Eof"]);
+    assert_eq!(kinds("0"), vec!["Number", "Eof"]);
+    assert_eq!(error_count("0x"), 1);
+    assert_eq!(error_count("1e"), 1);
+    assert_eq!(error_count("1e+"), 1);
+    assert_eq!(error_count("1_"), 1);
+  }
+
+  #[test]
+  fn hex_octal_and_binary_numbers() {
+    assert_eq!(number("0x1F"), 31.0);
+    assert_eq!(number("0XfF"), 255.0);
+    assert_eq!(number("0o755"), 493.0);
+    assert_eq!(number("0b1010"), 10.0);
+    assert_eq!(errors("0xZZ"), vec!["invalid number literal 0xZZ"]);
+    assert_eq!(errors("0x;"), vec!["invalid number literal 0x"]);
+    assert_eq!(errors("0b102"), vec!["invalid number literal 0b102"]);
+    assert_eq!(errors("0o8"), vec!["invalid number literal 0o8"]);
+  }
+
+  #[test]
+  fn exponents() {
+    assert_eq!(number("1e9"), 1e9);
+    assert_eq!(number("2.5e-3"), 2.5e-3);
+    assert_eq!(number("1E+6"), 1e6);
+    assert_eq!(errors("1e;"), vec!["invalid number literal 1e"]);
+    assert_eq!(errors("2.5e-x"), vec!["invalid number literal 2.5e-"]);
+    assert_eq!(errors("1e_"), vec!["invalid number literal 1e_"]);
+  }
+
+  #[test]
+  fn digit_separators() {
+    assert_eq!(number("1_000_000"), 1_000_000.0);
+    assert_eq!(number("1_000.000_1"), 1_000.000_1);
+    assert_eq!(number("1e1_0"), 1e10);
+    assert_eq!(number("0xFF_FF"), 65535.0);
+    assert_eq!(number("0b1010_0101"), 165.0);
+    assert_eq!(errors("_1"), vec!["invalid token _"]);
+    assert_eq!(errors("0x_FF"), vec!["misplaced digit separator `_` in number literal 0x_FF"]);
+    assert_eq!(errors("1_;"), vec!["misplaced digit separator `_` in number literal 1_"]);
+    assert_eq!(errors("1__0"), vec!["misplaced digit separator `_` in number literal 1__0"]);
+    assert_eq!(errors("1_.5"), vec!["misplaced digit separator `_` in number literal 1_.5"]);
+    assert_eq!(errors("1.0_e5"), vec!["misplaced digit separator `_` in number literal 1.0_e5"]);
+  }
+
+  #[test]
+  fn fraction_needs_a_digit_after_the_dot() {
+    assert_eq!(kinds("1.foo"), vec!["Number", "Dot", "Identifier", "Eof"]);
+    assert_eq!(kinds("1.2.3"), vec!["Number", "Dot", "Number", "Eof"]);
+  }
+
+  #[test]
+  fn strings_at_eof() {
+    assert_eq!(error_count("\""), 1);
+    assert_eq!(error_count("\"abc"), 1);
+    assert_eq!(error_count("\"a\\"), 1);
+    assert_eq!(error_count("\"\\u"), 2);
+    assert_eq!(error_count("\"\\u{"), 2);
+    assert_eq!(error_count("\"\\u{1F60"), 2);
+    assert_eq!(error_count("\"$"), 1);
+    assert_eq!(error_count("\"${"), 1);
+    assert_eq!(error_count("r\""), 1);
+    assert_eq!(error_count("r#\"a\""), 1);
+  }
+
+  #[test]
+  fn raw_strings_close_on_as_many_hashes_as_they_opened_with() {
+    assert_eq!(string(r#"r"C:\path\${x}""#), r"C:\path\${x}");
+    assert_eq!(string(r##"r#"say "hi""#"##), r#"say "hi""#);
+    assert_eq!(string(r###"r##"a "# b"##"###), r##"a "# b"##);
+    // a `#` past the ones that closed it is a comment of its own
+    assert_eq!(kinds(r###"r#"a"## b"###), vec!["String", "Eof"]);
+    assert_eq!(errors(r###"r##"a"#"###), vec!["non terminated string"]);
+    assert_eq!(errors(r##"r#"a""##), vec!["non terminated string"]);
+  }
+
+  #[test]
+  fn strings_spanning_lines() {
+    let code = "print \"a\n  b\" x;\n\"c\n${d}\ne\" \"f\ng";
+
+    assert_eq!(string("\"a\n  b\""), "a\n  b");
+    assert_eq!(string("r\"a\nb\""), "a\nb");
+    // the string left open is reported where it starts
+    assert_eq!(token_locations(code), vec![(1, 1), (1, 7), (2, 6), (2, 7), (3, 1), (4, 3), (4, 4), (5, 4), (6, 2)]);
+    assert_eq!(errors(code), vec!["non terminated string"]);
+  }
+
+  #[test]
+  fn identifiers_and_comments_at_eof() {
+    assert_eq!(kinds("r"), vec!["Identifier", "Eof"]);
+    assert_eq!(kinds("r#"), vec!["Identifier", "Eof"]);
+    assert_eq!(kinds("// note"), vec!["Eof"]);
+    assert_eq!(kinds("#"), vec!["Eof"]);
+    assert_eq!(error_count("/*"), 1);
+    assert_eq!(error_count("/* /*  */"), 1);
+  }
+
+  #[test]
+  fn shebang_only_on_the_first_line() {
+    let trivia_kinds = |code| {
+      Lexer::new(code)
+        .lex_with_trivia()
+        .unwrap()
+        .iter()
+        .flat_map(|token| token.leading.iter().chain(token.trailing.iter()))
+        .map(|trivia| trivia.kind)
+        .collect::<Vec<_>>()
+    };
+    let errors_without_hash_comments = |code| {
+      let options = LexerOptions {
+        hash_comments: false,
+        ..LexerOptions::default()
+      };
+      Lexer::with_options(code, options).filter(|result| result.is_err()).count()
+    };
+
+    let kinds = vec![TriviaKind::Shebang, TriviaKind::Newline, TriviaKind::LineComment];
+    assert_eq!(trivia_kinds("#!/usr/bin/env rslox\n#!x"), kinds);
+    assert_eq!(errors_without_hash_comments("#!/usr/bin/env rslox\nprint 1;"), 0);
+    assert_eq!(errors_without_hash_comments("print 1;\n#!/usr/bin/env rslox"), 1);
+    assert_eq!(errors_without_hash_comments(" #!/usr/bin/env rslox"), 1);
+  }
+
+  #[test]
+  fn lexing_from_an_offset_keeps_offsets_into_all_of_the_code() {
+    let code = "print 1;\n#!/usr/bin/env rslox\nimport \"m.lox\";";
+    let tokens = Lexer::from_offset(code, 9).lex().unwrap();
+    let kinds = tokens.iter().map(|token| token.kind()).collect::<Vec<_>>();
+
+    assert_eq!(kinds, vec!["Import", "String", "Semicolon", "Eof"]);
+    assert_eq!(tokens[0].span(), Span::new(30, 36));
+  }
+
+  #[test]
+  fn crlf_is_one_newline() {
+    let code = "a\r\nb\r\n";
+
+    assert_eq!(newline_spans(code), vec![Span::new(1, 3), Span::new(4, 6)]);
+    assert_eq!(token_lines(code), vec![1, 2, 3]);
+  }
+
+  #[test]
+  fn bare_cr_and_lf_are_newlines() {
+    assert_eq!(newline_spans("a\rb\nc"), vec![Span::new(1, 2), Span::new(3, 4)]);
+    assert_eq!(token_lines("a\rb\nc"), vec![1, 2, 3, 3]);
+  }
+
+  #[test]
+  fn mixed_line_endings() {
+    let code = "a // note\r\nb\n\rc\r\r\nd";
+
+    assert_eq!(token_lines(code), vec![1, 2, 4, 6, 6]);
+    assert_eq!(newline_spans(code).len(), 5);
+  }
+
+  #[test]
+  fn crlf_inside_strings_and_block_comments() {
+    let code = "\"one\r\ntwo\" /* x\r\ny */ z";
+
+    assert_eq!(token_lines(code), vec![1, 3, 3]);
+  }
+
+  #[test]
+  fn slash_is_division_and_two_slashes_start_a_comment() {
+    assert_eq!(kinds("a / b"), vec!["Identifier", "Slash", "Identifier", "Eof"]);
+    assert_eq!(kinds("a/b"), vec!["Identifier", "Slash", "Identifier", "Eof"]);
+    assert_eq!(kinds("a // b / c"), vec!["Identifier", "Eof"]);
+    assert_eq!(kinds("a / / b"), vec!["Identifier", "Slash", "Slash", "Identifier", "Eof"]);
+    assert_eq!(kinds("a // note\nb"), vec!["Identifier", "Identifier", "Eof"]);
+    assert_eq!(kinds("a ///"), vec!["Identifier", "Eof"]);
+    assert_eq!(token_lines("a // note\n/ b"), vec![1, 2, 2, 2]);
+  }
+
+  #[test]
+  fn nested_block_comments() {
+    assert_eq!(kinds("a /* b /* c */ d */ e"), vec!["Identifier", "Identifier", "Eof"]);
+    assert_eq!(kinds("/* /* /* */ */ */"), vec!["Eof"]);
+    assert_eq!(kinds("/**/ a /***/"), vec!["Identifier", "Eof"]);
+    // the error points at the outermost opening, not the one left unclosed inside it
+    let errors = Lexer::new("a /* b /* c */ d").lex().unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].to_string(), "non terminated block comment");
+    assert_eq!(errors[0].span(), Span::new(2, 4));
+  }
+
+  #[test]
+  fn tokens_after_a_multi_line_block_comment() {
+    let code = "a /* one\ntwo /* three\n */\n*/ b\n  c";
+    let tokens = Lexer::new(code).lex().unwrap();
+
+    assert_eq!(tokens[1].span(), Span::new(29, 30));
+    assert_eq!(tokens[2].span(), Span::new(33, 34));
+    assert_eq!(token_locations(code), vec![(1, 1), (4, 4), (5, 3), (5, 4)]);
+  }
+
+  #[test]
+  fn two_character_comparisons() {
+    assert_eq!(kinds("a <= b"), vec!["Identifier", "LessEqual", "Identifier", "Eof"]);
+    assert_eq!(kinds("a >= b"), vec!["Identifier", "GreaterEqual", "Identifier", "Eof"]);
+    assert_eq!(kinds("a == b"), vec!["Identifier", "Equal", "Identifier", "Eof"]);
+    assert_eq!(kinds("a != b"), vec!["Identifier", "NotEqual", "Identifier", "Eof"]);
+    assert_eq!(kinds("<=>=!==="), vec!["LessEqual", "GreaterEqual", "NotEqual", "Equal", "Eof"]);
+    assert_eq!(
+      kinds("< = > = ! ="),
+      vec!["Less", "Assignment", "Greater", "Assignment", "Bang", "Assignment", "Eof"]
+    );
+    assert_eq!(Lexer::new("a <= b").lex().unwrap()[1].span(), Span::new(2, 4));
+  }
+
+  #[test]
+  fn bang_and_or() {
+    assert_eq!(kinds("!a"), vec!["Bang", "Identifier", "Eof"]);
+    assert_eq!(kinds("!!a"), vec!["Bang", "Bang", "Identifier", "Eof"]);
+    assert_eq!(kinds("a and b or c"), vec!["Identifier", "And", "Identifier", "Or", "Identifier", "Eof"]);
+    // only the whole word is a keyword
+    assert_eq!(kinds("android orbit and_or"), vec!["Identifier", "Identifier", "Identifier", "Eof"]);
+    assert_eq!(kinds("AND Or"), vec!["Identifier", "Identifier", "Eof"]);
+  }
+
+  #[test]
+  fn punctuation() {
+    assert_eq!(
+      kinds("{}()[];:,."),
+      vec![
+        "OpenBrace",
+        "CloseBrace",
+        "OpenParen",
+        "CloseParen",
+        "OpenBracket",
+        "CloseBracket",
+        "Semicolon",
+        "Colon",
+        "Comma",
+        "Dot",
+        "Eof"
+      ]
+    );
+
+    let spans = Lexer::new("f(a, b);").lex().unwrap().iter().map(|token| token.span()).collect::<Vec<_>>();
+    assert_eq!(
+      spans,
+      vec![
+        Span::new(0, 1),
+        Span::new(1, 2),
+        Span::new(2, 3),
+        Span::new(3, 4),
+        Span::new(5, 6),
+        Span::new(6, 7),
+        Span::new(7, 8),
+        Span::new(8, 8)
+      ]
+    );
+  }
+
+  #[test]
+  fn escape_sequences() {
+    assert_eq!(string(r#""a\nb""#), "a\nb");
+    assert_eq!(string(r#""a\tb""#), "a\tb");
+    assert_eq!(string(r#""say \"hi\"""#), "say \"hi\"");
+    assert_eq!(string(r#""\${x}""#), "${x}");
+    assert_eq!(string(r#""C:\\path""#), "C:\\path");
+    assert_eq!(string(r#""\u{41}\u{1F600}""#), "A\u{1F600}");
+    assert_eq!(string(r#""\n\t\"\$\\""#), "\n\t\"$\\");
+    assert_eq!(errors(r#""\q""#), vec![r"invalid escape sequence \q"]);
+    assert_eq!(errors(r#""\u{110000}""#), vec![r"invalid escape sequence \u{110000}"]);
+    assert_eq!(errors(r#""\u{}""#), vec![r"invalid escape sequence \u{"]);
+  }
+
+  #[test]
+  fn invalid_escape_location() {
+    let code = "print \"ok\";\nprint \"a\\qb\";";
+    let error = &Lexer::new(code).lex().unwrap_err()[0];
+    let location = SourceMap::new(code).location(error.span().start);
+
+    assert_eq!(error.span(), Span::new(20, 22));
+    assert_eq!((location.line, location.col), (2, 9));
+  }
+
+  #[test]
+  fn lex_errors_carry_their_span_and_lexeme() {
+    let error = Lexer::new("a @ b").lex().unwrap_err().remove(0);
+
+    match &error {
+      LexError::InvalidToken { span, lexeme } => {
+        assert_eq!(*span, Span::new(2, 3));
+        assert_eq!(lexeme, "@");
+      }
+      error => panic!("expected an invalid token, found {:?}", error),
+    }
+    assert_eq!(error.to_string(), "invalid token @");
+
+    // a character outside ASCII is reported whole, not as one of its bytes
+    match Lexer::new("é€").lex().unwrap_err().as_slice() {
+      [LexError::InvalidToken { span, lexeme }] => {
+        assert_eq!(*span, Span::new(2, 5));
+        assert_eq!(lexeme, "€");
+      }
+      errors => panic!("expected one invalid token, found {:?}", errors),
+    }
+
+    assert_eq!(errors("\"abc"), vec!["non terminated string"]);
+    assert_eq!(errors("/* abc"), vec!["non terminated block comment"]);
+    assert_eq!(errors("\"${abc"), vec!["non terminated string interpolation"]);
+  }
+
+  #[test]
+  fn every_error_is_collected() {
+    let code = "var a = @;\nvar b = \"\\q\" + 0x;\nprint a $ b;";
+
+    assert_eq!(
+      errors(code),
+      vec!["invalid token @", r"invalid escape sequence \q", "invalid number literal 0x", "invalid token $"]
+    );
+    // the tokens around the bad ones are still produced
+    assert_eq!(kinds("@ a $ b"), vec!["Identifier", "Identifier", "Eof"]);
+    assert_eq!(token_locations("@ a\n$ b"), vec![(1, 1), (1, 3), (2, 1), (2, 3), (2, 4)]);
+  }
+
+  #[test]
+  fn iterator_ends_after_eof() {
+    let mut lexer = Lexer::new("a @");
+
+    assert!(matches!(lexer.next(), Some(Ok(Token::Identifier(_, "a")))));
+    assert!(matches!(lexer.next(), Some(Err(LexError::InvalidToken { .. }))));
+    assert!(matches!(lexer.next(), Some(Ok(Token::Eof(_)))));
+    assert!(lexer.next().is_none());
+    assert!(lexer.next().is_none());
+
+    let mut lexer = Lexer::new("");
+    assert!(matches!(lexer.next(), Some(Ok(Token::Eof(span))) if span == Span::new(0, 0)));
+    assert!(lexer.next().is_none());
+
+    let kinds = Lexer::new("a b c").take(2).map(|result| result.unwrap().kind()).collect::<Vec<_>>();
+    assert_eq!(kinds, vec!["Identifier", "Identifier"]);
+  }
+
+  #[test]
+  fn spans_are_byte_offsets() {
+    let code = "var naïve = \"é\" + 12.5;";
+    let spans = Lexer::new(code).lex().unwrap().iter().map(|token| token.span()).collect::<Vec<_>>();
+
+    assert_eq!(
+      spans,
+      vec![
+        Span::new(0, 3),
+        Span::new(4, 10),
+        Span::new(11, 12),
+        Span::new(13, 17),
+        Span::new(18, 19),
+        Span::new(20, 24),
+        Span::new(24, 25),
+        Span::new(25, 25)
+      ]
+    );
+    assert_eq!(&code[4..10], "naïve");
+    assert_eq!(&code[13..17], "\"é\"");
+  }
+
+  #[test]
+  fn trivia_and_tokens_rebuild_the_source() {
+    let rebuild = |code: &str| {
+      Lexer::new(code)
+        .lex_with_trivia()
+        .unwrap()
+        .iter()
+        .flat_map(|token| {
+          let leading = token.leading.iter().map(|trivia| trivia.span);
+          let trailing = token.trailing.iter().map(|trivia| trivia.span);
+
+          leading.chain(std::iter::once(token.token.span())).chain(trailing).collect::<Vec<_>>()
+        })
+        .map(|span| &code[span.start..span.end])
+        .collect::<String>()
+    };
+
+    let sources = [
+      "",
+      "#!/usr/bin/env rslox\nprint 1;",
+      "var a = 1; // one\n\n/* two\n /* three */ */\tvar b = a;\r\n",
+      "fun f(x) {\r\n  return \"a ${x + 1} b\\n\";\r}\n# old comment\n  ",
+      "print r#\"raw \"text\"\"#;   \t  print 0x1F_FF + 1e3;",
+      "var naïve = \"é\";  // ünïcode\n",
+    ];
+
+    for source in sources {
+      assert_eq!(rebuild(source), source);
+    }
+  }
+
+  #[test]
+  fn nested_interpolations() {
+    assert_eq!(
+      kinds("\"a ${ \"b ${c} d\" } e\""),
+      vec!["Interpolation", "Interpolation", "Identifier", "InterpolationEnd", "InterpolationEnd", "Eof"]
+    );
+    // braces opened inside `${` have to close before the interpolation does
+    assert_eq!(
+      kinds("\"${ {a: 1}[\"a\"] }\""),
+      vec![
+        "Interpolation",
+        "OpenBrace",
+        "Identifier",
+        "Colon",
+        "Number",
+        "CloseBrace",
+        "OpenBracket",
+        "String",
+        "CloseBracket",
+        "InterpolationEnd",
+        "Eof"
+      ]
+    );
+
+    let tokens = Lexer::new("\"x${a}y${b}z\"").lex().unwrap();
+    let parts = tokens
+      .iter()
+      .filter_map(|token| match token {
+        Token::Interpolation(_, value) | Token::InterpolationEnd(_, value) => Some(value.to_string()),
+        _ => None,
+      })
+      .collect::<Vec<_>>();
+    assert_eq!(parts, vec!["x", "y", "z"]);
+  }
+
+  #[test]
+  fn unterminated_interpolations() {
+    let only_error = |code| {
+      let errors = Lexer::new(code).lex().unwrap_err();
+      assert_eq!(errors.len(), 1, "{:?}", errors);
+      (errors[0].to_string(), errors[0].span())
+    };
+
+    assert_eq!(only_error("\"a ${b"), (String::from("non terminated string interpolation"), Span::new(3, 5)));
+    // the innermost one left open is reported
+    assert_eq!(
+      only_error("\"a ${ \"b ${c"),
+      (String::from("non terminated string interpolation"), Span::new(9, 11))
+    );
+    assert_eq!(only_error("\"a ${ {b }"), (String::from("non terminated string interpolation"), Span::new(3, 5)));
+    assert_eq!(only_error("\"a ${b} c"), (String::from("non terminated string"), Span::new(6, 9)));
+  }
+
+  #[test]
+  fn percent() {
+    assert_eq!(kinds("a % b"), vec!["Identifier", "Percent", "Identifier", "Eof"]);
+    assert_eq!(kinds("7%-3"), vec!["Number", "Percent", "Minus", "Number", "Eof"]);
+    assert_eq!(Lexer::new("a % b").lex().unwrap()[1].span(), Span::new(2, 3));
+  }
+
+  #[test]
+  fn bitwise_operators() {
+    assert_eq!(
+      kinds("a & b | c ^ ~d << 1 >> 2"),
+      vec![
+        "Identifier",
+        "BitAnd",
+        "Identifier",
+        "BitOr",
+        "Identifier",
+        "BitXor",
+        "BitNot",
+        "Identifier",
+        "ShiftLeft",
+        "Number",
+        "ShiftRight",
+        "Number",
+        "Eof"
+      ]
+    );
+    // a shift is two characters, the same ones apart are comparisons
+    assert_eq!(
+      kinds("<<= < < >>="),
+      vec!["ShiftLeft", "Assignment", "Less", "Less", "ShiftRight", "Assignment", "Eof"]
+    );
+    assert_eq!(kinds("&&||"), vec!["BitAnd", "BitAnd", "BitOr", "BitOr", "Eof"]);
+    assert_eq!(Lexer::new("a << b").lex().unwrap()[1].span(), Span::new(2, 4));
+  }
+
+  #[test]
+  fn compound_assignments() {
+    assert_eq!(
+      kinds("a += 1 -= 2 *= 3 /= 4"),
+      vec![
+        "Identifier",
+        "PlusAssignment",
+        "Number",
+        "MinusAssignment",
+        "Number",
+        "StarAssignment",
+        "Number",
+        "SlashAssignment",
+        "Number",
+        "Eof"
+      ]
+    );
+    assert_eq!(kinds("a++ --b"), vec!["Identifier", "Increment", "Decrement", "Identifier", "Eof"]);
+    assert_eq!(kinds("+ = - ="), vec!["Plus", "Assignment", "Minus", "Assignment", "Eof"]);
+    // `/=` isn't confused with the start of a comment, nor `//=` with a division
+    assert_eq!(kinds("a /=b //= c"), vec!["Identifier", "SlashAssignment", "Identifier", "Eof"]);
+    assert_eq!(Lexer::new("a *= 2").lex().unwrap()[1].span(), Span::new(2, 4));
   }
 }
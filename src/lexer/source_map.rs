@@ -0,0 +1,193 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location {
+  pub line: usize,
+  pub col: usize,
+}
+
+impl fmt::Display for Location {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}:{}", self.line, self.col)
+  }
+}
+
+// remembers where every line starts so byte offsets can be turned into line and column on demand
+pub struct SourceMap<'a> {
+  code: &'a str,
+  line_starts: Vec<usize>,
+  // the index into line_starts of each file's first line, when the code is several files one
+  // after another
+  file_lines: Vec<usize>,
+  tab_width: usize,
+}
+
+impl<'a> SourceMap<'a> {
+  pub fn new(code: &'a str) -> Self {
+    Self::with_tab_width(code, 1)
+  }
+
+  // a tab moves the column on to the next multiple of tab_width, 1 counts it like any other character
+  pub fn with_tab_width(code: &'a str, tab_width: usize) -> Self {
+    let mut line_starts = vec![0];
+
+    let bytes = code.as_bytes();
+
+    for (offset, &byte) in bytes.iter().enumerate() {
+      // `\r\n` is a single break, only its `\n` starts the next line
+      let is_break = byte == b'\n' || (byte == b'\r' && bytes.get(offset + 1) != Some(&b'\n'));
+
+      if is_break {
+        line_starts.push(offset + 1);
+      }
+    }
+
+    Self {
+      code,
+      line_starts,
+      file_lines: vec![0],
+      tab_width: tab_width.max(1),
+    }
+  }
+
+  // code made of files one after another, each starting at the beginning of a line at one of
+  // the offsets in starts. Lines are counted from 1 again at the top of every file
+  pub fn with_files(code: &'a str, starts: &[usize]) -> Self {
+    let mut source_map = Self::new(code);
+
+    for start in starts {
+      if let Ok(line) = source_map.line_starts.binary_search(start) {
+        source_map.file_lines.push(line);
+      }
+    }
+
+    source_map
+  }
+
+  pub fn location(&self, offset: usize) -> Location {
+    let offset = offset.min(self.code.len());
+    // line_starts always begins with 0 so the offset falls at or after the first entry
+    let line_index = match self.line_starts.binary_search(&offset) {
+      Ok(index) => index,
+      Err(index) => index - 1,
+    };
+
+    let line_start = self.line_starts[line_index];
+    let file_line = match self.file_lines.binary_search(&line_index) {
+      Ok(index) => self.file_lines[index],
+      Err(index) => self.file_lines[index - 1],
+    };
+    // columns count characters so multi-byte text lines up with what editors show
+    let col = match self.code.get(line_start..offset) {
+      Some(text) => text.chars().fold(0, |col, character| match character {
+        '\t' => (col / self.tab_width + 1) * self.tab_width,
+        _ => col + 1,
+      }),
+      None => offset - line_start,
+    };
+
+    Location {
+      line: line_index - file_line + 1,
+      col: col + 1,
+    }
+  }
+
+  // the text of a line, counted from 1 at the top of the code, without its line break
+  pub fn line_text(&self, line: usize) -> &'a str {
+    let start = match line.checked_sub(1).and_then(|index| self.line_starts.get(index)) {
+      Some(start) => *start,
+      None => return "",
+    };
+    let end = self.line_starts.get(line).copied().unwrap_or(self.code.len());
+
+    self.code[start..end].trim_end_matches(['\n', '\r'])
+  }
+
+  pub fn tab_width(&self) -> usize {
+    self.tab_width
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn line_col(code: &str, offset: usize) -> (usize, usize) {
+    let location = SourceMap::new(code).location(offset);
+    (location.line, location.col)
+  }
+
+  #[test]
+  fn crlf_is_a_single_line_break() {
+    let code = "a\r\nb\r\nc";
+
+    assert_eq!(line_col(code, 0), (1, 1));
+    assert_eq!(line_col(code, 3), (2, 1));
+    assert_eq!(line_col(code, 6), (3, 1));
+  }
+
+  #[test]
+  fn bare_cr_is_a_line_break() {
+    let code = "a\rb\r\rc";
+
+    assert_eq!(line_col(code, 2), (2, 1));
+    assert_eq!(line_col(code, 5), (4, 1));
+  }
+
+  #[test]
+  fn mixed_line_endings() {
+    let code = "a\nb\r\nc\rd\r\r\ne";
+
+    assert_eq!(line_col(code, 0), (1, 1));
+    assert_eq!(line_col(code, 2), (2, 1));
+    assert_eq!(line_col(code, 5), (3, 1));
+    assert_eq!(line_col(code, 7), (4, 1));
+    assert_eq!(line_col(code, 11), (6, 1));
+  }
+
+  #[test]
+  fn tabs_count_as_one_column_by_default() {
+    assert_eq!(line_col("\t\tx", 2), (1, 3));
+  }
+
+  #[test]
+  fn tabs_expand_to_the_next_stop() {
+    let source_map = SourceMap::with_tab_width("\tx\n  \ty\nab\t\tz", 4);
+
+    assert_eq!(source_map.location(1).col, 5);
+    assert_eq!(source_map.location(6).col, 5);
+    assert_eq!(source_map.location(12).col, 9);
+  }
+
+  #[test]
+  fn lines_start_over_in_every_file() {
+    let code = "a\nb\nc\nd";
+    let source_map = SourceMap::with_files(code, &[4]);
+
+    assert_eq!(source_map.location(2).line, 2);
+    assert_eq!(source_map.location(4).line, 1);
+    assert_eq!(source_map.location(6), Location { line: 2, col: 1 });
+  }
+
+  #[test]
+  fn break_characters_belong_to_the_line_they_end() {
+    let code = "ab\r\ncd";
+
+    assert_eq!(line_col(code, 2), (1, 3));
+    assert_eq!(line_col(code, 3), (1, 4));
+    assert_eq!(line_col(code, 4), (2, 1));
+  }
+
+  #[test]
+  fn multi_byte_characters_are_one_column() {
+    // é is 2 bytes, € 3 and 😀 4
+    let code = "é € 😀 x\nab€c";
+
+    assert_eq!(line_col(code, 2), (1, 2));
+    assert_eq!(line_col(code, 3), (1, 3));
+    assert_eq!(line_col(code, 7), (1, 5));
+    assert_eq!(line_col(code, 11), (1, 6));
+    assert_eq!(line_col(code, 12), (1, 7));
+    assert_eq!(line_col(code, 19), (2, 4));
+  }
+}
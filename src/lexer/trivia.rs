@@ -0,0 +1,26 @@
+use super::{Span, Token};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriviaKind {
+  Whitespace,
+  Newline,
+  LineComment,
+  BlockComment,
+  Shebang,
+}
+
+// source text that carries no meaning for the parser but that a formatter has to keep
+#[derive(Debug, Clone, Copy)]
+pub struct Trivia {
+  pub kind: TriviaKind,
+  pub span: Span,
+}
+
+// a token along with the trivia around it, trailing trivia runs up to and including
+// the end of the token's line and everything after that leads the next token
+#[derive(Debug)]
+pub struct TriviaToken<'a> {
+  pub leading: Vec<Trivia>,
+  pub token: Token<'a>,
+  pub trailing: Vec<Trivia>,
+}
@@ -0,0 +1,32 @@
+// the rslox binary is a thin wrapper around this library, other tools can depend on the crate
+// and drive each stage themselves
+pub mod ast;
+pub mod chunk;
+pub mod compiler;
+pub mod console;
+pub mod debug;
+pub mod diagnostics;
+#[cfg(feature = "file-io")]
+pub mod files;
+pub mod interpreter;
+pub mod json;
+pub mod lexer;
+pub mod lists;
+pub mod maps;
+pub mod modules;
+pub mod optimizer;
+pub mod parser;
+pub mod random;
+#[cfg(feature = "register-vm")]
+pub mod register;
+pub mod resolver;
+pub mod strings;
+pub mod system;
+pub mod vm;
+
+use lexer::{LexError, Lexer, Token};
+
+// every token of the code, or every error found in it
+pub fn lex<'a>(code: &'a str) -> Result<Vec<Token<'a>>, Vec<LexError>> {
+  Lexer::new(code).lex()
+}
@@ -0,0 +1,117 @@
+// the list handling behind indexing, slicing and the list natives, shared by the tree-walker and
+// the vm so both report the same errors. Indices are whole numbers counted from 0
+
+pub fn get<T: Clone>(items: &[T], index: f64) -> Result<T, String> {
+  Ok(items[position(index, items.len())?].clone())
+}
+
+pub fn set<T>(items: &mut [T], index: f64, value: T) -> Result<(), String> {
+  items[position(index, items.len())?] = value;
+
+  Ok(())
+}
+
+// the elements from start up to but not including end, a missing start is the first element and
+// a missing end the end of the list
+pub fn slice<T: Clone>(items: &[T], start: Option<f64>, end: Option<f64>) -> Result<Vec<T>, String> {
+  let len = items.len();
+  let (start, end) = (start.unwrap_or(0.0), end.unwrap_or(len as f64));
+
+  match (bound(start, len), bound(end, len)) {
+    (Some(first), Some(last)) if first <= last => Ok(items[first..last].to_vec()),
+    _ => Err(format!("Slice range {}..{} is out of bounds for a list of length {}.", start, end, len)),
+  }
+}
+
+// the index can be the length, which appends
+pub fn insert<T>(items: &mut Vec<T>, index: f64, value: T) -> Result<(), String> {
+  let len = items.len();
+
+  match bound(index, len) {
+    Some(index) => {
+      items.insert(index, value);
+      Ok(())
+    }
+    None => Err(out_of_bounds(index, len)),
+  }
+}
+
+pub fn remove<T>(items: &mut Vec<T>, index: f64) -> Result<T, String> {
+  let index = position(index, items.len())?;
+
+  Ok(items.remove(index))
+}
+
+pub fn pop<T>(items: &mut Vec<T>) -> Result<T, String> {
+  items.pop().ok_or_else(|| String::from("Can't pop from an empty list."))
+}
+
+// where an existing element is
+fn position(index: f64, len: usize) -> Result<usize, String> {
+  if index.fract() != 0.0 {
+    return Err(String::from("List index must be a whole number."));
+  }
+
+  match index >= 0.0 && index < len as f64 {
+    true => Ok(index as usize),
+    false => Err(out_of_bounds(index, len)),
+  }
+}
+
+// a whole number from 0 up to len, the end of the list included
+fn bound(value: f64, len: usize) -> Option<usize> {
+  (value.fract() == 0.0 && value >= 0.0 && value <= len as f64).then_some(value as usize)
+}
+
+fn out_of_bounds(index: f64, len: usize) -> String {
+  format!("List index {} is out of bounds for a list of length {}.", index, len)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn elements_are_read_and_written_in_bounds() {
+    let mut items = vec![1, 2, 3];
+
+    assert_eq!(get(&items, 2.0), Ok(3));
+    assert_eq!(set(&mut items, 0.0, 5), Ok(()));
+    assert_eq!(items, [5, 2, 3]);
+
+    assert_eq!(get(&items, 3.0), Err(String::from("List index 3 is out of bounds for a list of length 3.")));
+    assert_eq!(get(&items, -1.0), Err(String::from("List index -1 is out of bounds for a list of length 3.")));
+    assert_eq!(get(&items, 0.5), Err(String::from("List index must be a whole number.")));
+  }
+
+  #[test]
+  fn slices_default_to_the_ends() {
+    let items = [1, 2, 3];
+
+    assert_eq!(slice(&items, Some(1.0), None), Ok(vec![2, 3]));
+    assert_eq!(slice(&items, None, Some(1.0)), Ok(vec![1]));
+    assert_eq!(slice(&items, Some(3.0), Some(3.0)), Ok(vec![]));
+    assert_eq!(
+      slice(&items, Some(2.0), Some(1.0)),
+      Err(String::from("Slice range 2..1 is out of bounds for a list of length 3."))
+    );
+    assert!(slice(&items, None, Some(4.0)).is_err());
+  }
+
+  #[test]
+  fn insert_remove_and_pop() {
+    let mut items = vec![1, 2];
+
+    assert_eq!(insert(&mut items, 2.0, 3), Ok(()));
+    assert_eq!(insert(&mut items, 0.0, 0), Ok(()));
+    assert!(insert(&mut items, 5.0, 9).is_err());
+    assert_eq!(items, [0, 1, 2, 3]);
+
+    assert_eq!(remove(&mut items, 1.0), Ok(1));
+    assert_eq!(pop(&mut items), Ok(3));
+    assert_eq!(items, [0, 2]);
+
+    items.clear();
+    assert_eq!(pop(&mut items), Err(String::from("Can't pop from an empty list.")));
+  }
+}
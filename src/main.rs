@@ -1,13 +1,964 @@
-mod lexer;
+use std::env;
+use std::fs;
+use std::io::{self, IsTerminal, Read, Write};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+mod editor;
+
+use editor::{Editor, Input};
+use rslox::ast::{Ast, AstPrinter};
+use rslox::chunk::{self, Function};
+use rslox::compiler::Compiler;
+use rslox::debug;
+use rslox::diagnostics::{Diagnostic, Emitter};
+use rslox::interpreter::{Interpreter, RuntimeError};
+use rslox::json::Json;
+use rslox::lexer;
+use rslox::modules::{self, Sources};
+use rslox::optimizer;
+use rslox::parser::Parser;
+use rslox::resolver::Resolver;
+use rslox::vm::{self, Vm};
+
+// exit codes from sysexits.h, the same ones jlox and clox use so Lox test harnesses can tell
+// what kind of failure they got
+const EX_USAGE: i32 = 64;
+// the code didn't lex, parse or resolve
+const EX_DATAERR: i32 = 65;
+const EX_NOINPUT: i32 = 66;
+// the code failed while running
+const EX_SOFTWARE: i32 = 70;
+const EX_CANTCREAT: i32 = 73;
+const EX_IOERR: i32 = 74;
 
 fn main() {
-    let code = String::from("# I'm just a comment
-+-{}() ++ -- \"ameer\"  \"jhan\" 123.258 0.2 \"jhan\"
-first_variable = 3.14
-");
-    let mut lex = lexer::Lexer::new();
-    
-    let tokens = lex.lex(&code);
-
-    println!("{:?}", tokens);
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    match args.as_slice() {
+        [flag, rest @ ..] if flag == "--emit-tokens" => emit_tokens(rest),
+        [flag, path] if flag == "--print-ast" || flag == "--emit-ast" || flag == "--emit-ast=sexp" => {
+            print_ast(path)
+        }
+        [flag, path] if flag == "--emit-ast=json" => emit_ast(path),
+        [flag, path] if flag == "--disassemble" => disassemble(path, false),
+        [flag, optimize, path] if flag == "--disassemble" && optimize == "-O" => disassemble(path, true),
+        [command, path] if command == "check" => check(path),
+        [command, path, flag, out] if command == "compile" && flag == "-o" => compile(path, out, false),
+        [command, optimize, path, flag, out] if command == "compile" && optimize == "-O" && flag == "-o" => {
+            compile(path, out, true)
+        }
+        [command, path] if command == "watch" => watch(path),
+        [] => run_prompt(),
+        rest => run_with_options(rest),
+    }
+}
+
+// the flags can come in any order before the script, the ones debugging the vm imply --vm
+fn run_with_options(mut args: &[String]) {
+    let mut time = false;
+    let mut vm = false;
+    #[cfg(feature = "register-vm")]
+    let mut register_vm = false;
+    let mut options = VmOptions::default();
+
+    while let [flag, rest @ ..] = args {
+        match flag.as_str() {
+            "--time" => time = true,
+            "--vm" => vm = true,
+            #[cfg(feature = "register-vm")]
+            "--register-vm" => register_vm = true,
+            "-O" => options.optimize = true,
+            "--stress-gc" => options.stress_gc = true,
+            "--generational-gc" => options.generational_gc = true,
+            "--trace-execution" => options.trace_execution = true,
+            "--intern-stats" => options.intern_stats = true,
+            "--vm-stats" => options.vm_stats = true,
+            _ => break,
+        }
+
+        args = rest;
+    }
+
+    // what follows -- is the script's own, for args() to hand it. The code after -e is never one
+    let start = if args.first().is_some_and(|flag| flag == "-e") { 2 } else { 1 };
+    if let Some(split) = args.iter().skip(start).position(|arg| arg == "--") {
+        rslox::system::set_args(args[start + split + 1..].to_vec());
+        args = &args[..start + split];
+    }
+
+    let backend = if vm || options != VmOptions::default() {
+        Backend::Vm(options)
+    } else {
+        Backend::TreeWalker
+    };
+    #[cfg(feature = "register-vm")]
+    let backend = if register_vm { Backend::RegisterVm } else { backend };
+
+    // compiled scripts can only run on the vm
+    if let [path] = args {
+        if path.ends_with(".loxc") {
+            let options = match backend {
+                Backend::Vm(options) => options,
+                Backend::TreeWalker => VmOptions::default(),
+                #[cfg(feature = "register-vm")]
+                Backend::RegisterVm => VmOptions::default(),
+            };
+            return run_compiled(path, options, time);
+        }
+    }
+
+    match script(args) {
+        Some((path, code)) => run_script(&path, &code, backend, time),
+        None => usage(),
+    }
+}
+
+// the name errors should give the program and its code, for the ways of handing rslox one to run
+fn script(args: &[String]) -> Option<(String, String)> {
+    match args {
+        [flag, code] if flag == "-e" => Some((String::from("<eval>"), code.clone())),
+        [path] if path == "-" => Some((String::from("<stdin>"), read_stdin())),
+        [path] if !path.starts_with('-') => Some((path.clone(), read_source(path))),
+        _ => None,
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("usage: rslox [--time] [--vm] [vm flags] [script | -] [-- args...]");
+    eprintln!("       rslox [--time] [--vm] [vm flags] -e <code> [-- args...]");
+    eprintln!("       rslox [--time] [vm flags] <script.loxc> [-- args...]");
+    #[cfg(feature = "register-vm")]
+    eprintln!("       rslox [--time] --register-vm [script | -] [-- args...]");
+    eprintln!("       rslox check <path>");
+    eprintln!("       rslox compile [-O] <path> -o <script.loxc>");
+    eprintln!("       rslox watch <path>");
+    eprintln!("       rslox --emit-tokens [--trivia] [--tab-width <columns>] <path>");
+    eprintln!("       rslox --emit-ast[=sexp|json] <path>");
+    eprintln!("       rslox --disassemble [-O] <path>");
+    eprintln!("vm flags: -O --stress-gc --generational-gc --trace-execution --intern-stats --vm-stats");
+    process::exit(EX_USAGE);
+}
+
+fn read_source(path: &str) -> String {
+    match fs::read_to_string(path) {
+        Ok(code) => code,
+        Err(error) => {
+            eprintln!("could not read {}: {}", path, error);
+            process::exit(EX_NOINPUT);
+        }
+    }
+}
+
+fn read_stdin() -> String {
+    let mut code = String::new();
+
+    if let Err(error) = io::stdin().read_to_string(&mut code) {
+        eprintln!("could not read stdin: {}", error);
+        process::exit(EX_NOINPUT);
+    }
+
+    code
+}
+
+// every error of every stage goes through here, colored only when stderr is a terminal
+fn report_errors<'e, E: 'e>(
+    path: &str,
+    source_map: &lexer::SourceMap,
+    errors: impl IntoIterator<Item = &'e E>,
+) where
+    Diagnostic: From<&'e E>,
+{
+    let emitter = Emitter::new(path, source_map, io::stderr().is_terminal());
+
+    for error in errors {
+        eprintln!("{}\n", emitter.render(&Diagnostic::from(error)));
+    }
+}
+
+// the diagnostic rendered against the file it's in, for a program that imported others
+fn render(sources: &Sources, diagnostic: Diagnostic) -> String {
+    let (file, span) = sources.locate(diagnostic.span);
+    let source_map = lexer::SourceMap::new(sources.text(file));
+    let emitter = Emitter::new(&file.path, &source_map, io::stderr().is_terminal());
+
+    emitter.render(&Diagnostic { span, ..diagnostic })
+}
+
+// report_errors for the program and the files it imports
+fn report_source_errors<'e, E: 'e>(sources: &Sources, errors: impl IntoIterator<Item = &'e E>)
+where
+    Diagnostic: From<&'e E>,
+{
+    for error in errors {
+        eprintln!("{}\n", render(sources, Diagnostic::from(error)));
+    }
+}
+
+// the error and the calls it happened in, innermost first
+fn report_runtime_error(sources: &Sources, error: &RuntimeError) {
+    eprintln!("{}", render(sources, Diagnostic::from(error)));
+
+    for frame in error.stack_trace(&sources.source_map()) {
+        eprintln!("{}", frame);
+    }
+
+    eprintln!();
+}
+
+// reports every lex or parse error and exits when the file doesn't parse
+fn parse_file(path: &str) -> Ast {
+    let code = read_source(path);
+    let lexer = lexer::Lexer::new(&code);
+    let source_map = lexer.source_map();
+
+    let tokens = lexer.lex().unwrap_or_else(|errors| {
+        report_errors(path, &source_map, &errors);
+        process::exit(EX_DATAERR);
+    });
+
+    Parser::new(tokens).parse().unwrap_or_else(|errors| {
+        report_errors(path, &source_map, &errors);
+        process::exit(EX_DATAERR);
+    })
+}
+
+// lexes and parses the program onto the tree, then loads the files it imports. Errors are
+// reported against the file they're in
+fn parse_program(sources: &mut Sources, ast: &mut Ast, timings: &mut Timings) -> Result<(), Status> {
+    let tokens = timed(timings, "lex", || lexer::Lexer::new(sources.code()).lex()).map_err(|errors| {
+        report_source_errors(sources, &errors);
+        Status::StaticError
+    })?;
+
+    timed(timings, "parse", || Parser::new(tokens).parse_onto(ast)).map_err(|errors| {
+        report_source_errors(sources, &errors);
+        Status::StaticError
+    })?;
+
+    timed(timings, "import", || modules::load_imports(ast, sources)).map_err(|errors| {
+        report_source_errors(sources, &errors);
+        Status::StaticError
+    })
+}
+
+// reports every static error, of the file and the files it imports, and exits when any of them
+// doesn't lex, parse or resolve
+fn resolve_file(path: &str) -> (Ast, Sources) {
+    let mut sources = Sources::new(path, &read_source(path));
+    let mut ast = Ast::new();
+
+    if parse_program(&mut sources, &mut ast, &mut Timings::new()).is_err() {
+        process::exit(EX_DATAERR);
+    }
+
+    if let Err(errors) = Resolver::new().resolve(&ast) {
+        report_source_errors(&sources, &errors);
+        process::exit(EX_DATAERR);
+    }
+
+    (ast, sources)
+}
+
+// every static error in the file without running any of it, for editors to call on save
+fn check(path: &str) {
+    resolve_file(path);
+}
+
+fn compile_file(path: &str) -> Function {
+    let (ast, sources) = resolve_file(path);
+    let source_map = sources.source_map();
+
+    match Compiler::new(&source_map).compile(&ast) {
+        Ok(script) => script,
+        Err(errors) => {
+            report_source_errors(&sources, &errors);
+            process::exit(EX_DATAERR);
+        }
+    }
+}
+
+// the bytecode the file compiles to, for debugging the compiler. With -O the optimized code
+// follows, to see what the optimizer made of it
+fn disassemble(path: &str, optimize: bool) {
+    let script = compile_file(path);
+    let mut output = debug::disassemble_function(&script);
+
+    if optimize {
+        output.push_str("\n---------- optimized ----------\n\n");
+        output.push_str(&debug::disassemble_function(&optimizer::optimize(&script)));
+    }
+
+    write_output(&output);
+}
+
+// output that's often piped into another program. One that stops reading early, like
+// `rslox --emit-tokens f.lox | head`, has all it wanted, so a closed pipe isn't an error
+fn write_output(output: &str) {
+    let mut stdout = io::stdout().lock();
+
+    match stdout.write_all(output.as_bytes()).and_then(|_| stdout.flush()) {
+        Err(error) if error.kind() != io::ErrorKind::BrokenPipe => {
+            eprintln!("could not write the output: {}", error);
+            process::exit(EX_IOERR);
+        }
+        _ => (),
+    }
+}
+
+// the bytecode the file compiles to, written out so it can be run later without the source
+fn compile(path: &str, out: &str, optimize: bool) {
+    let mut script = compile_file(path);
+
+    if optimize {
+        script = optimizer::optimize(&script);
+    }
+
+    if let Err(error) = fs::write(out, chunk::to_bytes(&script)) {
+        eprintln!("could not write {}: {}", out, error);
+        process::exit(EX_CANTCREAT);
+    }
+}
+
+// the program as s-expressions, handy for seeing how the parser grouped things
+fn print_ast(path: &str) {
+    write_output(&AstPrinter.print_program(&parse_file(path)));
+}
+
+// the program as a JSON array of statements for tools that don't link against the crate
+fn emit_ast(path: &str) {
+    write_output(&format!("{}\n", parse_file(path).to_json().pretty()));
+}
+
+fn span_json(span: lexer::Span) -> Json {
+    Json::object(vec![
+        ("start", Json::Number(span.start as f64)),
+        ("end", Json::Number(span.end as f64)),
+    ])
+}
+
+fn token_json(code: &str, source_map: &lexer::SourceMap, token: &lexer::Token) -> Vec<(&'static str, Json)> {
+    let span = token.span();
+    let location = source_map.location(span.start);
+    let mut fields = vec![
+        ("kind", Json::String(token.kind())),
+        ("lexeme", Json::String(String::from(&code[span.start..span.end]))),
+        ("span", span_json(span)),
+        ("line", Json::Number(location.line as f64)),
+        ("col", Json::Number(location.col as f64)),
+    ];
+
+    match token {
+        lexer::Token::Literal(_, lexer::Literals::String(value))
+        | lexer::Token::Interpolation(_, value)
+        | lexer::Token::InterpolationEnd(_, value) => {
+            fields.push(("value", Json::String(value.to_string())))
+        }
+        lexer::Token::Literal(_, lexer::Literals::Number(value)) => {
+            fields.push(("value", Json::Number(*value)))
+        }
+        lexer::Token::Identifier(_, name) => fields.push(("value", Json::String(name.to_string()))),
+        _ => (),
+    }
+
+    fields
+}
+
+fn trivia_json(trivia: &[lexer::Trivia]) -> Json {
+    Json::Array(
+        trivia
+            .iter()
+            .map(|trivia| {
+                Json::object(vec![
+                    ("kind", Json::String(format!("{:?}", trivia.kind))),
+                    ("span", span_json(trivia.span)),
+                ])
+            })
+            .collect(),
+    )
+}
+
+// prints the token stream as JSON for editor tooling that doesn't link against the crate,
+// --trivia also lists the whitespace and comments around every token
+fn emit_tokens(args: &[String]) {
+    let mut with_trivia = false;
+    let mut options = lexer::LexerOptions::default();
+    let mut path = None;
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--trivia" => with_trivia = true,
+            "--tab-width" => match args.next().and_then(|width| width.parse().ok()) {
+                Some(width) => options.tab_width = width,
+                None => usage(),
+            },
+            _ if path.is_none() => path = Some(arg),
+            _ => usage(),
+        }
+    }
+
+    let path = path.unwrap_or_else(|| usage());
+    let code = read_source(path);
+    let lexer = lexer::Lexer::with_options(&code, options);
+    let source_map = lexer.source_map();
+
+    let tokens = if with_trivia {
+        lexer.lex_with_trivia().map(|tokens| {
+            tokens
+                .iter()
+                .map(|token| {
+                    let mut fields = token_json(&code, &source_map, &token.token);
+                    fields.push(("leading", trivia_json(&token.leading)));
+                    fields.push(("trailing", trivia_json(&token.trailing)));
+
+                    Json::object(fields)
+                })
+                .collect()
+        })
+    } else {
+        lexer.lex().map(|tokens| {
+            tokens
+                .iter()
+                .map(|token| Json::object(token_json(&code, &source_map, token)))
+                .collect()
+        })
+    };
+
+    match tokens {
+        Ok(tokens) => write_output(&format!("{}\n", Json::Array(tokens).pretty())),
+        Err(errors) => {
+            report_errors(path, &source_map, &errors);
+            process::exit(EX_DATAERR);
+        }
+    }
+}
+
+// how long each stage took, in the order they ran
+type Timings = Vec<(&'static str, Duration)>;
+
+fn timed<T>(timings: &mut Timings, stage: &'static str, f: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = f();
+    timings.push((stage, start.elapsed()));
+
+    result
+}
+
+// one pass through every stage for a piece of code, parsed onto the nodes of the pieces before
+// it so functions and classes they declared can still be called. The files it imports are added
+// to the sources
+fn run(
+    sources: &mut Sources,
+    ast: &mut Ast,
+    interpreter: &mut Interpreter,
+    timings: &mut Timings,
+) -> Result<(), Status> {
+    parse_program(sources, ast, timings)?;
+
+    let locals = timed(timings, "resolve", || Resolver::new().resolve(ast)).map_err(|errors| {
+        report_source_errors(sources, &errors);
+        Status::StaticError
+    })?;
+
+    interpreter.resolve(locals);
+    timed(timings, "execute", || interpreter.interpret(ast)).map_err(|error| match error.exit_status() {
+        Some(status) => Status::Exit(status),
+        None => {
+            report_runtime_error(sources, &error);
+            Status::RuntimeError
+        }
+    })
+}
+
+// the message and the calls it happened in. The vm only knows lines, so when the source is at
+// hand the line it stopped on is shown without an underline
+fn report_vm_error(error: &vm::RuntimeError, source: Option<(&str, &lexer::SourceMap)>) {
+    eprintln!("error: {}", error);
+
+    if let (Some((path, source_map)), Some(frame)) = (source, error.trace.first()) {
+        let line_number = frame.line.to_string();
+        let gutter = " ".repeat(line_number.len());
+
+        eprintln!("{}--> {}:{}", gutter, path, frame.line);
+        eprintln!("{} |", gutter);
+        eprintln!("{} | {}", line_number, source_map.line_text(frame.line).trim_end());
+        eprintln!("{} |", gutter);
+    }
+
+    for frame in error.stack_trace() {
+        eprintln!("{}", frame);
+    }
+
+    eprintln!();
+}
+
+// the same stages as run, with the resolved tree compiled to bytecode and run on the vm. The vm
+// only knows the line an error was on, not the file, so the line is only shown when there's one
+fn run_vm(path: &str, code: &str, options: VmOptions, timings: &mut Timings) -> Result<(), Status> {
+    let mut sources = Sources::new(path, code);
+    let mut ast = Ast::new();
+    parse_program(&mut sources, &mut ast, timings)?;
+
+    timed(timings, "resolve", || Resolver::new().resolve(&ast)).map_err(|errors| {
+        report_source_errors(&sources, &errors);
+        Status::StaticError
+    })?;
+
+    let source_map = sources.source_map();
+    let mut script = timed(timings, "compile", || Compiler::new(&source_map).compile(&ast)).map_err(|errors| {
+        report_source_errors(&sources, &errors);
+        Status::StaticError
+    })?;
+
+    if options.optimize {
+        script = timed(timings, "optimize", || optimizer::optimize(&script));
+    }
+
+    let source = (sources.files().len() == 1).then_some((path, &source_map));
+    execute(script, options, source, timings)
+}
+
+// the same stages as run_vm, compiled for the register vm instead
+#[cfg(feature = "register-vm")]
+fn run_register_vm(path: &str, code: &str, timings: &mut Timings) -> Result<(), Status> {
+    use rslox::register::{RegisterCompiler, RegisterVm};
+
+    let mut sources = Sources::new(path, code);
+    let mut ast = Ast::new();
+    parse_program(&mut sources, &mut ast, timings)?;
+
+    timed(timings, "resolve", || Resolver::new().resolve(&ast)).map_err(|errors| {
+        report_source_errors(&sources, &errors);
+        Status::StaticError
+    })?;
+
+    // imports are reported as unsupported, so the program is only ever the one file
+    let source_map = sources.source_map();
+    let script = timed(timings, "compile", || RegisterCompiler::new(&source_map).compile(&ast)).map_err(|errors| {
+        report_source_errors(&sources, &errors);
+        Status::StaticError
+    })?;
+
+    timed(timings, "execute", || RegisterVm::new().interpret(script)).map_err(|error| match error.exit {
+        Some(status) => Status::Exit(status),
+        None => {
+            report_vm_error(&error, Some((path, &source_map)));
+            Status::RuntimeError
+        }
+    })
+}
+
+// a script compiled by `rslox compile`, checked before any of it runs
+fn run_compiled(path: &str, options: VmOptions, time: bool) {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(error) => {
+            eprintln!("could not read {}: {}", path, error);
+            process::exit(EX_NOINPUT);
+        }
+    };
+
+    let mut timings = Timings::new();
+    let status = timed(&mut timings, "load", || chunk::from_bytes(&bytes))
+        .map_err(|error| {
+            eprintln!("error: could not load {}: {}", path, error);
+            Status::StaticError
+        })
+        .and_then(|script| execute(script, options, None, &mut timings));
+
+    finish(status, &timings, time);
+}
+
+// runs a compiled script on a fresh vm, reporting the stats the options ask for once it's done.
+// The source is there for errors to show, a script loaded from a .loxc file has none
+fn execute(
+    script: Function,
+    options: VmOptions,
+    source: Option<(&str, &lexer::SourceMap)>,
+    timings: &mut Timings,
+) -> Result<(), Status> {
+    let mut vm = Vm::new();
+    vm.set_stress_gc(options.stress_gc);
+    vm.set_generational_gc(options.generational_gc);
+    vm.set_trace_execution(options.trace_execution);
+
+    let result = timed(timings, "execute", || vm.interpret(script)).map_err(|error| match error.exit {
+        Some(status) => Status::Exit(status),
+        None => {
+            report_vm_error(&error, source);
+            Status::RuntimeError
+        }
+    });
+
+    if options.intern_stats {
+        let stats = vm.intern_stats();
+        eprintln!("interned strings {:>8}", stats.strings);
+        eprintln!("lookups          {:>8}", stats.lookups);
+        eprintln!("hits             {:>8}", stats.hits);
+    }
+
+    if options.vm_stats {
+        report_vm_stats(&vm.vm_stats());
+    }
+
+    result
+}
+
+// the inline cache hits and misses of each kind of instruction that has one
+fn report_vm_stats(stats: &vm::VmStats) {
+    let kinds = [
+        ("globals", stats.globals),
+        ("get property", stats.get_property),
+        ("set property", stats.set_property),
+        ("invoke", stats.invoke),
+    ];
+
+    eprintln!("{:<16} {:>10} {:>10} {:>8}", "cache", "hits", "misses", "hit rate");
+
+    for (kind, counts) in kinds {
+        let total = counts.hits + counts.misses;
+        let rate = if total == 0 { 0.0 } else { counts.hits as f64 / total as f64 * 100.0 };
+        eprintln!("{:<16} {:>10} {:>10} {:>7.1}%", kind, counts.hits, counts.misses, rate);
+    }
+}
+
+// goes to stderr so it doesn't mix with what the script prints, stages that never ran because
+// an earlier one failed are left out
+fn report_timings(timings: &Timings) {
+    let total = timings.iter().map(|(_, duration)| *duration).sum::<Duration>();
+
+    for (stage, duration) in timings.iter().chain([&("total", total)]) {
+        eprintln!("{:<8} {:>10.3}ms", stage, duration.as_secs_f64() * 1000.0);
+    }
+}
+
+// why a script stopped
+enum Status {
+    StaticError,
+    RuntimeError,
+    // the script called exit(), after whatever was left to report once it stopped
+    Exit(i32),
+}
+
+// what runs a script once it's resolved
+enum Backend {
+    TreeWalker,
+    Vm(VmOptions),
+    // the experimental register vm, which takes none of the stack vm's options
+    #[cfg(feature = "register-vm")]
+    RegisterVm,
+}
+
+// switches for the vm, mostly for debugging it
+#[derive(Debug, Default, PartialEq)]
+struct VmOptions {
+    // run the peephole optimizer over the compiled code first
+    optimize: bool,
+    // collect garbage at every allocation, for shaking out collector bugs
+    stress_gc: bool,
+    // collect the young generation on its own most of the time, for shorter pauses
+    generational_gc: bool,
+    // print the stack and each instruction as it runs, for finding where the compiler's code
+    // goes wrong
+    trace_execution: bool,
+    // how many strings were interned and how often an allocation found its text already there,
+    // reported to stderr once the script is done
+    intern_stats: bool,
+    // how often the inline caches on globals, properties and method calls hit, also reported
+    // once the script is done
+    vm_stats: bool,
+}
+
+// a whole program, path is only used to say where errors are. time reports how long each stage
+// took once it's done
+fn run_script(path: &str, code: &str, backend: Backend, time: bool) {
+    let mut timings = Timings::new();
+    let status = match backend {
+        Backend::TreeWalker => {
+            run(&mut Sources::new(path, code), &mut Ast::new(), &mut Interpreter::new(), &mut timings)
+        }
+        Backend::Vm(options) => run_vm(path, code, options, &mut timings),
+        #[cfg(feature = "register-vm")]
+        Backend::RegisterVm => run_register_vm(path, code, &mut timings),
+    };
+
+    finish(status, &timings, time);
+}
+
+// exits with the code for how the script stopped, after reporting the timings if asked to
+fn finish(status: Result<(), Status>, timings: &Timings, time: bool) {
+    if time {
+        report_timings(timings);
+    }
+
+    match status {
+        Ok(()) => (),
+        Err(Status::StaticError) => process::exit(EX_DATAERR),
+        Err(Status::RuntimeError) => process::exit(EX_SOFTWARE),
+        Err(Status::Exit(status)) => process::exit(status),
+    }
+}
+
+// how often watch looks at the file, and how long it has to stay unchanged before it runs, as
+// editors often write a file in several steps when saving it
+const WATCH_POLL: Duration = Duration::from_millis(100);
+const WATCH_SETTLE: Duration = Duration::from_millis(200);
+
+fn modified(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+// the files a watch looks at, the script and the ones it imported on its last run, with when
+// each of them was modified as of that run
+struct Watched {
+    files: Vec<String>,
+    last_run: Vec<Option<SystemTime>>,
+}
+
+impl Watched {
+    fn new(path: &str) -> Self {
+        Self {
+            files: vec![String::from(path)],
+            last_run: vec![modified(path)],
+        }
+    }
+
+    // the files of the run that just finished. The times of files watched before are the ones
+    // from before the run, so a save while it ran isn't missed. Files imported for the first time
+    // are watched from now
+    fn update(&mut self, imported: Vec<String>) {
+        self.last_run = imported
+            .iter()
+            .map(|file| match self.files.iter().position(|watched| watched == file) {
+                Some(index) => self.last_run[index],
+                None => modified(file),
+            })
+            .collect();
+        self.files = imported;
+    }
+
+    fn current(&self) -> Vec<Option<SystemTime>> {
+        self.files.iter().map(|file| modified(file)).collect()
+    }
+
+    // the first file whose time in seen isn't the one it had on the last run
+    fn changed(&self, seen: &[Option<SystemTime>]) -> Option<&str> {
+        let index = (0..self.files.len()).find(|index| seen[*index] != self.last_run[*index])?;
+
+        Some(&self.files[index])
+    }
+}
+
+// one run of a watched script, on a fresh interpreter with errors reported but not stopping the
+// watch. What it imported is watched from then on
+fn run_watched(path: &str, watched: &mut Watched) {
+    match fs::read_to_string(path) {
+        Ok(code) => {
+            let mut sources = Sources::new(path, &code);
+            let _ = run(&mut sources, &mut Ast::new(), &mut Interpreter::new(), &mut Timings::new());
+
+            watched.update(sources.files().iter().map(|file| file.path.clone()).collect());
+        }
+        Err(error) => eprintln!("could not read {}: {}", path, error),
+    }
+}
+
+// runs the script and then again every time it or a file it imports is saved, until interrupted
+fn watch(path: &str) {
+    let mut watched = Watched::new(path);
+
+    loop {
+        run_watched(path, &mut watched);
+
+        let mut seen = watched.last_run.clone();
+        let mut unchanged_for = Duration::ZERO;
+
+        while seen == watched.last_run || unchanged_for < WATCH_SETTLE {
+            thread::sleep(WATCH_POLL);
+
+            let current = watched.current();
+            if current == seen {
+                unchanged_for += WATCH_POLL;
+            } else {
+                seen = current;
+                unchanged_for = Duration::ZERO;
+            }
+        }
+
+        let changed = String::from(watched.changed(&seen).unwrap_or(path));
+        watched.last_run = seen;
+        println!("\n---------- {} changed, running again ----------\n", changed);
+    }
+}
+
+const COMMANDS: &str = "\
+:tokens <code>  show the tokens the code lexes into
+:ast <code>     show the tree the code parses into
+:env            show every global and its value
+:help           show this list
+:quit           leave the prompt";
+
+// a colon command typed at the prompt, false when it was the one to quit
+fn run_command(line: &str, interpreter: &Interpreter) -> bool {
+    let (command, code) = line.split_once(' ').unwrap_or((line, ""));
+    let code = code.trim();
+    let lexer = lexer::Lexer::new(code);
+    let source_map = lexer.source_map();
+
+    match command {
+        ":tokens" => match lexer.lex() {
+            Ok(tokens) => {
+                for token in tokens {
+                    let span = token.span();
+                    println!("{}", format!("{} {}", token.kind(), &code[span.start..span.end]).trim_end());
+                }
+            }
+            Err(errors) => report_errors("<stdin>", &source_map, &errors),
+        },
+        // a lone expression prints without needing a statement around it
+        ":ast" => match lexer.lex() {
+            Ok(tokens) => match Parser::new(tokens.clone()).parse_expression() {
+                Ok((ast, expr)) => println!("{}", AstPrinter.print_expr(&ast, expr)),
+                Err(_) => match Parser::new(tokens).parse() {
+                    Ok(ast) => print!("{}", AstPrinter.print_program(&ast)),
+                    Err(errors) => report_errors("<stdin>", &source_map, &errors),
+                },
+            },
+            Err(errors) => report_errors("<stdin>", &source_map, &errors),
+        },
+        ":env" => {
+            for (name, value) in interpreter.globals() {
+                println!("{} = {}", name, value);
+            }
+        }
+        ":help" => println!("{}", COMMANDS),
+        ":quit" => return false,
+        _ => eprintln!("unknown command {}, :help lists them", command),
+    }
+
+    true
+}
+
+// reads a line at a time and runs it, an error only ends the line it was on and everything
+// declared before it is still there for the next one. A line that leaves a string, comment or
+// bracket open is continued on the next, and Ctrl-C drops everything typed since the last run
+fn run_prompt() {
+    let mut ast = Ast::new();
+    let mut interpreter = Interpreter::new();
+    let mut editor = Editor::new();
+    let mut code = String::new();
+
+    loop {
+        let prompt = if code.is_empty() { "> " } else { "... " };
+
+        match editor.read_line(prompt) {
+            Ok(Input::Line(line)) if code.is_empty() && line.trim_start().starts_with(':') => {
+                if !run_command(line.trim(), &interpreter) {
+                    break;
+                }
+            }
+            Ok(Input::Line(line)) => {
+                code.push_str(&line);
+                code.push('\n');
+
+                if !lexer::Lexer::new(&code).needs_more_input() {
+                    let mut sources = Sources::new("<stdin>", &code);
+                    let result = run(&mut sources, &mut ast, &mut interpreter, &mut Timings::new());
+                    code.clear();
+
+                    // exit() leaves the prompt the way it would a script
+                    if let Err(Status::Exit(status)) = result {
+                        process::exit(status);
+                    }
+                }
+            }
+            Ok(Input::Interrupted) => code.clear(),
+            // end of input, or input that isn't text. Whatever was left open still runs so
+            // its errors get reported
+            Ok(Input::Eof) | Err(_) => {
+                if !code.is_empty() {
+                    let _ = run(&mut Sources::new("<stdin>", &code), &mut ast, &mut interpreter, &mut Timings::new());
+                }
+
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a directory of the test's own for the scripts it watches
+    fn directory(test: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("rslox-watch-{}-{}", process::id(), test));
+        fs::create_dir_all(&dir).unwrap();
+
+        dir
+    }
+
+    fn write(dir: &std::path::Path, name: &str, code: &str) -> String {
+        let path = dir.join(name);
+        fs::write(&path, code).unwrap();
+
+        path.to_str().unwrap().to_string()
+    }
+
+    // moves the file's modified time on without waiting for the clock to
+    fn touch(path: &str, seconds: u64) {
+        let file = fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(seconds)).unwrap();
+    }
+
+    #[test]
+    fn watching_follows_imports() {
+        let dir = directory("watching_follows_imports");
+        let module = write(&dir, "module.lox", "var x = 1;");
+        let main = write(&dir, "main.lox", "import \"module.lox\";");
+        let mut watched = Watched::new(&main);
+        assert_eq!(watched.files, vec![main.clone()]);
+
+        run_watched(&main, &mut watched);
+        assert_eq!(watched.files, vec![main.clone(), module.clone()]);
+        assert_eq!(watched.last_run, vec![modified(&main), modified(&module)]);
+
+        // a script that no longer imports the file stops watching it
+        write(&dir, "main.lox", "var y = 2;");
+        run_watched(&main, &mut watched);
+        assert_eq!(watched.files, vec![main]);
+    }
+
+    #[test]
+    fn watching_sees_which_file_changed() {
+        let dir = directory("watching_sees_which_file_changed");
+        let module = write(&dir, "module.lox", "var x = 1;");
+        let main = write(&dir, "main.lox", "import \"module.lox\";");
+        let mut watched = Watched::new(&main);
+        run_watched(&main, &mut watched);
+        assert_eq!(watched.changed(&watched.current()), None);
+
+        touch(&module, 10);
+        assert_eq!(watched.changed(&watched.current()), Some(module.as_str()));
+
+        touch(&main, 20);
+        assert_eq!(watched.changed(&watched.current()), Some(main.as_str()));
+
+        watched.last_run = watched.current();
+        assert_eq!(watched.changed(&watched.current()), None);
+    }
+
+    #[test]
+    fn a_save_during_a_run_is_not_missed() {
+        let dir = directory("a_save_during_a_run_is_not_missed");
+        let main = write(&dir, "main.lox", "var x = 1;");
+        let mut watched = Watched::new(&main);
+
+        // saved once the times were taken, the way a save while the script runs would be
+        touch(&main, 10);
+        run_watched(&main, &mut watched);
+        assert_eq!(watched.changed(&watched.current()), Some(main.as_str()));
+    }
 }
@@ -0,0 +1,122 @@
+// the table behind maps, shared by the tree-walker and the vm so both agree on which keys are
+// the same one. It's laid out the way an instance's fields are on the vm, a slot for every key
+// and the entries in the order they were first set, so printing a map or asking for its keys
+// gives the same answer on every run
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+
+// strings are keyed by whatever the backend holds a string as, numbers by their bits with -0
+// made 0 so the two are the same key the way they're equal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Key<S> {
+  String(S),
+  Number(u64),
+}
+
+impl<S> Key<S> {
+  pub fn number(value: f64) -> Self {
+    Key::Number((value + 0.0).to_bits())
+  }
+}
+
+pub fn invalid_key() -> String {
+  String::from("Map keys must be strings or numbers.")
+}
+
+// key is shown the way print would show it
+pub fn undefined_key(key: impl fmt::Display) -> String {
+  format!("Undefined key '{}'.", key)
+}
+
+#[derive(Debug, Clone)]
+pub struct Table<K, V> {
+  slots: HashMap<K, usize>,
+  entries: Vec<(K, V)>,
+}
+
+impl<K: Clone + Eq + Hash, V> Table<K, V> {
+  pub fn new() -> Self {
+    Self {
+      slots: HashMap::new(),
+      entries: vec![],
+    }
+  }
+
+  pub fn get(&self, key: &K) -> Option<&V> {
+    self.slots.get(key).map(|slot| &self.entries[*slot].1)
+  }
+
+  pub fn contains(&self, key: &K) -> bool {
+    self.slots.contains_key(key)
+  }
+
+  // a key set again keeps its place
+  pub fn set(&mut self, key: K, value: V) {
+    match self.slots.get(&key) {
+      Some(slot) => self.entries[*slot].1 = value,
+      None => {
+        self.slots.insert(key.clone(), self.entries.len());
+        self.entries.push((key, value));
+      }
+    }
+  }
+
+  // the entries after the removed one move down a slot
+  pub fn remove(&mut self, key: &K) -> Option<V> {
+    let slot = self.slots.remove(key)?;
+
+    for later in self.slots.values_mut().filter(|later| **later > slot) {
+      *later -= 1;
+    }
+
+    Some(self.entries.remove(slot).1)
+  }
+
+  pub fn entries(&self) -> &[(K, V)] {
+    &self.entries
+  }
+
+  pub fn len(&self) -> usize {
+    self.entries.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.entries.is_empty()
+  }
+}
+
+impl<K: Clone + Eq + Hash, V> Default for Table<K, V> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn entries_keep_the_order_they_were_set_in() {
+    let mut table = Table::new();
+    table.set(Key::String("b"), 1);
+    table.set(Key::number(2.0), 2);
+    table.set(Key::String("a"), 3);
+    table.set(Key::String("b"), 4);
+
+    assert_eq!(table.entries(), [(Key::String("b"), 4), (Key::number(2.0), 2), (Key::String("a"), 3)]);
+    assert_eq!(table.remove(&Key::number(2.0)), Some(2));
+    assert_eq!(table.remove(&Key::number(2.0)), None);
+    assert_eq!(table.get(&Key::String("a")), Some(&3));
+    assert_eq!(table.entries(), [(Key::String("b"), 4), (Key::String("a"), 3)]);
+  }
+
+  #[test]
+  fn zero_is_one_key_whatever_its_sign() {
+    let mut table = Table::new();
+    table.set(Key::<&str>::number(-0.0), 1);
+
+    assert!(table.contains(&Key::number(0.0)));
+    assert!(!table.contains(&Key::String("0")));
+  }
+}
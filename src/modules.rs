@@ -0,0 +1,399 @@
+// the loader behind import. It follows the imports of a parsed program to the files they name,
+// parsing each file once onto the program's tree however many imports lead to it. The code of
+// every file is kept one after another in a single string, so the spans of every node are
+// offsets into it and an error anywhere can be traced back to the file and line it came from
+use std::collections::HashSet;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::ast::{Ast, Module, ModuleId, StmtId, StmtKind};
+use crate::lexer::{LexError, Lexer, SourceMap, Span};
+use crate::parser::{ParseError, Parser};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceFile {
+  pub path: String,
+  // where the file's code starts and ends in the code of them all
+  pub start: usize,
+  pub end: usize,
+}
+
+// the program's file first and the files it imported after it in the order they were loaded
+#[derive(Debug, Clone)]
+pub struct Sources {
+  code: String,
+  files: Vec<SourceFile>,
+}
+
+impl Sources {
+  pub fn new(path: &str, code: &str) -> Self {
+    Self {
+      code: String::from(code),
+      files: vec![SourceFile {
+        path: String::from(path),
+        start: 0,
+        end: code.len(),
+      }],
+    }
+  }
+
+  pub fn code(&self) -> &str {
+    &self.code
+  }
+
+  pub fn files(&self) -> &[SourceFile] {
+    &self.files
+  }
+
+  pub fn text(&self, file: &SourceFile) -> &str {
+    &self.code[file.start..file.end]
+  }
+
+  // lines are counted from 1 at the top of every file
+  pub fn source_map(&self) -> SourceMap<'_> {
+    let starts: Vec<usize> = self.files.iter().skip(1).map(|file| file.start).collect();
+
+    SourceMap::with_files(&self.code, &starts)
+  }
+
+  // the file the span is in and where it is in that file's code
+  pub fn locate(&self, span: Span) -> (&SourceFile, Span) {
+    let index = self.files.partition_point(|file| file.start <= span.start).max(1) - 1;
+    let file = &self.files[index];
+    let local = |offset: usize| offset.clamp(file.start, file.end) - file.start;
+
+    (file, Span::new(local(span.start), local(span.end)))
+  }
+
+  // every file starts on a line of its own, where its code starts is returned
+  fn add(&mut self, path: &str, code: &str) -> usize {
+    if !self.code.is_empty() && !self.code.ends_with(['\n', '\r']) {
+      self.code.push('\n');
+    }
+
+    let start = self.code.len();
+    self.code.push_str(code);
+    self.files.push(SourceFile {
+      path: String::from(path),
+      start,
+      end: self.code.len(),
+    });
+
+    start
+  }
+}
+
+#[derive(Debug, Clone)]
+pub enum ModuleError {
+  Lex(LexError),
+  Parse(ParseError),
+  Unreadable {
+    span: Span,
+    path: String,
+    message: String,
+  },
+  // the files from the first one imported again back around to it
+  Circular {
+    span: Span,
+    cycle: Vec<String>,
+  },
+  Undefined {
+    span: Span,
+    path: String,
+    name: String,
+  },
+}
+
+impl ModuleError {
+  pub fn span(&self) -> Span {
+    match self {
+      ModuleError::Lex(error) => error.span(),
+      ModuleError::Parse(error) => error.span(),
+      ModuleError::Unreadable { span, .. } | ModuleError::Circular { span, .. } | ModuleError::Undefined { span, .. } => {
+        *span
+      }
+    }
+  }
+}
+
+impl fmt::Display for ModuleError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ModuleError::Lex(error) => write!(f, "{}", error),
+      ModuleError::Parse(error) => write!(f, "{}", error),
+      ModuleError::Unreadable { path, message, .. } => write!(f, "could not read '{}': {}", path, message),
+      ModuleError::Circular { cycle, .. } => write!(f, "circular import: {}", cycle.join(" -> ")),
+      ModuleError::Undefined { path, name, .. } => write!(f, "module '{}' doesn't define '{}'", path, name),
+    }
+  }
+}
+
+impl error::Error for ModuleError {}
+
+// loads every file the program imports, and every file those import, linking each import to
+// its module. Paths are relative to the file the import is in, the program's own file is the
+// first of the sources. Imports that were linked by an earlier load are left as they are
+pub fn load_imports(ast: &mut Ast, sources: &mut Sources) -> Result<(), Vec<ModuleError>> {
+  let entry = sources.files[0].path.clone();
+  let program = ast.program().to_vec();
+
+  let mut loader = Loader {
+    ast,
+    sources,
+    errors: vec![],
+    loading: vec![],
+    failed: HashSet::new(),
+  };
+
+  if let Ok(canonical) = fs::canonicalize(&entry) {
+    loader.loading.push((canonical, entry.clone()));
+  }
+
+  loader.link(&entry, &program);
+
+  if loader.errors.is_empty() {
+    Ok(())
+  } else {
+    Err(loader.errors)
+  }
+}
+
+struct Loader<'a> {
+  ast: &'a mut Ast,
+  sources: &'a mut Sources,
+  errors: Vec<ModuleError>,
+  // the files being loaded, each imported by the one before it, with the path it was imported by
+  loading: Vec<(PathBuf, String)>,
+  // files that didn't read, lex or parse, their errors are only reported the first time
+  failed: HashSet<PathBuf>,
+}
+
+impl Loader<'_> {
+  fn link(&mut self, importer: &str, statements: &[StmtId]) {
+    for statement in statements {
+      let (path, names) = match &self.ast[*statement].kind {
+        StmtKind::Import { path, names } if self.ast.imported(*statement).is_none() => (path.clone(), names.clone()),
+        _ => continue,
+      };
+      let span = self.ast[*statement].span;
+
+      let module = match self.load(span, importer, &path) {
+        Some(module) => module,
+        None => continue,
+      };
+
+      let exports = self.ast.exports(module);
+
+      for name in names.iter().flatten().filter(|name| !exports.contains(name)) {
+        self.errors.push(ModuleError::Undefined {
+          span,
+          path: path.clone(),
+          name: name.clone(),
+        });
+      }
+
+      self.ast.link_import(*statement, module);
+    }
+  }
+
+  fn load(&mut self, span: Span, importer: &str, path: &str) -> Option<ModuleId> {
+    let joined = Path::new(importer).parent().unwrap_or(Path::new("")).join(path);
+    let name = joined.to_string_lossy().into_owned();
+
+    let canonical = match fs::canonicalize(&joined) {
+      Ok(canonical) => canonical,
+      Err(error) => {
+        self.errors.push(ModuleError::Unreadable {
+          span,
+          path: name,
+          message: error.to_string(),
+        });
+        return None;
+      }
+    };
+
+    if let Some(index) = self.loading.iter().position(|(loading, _)| *loading == canonical) {
+      let cycle = self.loading[index..].iter().map(|(_, name)| name.clone()).chain([name]).collect();
+      self.errors.push(ModuleError::Circular { span, cycle });
+      return None;
+    }
+
+    if let Some(module) = self.ast.find_module(&canonical) {
+      return Some(module);
+    }
+
+    if self.failed.contains(&canonical) {
+      return None;
+    }
+
+    let body = match self.parse(span, &name, &canonical) {
+      Some(body) => body,
+      None => {
+        self.failed.insert(canonical);
+        return None;
+      }
+    };
+
+    self.loading.push((canonical.clone(), name.clone()));
+    self.link(&name, &body);
+    self.loading.pop();
+
+    Some(self.ast.add_module(Module {
+      path: name,
+      canonical,
+      body,
+    }))
+  }
+
+  fn parse(&mut self, span: Span, name: &str, canonical: &Path) -> Option<Vec<StmtId>> {
+    let code = match fs::read_to_string(canonical) {
+      Ok(code) => code,
+      Err(error) => {
+        self.errors.push(ModuleError::Unreadable {
+          span,
+          path: String::from(name),
+          message: error.to_string(),
+        });
+        return None;
+      }
+    };
+
+    let start = self.sources.add(name, &code);
+
+    let tokens = match Lexer::from_offset(&self.sources.code, start).lex() {
+      Ok(tokens) => tokens,
+      Err(errors) => {
+        self.errors.extend(errors.into_iter().map(ModuleError::Lex));
+        return None;
+      }
+    };
+
+    match Parser::new(tokens).parse_module(self.ast) {
+      Ok(body) => Some(body),
+      Err(errors) => {
+        self.errors.extend(errors.into_iter().map(ModuleError::Parse));
+        None
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // a directory of its own for each test, with the files in it
+  fn directory(test: &str, files: &[(&str, &str)]) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("rslox-modules-{}-{}", std::process::id(), test));
+
+    for (name, code) in files {
+      let path = directory.join(name);
+      fs::create_dir_all(path.parent().unwrap()).unwrap();
+      fs::write(path, code).unwrap();
+    }
+
+    directory
+  }
+
+  fn load(directory: &Path, code: &str) -> (Ast, Sources, Result<(), Vec<ModuleError>>) {
+    let path = directory.join("main.lox");
+    let mut sources = Sources::new(path.to_str().unwrap(), code);
+    let mut ast = Parser::new(Lexer::new(code).lex().unwrap()).parse().unwrap();
+    let result = load_imports(&mut ast, &mut sources);
+
+    (ast, sources, result)
+  }
+
+  fn messages(result: Result<(), Vec<ModuleError>>) -> Vec<String> {
+    result.unwrap_err().iter().map(ModuleError::to_string).collect()
+  }
+
+  #[test]
+  fn a_file_imported_twice_is_loaded_once() {
+    let directory = directory(
+      "diamond",
+      &[
+        ("lib/a.lox", "import \"c.lox\";\nvar a = 1;"),
+        ("lib/b.lox", "import c from \"./c.lox\";"),
+        ("lib/c.lox", "var c = 3;\nfun d() {}"),
+      ],
+    );
+    let (ast, sources, result) = load(&directory, "import \"lib/a.lox\";\nimport \"lib/b.lox\";");
+
+    assert!(result.is_ok());
+    assert_eq!(sources.files().len(), 4);
+
+    let a = ast.imported(ast.program()[0]).unwrap();
+    let b = ast.imported(ast.program()[1]).unwrap();
+    assert_eq!(ast.exports(a), ["c", "d", "a"]);
+    assert_eq!(ast.exports(b), ["c"]);
+
+    fs::remove_dir_all(directory).unwrap();
+  }
+
+  #[test]
+  fn spans_are_located_in_the_file_they_are_in() {
+    let directory = directory("spans", &[("m.lox", "var x = 1;\nvar y = 2;")]);
+    let (ast, sources, result) = load(&directory, "import \"m.lox\";");
+
+    assert!(result.is_ok());
+
+    let module = ast.module(ast.imported(ast.program()[0]).unwrap());
+    let (file, span) = sources.locate(ast[module.body[1]].span);
+    assert!(file.path.ends_with("m.lox"));
+    assert_eq!(span, Span::new(11, 21));
+    assert_eq!(sources.text(file), "var x = 1;\nvar y = 2;");
+    assert_eq!(sources.source_map().location(ast[module.body[1]].span.start).line, 2);
+
+    fs::remove_dir_all(directory).unwrap();
+  }
+
+  #[test]
+  fn circular_imports_show_the_whole_cycle() {
+    let directory = directory(
+      "cycle",
+      &[("main.lox", ""), ("a.lox", "import \"b.lox\";"), ("b.lox", "import \"main.lox\";")],
+    );
+    let (_, _, result) = load(&directory, "import \"a.lox\";");
+
+    let main = directory.join("main.lox");
+    let main = main.to_str().unwrap();
+    let a = directory.join("a.lox");
+    let b = directory.join("b.lox");
+    assert_eq!(
+      messages(result),
+      [format!("circular import: {} -> {} -> {} -> {}", main, a.display(), b.display(), main)]
+    );
+
+    fs::remove_dir_all(directory).unwrap();
+  }
+
+  #[test]
+  fn named_imports_must_be_defined() {
+    let directory = directory("undefined", &[("m.lox", "var x = 1;")]);
+    let (_, _, result) = load(&directory, "import x, y from \"m.lox\";");
+
+    assert_eq!(messages(result), ["module 'm.lox' doesn't define 'y'"]);
+
+    fs::remove_dir_all(directory).unwrap();
+  }
+
+  #[test]
+  fn errors_in_imported_files_are_reported() {
+    let directory = directory("errors", &[("bad.lox", "var = 1;")]);
+    let (_, sources, result) = load(&directory, "import \"missing.lox\";\nimport \"bad.lox\";\nimport \"bad.lox\";");
+
+    let errors = result.unwrap_err();
+    assert_eq!(errors.len(), 2);
+    assert!(errors[0].to_string().starts_with(&format!("could not read '{}': ", directory.join("missing.lox").display())));
+    assert_eq!(errors[1].to_string(), "expected variable name");
+
+    let (file, span) = sources.locate(errors[1].span());
+    assert!(file.path.ends_with("bad.lox"));
+    assert_eq!(span, Span::new(4, 5));
+
+    fs::remove_dir_all(directory).unwrap();
+  }
+}
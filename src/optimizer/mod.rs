@@ -0,0 +1,343 @@
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use crate::chunk::{Chunk, Function, OpCode, Value};
+
+// an instruction taken out of its chunk, so sequences can be replaced without keeping track of
+// offsets. Jumps point at the index of the instruction they land on rather than a distance
+#[derive(Debug, Clone, PartialEq)]
+struct Instruction {
+  op: OpCode,
+  // everything after the opcode except a jump's distance
+  operands: Vec<u8>,
+  target: Option<usize>,
+  line: usize,
+}
+
+// the same function with its code simplified, and that of every function declared in it. The
+// result runs the same as the original, errors included, just in fewer instructions:
+//
+//   constant, constant, arithmetic   the result as one constant
+//   get-local, get-local, add         add-locals
+//   jump to a jump                    a jump straight to where the second one goes
+//
+// Sequences that something jumps into the middle of are left alone
+pub fn optimize(function: &Function) -> Function {
+  Function {
+    name: function.name.clone(),
+    arity: function.arity,
+    upvalue_count: function.upvalue_count,
+    chunk: optimize_chunk(&function.chunk),
+  }
+}
+
+fn optimize_chunk(chunk: &Chunk) -> Chunk {
+  // the compiler already stored each number and string once, so the constants keep their
+  // indices and folded ones are added after them
+  let mut optimized = Chunk::new();
+
+  for constant in chunk.constants() {
+    let constant = match constant {
+      Value::Function(function) => Value::Function(Rc::new(optimize(function))),
+      constant => constant.clone(),
+    };
+    optimized.add_constant(constant);
+  }
+
+  let mut instructions = decode(chunk);
+
+  while let Some(folded) = fold(&instructions, &mut optimized) {
+    instructions = folded;
+  }
+
+  thread_jumps(&mut instructions);
+  encode(&instructions, &mut optimized);
+
+  optimized
+}
+
+fn decode(chunk: &Chunk) -> Vec<Instruction> {
+  let code = chunk.code();
+  let mut offsets = vec![];
+  let mut instructions = vec![];
+  let mut offset = 0;
+
+  while offset < code.len() {
+    let op = OpCode::try_from(code[offset]).unwrap_or_else(|byte| unreachable!("{} is not an instruction", byte));
+    let len = chunk.instruction_len(offset);
+    let (operands, target) = match op {
+      OpCode::Jump | OpCode::JumpIfFalse | OpCode::JumpIfNotNil | OpCode::Case | OpCode::Try => {
+        let distance = u16::from_be_bytes([code[offset + 1], code[offset + 2]]) as usize;
+        (vec![], Some(offset + 3 + distance))
+      }
+      OpCode::Loop => {
+        let distance = u16::from_be_bytes([code[offset + 1], code[offset + 2]]) as usize;
+        (vec![], Some(offset + 3 - distance))
+      }
+      _ => (code[offset + 1..offset + len].to_vec(), None),
+    };
+
+    offsets.push(offset);
+    instructions.push(Instruction {
+      op,
+      operands,
+      target,
+      line: chunk.get_line(offset),
+    });
+    offset += len;
+  }
+
+  // jump targets from offsets to indices, every jump lands on the start of an instruction
+  for instruction in &mut instructions {
+    if let Some(target) = instruction.target {
+      instruction.target = Some(offsets.binary_search(&target).expect("jumps land on instructions"));
+    }
+  }
+
+  instructions
+}
+
+fn constant_index(instruction: &Instruction) -> Option<usize> {
+  match (instruction.op, instruction.operands.as_slice()) {
+    (OpCode::Constant, [index]) => Some(*index as usize),
+    (OpCode::ConstantLong, [a, b, c]) => Some(u32::from_be_bytes([0, *a, *b, *c]) as usize),
+    _ => None,
+  }
+}
+
+fn load_constant(index: usize, line: usize) -> Instruction {
+  let (op, operands) = match u8::try_from(index) {
+    Ok(index) => (OpCode::Constant, vec![index]),
+    Err(_) => (OpCode::ConstantLong, (index as u32).to_be_bytes()[1..].to_vec()),
+  };
+
+  Instruction {
+    op,
+    operands,
+    target: None,
+    line,
+  }
+}
+
+// what the vm would compute from the two constants, when it can't fail
+fn fold_constants(op: OpCode, left: &Value, right: &Value) -> Option<Value> {
+  match (op, left, right) {
+    (OpCode::Add, Value::Number(left), Value::Number(right)) => Some(Value::Number(left + right)),
+    (OpCode::Subtract, Value::Number(left), Value::Number(right)) => Some(Value::Number(left - right)),
+    (OpCode::Multiply, Value::Number(left), Value::Number(right)) => Some(Value::Number(left * right)),
+    (OpCode::Divide, Value::Number(left), Value::Number(right)) => Some(Value::Number(left / right)),
+    (OpCode::Add, Value::String(left), Value::String(right)) => Some(Value::String(Rc::from(format!("{}{}", left, right)))),
+    _ => None,
+  }
+}
+
+// the three instructions as one, if they're a sequence that has a shorter form
+fn fold_sequence(window: &[Instruction], chunk: &mut Chunk) -> Option<Instruction> {
+  let [first, second, third] = window else {
+    return None;
+  };
+
+  if first.op == OpCode::GetLocal && second.op == OpCode::GetLocal && third.op == OpCode::Add {
+    return Some(Instruction {
+      op: OpCode::AddLocals,
+      operands: vec![first.operands[0], second.operands[0]],
+      target: None,
+      line: third.line,
+    });
+  }
+
+  let left = chunk.constants().get(constant_index(first)?)?.clone();
+  let right = chunk.constants().get(constant_index(second)?)?.clone();
+  let value = fold_constants(third.op, &left, &right)?;
+
+  Some(load_constant(chunk.add_constant(value), first.line))
+}
+
+// one pass replacing every sequence it can, None once there's nothing left to replace
+fn fold(instructions: &[Instruction], chunk: &mut Chunk) -> Option<Vec<Instruction>> {
+  let targets = instructions.iter().filter_map(|instruction| instruction.target).collect::<HashSet<_>>();
+  let mut folded = vec![];
+  // where each instruction ended up, the ones folded away map to what replaced them
+  let mut moved = vec![0; instructions.len()];
+  let mut index = 0;
+
+  while index < instructions.len() {
+    let window = &instructions[index..(index + 3).min(instructions.len())];
+    let jumped_into = targets.contains(&(index + 1)) || targets.contains(&(index + 2));
+
+    match fold_sequence(window, chunk).filter(|_| !jumped_into) {
+      Some(instruction) => {
+        moved[index..index + 3].fill(folded.len());
+        folded.push(instruction);
+        index += 3;
+      }
+      None => {
+        moved[index] = folded.len();
+        folded.push(instructions[index].clone());
+        index += 1;
+      }
+    }
+  }
+
+  if folded.len() == instructions.len() {
+    return None;
+  }
+
+  for instruction in &mut folded {
+    instruction.target = instruction.target.map(|target| moved[target]);
+  }
+
+  Some(folded)
+}
+
+// a jump landing on an unconditional jump can go straight to where that one goes. Loops are
+// left alone as a forward jump can't become a backward one
+fn thread_jumps(instructions: &mut [Instruction]) {
+  for index in 0..instructions.len() {
+    if !matches!(instructions[index].op, OpCode::Jump | OpCode::JumpIfFalse | OpCode::JumpIfNotNil | OpCode::Case) {
+      continue;
+    }
+
+    let mut target = instructions[index].target.expect("jumps have targets");
+    // a chain of jumps is never longer than the code, one that is goes round in a circle
+    let mut hops = 0;
+
+    while instructions[target].op == OpCode::Jump && hops < instructions.len() {
+      target = instructions[target].target.expect("jumps have targets");
+      hops += 1;
+    }
+
+    instructions[index].target = Some(target);
+  }
+}
+
+fn encode(instructions: &[Instruction], chunk: &mut Chunk) {
+  let mut offsets = vec![];
+  let mut offset = 0;
+
+  for instruction in instructions {
+    offsets.push(offset);
+    offset += 1 + instruction.operands.len() + if instruction.target.is_some() { 2 } else { 0 };
+  }
+
+  for (index, instruction) in instructions.iter().enumerate() {
+    chunk.write_op(instruction.op, instruction.line);
+
+    for operand in &instruction.operands {
+      chunk.write(*operand, instruction.line);
+    }
+
+    if let Some(target) = instruction.target {
+      let after = offsets[index] + 3;
+      let distance = match instruction.op {
+        OpCode::Loop => after - offsets[target],
+        _ => offsets[target] - after,
+      };
+
+      for byte in (distance as u16).to_be_bytes() {
+        chunk.write(byte, instruction.line);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::compiler::Compiler;
+  use crate::lexer::{Lexer, SourceMap};
+  use crate::parser::Parser;
+  use crate::vm::Vm;
+  use OpCode::*;
+
+  struct SharedOutput(Rc<std::cell::RefCell<Vec<u8>>>);
+
+  impl std::io::Write for SharedOutput {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+      self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+      Ok(())
+    }
+  }
+
+  fn compile(code: &str) -> Function {
+    let ast = Parser::new(Lexer::new(code).lex().unwrap()).parse().unwrap();
+
+    Compiler::new(&SourceMap::new(code)).compile(&ast).unwrap()
+  }
+
+  fn optimized(code: &str) -> Chunk {
+    optimize(&compile(code)).chunk
+  }
+
+  #[test]
+  fn arithmetic_on_constants_is_done_once_ahead_of_time() {
+    let chunk = optimized("print 1 + 2 * 3 - 4;");
+
+    // 3 was already a constant, the folding ends up back at it
+    assert_eq!(chunk.code(), &[Constant as u8, 2, Print as u8, Nil as u8, Return as u8]);
+    assert_eq!(chunk.constants()[2], Value::Number(3.0));
+    assert_eq!(optimized("print \"a\" + \"b\";").constants()[2], Value::String(Rc::from("ab")));
+  }
+
+  #[test]
+  fn operations_that_could_fail_are_left_to_run() {
+    let code = optimized("print 1 + \"a\";");
+
+    assert_eq!(code.code()[..5], [Constant as u8, 0, Constant as u8, 1, Add as u8]);
+  }
+
+  #[test]
+  fn adding_two_locals_is_one_instruction() {
+    let function = optimize(&compile("fun f(a, b) { return a + b; }"));
+    let f = match &function.chunk.constants()[0] {
+      Value::Function(f) => f,
+      value => panic!("expected f, found {}", value),
+    };
+
+    assert_eq!(f.chunk.code()[..4], [AddLocals as u8, 1, 2, Return as u8]);
+  }
+
+  #[test]
+  fn jumps_to_jumps_go_straight_to_the_end() {
+    let chunk = optimized("if (true) { if (false) print 1; else print 2; } else print 3;");
+
+    // the jump over the inner else used to land on the jump over the outer one
+    assert_eq!(chunk.code()[13..16], [Jump as u8, 0, 11]);
+    assert_eq!(chunk.code()[20..23], [Jump as u8, 0, 4]);
+  }
+
+  #[test]
+  fn optimized_code_does_what_it_did_before() {
+    let run = |script: Function| {
+      let output = Rc::new(std::cell::RefCell::new(vec![]));
+      let mut vm = Vm::with_output(Box::new(SharedOutput(Rc::clone(&output))));
+      let result = vm.interpret(script).map_err(|error| error.to_string());
+      let printed = String::from_utf8(output.take()).unwrap();
+      (printed, result)
+    };
+    let programs = [
+      "fun f(a, b) { return a + b; } print f(1, 2); print f(\"a\", \"b\"); print 2 * 3 + 1 - 8 / 2;",
+      "for (var i = 0; i < 3; i = i + 1) { if (i == 1) print \"one\"; else print i; }",
+      "var a = false; print (a and 1) + 2;",
+      "fun f(a, b) { return a + b; } f(1, nil);",
+      "try { print 1 + 2; throw 3 * 4; } catch (e) { print e; }",
+      "for (var i = 0; i < 5; i += 1) { var a = i * 2; if (a == 2) continue; if (a > 6) break; print a; }",
+      "for (var i = 0; i < 4; i += 1) switch (i * 1) { case 0, 1 + 1: print i; case 3: break; default: print -i; }",
+    ];
+
+    for program in programs {
+      assert_eq!(run(optimize(&compile(program))), run(compile(program)), "{}", program);
+    }
+  }
+
+  #[test]
+  fn sequences_jumped_into_are_kept() {
+    // the and jumps to the 2 when a is false, so it can't be folded into the 1
+    let chunk = optimized("var a = true; print (a and 1) + 2;");
+
+    assert!(chunk.code().contains(&(Add as u8)));
+  }
+}
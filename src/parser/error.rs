@@ -0,0 +1,67 @@
+use std::error;
+use std::fmt;
+
+use crate::lexer::Span;
+
+pub const MAX_ARGUMENTS: usize = 255;
+
+#[derive(Debug, Clone)]
+pub enum ParseError {
+  // expected describes what should have come next, like "')' after expression"
+  Expected {
+    span: Span,
+    expected: &'static str,
+  },
+  InvalidAssignmentTarget {
+    span: Span,
+  },
+  // calls are limited so the bytecode can count arguments in a single byte
+  TooManyArguments {
+    span: Span,
+  },
+  TooManyParameters {
+    span: Span,
+  },
+  InheritsFromItself {
+    span: Span,
+  },
+  // a break or continue with no loop around it in its function, keyword is which one
+  OutsideLoop {
+    span: Span,
+    keyword: &'static str,
+  },
+  // at the second one
+  DuplicateDefault {
+    span: Span,
+  },
+}
+
+impl ParseError {
+  pub fn span(&self) -> Span {
+    match self {
+      ParseError::Expected { span, .. }
+      | ParseError::InvalidAssignmentTarget { span }
+      | ParseError::TooManyArguments { span }
+      | ParseError::TooManyParameters { span }
+      | ParseError::InheritsFromItself { span }
+      | ParseError::OutsideLoop { span, .. }
+      | ParseError::DuplicateDefault { span } => *span,
+    }
+  }
+}
+
+impl fmt::Display for ParseError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ParseError::Expected { expected, .. } => write!(f, "expected {}", expected),
+      ParseError::InvalidAssignmentTarget { .. } => write!(f, "invalid assignment target"),
+      ParseError::TooManyArguments { .. } => write!(f, "can't have more than {} arguments", MAX_ARGUMENTS),
+      ParseError::TooManyParameters { .. } => write!(f, "can't have more than {} parameters", MAX_ARGUMENTS),
+      ParseError::InheritsFromItself { .. } => write!(f, "a class can't inherit from itself"),
+      ParseError::OutsideLoop { keyword, .. } => write!(f, "can't use '{}' outside of a loop", keyword),
+      ParseError::DuplicateDefault { .. } => write!(f, "a switch can't have more than one default"),
+    }
+  }
+}
+
+impl error::Error for ParseError {}
@@ -0,0 +1,1579 @@
+use std::iter::Peekable;
+use std::mem;
+use std::vec;
+
+use crate::ast::{
+  Ast, BinaryOperator, Case, Expr, ExprId, ExprKind, Literal, LogicalOperator, Stmt, StmtId, StmtKind,
+  UnaryOperator,
+};
+use crate::lexer::{Keywords, Literals, Operators, Span, Token};
+
+mod error;
+
+pub use error::{ParseError, MAX_ARGUMENTS};
+
+type ParseResult = Result<ExprId, ParseError>;
+type StmtResult = Result<StmtId, ParseError>;
+
+// binding power of infix operators, from the loosest to the tightest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Precedence {
+  Assignment,
+  // looser than or, so `a ?? b or c` falls back on whether either of the others is truthy
+  Coalesce,
+  Or,
+  And,
+  Equality,
+  Comparison,
+  // bitwise operators bind tighter than comparisons so `x & 1 == 0` tests the masked value
+  BitOr,
+  BitXor,
+  BitAnd,
+  Shift,
+  Term,
+  Factor,
+  Unary,
+  Call,
+}
+
+impl Precedence {
+  // the level a left associative operator parses its right operand at
+  fn tighter(self) -> Self {
+    match self {
+      Precedence::Assignment => Precedence::Coalesce,
+      Precedence::Coalesce => Precedence::Or,
+      Precedence::Or => Precedence::And,
+      Precedence::And => Precedence::Equality,
+      Precedence::Equality => Precedence::Comparison,
+      Precedence::Comparison => Precedence::BitOr,
+      Precedence::BitOr => Precedence::BitXor,
+      Precedence::BitXor => Precedence::BitAnd,
+      Precedence::BitAnd => Precedence::Shift,
+      Precedence::Shift => Precedence::Term,
+      Precedence::Term => Precedence::Factor,
+      Precedence::Factor => Precedence::Unary,
+      Precedence::Unary | Precedence::Call => Precedence::Call,
+    }
+  }
+}
+
+enum Infix {
+  Binary(BinaryOperator),
+  Logical(LogicalOperator),
+  // `a += b` is sugar for `a = a + b`
+  Assign(Option<BinaryOperator>),
+  Call,
+  Get,
+  Index,
+}
+
+// the precedence table, a new infix operator only needs an entry here
+fn infix_rule(token: &Token) -> Option<(Precedence, Infix)> {
+  let rule = match token {
+    Token::Operator(_, operator) => match operator {
+      Operators::Assignment => (Precedence::Assignment, Infix::Assign(None)),
+      Operators::PlusAssignment => (Precedence::Assignment, Infix::Assign(Some(BinaryOperator::Add))),
+      Operators::MinusAssignment => (Precedence::Assignment, Infix::Assign(Some(BinaryOperator::Subtract))),
+      Operators::StarAssignment => (Precedence::Assignment, Infix::Assign(Some(BinaryOperator::Multiply))),
+      Operators::SlashAssignment => (Precedence::Assignment, Infix::Assign(Some(BinaryOperator::Divide))),
+      Operators::Equal => (Precedence::Equality, Infix::Binary(BinaryOperator::Equal)),
+      Operators::NotEqual => (Precedence::Equality, Infix::Binary(BinaryOperator::NotEqual)),
+      Operators::Less => (Precedence::Comparison, Infix::Binary(BinaryOperator::Less)),
+      Operators::LessEqual => (Precedence::Comparison, Infix::Binary(BinaryOperator::LessEqual)),
+      Operators::Greater => (Precedence::Comparison, Infix::Binary(BinaryOperator::Greater)),
+      Operators::GreaterEqual => (Precedence::Comparison, Infix::Binary(BinaryOperator::GreaterEqual)),
+      Operators::BitOr => (Precedence::BitOr, Infix::Binary(BinaryOperator::BitOr)),
+      Operators::BitXor => (Precedence::BitXor, Infix::Binary(BinaryOperator::BitXor)),
+      Operators::BitAnd => (Precedence::BitAnd, Infix::Binary(BinaryOperator::BitAnd)),
+      Operators::ShiftLeft => (Precedence::Shift, Infix::Binary(BinaryOperator::ShiftLeft)),
+      Operators::ShiftRight => (Precedence::Shift, Infix::Binary(BinaryOperator::ShiftRight)),
+      Operators::Coalesce => (Precedence::Coalesce, Infix::Logical(LogicalOperator::Coalesce)),
+      Operators::Plus => (Precedence::Term, Infix::Binary(BinaryOperator::Add)),
+      Operators::Minus => (Precedence::Term, Infix::Binary(BinaryOperator::Subtract)),
+      Operators::Star => (Precedence::Factor, Infix::Binary(BinaryOperator::Multiply)),
+      Operators::Slash => (Precedence::Factor, Infix::Binary(BinaryOperator::Divide)),
+      Operators::Percent => (Precedence::Factor, Infix::Binary(BinaryOperator::Remainder)),
+      Operators::Increment | Operators::Decrement | Operators::Bang | Operators::BitNot => return None,
+    },
+    Token::Keyword(_, Keywords::Or) => (Precedence::Or, Infix::Logical(LogicalOperator::Or)),
+    Token::Keyword(_, Keywords::And) => (Precedence::And, Infix::Logical(LogicalOperator::And)),
+    Token::OpenParen(_) => (Precedence::Call, Infix::Call),
+    Token::Dot(_) => (Precedence::Call, Infix::Get),
+    Token::OpenBracket(_) => (Precedence::Call, Infix::Index),
+    _ => return None,
+  };
+
+  Some(rule)
+}
+
+// keywords recovery can safely pick up parsing again at
+fn starts_statement(keyword: Keywords) -> bool {
+  matches!(
+    keyword,
+    Keywords::Var
+      | Keywords::Class
+      | Keywords::Fun
+      | Keywords::Print
+      | Keywords::If
+      | Keywords::While
+      | Keywords::For
+      | Keywords::Return
+      | Keywords::Throw
+      | Keywords::Try
+      | Keywords::Import
+      | Keywords::Switch
+      | Keywords::Break
+      | Keywords::Continue
+  )
+}
+
+// recursive descent for statements, expressions are parsed Pratt style off the precedence table
+pub struct Parser<'a> {
+  tokens: Peekable<vec::IntoIter<Token<'a>>>,
+  // handed out once the tokens run out so callers never have to deal with a missing token
+  eof: Token<'a>,
+  errors: Vec<ParseError>,
+  // blocks currently open, recovering from an error stops at a `}` that closes one of them
+  block_depth: usize,
+  // loops around the statement being parsed in the function it's in, break and continue need one
+  loop_depth: usize,
+  ast: Ast,
+}
+
+impl<'a> Parser<'a> {
+  pub fn new(tokens: Vec<Token<'a>>) -> Self {
+    let end = tokens.last().map_or(0, |token| token.span().end);
+
+    Self {
+      tokens: tokens.into_iter().peekable(),
+      eof: Token::Eof(Span::new(end, end)),
+      errors: vec![],
+      block_depth: 0,
+      loop_depth: 0,
+      ast: Ast::new(),
+    }
+  }
+
+  fn add_expr(&mut self, kind: ExprKind, span: Span) -> ExprId {
+    self.ast.add_expr(Expr::new(kind, span))
+  }
+
+  fn add_stmt(&mut self, kind: StmtKind, span: Span) -> StmtId {
+    self.ast.add_stmt(Stmt::new(kind, span))
+  }
+
+  fn span(&self, expr: ExprId) -> Span {
+    self.ast[expr].span
+  }
+
+  // the span running from the start of first through the end of last
+  fn join(&self, first: ExprId, last: ExprId) -> Span {
+    Span::new(self.span(first).start, self.span(last).end)
+  }
+
+  // None when the target can't be assigned to. A compound assignment to a variable reads it in
+  // a binary expression, one to a property or an element keeps its operator
+  fn assignment(
+    &mut self,
+    target: ExprId,
+    compound: Option<BinaryOperator>,
+    value: ExprId,
+  ) -> Option<ExprId> {
+    if !matches!(self.ast[target].kind, ExprKind::Variable(_) | ExprKind::Get { .. } | ExprKind::Index { .. }) {
+      return None;
+    }
+
+    let span = self.join(target, value);
+
+    let kind = match &self.ast[target].kind {
+      ExprKind::Variable(name) => {
+        let name = name.clone();
+        let value = match compound {
+          Some(operator) => self.add_expr(
+            ExprKind::Binary {
+              left: target,
+              operator,
+              right: value,
+            },
+            span,
+          ),
+          None => value,
+        };
+
+        ExprKind::Assign { name, value }
+      }
+      ExprKind::Get { object, name } => ExprKind::Set {
+        object: *object,
+        name: name.clone(),
+        operator: compound,
+        value,
+      },
+      ExprKind::Index { object, index } => ExprKind::SetIndex {
+        object: *object,
+        index: *index,
+        operator: compound,
+        value,
+      },
+      _ => unreachable!("assignment targets are checked above"),
+    };
+
+    Some(self.add_expr(kind, span))
+  }
+
+  fn peek(&mut self) -> &Token<'a> {
+    self.tokens.peek().unwrap_or(&self.eof)
+  }
+
+  fn advance(&mut self) -> Token<'a> {
+    self.tokens.next().unwrap_or_else(|| self.eof.clone())
+  }
+
+  fn expected(&mut self, expected: &'static str) -> ParseError {
+    ParseError::Expected {
+      span: self.peek().span(),
+      expected,
+    }
+  }
+
+  // takes the next token if is_expected accepts it
+  fn consume(
+    &mut self,
+    is_expected: fn(&Token) -> bool,
+    expected: &'static str,
+  ) -> Result<Token<'a>, ParseError> {
+    if is_expected(self.peek()) {
+      Ok(self.advance())
+    } else {
+      Err(self.expected(expected))
+    }
+  }
+
+  fn consume_semicolon(&mut self, expected: &'static str) -> Result<usize, ParseError> {
+    let semicolon = self.consume(|token| matches!(token, Token::Semicolon(_)), expected)?;
+
+    Ok(semicolon.span().end)
+  }
+
+  fn finish<T>(self, parsed: impl FnOnce(Ast) -> T) -> Result<T, Vec<ParseError>> {
+    if self.errors.is_empty() {
+      Ok(parsed(self.ast))
+    } else {
+      Err(self.errors)
+    }
+  }
+
+  // a whole program, statement after statement up to the end of input, every syntax error
+  // is collected rather than stopping at the first one
+  pub fn parse(mut self) -> Result<Ast, Vec<ParseError>> {
+    while !matches!(self.peek(), Token::Eof(_)) {
+      if let Some(statement) = self.declaration() {
+        self.ast.push_top_level(statement);
+      }
+    }
+
+    self.finish(|ast| ast)
+  }
+
+  // a program added to the nodes of an earlier parse, for input that arrives a piece at a time
+  // and refers to what came before. ast.program() holds just the new statements afterwards,
+  // or none when there were syntax errors
+  pub fn parse_onto(mut self, ast: &mut Ast) -> Result<(), Vec<ParseError>> {
+    ast.clear_program();
+    self.ast = mem::take(ast);
+
+    while !matches!(self.peek(), Token::Eof(_)) {
+      if let Some(statement) = self.declaration() {
+        self.ast.push_top_level(statement);
+      }
+    }
+
+    *ast = mem::take(&mut self.ast);
+
+    if self.errors.is_empty() {
+      Ok(())
+    } else {
+      ast.clear_program();
+      Err(self.errors)
+    }
+  }
+
+  // the statements of an imported file, added to the nodes of an earlier parse but left out of
+  // its program since they only run when the import does
+  pub fn parse_module(mut self, ast: &mut Ast) -> Result<Vec<StmtId>, Vec<ParseError>> {
+    self.ast = mem::take(ast);
+    let mut body = vec![];
+
+    while !matches!(self.peek(), Token::Eof(_)) {
+      body.extend(self.declaration());
+    }
+
+    *ast = mem::take(&mut self.ast);
+
+    if self.errors.is_empty() {
+      Ok(body)
+    } else {
+      Err(self.errors)
+    }
+  }
+
+  // a whole input that is a single expression
+  pub fn parse_expression(mut self) -> Result<(Ast, ExprId), Vec<ParseError>> {
+    let expr = match self.expression() {
+      Ok(expr) if matches!(self.peek(), Token::Eof(_)) => expr,
+      Ok(_) => return Err(vec![self.expected("end of input")]),
+      Err(error) => return Err(vec![error]),
+    };
+
+    self.finish(|ast| (ast, expr))
+  }
+
+  // skips what is left of a broken statement so parsing can pick up again at the next one
+  fn synchronize(&mut self) {
+    let is_in_block = self.block_depth > 0;
+
+    loop {
+      match self.peek() {
+        Token::Eof(_) => return,
+        Token::Semicolon(_) => {
+          self.advance();
+          return;
+        }
+        Token::CloseBrace(_) if is_in_block => return,
+        Token::Keyword(_, keyword) if starts_statement(*keyword) => return,
+        _ => {
+          self.advance();
+        }
+      }
+    }
+  }
+
+  // None when the declaration had a syntax error, which is recorded before recovering
+  fn declaration(&mut self) -> Option<StmtId> {
+    match self.parse_declaration() {
+      Ok(stmt) => Some(stmt),
+      Err(error) => {
+        self.errors.push(error);
+        self.synchronize();
+
+        None
+      }
+    }
+  }
+
+  fn parse_declaration(&mut self) -> StmtResult {
+    match self.peek() {
+      Token::Keyword(_, Keywords::Var) => self.var_declaration(),
+      Token::Keyword(_, Keywords::Fun) => self.function_declaration(),
+      Token::Keyword(_, Keywords::Class) => self.class_declaration(),
+      Token::Keyword(_, Keywords::Import) => self.import_declaration(),
+      _ => self.statement(),
+    }
+  }
+
+  fn identifier(&mut self, expected: &'static str) -> Result<String, ParseError> {
+    match self.consume(|token| matches!(token, Token::Identifier(_, _)), expected)? {
+      Token::Identifier(_, name) => Ok(name.to_string()),
+      _ => unreachable!("consume only hands back identifiers here"),
+    }
+  }
+
+  fn class_declaration(&mut self) -> StmtResult {
+    let start = self.advance().span().start;
+    let name = self.identifier("class name")?;
+
+    let superclass = match self.peek() {
+      Token::Operator(_, Operators::Less) => {
+        self.advance();
+        let span = self.peek().span();
+        let superclass = self.identifier("superclass name")?;
+
+        if superclass == name {
+          self.errors.push(ParseError::InheritsFromItself { span });
+        }
+
+        Some(self.add_expr(ExprKind::Variable(superclass), span))
+      }
+      _ => None,
+    };
+
+    self.consume(|token| matches!(token, Token::OpenBrace(_)), "'{' before class body")?;
+
+    let mut methods = vec![];
+
+    while !matches!(self.peek(), Token::CloseBrace(_) | Token::Eof(_)) {
+      let start = self.peek().span().start;
+      methods.push(self.function(start, "method name")?);
+    }
+
+    let end = self.consume(|token| matches!(token, Token::CloseBrace(_)), "'}' after class body")?;
+
+    let kind = StmtKind::Class {
+      name,
+      superclass,
+      methods,
+    };
+
+    Ok(self.add_stmt(kind, Span::new(start, end.span().end)))
+  }
+
+  // `from` is only special here, it's still a name anywhere else
+  fn import_declaration(&mut self) -> StmtResult {
+    let start = self.advance().span().start;
+
+    let names = match self.peek() {
+      Token::Identifier(_, _) => {
+        let mut names = vec![self.identifier("imported name")?];
+
+        while let Token::Comma(_) = self.peek() {
+          self.advance();
+          names.push(self.identifier("imported name")?);
+        }
+
+        self.consume(|token| matches!(token, Token::Identifier(_, "from")), "'from' after imported names")?;
+        Some(names)
+      }
+      _ => None,
+    };
+
+    let path = match self.consume(|token| matches!(token, Token::Literal(_, Literals::String(_))), "module path")? {
+      Token::Literal(_, Literals::String(path)) => path.to_string(),
+      _ => unreachable!("consume only hands back strings here"),
+    };
+
+    let end = self.consume_semicolon("';' after import")?;
+
+    Ok(self.add_stmt(StmtKind::Import { path, names }, Span::new(start, end)))
+  }
+
+  fn function_declaration(&mut self) -> StmtResult {
+    let start = self.advance().span().start;
+
+    self.function(start, "function name")
+  }
+
+  // a function from its name on, methods are written without the `fun`
+  fn function(&mut self, start: usize, expected_name: &'static str) -> StmtResult {
+    let name = self.identifier(expected_name)?;
+    self.consume(|token| matches!(token, Token::OpenParen(_)), "'(' after function name")?;
+
+    self.function_body(start, name)
+  }
+
+  // `fun` where an expression is expected, a statement starting with it is still a declaration
+  fn lambda(&mut self) -> ParseResult {
+    let start = self.advance().span().start;
+    self.consume(|token| matches!(token, Token::OpenParen(_)), "'(' after 'fun'")?;
+    let declaration = self.function_body(start, String::from("<lambda>"))?;
+    let span = self.ast[declaration].span;
+
+    Ok(self.add_expr(ExprKind::Lambda(declaration), span))
+  }
+
+  // the parameters after the opening paren, then the body
+  fn function_body(&mut self, start: usize, name: String) -> StmtResult {
+
+    let mut params = vec![];
+
+    if !matches!(self.peek(), Token::CloseParen(_)) {
+      loop {
+        if params.len() == MAX_ARGUMENTS {
+          let span = self.peek().span();
+          self.errors.push(ParseError::TooManyParameters { span });
+        }
+
+        params.push(self.identifier("parameter name")?);
+
+        match self.peek() {
+          Token::Comma(_) => self.advance(),
+          _ => break,
+        };
+      }
+    }
+
+    self.consume(|token| matches!(token, Token::CloseParen(_)), "')' after parameters")?;
+
+    if !matches!(self.peek(), Token::OpenBrace(_)) {
+      return Err(self.expected("'{' before function body"));
+    }
+
+    // a loop around the declaration isn't one a break in the body can leave
+    let loop_depth = mem::take(&mut self.loop_depth);
+    let body = self.block_statements();
+    self.loop_depth = loop_depth;
+    let (body, end) = body?;
+
+    Ok(self.add_stmt(StmtKind::Function { name, params, body }, Span::new(start, end)))
+  }
+
+  fn var_declaration(&mut self) -> StmtResult {
+    let start = self.advance().span().start;
+    let name = self.identifier("variable name")?;
+
+    self.var_initializer(start, name)
+  }
+
+  // a variable declaration from after its name on
+  fn var_initializer(&mut self, start: usize, name: String) -> StmtResult {
+    let initializer = match self.peek() {
+      Token::Operator(_, Operators::Assignment) => {
+        self.advance();
+        Some(self.expression()?)
+      }
+      _ => None,
+    };
+
+    let end = self.consume_semicolon("';' after variable declaration")?;
+
+    Ok(self.add_stmt(StmtKind::Var { name, initializer }, Span::new(start, end)))
+  }
+
+  fn statement(&mut self) -> StmtResult {
+    match self.peek() {
+      Token::Keyword(_, Keywords::Print) => self.print_statement(),
+      Token::Keyword(_, Keywords::If) => self.if_statement(),
+      Token::Keyword(_, Keywords::While) => self.while_statement(),
+      Token::Keyword(_, Keywords::For) => self.for_statement(),
+      Token::Keyword(_, Keywords::Return) => self.return_statement(),
+      Token::Keyword(_, Keywords::Throw) => self.throw_statement(),
+      Token::Keyword(_, Keywords::Try) => self.try_statement(),
+      Token::Keyword(_, Keywords::Switch) => self.switch_statement(),
+      Token::Keyword(_, Keywords::Break) => self.loop_jump(StmtKind::Break, "break", "';' after 'break'"),
+      Token::Keyword(_, Keywords::Continue) => {
+        self.loop_jump(StmtKind::Continue, "continue", "';' after 'continue'")
+      }
+      Token::OpenBrace(_) => self.block(),
+      _ => self.expression_statement(),
+    }
+  }
+
+  fn print_statement(&mut self) -> StmtResult {
+    let start = self.advance().span().start;
+    let value = self.expression()?;
+    let end = self.consume_semicolon("';' after value")?;
+
+    Ok(self.add_stmt(StmtKind::Print(value), Span::new(start, end)))
+  }
+
+  // the condition of an if or while, parenthesized as in C
+  fn condition(&mut self, expected: &'static str) -> ParseResult {
+    self.consume(|token| matches!(token, Token::OpenParen(_)), expected)?;
+    let condition = self.expression()?;
+    self.consume(|token| matches!(token, Token::CloseParen(_)), "')' after condition")?;
+
+    Ok(condition)
+  }
+
+  // an else belongs to the nearest if before it
+  fn if_statement(&mut self) -> StmtResult {
+    let start = self.advance().span().start;
+    let condition = self.condition("'(' after 'if'")?;
+    let then_branch = self.statement()?;
+
+    let else_branch = match self.peek() {
+      Token::Keyword(_, Keywords::Else) => {
+        self.advance();
+        Some(self.statement()?)
+      }
+      _ => None,
+    };
+
+    let end = self.ast[else_branch.unwrap_or(then_branch)].span.end;
+    let kind = StmtKind::If {
+      condition,
+      then_branch,
+      else_branch,
+    };
+
+    Ok(self.add_stmt(kind, Span::new(start, end)))
+  }
+
+  fn while_statement(&mut self) -> StmtResult {
+    let start = self.advance().span().start;
+    let condition = self.condition("'(' after 'while'")?;
+    let body = self.loop_body()?;
+    let end = self.ast[body].span.end;
+    let kind = StmtKind::While {
+      condition,
+      body,
+      increment: None,
+    };
+
+    Ok(self.add_stmt(kind, Span::new(start, end)))
+  }
+
+  // the statement a loop repeats, where break and continue can be used
+  fn loop_body(&mut self) -> StmtResult {
+    self.loop_depth += 1;
+    let body = self.statement();
+    self.loop_depth -= 1;
+
+    body
+  }
+
+  // there is no for node, `for (init; condition; increment) body` is rewritten into
+  // `{ init; while (condition) body }` with the increment kept on the while, so later stages
+  // only know about while. `for (var name in iterable)` is told apart by the `in` after the name
+  fn for_statement(&mut self) -> StmtResult {
+    let start = self.advance().span().start;
+    self.consume(|token| matches!(token, Token::OpenParen(_)), "'(' after 'for'")?;
+
+    let initializer = match self.peek() {
+      Token::Semicolon(_) => {
+        self.advance();
+        None
+      }
+      Token::Keyword(_, Keywords::Var) => {
+        let var_start = self.advance().span().start;
+        let name = self.identifier("variable name")?;
+
+        if let Token::Keyword(_, Keywords::In) = self.peek() {
+          return self.for_in_statement(start, name);
+        }
+
+        Some(self.var_initializer(var_start, name)?)
+      }
+      _ => Some(self.expression_statement()?),
+    };
+
+    let condition = match self.peek() {
+      // a missing condition loops forever
+      Token::Semicolon(span) => {
+        let span = *span;
+        self.add_expr(ExprKind::Literal(Literal::Bool(true)), span)
+      }
+      _ => self.expression()?,
+    };
+    self.consume_semicolon("';' after loop condition")?;
+
+    let increment = match self.peek() {
+      Token::CloseParen(_) => None,
+      _ => Some(self.expression()?),
+    };
+    self.consume(|token| matches!(token, Token::CloseParen(_)), "')' after for clauses")?;
+
+    let body = self.loop_body()?;
+    let span = Span::new(start, self.ast[body].span.end);
+    let kind = StmtKind::While {
+      condition,
+      body,
+      increment,
+    };
+
+    let mut statement = self.add_stmt(kind, span);
+
+    if let Some(initializer) = initializer {
+      statement = self.add_stmt(StmtKind::Block(vec![initializer, statement]), span);
+    }
+
+    Ok(statement)
+  }
+
+  // the `in` is next, the loop variable's name has been taken
+  fn for_in_statement(&mut self, start: usize, name: String) -> StmtResult {
+    self.advance();
+    let iterable = self.expression()?;
+    self.consume(|token| matches!(token, Token::CloseParen(_)), "')' after for-in clause")?;
+
+    let body = self.loop_body()?;
+    let span = Span::new(start, self.ast[body].span.end);
+
+    Ok(self.add_stmt(StmtKind::ForIn { name, iterable, body }, span))
+  }
+
+  fn return_statement(&mut self) -> StmtResult {
+    let start = self.advance().span().start;
+
+    let value = match self.peek() {
+      Token::Semicolon(_) => None,
+      _ => Some(self.expression()?),
+    };
+
+    let end = self.consume_semicolon("';' after return value")?;
+
+    Ok(self.add_stmt(StmtKind::Return(value), Span::new(start, end)))
+  }
+
+  // break or continue, one outside of a loop is reported without stopping the parse
+  fn loop_jump(&mut self, kind: StmtKind, keyword: &'static str, expected: &'static str) -> StmtResult {
+    let span = self.advance().span();
+
+    if self.loop_depth == 0 {
+      self.errors.push(ParseError::OutsideLoop { span, keyword });
+    }
+
+    let end = self.consume_semicolon(expected)?;
+
+    Ok(self.add_stmt(kind, Span::new(span.start, end)))
+  }
+
+  fn throw_statement(&mut self) -> StmtResult {
+    let start = self.advance().span().start;
+    let value = self.expression()?;
+    let end = self.consume_semicolon("';' after thrown value")?;
+
+    Ok(self.add_stmt(StmtKind::Throw(value), Span::new(start, end)))
+  }
+
+  fn try_statement(&mut self) -> StmtResult {
+    let start = self.advance().span().start;
+    let body = self.braced_block("'{' after 'try'")?;
+    self.consume(|token| matches!(token, Token::Keyword(_, Keywords::Catch)), "'catch' after try block")?;
+    self.consume(|token| matches!(token, Token::OpenParen(_)), "'(' after 'catch'")?;
+    let name = self.identifier("catch variable name")?;
+    self.consume(|token| matches!(token, Token::CloseParen(_)), "')' after catch variable")?;
+    let handler = self.braced_block("'{' after catch clause")?;
+    let span = Span::new(start, self.ast[handler].span.end);
+
+    Ok(self.add_stmt(StmtKind::Try { body, name, handler }, span))
+  }
+
+  fn switch_statement(&mut self) -> StmtResult {
+    let start = self.advance().span().start;
+    self.consume(|token| matches!(token, Token::OpenParen(_)), "'(' after 'switch'")?;
+    let value = self.expression()?;
+    self.consume(|token| matches!(token, Token::CloseParen(_)), "')' after switch value")?;
+    self.consume(|token| matches!(token, Token::OpenBrace(_)), "'{' before switch cases")?;
+
+    let mut cases = vec![];
+    let mut default = None;
+
+    while !matches!(self.peek(), Token::CloseBrace(_) | Token::Eof(_)) {
+      match self.peek() {
+        Token::Keyword(span, Keywords::Default) => {
+          let span = *span;
+          self.advance();
+          let colon = self.consume(|token| matches!(token, Token::Colon(_)), "':' after 'default'")?;
+
+          if default.is_some() {
+            self.errors.push(ParseError::DuplicateDefault { span });
+          }
+
+          default = Some(self.switch_arm(span.start, colon.span().end));
+        }
+        Token::Keyword(_, Keywords::Case) => {
+          let case_start = self.advance().span().start;
+          let mut values = vec![self.expression()?];
+
+          while matches!(self.peek(), Token::Comma(_)) {
+            self.advance();
+            values.push(self.expression()?);
+          }
+
+          let colon = self.consume(|token| matches!(token, Token::Colon(_)), "':' after case values")?;
+          let body = self.switch_arm(case_start, colon.span().end);
+          cases.push(Case { values, body });
+        }
+        _ => return Err(self.expected("'case' or 'default'")),
+      }
+    }
+
+    let end = self.consume(|token| matches!(token, Token::CloseBrace(_)), "'}' after switch cases")?;
+
+    Ok(self.add_stmt(StmtKind::Switch { value, cases, default }, Span::new(start, end.span().end)))
+  }
+
+  // the statements up to the next arm or the end of the switch, there's no falling into the next
+  // one. The block runs from the arm's keyword, through its colon when it has no statements
+  fn switch_arm(&mut self, start: usize, colon_end: usize) -> StmtId {
+    let mut statements = vec![];
+    self.block_depth += 1;
+
+    while !matches!(
+      self.peek(),
+      Token::Keyword(_, Keywords::Case | Keywords::Default) | Token::CloseBrace(_) | Token::Eof(_)
+    ) {
+      statements.extend(self.declaration());
+    }
+
+    self.block_depth -= 1;
+    let end = statements.last().map_or(colon_end, |last| self.ast[*last].span.end);
+
+    self.add_stmt(StmtKind::Block(statements), Span::new(start, end))
+  }
+
+  // a block that has to be there, expected names what should have opened it
+  fn braced_block(&mut self, expected: &'static str) -> StmtResult {
+    if !matches!(self.peek(), Token::OpenBrace(_)) {
+      return Err(self.expected(expected));
+    }
+
+    self.block()
+  }
+
+  fn block(&mut self) -> StmtResult {
+    let start = self.peek().span().start;
+    let (statements, end) = self.block_statements()?;
+
+    Ok(self.add_stmt(StmtKind::Block(statements), Span::new(start, end)))
+  }
+
+  // the statements between a pair of braces, and where the closing brace ends
+  fn block_statements(&mut self) -> Result<(Vec<StmtId>, usize), ParseError> {
+    self.advance();
+    let mut statements = vec![];
+    self.block_depth += 1;
+
+    while !matches!(self.peek(), Token::CloseBrace(_) | Token::Eof(_)) {
+      statements.extend(self.declaration());
+    }
+
+    self.block_depth -= 1;
+    let end = self.consume(|token| matches!(token, Token::CloseBrace(_)), "'}' after block")?;
+
+    Ok((statements, end.span().end))
+  }
+
+  fn expression_statement(&mut self) -> StmtResult {
+    let expr = self.expression()?;
+    let start = self.span(expr).start;
+    let end = self.consume_semicolon("';' after expression")?;
+
+    Ok(self.add_stmt(StmtKind::Expression(expr), Span::new(start, end)))
+  }
+
+  fn expression(&mut self) -> ParseResult {
+    self.parse_precedence(Precedence::Assignment)
+  }
+
+  // parses a prefix expression and then keeps folding in infix operators for as long as
+  // they bind at least as tightly as min
+  fn parse_precedence(&mut self, min: Precedence) -> ParseResult {
+    let mut left = self.prefix()?;
+
+    while let Some((precedence, infix)) = infix_rule(self.peek()) {
+      if precedence < min {
+        break;
+      }
+
+      self.advance();
+
+      left = match infix {
+        Infix::Binary(operator) => {
+          let right = self.parse_precedence(precedence.tighter())?;
+          let span = self.join(left, right);
+
+          self.add_expr(ExprKind::Binary { left, operator, right }, span)
+        }
+        Infix::Logical(operator) => {
+          let right = self.parse_precedence(precedence.tighter())?;
+          let span = self.join(left, right);
+
+          self.add_expr(ExprKind::Logical { left, operator, right }, span)
+        }
+        // parsing the value at the same level makes assignment right associative, a bad
+        // target is reported without unwinding since the parser knows exactly where it is
+        Infix::Assign(compound) => {
+          let value = self.parse_precedence(precedence)?;
+
+          match self.assignment(left, compound, value) {
+            Some(assignment) => assignment,
+            None => {
+              let span = self.span(left);
+              self.errors.push(ParseError::InvalidAssignmentTarget { span });
+              value
+            }
+          }
+        }
+        Infix::Call => self.call(left)?,
+        Infix::Get => {
+          let end = self.peek().span().end;
+          let name = self.identifier("property name after '.'")?;
+          let span = Span::new(self.span(left).start, end);
+
+          self.add_expr(ExprKind::Get { object: left, name }, span)
+        }
+        Infix::Index => self.index(left)?,
+      };
+    }
+
+    Ok(left)
+  }
+
+  // the callee has been parsed and its opening paren taken
+  fn call(&mut self, callee: ExprId) -> ParseResult {
+    let mut arguments = vec![];
+
+    if !matches!(self.peek(), Token::CloseParen(_)) {
+      loop {
+        if arguments.len() == MAX_ARGUMENTS {
+          let span = self.peek().span();
+          self.errors.push(ParseError::TooManyArguments { span });
+        }
+
+        arguments.push(self.expression()?);
+
+        match self.peek() {
+          Token::Comma(_) => self.advance(),
+          _ => break,
+        };
+      }
+    }
+
+    let end = self.consume(|token| matches!(token, Token::CloseParen(_)), "')' after arguments")?;
+    let span = Span::new(self.span(callee).start, end.span().end);
+
+    Ok(self.add_expr(ExprKind::Call { callee, arguments }, span))
+  }
+
+  // the object has been parsed and its opening bracket taken, a colon makes it a slice
+  fn index(&mut self, object: ExprId) -> ParseResult {
+    if let Token::Colon(_) = self.peek() {
+      return self.slice(object, None);
+    }
+
+    let index = self.expression()?;
+
+    if let Token::Colon(_) = self.peek() {
+      return self.slice(object, Some(index));
+    }
+
+    let end = self.consume(|token| matches!(token, Token::CloseBracket(_)), "']' after index")?;
+    let span = Span::new(self.span(object).start, end.span().end);
+
+    Ok(self.add_expr(ExprKind::Index { object, index }, span))
+  }
+
+  // the colon is next
+  fn slice(&mut self, object: ExprId, start: Option<ExprId>) -> ParseResult {
+    self.advance();
+
+    let end = match self.peek() {
+      Token::CloseBracket(_) => None,
+      _ => Some(self.expression()?),
+    };
+
+    let bracket = self.consume(|token| matches!(token, Token::CloseBracket(_)), "']' after slice")?;
+    let span = Span::new(self.span(object).start, bracket.span().end);
+
+    Ok(self.add_expr(ExprKind::Slice { object, start, end }, span))
+  }
+
+  fn prefix(&mut self) -> ParseResult {
+    let operator = match self.peek() {
+      Token::Operator(_, Operators::Bang) => UnaryOperator::Not,
+      Token::Operator(_, Operators::Minus) => UnaryOperator::Negate,
+      Token::Operator(_, Operators::BitNot) => UnaryOperator::BitNot,
+      _ => return self.primary(),
+    };
+
+    let start = self.advance().span().start;
+    let operand = self.parse_precedence(Precedence::Unary)?;
+    let span = Span::new(start, self.span(operand).end);
+
+    Ok(self.add_expr(ExprKind::Unary { operator, operand }, span))
+  }
+
+  fn primary(&mut self) -> ParseResult {
+    let literal = match self.peek() {
+      Token::Literal(_, Literals::Number(value)) => Literal::Number(*value),
+      Token::Literal(_, Literals::String(value)) => Literal::String(value.to_string()),
+      Token::Keyword(_, Keywords::True) => Literal::Bool(true),
+      Token::Keyword(_, Keywords::False) => Literal::Bool(false),
+      Token::Keyword(_, Keywords::Nil) => Literal::Nil,
+      Token::Keyword(span, Keywords::This) => {
+        let span = *span;
+        self.advance();
+
+        return Ok(self.add_expr(ExprKind::This, span));
+      }
+      Token::Keyword(_, Keywords::Super) => return self.super_method(),
+      Token::Keyword(_, Keywords::Fun) => return self.lambda(),
+      Token::Identifier(span, name) => {
+        let (kind, span) = (ExprKind::Variable(name.to_string()), *span);
+        self.advance();
+
+        return Ok(self.add_expr(kind, span));
+      }
+      Token::OpenParen(_) => return self.grouping(),
+      Token::OpenBracket(_) => return self.list(),
+      Token::OpenBrace(_) => return self.map(),
+      Token::Interpolation(_, _) => return self.interpolation(),
+      _ => return Err(self.expected("expression")),
+    };
+
+    let span = self.advance().span();
+
+    Ok(self.add_expr(ExprKind::Literal(literal), span))
+  }
+
+  fn super_method(&mut self) -> ParseResult {
+    let start = self.advance().span().start;
+    self.consume(|token| matches!(token, Token::Dot(_)), "'.' after 'super'")?;
+    let end = self.peek().span().end;
+    let method = self.identifier("superclass method name")?;
+
+    Ok(self.add_expr(ExprKind::Super { method }, Span::new(start, end)))
+  }
+
+  fn grouping(&mut self) -> ParseResult {
+    let start = self.advance().span().start;
+    let inner = self.expression()?;
+    let end = self.consume(|token| matches!(token, Token::CloseParen(_)), "')' after expression")?;
+
+    Ok(self.add_expr(ExprKind::Grouping(inner), Span::new(start, end.span().end)))
+  }
+
+  fn list(&mut self) -> ParseResult {
+    let start = self.advance().span().start;
+    let mut elements = vec![];
+
+    if !matches!(self.peek(), Token::CloseBracket(_)) {
+      loop {
+        elements.push(self.expression()?);
+
+        match self.peek() {
+          Token::Comma(_) => self.advance(),
+          _ => break,
+        };
+      }
+    }
+
+    let end = self.consume(|token| matches!(token, Token::CloseBracket(_)), "']' after list elements")?;
+
+    Ok(self.add_expr(ExprKind::List(elements), Span::new(start, end.span().end)))
+  }
+
+  // only where an expression is expected, a statement starting with a brace is still a block
+  fn map(&mut self) -> ParseResult {
+    let start = self.advance().span().start;
+    let mut entries = vec![];
+
+    if !matches!(self.peek(), Token::CloseBrace(_)) {
+      loop {
+        let key = self.expression()?;
+        self.consume(|token| matches!(token, Token::Colon(_)), "':' after map key")?;
+        entries.push((key, self.expression()?));
+
+        match self.peek() {
+          Token::Comma(_) => self.advance(),
+          _ => break,
+        };
+      }
+    }
+
+    let end = self.consume(|token| matches!(token, Token::CloseBrace(_)), "'}' after map entries")?;
+
+    Ok(self.add_expr(ExprKind::Map(entries), Span::new(start, end.span().end)))
+  }
+
+  // the lexer splits "a ${b} c" into an Interpolation segment, the tokens of b and an
+  // InterpolationEnd, with more segments in between when the string embeds several expressions
+  fn interpolation(&mut self) -> ParseResult {
+    let mut parts = vec![];
+    let mut segment = self.advance();
+    let start = segment.span().start;
+
+    loop {
+      let (span, text) = match &segment {
+        Token::Interpolation(span, text) | Token::InterpolationEnd(span, text) => (*span, text.to_string()),
+        _ => unreachable!("interpolation segments are checked before they are taken"),
+      };
+
+      if !text.is_empty() {
+        parts.push(self.add_expr(ExprKind::Literal(Literal::String(text)), span));
+      }
+
+      if let Token::InterpolationEnd(_, _) = segment {
+        return Ok(self.add_expr(ExprKind::Interpolation(parts), Span::new(start, span.end)));
+      }
+
+      parts.push(self.expression()?);
+
+      segment = match self.peek() {
+        Token::Interpolation(_, _) | Token::InterpolationEnd(_, _) => self.advance(),
+        _ => return Err(self.expected("'}' after interpolated expression")),
+      };
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::lexer::Lexer;
+
+  fn parse(code: &str) -> Result<(Ast, ExprId), ParseError> {
+    Parser::new(Lexer::new(code).lex().unwrap())
+      .parse_expression()
+      .map_err(|errors| errors[0].clone())
+  }
+
+  fn parse_program(code: &str) -> Result<Ast, Vec<ParseError>> {
+    Parser::new(Lexer::new(code).lex().unwrap()).parse()
+  }
+
+  fn error_messages(code: &str) -> Vec<String> {
+    match parse_program(code) {
+      Ok(program) => panic!("expected parse errors, got {:?}", program),
+      Err(errors) => errors.iter().map(|error| error.to_string()).collect(),
+    }
+  }
+
+  fn binary_parts(ast: &Ast, expr: ExprId) -> (ExprId, BinaryOperator, ExprId) {
+    match &ast[expr].kind {
+      ExprKind::Binary { left, operator, right } => (*left, *operator, *right),
+      kind => panic!("expected a binary expression, got {:?}", kind),
+    }
+  }
+
+  #[test]
+  fn parsing_onto_an_ast_keeps_its_nodes() {
+    let mut ast = Ast::new();
+    Parser::new(Lexer::new("var a = 1;").lex().unwrap()).parse_onto(&mut ast).unwrap();
+    let first = ast.program()[0];
+
+    assert!(Parser::new(Lexer::new("print;").lex().unwrap()).parse_onto(&mut ast).is_err());
+    assert!(ast.program().is_empty());
+
+    Parser::new(Lexer::new("print a;").lex().unwrap()).parse_onto(&mut ast).unwrap();
+    assert_eq!(ast.program().len(), 1);
+    assert!(matches!(&ast[first].kind, StmtKind::Var { name, .. } if name == "a"));
+  }
+
+  #[test]
+  fn factor_binds_tighter_than_term() {
+    let (ast, expr) = parse("1 + 2 * 3").unwrap();
+    let (left, operator, right) = binary_parts(&ast, expr);
+
+    assert_eq!(operator, BinaryOperator::Add);
+    assert!(matches!(ast[left].kind, ExprKind::Literal(Literal::Number(_))));
+    assert_eq!(binary_parts(&ast, right).1, BinaryOperator::Multiply);
+  }
+
+  #[test]
+  fn remainder_binds_like_multiplication() {
+    let (ast, expr) = parse("1 + 7 % 3 * 2").unwrap();
+    let (_, operator, right) = binary_parts(&ast, expr);
+    assert_eq!(operator, BinaryOperator::Add);
+
+    let (left, operator, _) = binary_parts(&ast, right);
+    assert_eq!(operator, BinaryOperator::Multiply);
+    assert_eq!(binary_parts(&ast, left).1, BinaryOperator::Remainder);
+
+    let (ast, expr) = parse("-7 % 3").unwrap();
+    let (left, operator, _) = binary_parts(&ast, expr);
+    assert_eq!(operator, BinaryOperator::Remainder);
+    assert!(matches!(ast[left].kind, ExprKind::Unary { operator: UnaryOperator::Negate, .. }));
+  }
+
+  #[test]
+  fn binary_operators_are_left_associative() {
+    let (ast, expr) = parse("1 - 2 - 3").unwrap();
+    let (left, operator, _) = binary_parts(&ast, expr);
+
+    assert_eq!(operator, BinaryOperator::Subtract);
+    assert_eq!(binary_parts(&ast, left).1, BinaryOperator::Subtract);
+  }
+
+  #[test]
+  fn bitwise_binds_tighter_than_equality() {
+    let (ast, expr) = parse("x & 1 == 0").unwrap();
+    let (left, operator, _) = binary_parts(&ast, expr);
+
+    assert_eq!(operator, BinaryOperator::Equal);
+    assert_eq!(binary_parts(&ast, left).1, BinaryOperator::BitAnd);
+  }
+
+  #[test]
+  fn bitwise_levels_follow_the_table() {
+    let (ast, expr) = parse("a | b ^ c & d << 1").unwrap();
+    let (_, operator, right) = binary_parts(&ast, expr);
+    assert_eq!(operator, BinaryOperator::BitOr);
+
+    let (_, operator, right) = binary_parts(&ast, right);
+    assert_eq!(operator, BinaryOperator::BitXor);
+
+    let (_, operator, right) = binary_parts(&ast, right);
+    assert_eq!(operator, BinaryOperator::BitAnd);
+    assert_eq!(binary_parts(&ast, right).1, BinaryOperator::ShiftLeft);
+  }
+
+  #[test]
+  fn shift_binds_looser_than_term_and_bit_not_tightest() {
+    let (ast, expr) = parse("1 << 2 + 3").unwrap();
+    let (_, operator, right) = binary_parts(&ast, expr);
+    assert_eq!(operator, BinaryOperator::ShiftLeft);
+    assert_eq!(binary_parts(&ast, right).1, BinaryOperator::Add);
+
+    let (ast, expr) = parse("~a & b").unwrap();
+    let (left, operator, _) = binary_parts(&ast, expr);
+    assert_eq!(operator, BinaryOperator::BitAnd);
+    assert!(matches!(ast[left].kind, ExprKind::Unary { operator: UnaryOperator::BitNot, .. }));
+
+    let (ast, expr) = parse("8 >> 1 >> 1").unwrap();
+    let (left, operator, _) = binary_parts(&ast, expr);
+    assert_eq!(operator, BinaryOperator::ShiftRight);
+    assert_eq!(binary_parts(&ast, left).1, BinaryOperator::ShiftRight);
+  }
+
+  #[test]
+  fn and_binds_tighter_than_or() {
+    let (ast, expr) = parse("a or b and c").unwrap();
+
+    match ast[expr].kind {
+      ExprKind::Logical { operator, right, .. } => {
+        assert_eq!(operator, LogicalOperator::Or);
+        assert!(matches!(ast[right].kind, ExprKind::Logical { operator: LogicalOperator::And, .. }));
+      }
+      ref kind => panic!("expected a logical expression, got {:?}", kind),
+    }
+  }
+
+  #[test]
+  fn coalesce_binds_looser_than_or() {
+    let (ast, expr) = parse("a ?? b or c").unwrap();
+
+    match ast[expr].kind {
+      ExprKind::Logical { operator, right, .. } => {
+        assert_eq!(operator, LogicalOperator::Coalesce);
+        assert!(matches!(ast[right].kind, ExprKind::Logical { operator: LogicalOperator::Or, .. }));
+      }
+      ref kind => panic!("expected a logical expression, got {:?}", kind),
+    }
+  }
+
+  #[test]
+  fn unary_binds_tighter_than_factor() {
+    let (ast, expr) = parse("-a * b").unwrap();
+    let (left, operator, _) = binary_parts(&ast, expr);
+
+    assert_eq!(operator, BinaryOperator::Multiply);
+    assert!(matches!(ast[left].kind, ExprKind::Unary { operator: UnaryOperator::Negate, .. }));
+  }
+
+  #[test]
+  fn assignment_is_right_associative() {
+    let (ast, expr) = parse("a = b = 1").unwrap();
+
+    match &ast[expr].kind {
+      ExprKind::Assign { name, value } => {
+        assert_eq!(name, "a");
+        assert!(matches!(ast[*value].kind, ExprKind::Assign { ref name, .. } if name == "b"));
+      }
+      kind => panic!("expected an assignment, got {:?}", kind),
+    }
+  }
+
+  #[test]
+  fn compound_assignment_is_desugared() {
+    let (ast, expr) = parse("a += 1").unwrap();
+
+    match &ast[expr].kind {
+      ExprKind::Assign { name, value } => {
+        assert_eq!(name, "a");
+        let (left, operator, _) = binary_parts(&ast, *value);
+        assert_eq!(operator, BinaryOperator::Add);
+        assert!(matches!(ast[left].kind, ExprKind::Variable(ref name) if name == "a"));
+      }
+      kind => panic!("expected an assignment, got {:?}", kind),
+    }
+  }
+
+  #[test]
+  fn spans_cover_the_whole_expression() {
+    let span = |code| {
+      let (ast, expr) = parse(code).unwrap();
+      ast[expr].span
+    };
+
+    assert_eq!(span("(1 + 2)"), Span::new(0, 7));
+    assert_eq!(span("-x * y"), Span::new(0, 6));
+  }
+
+  #[test]
+  fn interpolation_collects_its_parts() {
+    let (ast, expr) = parse("\"a ${x + 1} b\"").unwrap();
+
+    match &ast[expr].kind {
+      ExprKind::Interpolation(parts) => {
+        assert_eq!(parts.len(), 3);
+        assert!(matches!(ast[parts[1]].kind, ExprKind::Binary { .. }));
+      }
+      kind => panic!("expected an interpolation, got {:?}", kind),
+    }
+
+    let (ast, expr) = parse("\"${\"nested\"}\"").unwrap();
+
+    match &ast[expr].kind {
+      ExprKind::Interpolation(parts) => {
+        assert_eq!(parts.len(), 1);
+        assert!(matches!(&ast[parts[0]].kind, ExprKind::Literal(Literal::String(text)) if text == "nested"));
+      }
+      kind => panic!("expected an interpolation, got {:?}", kind),
+    }
+  }
+
+  #[test]
+  fn property_assignment_becomes_set() {
+    let (ast, expr) = parse("a.b.c = 1").unwrap();
+
+    match &ast[expr].kind {
+      ExprKind::Set { object, name, .. } => {
+        assert_eq!(name, "c");
+        assert!(matches!(&ast[*object].kind, ExprKind::Get { name, .. } if name == "b"));
+      }
+      kind => panic!("expected a property assignment, got {:?}", kind),
+    }
+
+    assert!(matches!(parse("a.f() = 1"), Err(ParseError::InvalidAssignmentTarget { .. })));
+  }
+
+  #[test]
+  fn index_assignment_becomes_set_index() {
+    let (ast, expr) = parse("a[0][1] = 2").unwrap();
+
+    match &ast[expr].kind {
+      ExprKind::SetIndex { object, .. } => assert!(matches!(&ast[*object].kind, ExprKind::Index { .. })),
+      kind => panic!("expected an index assignment, got {:?}", kind),
+    }
+
+    assert!(matches!(parse("a[0:1] = 2"), Err(ParseError::InvalidAssignmentTarget { .. })));
+  }
+
+  #[test]
+  fn compound_assignment_to_a_property_or_element_keeps_its_operator() {
+    let (ast, expr) = parse("a.b -= 1").unwrap();
+    let operator = Some(BinaryOperator::Subtract);
+    assert!(matches!(&ast[expr].kind, ExprKind::Set { operator: found, value, .. }
+      if *found == operator && matches!(ast[*value].kind, ExprKind::Literal(_))));
+
+    let (ast, expr) = parse("a[0] *= 2").unwrap();
+    let operator = Some(BinaryOperator::Multiply);
+    assert!(matches!(&ast[expr].kind, ExprKind::SetIndex { operator: found, value, .. }
+      if *found == operator && matches!(ast[*value].kind, ExprKind::Literal(_))));
+  }
+
+  #[test]
+  fn fun_in_an_expression_is_a_lambda() {
+    let (ast, expr) = parse("fun (a, b) { return a + b; }").unwrap();
+
+    match &ast[expr].kind {
+      ExprKind::Lambda(declaration) => {
+        assert!(matches!(&ast[*declaration].kind, StmtKind::Function { name, params, body }
+          if name == "<lambda>" && params == &["a", "b"] && body.len() == 1));
+      }
+      kind => panic!("expected a lambda, got {:?}", kind),
+    }
+
+    assert_eq!(ast[expr].span, Span::new(0, 28));
+    assert!(matches!(parse("fun a() {}"), Err(ParseError::Expected { expected: "'(' after 'fun'", .. })));
+    assert!(matches!(parse("fun (a) 1"), Err(ParseError::Expected { expected: "'{' before function body", .. })));
+  }
+
+  #[test]
+  fn errors() {
+    assert!(matches!(parse("1 = 2"), Err(ParseError::InvalidAssignmentTarget { .. })));
+    assert!(matches!(parse("a + b = c"), Err(ParseError::InvalidAssignmentTarget { .. })));
+    assert!(matches!(parse("(1"), Err(ParseError::Expected { expected: "')' after expression", .. })));
+    assert!(matches!(parse("1 +"), Err(ParseError::Expected { expected: "expression", .. })));
+    assert!(matches!(parse("1 2"), Err(ParseError::Expected { expected: "end of input", .. })));
+    assert!(matches!(parse(""), Err(ParseError::Expected { expected: "expression", .. })));
+  }
+
+  #[test]
+  fn statements() {
+    let ast = parse_program("var a = 1; var b; print a + b; a = 2;").unwrap();
+    let program: Vec<_> = ast.program().iter().map(|statement| &ast[*statement]).collect();
+
+    assert_eq!(program.len(), 4);
+    assert!(matches!(&program[0].kind, StmtKind::Var { name, initializer: Some(_) } if name == "a"));
+    assert!(matches!(&program[1].kind, StmtKind::Var { name, initializer: None } if name == "b"));
+    assert!(matches!(program[2].kind, StmtKind::Print(_)));
+    assert!(matches!(program[3].kind, StmtKind::Expression(_)));
+  }
+
+  #[test]
+  fn blocks_nest() {
+    let ast = parse_program("{ var a = 1; { print a; } }").unwrap();
+
+    match &ast[ast.program()[0]].kind {
+      StmtKind::Block(statements) => {
+        assert_eq!(statements.len(), 2);
+        assert!(matches!(&ast[statements[1]].kind, StmtKind::Block(inner) if inner.len() == 1));
+      }
+      kind => panic!("expected a block, got {:?}", kind),
+    }
+  }
+
+  #[test]
+  fn switch_arms_are_blocks() {
+    let ast = parse_program("switch (a) { case 1, 2: var b = a; print b; case 3: default: print 0; }").unwrap();
+
+    match &ast[ast.program()[0]].kind {
+      StmtKind::Switch {
+        cases,
+        default: Some(default),
+        ..
+      } => {
+        assert_eq!(cases.iter().map(|case| case.values.len()).collect::<Vec<_>>(), [2, 1]);
+        assert!(matches!(&ast[cases[0].body].kind, StmtKind::Block(statements) if statements.len() == 2));
+        assert!(matches!(&ast[cases[1].body].kind, StmtKind::Block(statements) if statements.is_empty()));
+        assert_eq!(ast[cases[1].body].span, Span::new(44, 51));
+        assert!(matches!(&ast[*default].kind, StmtKind::Block(statements) if statements.len() == 1));
+      }
+      kind => panic!("expected a switch with a default, got {:?}", kind),
+    }
+
+    assert_eq!(
+      error_messages("switch (a) { default: print 1; case 1: default: }"),
+      vec!["a switch can't have more than one default"]
+    );
+  }
+
+  #[test]
+  fn else_binds_to_the_nearest_if() {
+    let ast = parse_program("if (a) if (b) print 1; else print 2;").unwrap();
+
+    match &ast[ast.program()[0]].kind {
+      StmtKind::If {
+        then_branch,
+        else_branch: None,
+        ..
+      } => assert!(matches!(ast[*then_branch].kind, StmtKind::If { else_branch: Some(_), .. })),
+      kind => panic!("expected an if without else, got {:?}", kind),
+    }
+  }
+
+  #[test]
+  fn argument_lists_are_limited() {
+    let arguments = vec!["a"; MAX_ARGUMENTS + 1].join(", ");
+
+    assert_eq!(error_messages(&format!("f({});", arguments)), vec!["can't have more than 255 arguments"]);
+    assert_eq!(
+      error_messages(&format!("fun f({}) {{}}", arguments)),
+      vec!["can't have more than 255 parameters"]
+    );
+    assert!(parse_program(&format!("f({});", vec!["a"; MAX_ARGUMENTS].join(", "))).is_ok());
+  }
+
+  #[test]
+  fn statement_spans_include_the_terminator() {
+    let ast = parse_program("print 1;\n{ x; }").unwrap();
+
+    assert_eq!(ast[ast.program()[0]].span, Span::new(0, 8));
+    assert_eq!(ast[ast.program()[1]].span, Span::new(9, 15));
+  }
+
+  #[test]
+  fn statement_errors() {
+    let expected = |code| match parse_program(code).as_ref().map_err(Vec::as_slice) {
+      Err([ParseError::Expected { expected, .. }]) => *expected,
+      result => panic!("expected a single parse error, got {:?}", result),
+    };
+
+    assert_eq!(expected("print 1"), "';' after value");
+    assert_eq!(expected("1 + 2"), "';' after expression");
+    assert_eq!(expected("var = 1;"), "variable name");
+    assert_eq!(expected("var a = 1"), "';' after variable declaration");
+    assert_eq!(expected("{ print 1;"), "'}' after block");
+    assert_eq!(expected("if a print 1;"), "'(' after 'if'");
+    assert_eq!(expected("while (a print 1;"), "')' after condition");
+    assert_eq!(expected("for (var i = 0; i < 1) print i;"), "';' after loop condition");
+    assert_eq!(expected("for (;; i += 1 print i;"), "')' after for clauses");
+    assert_eq!(expected("switch a {}"), "'(' after 'switch'");
+    assert_eq!(expected("switch (a) case 1: {}"), "'{' before switch cases");
+    assert_eq!(expected("switch (a) { case 1 }"), "':' after case values");
+    assert_eq!(expected("switch (a) { default }"), "':' after 'default'");
+    assert_eq!(expected("switch (a) { x }"), "'case' or 'default'");
+    assert_eq!(expected("switch (a) { case 1: print 1;"), "'}' after switch cases");
+    assert_eq!(expected("f(1, 2;"), "')' after arguments");
+    assert_eq!(expected("fun (a) {}"), "function name");
+    assert_eq!(expected("fun f(a, 1) {}"), "parameter name");
+    assert_eq!(expected("fun f(a) print a;"), "'{' before function body");
+    assert_eq!(expected("return 1"), "';' after return value");
+    assert_eq!(expected("throw 1"), "';' after thrown value");
+    assert_eq!(expected("while (a) break"), "';' after 'break'");
+    assert_eq!(expected("while (a) continue 1;"), "';' after 'continue'");
+    assert_eq!(expected("try print 1;"), "'{' after 'try'");
+    assert_eq!(expected("try {} print 1;"), "'catch' after try block");
+    assert_eq!(expected("try {} catch e {}"), "'(' after 'catch'");
+    assert_eq!(expected("try {} catch () {}"), "catch variable name");
+    assert_eq!(expected("try {} catch (e {}"), "')' after catch variable");
+    assert_eq!(expected("try {} catch (e) print e;"), "'{' after catch clause");
+    assert_eq!(expected("import m;"), "'from' after imported names");
+    assert_eq!(expected("import a, \"m.lox\";"), "imported name");
+    assert_eq!(expected("import 1;"), "module path");
+    assert_eq!(expected("import a from \"m${1}.lox\";"), "module path");
+    assert_eq!(expected("import \"m.lox\""), "';' after import");
+    assert_eq!(expected("class { }"), "class name");
+    assert_eq!(expected("class A { 1 }"), "method name");
+    assert_eq!(expected("class A { f() {}"), "'}' after class body");
+    assert_eq!(expected("a.1;"), "property name after '.'");
+    assert_eq!(expected("class A < {}"), "superclass name");
+    assert_eq!(expected("super();"), "'.' after 'super'");
+    assert_eq!(expected("super.;"), "superclass method name");
+    assert_eq!(expected("[1, 2;"), "']' after list elements");
+    assert_eq!(expected("print {1 2};"), "':' after map key");
+    assert_eq!(expected("print {1: 2;"), "'}' after map entries");
+    assert_eq!(expected("a[1;"), "']' after index");
+    assert_eq!(expected("a[1:2;"), "']' after slice");
+    assert_eq!(expected("for (var x in xs print x;"), "')' after for-in clause");
+  }
+
+  #[test]
+  fn every_broken_statement_is_reported() {
+    assert_eq!(
+      error_messages("print ; var = 1; print 2"),
+      vec!["expected expression", "expected variable name", "expected ';' after value"]
+    );
+  }
+
+  #[test]
+  fn recovery_stops_at_statement_keywords() {
+    assert_eq!(
+      error_messages("1 + print 2; var x = ) var y = 1;"),
+      vec!["expected expression", "expected expression"]
+    );
+  }
+
+  #[test]
+  fn recovery_stays_inside_blocks() {
+    assert_eq!(error_messages("{ print ; print 1; } print 2;"), vec!["expected expression"]);
+    assert_eq!(error_messages("{ var } print 1;"), vec!["expected variable name"]);
+    assert_eq!(error_messages("} print 1 }"), vec!["expected expression", "expected ';' after value"]);
+  }
+
+  #[test]
+  fn classes_cannot_inherit_from_themselves() {
+    assert_eq!(error_messages("class A < A {}"), vec!["a class can't inherit from itself"]);
+  }
+
+  #[test]
+  fn break_and_continue_only_go_in_loops() {
+    assert_eq!(
+      error_messages("break; if (a) { continue; } print 1;"),
+      vec!["can't use 'break' outside of a loop", "can't use 'continue' outside of a loop"]
+    );
+    // a function declared in a loop is outside of it
+    assert_eq!(
+      error_messages("while (a) { fun f() { break; } }"),
+      vec!["can't use 'break' outside of a loop"]
+    );
+    assert_eq!(
+      error_messages("while (a) { var f = fun () { continue; }; }"),
+      vec!["can't use 'continue' outside of a loop"]
+    );
+    assert!(parse_program("while (a) if (b) break; else { continue; } for (var x in xs) { { break; } }").is_ok());
+  }
+
+  #[test]
+  fn invalid_assignment_targets_do_not_stop_the_statement() {
+    assert_eq!(
+      error_messages("a + b = c; (d) = 1; print;"),
+      vec!["invalid assignment target", "invalid assignment target", "expected expression"]
+    );
+  }
+
+  #[test]
+  fn parsed_statements_survive_errors_around_them() {
+    let mut parser = Parser::new(Lexer::new("print 1; print ; print 2;").lex().unwrap());
+    let mut statements = vec![];
+
+    while !matches!(parser.peek(), Token::Eof(_)) {
+      statements.extend(parser.declaration());
+    }
+
+    assert_eq!(statements.len(), 2);
+    assert_eq!(parser.errors.len(), 1);
+  }
+}
@@ -0,0 +1,72 @@
+// the generator behind the random natives of every backend, splitmix64 with one state per
+// thread. It starts seeded from the clock, seedRandom makes what follows the same on every run
+// and on every backend
+use std::cell::Cell;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+thread_local! {
+  static STATE: Cell<u64> = Cell::new(
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_nanos() as u64)
+  );
+}
+
+// any number works as a seed, fractions included
+pub fn seed(seed: f64) {
+  STATE.with(|state| state.set(seed.to_bits()));
+}
+
+fn next() -> u64 {
+  STATE.with(|state| {
+    let next = state.get().wrapping_add(0x9e3779b97f4a7c15);
+    state.set(next);
+
+    let mut z = next;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+  })
+}
+
+// from 0 up to but not including 1, with every one of the 53 bits of a number random
+pub fn random() -> f64 {
+  (next() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+// a whole number from low to high, both included
+pub fn random_int(low: f64, high: f64) -> Result<f64, String> {
+  if low.fract() != 0.0 || high.fract() != 0.0 || low > high {
+    return Err(String::from("Arguments to randomInt() must be whole numbers, the first no greater than the second."));
+  }
+
+  let choices = (high - low) as u64 + 1;
+
+  Ok(low + (next() % choices) as f64)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn seeding_repeats_the_numbers() {
+    seed(42.0);
+    let first = (0..5).map(|_| random()).collect::<Vec<_>>();
+    seed(42.0);
+    let second = (0..5).map(|_| random()).collect::<Vec<_>>();
+
+    assert_eq!(first, second);
+    assert!(first.iter().all(|number| (0.0..1.0).contains(number)));
+  }
+
+  #[test]
+  fn random_ints_stay_in_range() {
+    seed(7.0);
+    let rolls = (0..1000).map(|_| random_int(1.0, 6.0).unwrap()).collect::<Vec<_>>();
+
+    assert!(rolls.iter().all(|roll| (1.0..=6.0).contains(roll) && roll.fract() == 0.0));
+    assert!((1..=6).all(|side| rolls.contains(&(side as f64))));
+    assert_eq!(random_int(3.0, 3.0), Ok(3.0));
+    assert!(random_int(1.5, 3.0).is_err());
+    assert!(random_int(3.0, 1.0).is_err());
+  }
+}
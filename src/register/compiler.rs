@@ -0,0 +1,949 @@
+use std::rc::Rc;
+
+use crate::ast::{
+  Ast, BinaryOperator, Case, ExprId, ExprKind, Literal, LogicalOperator, StmtId, StmtKind, StmtVisitor, UnaryOperator,
+};
+use crate::chunk::Value;
+use crate::lexer::{SourceMap, Span};
+
+use super::{CompileError, Instruction, Prototype, Register};
+
+#[derive(Debug)]
+struct Local {
+  name: String,
+  // how many blocks deep it was declared, its register is freed when that block ends
+  depth: usize,
+}
+
+// where a name was found
+enum Variable {
+  Local(Register),
+  Global(usize),
+}
+
+// the jumps out of a loop partway through being compiled, patched once the code they go to is
+// written. Leaving a loop needs nothing else, its locals are registers that are simply reused
+#[derive(Debug, Default)]
+struct Loop {
+  breaks: Vec<usize>,
+  continues: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FunctionKind {
+  Function,
+  Method,
+  // returns the instance rather than nil when it runs off its end
+  Initializer,
+}
+
+// a function partway through being compiled. Locals hold the registers from 0 up in the order
+// they were declared, the temporaries of the expression being compiled come after them
+#[derive(Debug)]
+struct FunctionState {
+  prototype: Prototype,
+  kind: FunctionKind,
+  locals: Vec<Local>,
+  // the first register that's neither a local nor a temporary in use
+  next: usize,
+  scope_depth: usize,
+  // innermost last
+  loops: Vec<Loop>,
+}
+
+impl FunctionState {
+  // register 0 is `this` in a method, where the receiver is called in place of the function
+  fn new(name: &str, arity: usize, kind: FunctionKind) -> Self {
+    let receiver = match kind {
+      FunctionKind::Function => "",
+      FunctionKind::Method | FunctionKind::Initializer => "this",
+    };
+
+    Self {
+      prototype: Prototype::new(name, arity),
+      kind,
+      locals: vec![Local {
+        name: String::from(receiver),
+        depth: 0,
+      }],
+      next: 1,
+      scope_depth: 0,
+      loops: vec![],
+    }
+  }
+}
+
+// walks the tree once like the stack compiler, but each expression is compiled into a register
+// it's given. Reading a local needs no instruction at all, the register it's in is used as the
+// operand straight away
+pub struct RegisterCompiler<'a> {
+  source_map: &'a SourceMap<'a>,
+  // the function being compiled and the ones it is nested in, innermost last
+  functions: Vec<FunctionState>,
+  errors: Vec<CompileError>,
+}
+
+impl<'a> RegisterCompiler<'a> {
+  pub fn new(source_map: &'a SourceMap<'a>) -> Self {
+    Self {
+      source_map,
+      functions: vec![FunctionState::new("", 0, FunctionKind::Function)],
+      errors: vec![],
+    }
+  }
+
+  // the whole program as a function taking no arguments, or every error found compiling it.
+  // The tree should have been through the resolver first
+  pub fn compile(mut self, ast: &Ast) -> Result<Prototype, Vec<CompileError>> {
+    for statement in ast.program() {
+      ast.visit_stmt(*statement, &mut self);
+    }
+
+    let end = ast.program().last().map_or(0, |statement| ast[*statement].span.end);
+    self.emit_return(Span::new(end, end));
+    let script = self.functions.pop().expect("the script is compiled last");
+
+    if self.errors.is_empty() {
+      Ok(script.prototype)
+    } else {
+      Err(self.errors)
+    }
+  }
+
+  fn current(&mut self) -> &mut FunctionState {
+    self.functions.last_mut().expect("there is always a function being compiled")
+  }
+
+  fn compile_stmt(&mut self, ast: &Ast, stmt: StmtId) {
+    ast.visit_stmt(stmt, self)
+  }
+
+  // the index of the instruction, for jumps to be patched once their target is known
+  fn emit(&mut self, instruction: Instruction, span: Span) -> usize {
+    let line = self.source_map.location(span.start).line;
+    let prototype = &mut self.current().prototype;
+    prototype.code.push(instruction);
+    prototype.lines.push(line);
+
+    prototype.code.len() - 1
+  }
+
+  fn next_instruction(&mut self) -> usize {
+    self.current().prototype.code.len()
+  }
+
+  // points the jump at the next instruction to be emitted
+  fn patch_jump(&mut self, jump: usize) {
+    let next = self.next_instruction();
+
+    match &mut self.current().prototype.code[jump] {
+      Instruction::Jump { target }
+      | Instruction::JumpIfFalse { target, .. }
+      | Instruction::JumpIfTrue { target, .. }
+      | Instruction::JumpIfNotNil { target, .. } => *target = next,
+      instruction => unreachable!("{:?} isn't a jump", instruction),
+    }
+  }
+
+  fn name_constant(&mut self, name: &str) -> usize {
+    self.make_constant(Value::String(Rc::from(name)))
+  }
+
+  fn make_constant(&mut self, value: Value) -> usize {
+    let constants = &mut self.current().prototype.constants;
+
+    match constants.iter().position(|constant| *constant == value) {
+      Some(index) => index,
+      None => {
+        constants.push(value);
+        constants.len() - 1
+      }
+    }
+  }
+
+  // a function that runs off its end returns nil, an initializer the instance
+  fn emit_return(&mut self, span: Span) {
+    if self.current().kind == FunctionKind::Initializer {
+      self.emit(Instruction::Return { source: 0 }, span);
+      return;
+    }
+
+    let mark = self.current().next;
+    let register = self.allocate(span);
+    self.emit(Instruction::LoadNil { dest: register }, span);
+    self.emit(Instruction::Return { source: register }, span);
+    self.current().next = mark;
+  }
+
+  // the next free register, for a temporary or a local about to be declared. Instructions
+  // address registers with a byte
+  fn allocate(&mut self, span: Span) -> Register {
+    let current = self.current();
+    let register = current.next;
+    current.next += 1;
+    current.prototype.registers = current.prototype.registers.max(current.next);
+
+    Register::try_from(register).unwrap_or_else(|_| {
+      self.errors.push(CompileError::TooManyRegisters { span });
+      0
+    })
+  }
+
+  // temporaries are everything above the locals, their old values are never read again
+  fn is_temporary(&self, register: Register) -> bool {
+    let current = self.functions.last().expect("there is always a function being compiled");
+
+    register as usize >= current.locals.len()
+  }
+
+  fn unsupported(&mut self, feature: &'static str, span: Span) {
+    self.errors.push(CompileError::Unsupported { span, feature });
+  }
+
+  fn begin_scope(&mut self) {
+    self.current().scope_depth += 1;
+  }
+
+  // the registers of the block's locals are free again once it ends
+  fn end_scope(&mut self) {
+    let current = self.current();
+    current.scope_depth -= 1;
+
+    while current.locals.last().is_some_and(|local| local.depth > current.scope_depth) {
+      current.locals.pop();
+    }
+
+    current.next = current.locals.len();
+  }
+
+  fn is_global_scope(&self) -> bool {
+    self.functions.len() == 1 && self.functions[0].scope_depth == 0
+  }
+
+  // the local's register is the next one, which its value is already in
+  fn add_local(&mut self, name: &str) {
+    let depth = self.current().scope_depth;
+    self.current().locals.push(Local {
+      name: String::from(name),
+      depth,
+    });
+  }
+
+  // the name as a local of the function being compiled, and otherwise as a global. A local of a
+  // function around it would have to be captured, which this backend can't do
+  fn resolve(&mut self, name: &str, span: Span) -> Variable {
+    if let Some(register) = self.current().locals.iter().rposition(|local| local.name == name) {
+      return Variable::Local(register as Register);
+    }
+
+    let enclosing = &self.functions[..self.functions.len() - 1];
+
+    if enclosing.iter().any(|function| function.locals.iter().any(|local| local.name == name)) {
+      self.unsupported("closures capturing variables", span);
+    }
+
+    let globals = &mut self.current().prototype.globals;
+
+    match globals.iter().position(|global| global == name) {
+      Some(index) => Variable::Global(index),
+      None => {
+        globals.push(String::from(name));
+        Variable::Global(globals.len() - 1)
+      }
+    }
+  }
+
+  // the register holding the expression's value. A local is read from where it is, anything
+  // else is computed into a new temporary
+  fn operand(&mut self, ast: &Ast, expr: ExprId) -> Register {
+    match &ast[expr].kind {
+      ExprKind::Grouping(inner) => self.operand(ast, *inner),
+      ExprKind::Variable(name) => match self.resolve(name, ast[expr].span) {
+        Variable::Local(register) => register,
+        Variable::Global(global) => {
+          let register = self.allocate(ast[expr].span);
+          self.emit(Instruction::GetGlobal { dest: register, global }, ast[expr].span);
+          register
+        }
+      },
+      _ => self.temporary(ast, expr),
+    }
+  }
+
+  fn temporary(&mut self, ast: &Ast, expr: ExprId) -> Register {
+    let register = self.allocate(ast[expr].span);
+    self.compile_expr(ast, expr, register);
+
+    register
+  }
+
+  // literals and variables can't change a local, so a local on the left of one can be read
+  // after it is evaluated rather than copied before
+  fn is_simple(ast: &Ast, expr: ExprId) -> bool {
+    match &ast[expr].kind {
+      ExprKind::Literal(_) | ExprKind::Variable(_) => true,
+      ExprKind::Grouping(inner) => Self::is_simple(ast, *inner),
+      _ => false,
+    }
+  }
+
+  // leaves the expression's value in dest. Temporaries it needs along the way are given back
+  // once it's done. Expressions need a register to put their value in, which the visitor has no
+  // room for, so they're matched on here instead
+  fn compile_expr(&mut self, ast: &Ast, expr: ExprId, dest: Register) {
+    let span = ast[expr].span;
+    let mark = self.current().next;
+
+    match &ast[expr].kind {
+      ExprKind::Literal(literal) => {
+        let instruction = match literal {
+          Literal::Number(value) => Instruction::LoadConstant {
+            dest,
+            constant: self.make_constant(Value::Number(*value)),
+          },
+          Literal::String(value) => Instruction::LoadConstant {
+            dest,
+            constant: self.make_constant(Value::String(Rc::from(value.as_str()))),
+          },
+          Literal::Bool(value) => Instruction::LoadBool { dest, value: *value },
+          Literal::Nil => Instruction::LoadNil { dest },
+        };
+        self.emit(instruction, span);
+      }
+      // the parts go in registers next to each other to be joined in one go
+      ExprKind::Interpolation(parts) => {
+        let first = self.current().next as Register;
+
+        for part in parts {
+          self.temporary(ast, *part);
+        }
+
+        let count = parts.len() as Register;
+        self.emit(Instruction::Interpolate { dest, first, count }, span);
+      }
+      ExprKind::Grouping(inner) => self.compile_expr(ast, *inner, dest),
+      ExprKind::Unary { operator, operand } => {
+        let source = self.operand(ast, *operand);
+        let instruction = match operator {
+          UnaryOperator::Negate => Instruction::Negate { dest, source },
+          UnaryOperator::Not => Instruction::Not { dest, source },
+          UnaryOperator::BitNot => Instruction::BitNot { dest, source },
+        };
+        self.emit(instruction, span);
+      }
+      ExprKind::Binary { left, operator, right } => {
+        let left = if Self::is_simple(ast, *right) {
+          self.operand(ast, *left)
+        } else {
+          self.temporary(ast, *left)
+        };
+        let right = self.operand(ast, *right);
+        self.binary(*operator, dest, left, right, span);
+      }
+      // a local assigned the result isn't written until both sides are done with it
+      ExprKind::Logical { .. } if !self.is_temporary(dest) => {
+        let result = self.temporary(ast, expr);
+        self.emit(Instruction::Move { dest, source: result }, span);
+      }
+      // the left side stays in dest as the result when it decides it
+      ExprKind::Logical { left, operator, right } => {
+        self.compile_expr(ast, *left, dest);
+
+        let jump = match operator {
+          LogicalOperator::And => Instruction::JumpIfFalse {
+            condition: dest,
+            target: 0,
+          },
+          LogicalOperator::Or => Instruction::JumpIfTrue {
+            condition: dest,
+            target: 0,
+          },
+          LogicalOperator::Coalesce => Instruction::JumpIfNotNil {
+            condition: dest,
+            target: 0,
+          },
+        };
+        let jump = self.emit(jump, span);
+        self.compile_expr(ast, *right, dest);
+        self.patch_jump(jump);
+      }
+      ExprKind::Variable(name) => match self.resolve(name, span) {
+        Variable::Local(source) if source == dest => (),
+        Variable::Local(source) => {
+          self.emit(Instruction::Move { dest, source }, span);
+        }
+        Variable::Global(global) => {
+          self.emit(Instruction::GetGlobal { dest, global }, span);
+        }
+      },
+      // a local gets the value computed straight into its register
+      ExprKind::Assign { name, value } => match self.resolve(name, span) {
+        Variable::Local(register) => {
+          self.compile_expr(ast, *value, register);
+
+          if register != dest {
+            self.emit(Instruction::Move { dest, source: register }, span);
+          }
+        }
+        Variable::Global(global) => {
+          self.compile_expr(ast, *value, dest);
+          self.emit(Instruction::SetGlobal { source: dest, global }, span);
+        }
+      },
+      // the callee and its arguments go in the registers at the top, where the call's window
+      // starts. The parser already rejects calls with more arguments than fit in the operand
+      ExprKind::Call { callee, arguments } => {
+        let base = if self.is_temporary(dest) && dest as usize + 1 == mark {
+          dest
+        } else {
+          self.allocate(span)
+        };
+        // a method called straight away is invoked on the receiver without binding it
+        let method = match &ast[*callee].kind {
+          ExprKind::Get { object, name } => {
+            self.compile_expr(ast, *object, base);
+            Some(self.name_constant(name))
+          }
+          _ => {
+            self.compile_expr(ast, *callee, base);
+            None
+          }
+        };
+
+        for argument in arguments {
+          self.temporary(ast, *argument);
+        }
+
+        let argument_count = arguments.len() as Register;
+        match method {
+          Some(name) => self.emit(
+            Instruction::Invoke {
+              callee: base,
+              name,
+              argument_count,
+            },
+            span,
+          ),
+          None => self.emit(Instruction::Call { callee: base, argument_count }, span),
+        };
+
+        if base != dest {
+          self.emit(Instruction::Move { dest, source: base }, span);
+        }
+      }
+      ExprKind::Get { object, name } => {
+        let object = self.operand(ast, *object);
+        let name = self.name_constant(name);
+        self.emit(Instruction::GetProperty { dest, object, name }, span);
+      }
+      // the value goes in a register of its own, dest could be the local the object is in. A
+      // compound assignment reads the property into it first
+      ExprKind::Set {
+        object,
+        name,
+        operator,
+        value,
+      } => {
+        let object = if Self::is_simple(ast, *value) {
+          self.operand(ast, *object)
+        } else {
+          self.temporary(ast, *object)
+        };
+        let name = self.name_constant(name);
+        let source = match operator {
+          Some(operator) => {
+            let current = self.allocate(span);
+            self.emit(Instruction::GetProperty { dest: current, object, name }, span);
+            let right = self.operand(ast, *value);
+            self.binary(*operator, current, current, right, span);
+            current
+          }
+          None => self.operand(ast, *value),
+        };
+        self.emit(Instruction::SetProperty { object, name, source }, span);
+
+        if source != dest {
+          self.emit(Instruction::Move { dest, source }, span);
+        }
+      }
+      // a local of every method, in register 0
+      ExprKind::This => match self.resolve("this", span) {
+        Variable::Local(source) if source == dest => (),
+        Variable::Local(source) => {
+          self.emit(Instruction::Move { dest, source }, span);
+        }
+        Variable::Global(_) => unreachable!("the resolver rejects this outside of a class"),
+      },
+      ExprKind::Super { .. } => self.unsupported("super calls", span),
+      ExprKind::List(_) | ExprKind::Slice { .. } => self.unsupported("lists", span),
+      ExprKind::Index { .. } | ExprKind::SetIndex { .. } => self.unsupported("lists and maps", span),
+      ExprKind::Map(_) => self.unsupported("maps", span),
+      ExprKind::Lambda(declaration) => {
+        let (name, params, body) = match &ast[*declaration].kind {
+          StmtKind::Function { name, params, body } => (name, params, body),
+          kind => unreachable!("lambdas wrap function declarations, not {:?}", kind),
+        };
+        let function = self.function(ast, *declaration, name, params, body, FunctionKind::Function);
+        self.emit(Instruction::Closure { dest, function }, span);
+      }
+    }
+
+    self.current().next = mark;
+  }
+
+  fn binary(&mut self, operator: BinaryOperator, dest: Register, left: Register, right: Register, span: Span) {
+    let instruction = match operator {
+      BinaryOperator::Add => Instruction::Add { dest, left, right },
+      BinaryOperator::Subtract => Instruction::Subtract { dest, left, right },
+      BinaryOperator::Multiply => Instruction::Multiply { dest, left, right },
+      BinaryOperator::Divide => Instruction::Divide { dest, left, right },
+      BinaryOperator::Remainder => Instruction::Remainder { dest, left, right },
+      BinaryOperator::Equal => Instruction::Equal { dest, left, right },
+      BinaryOperator::NotEqual => {
+        self.emit(Instruction::Equal { dest, left, right }, span);
+        Instruction::Not { dest, source: dest }
+      }
+      BinaryOperator::Less => Instruction::Less { dest, left, right },
+      BinaryOperator::LessEqual => Instruction::LessEqual { dest, left, right },
+      BinaryOperator::Greater => Instruction::Greater { dest, left, right },
+      BinaryOperator::GreaterEqual => Instruction::GreaterEqual { dest, left, right },
+      BinaryOperator::BitAnd => Instruction::BitAnd { dest, left, right },
+      BinaryOperator::BitOr => Instruction::BitOr { dest, left, right },
+      BinaryOperator::BitXor => Instruction::BitXor { dest, left, right },
+      BinaryOperator::ShiftLeft => Instruction::ShiftLeft { dest, left, right },
+      BinaryOperator::ShiftRight => Instruction::ShiftRight { dest, left, right },
+    };
+    self.emit(instruction, span);
+  }
+
+  // parameters are the first locals after the function itself, the body shares their scope.
+  // The index of the function in the prototype it's declared in
+  fn function(
+    &mut self,
+    ast: &Ast,
+    id: StmtId,
+    name: &str,
+    params: &[String],
+    body: &[StmtId],
+    kind: FunctionKind,
+  ) -> usize {
+    let span = ast[id].span;
+    self.functions.push(FunctionState::new(name, params.len(), kind));
+    self.begin_scope();
+
+    for param in params {
+      self.allocate(span);
+      self.add_local(param);
+    }
+
+    body.iter().for_each(|statement| self.compile_stmt(ast, *statement));
+    self.emit_return(Span::new(span.end, span.end));
+
+    let function = self.functions.pop().expect("the function was pushed above");
+    let functions = &mut self.current().prototype.functions;
+    functions.push(Rc::new(function.prototype));
+
+    functions.len() - 1
+  }
+}
+
+// every statement leaves the registers as it found them, only declarations add a local
+impl<'a> StmtVisitor<()> for RegisterCompiler<'a> {
+  // an assignment to a local is computed straight into it, nothing else needs the value
+  fn visit_expression_stmt(&mut self, ast: &Ast, _: StmtId, expr: ExprId) {
+    let mark = self.current().next;
+
+    match &ast[expr].kind {
+      ExprKind::Assign { name, value } => match self.resolve(name, ast[expr].span) {
+        Variable::Local(register) => self.compile_expr(ast, *value, register),
+        Variable::Global(_) => {
+          self.temporary(ast, expr);
+        }
+      },
+      _ => {
+        self.temporary(ast, expr);
+      }
+    }
+
+    self.current().next = mark;
+  }
+
+  fn visit_print(&mut self, ast: &Ast, id: StmtId, expr: ExprId) {
+    let mark = self.current().next;
+    let source = self.operand(ast, expr);
+    self.emit(Instruction::Print { source }, ast[id].span);
+    self.current().next = mark;
+  }
+
+  // a local is only added once its initializer is compiled, so the initializer can't see it
+  fn visit_var(&mut self, ast: &Ast, id: StmtId, name: &str, initializer: Option<ExprId>) {
+    let span = ast[id].span;
+    let mark = self.current().next;
+    let register = self.allocate(span);
+
+    match initializer {
+      Some(initializer) => self.compile_expr(ast, initializer, register),
+      None => {
+        self.emit(Instruction::LoadNil { dest: register }, span);
+      }
+    }
+
+    if self.is_global_scope() {
+      let Variable::Global(global) = self.resolve(name, span) else {
+        unreachable!("there are no locals at the top level of the script")
+      };
+      self.emit(Instruction::DefineGlobal { source: register, global }, span);
+      self.current().next = mark;
+    } else {
+      self.add_local(name);
+    }
+  }
+
+  fn visit_block(&mut self, ast: &Ast, _: StmtId, statements: &[StmtId]) {
+    self.begin_scope();
+    statements.iter().for_each(|statement| self.compile_stmt(ast, *statement));
+    self.end_scope();
+  }
+
+  fn visit_if(
+    &mut self,
+    ast: &Ast,
+    id: StmtId,
+    condition: ExprId,
+    then_branch: StmtId,
+    else_branch: Option<StmtId>,
+  ) {
+    let span = ast[id].span;
+    let mark = self.current().next;
+    let condition = self.operand(ast, condition);
+    let else_jump = self.emit(Instruction::JumpIfFalse { condition, target: 0 }, span);
+    self.current().next = mark;
+    self.compile_stmt(ast, then_branch);
+
+    match else_branch {
+      Some(else_branch) => {
+        let end_jump = self.emit(Instruction::Jump { target: 0 }, span);
+        self.patch_jump(else_jump);
+        self.compile_stmt(ast, else_branch);
+        self.patch_jump(end_jump);
+      }
+      None => self.patch_jump(else_jump),
+    }
+  }
+
+  // continues go to the increment, or back to the condition when there isn't one
+  fn visit_while(&mut self, ast: &Ast, id: StmtId, condition: ExprId, body: StmtId, increment: Option<ExprId>) {
+    let span = ast[id].span;
+    let loop_start = self.next_instruction();
+    let mark = self.current().next;
+    let condition = self.operand(ast, condition);
+    let exit_jump = self.emit(Instruction::JumpIfFalse { condition, target: 0 }, span);
+    self.current().next = mark;
+
+    self.current().loops.push(Loop::default());
+    self.compile_stmt(ast, body);
+    let Loop { breaks, continues } = self.current().loops.pop().expect("the loop pushed above");
+    continues.into_iter().for_each(|jump| self.patch_jump(jump));
+
+    // compiled as the statement it was written as, so `i += 1` still goes straight into i
+    if let Some(increment) = increment {
+      self.visit_expression_stmt(ast, id, increment);
+    }
+
+    self.emit(Instruction::Jump { target: loop_start }, span);
+    self.patch_jump(exit_jump);
+    breaks.into_iter().for_each(|jump| self.patch_jump(jump));
+  }
+
+  // laid out the way the stack compiler does it, the comparisons and then the statements. The
+  // value is read from a local where it is unless a case value could assign to it
+  fn visit_switch(&mut self, ast: &Ast, id: StmtId, value: ExprId, cases: &[Case], default: Option<StmtId>) {
+    let span = ast[id].span;
+    let mark = self.current().next;
+    let simple = cases.iter().flat_map(|case| &case.values).all(|value| Self::is_simple(ast, *value));
+    let value = if simple {
+      self.operand(ast, value)
+    } else {
+      self.temporary(ast, value)
+    };
+    let after_value = self.current().next;
+
+    let mut case_jumps = vec![];
+
+    for case in cases {
+      let mut jumps = vec![];
+
+      for candidate in &case.values {
+        let candidate_span = ast[*candidate].span;
+        let right = self.operand(ast, *candidate);
+        let equal = self.allocate(candidate_span);
+        self.emit(Instruction::Equal { dest: equal, left: value, right }, candidate_span);
+        jumps.push(self.emit(Instruction::JumpIfTrue { condition: equal, target: 0 }, candidate_span));
+        self.current().next = after_value;
+      }
+
+      case_jumps.push(jumps);
+    }
+
+    self.current().next = mark;
+
+    if let Some(default) = default {
+      self.compile_stmt(ast, default);
+    }
+
+    let mut end_jumps = vec![];
+
+    for (case, jumps) in cases.iter().zip(case_jumps) {
+      end_jumps.push(self.emit(Instruction::Jump { target: 0 }, span));
+      jumps.into_iter().for_each(|jump| self.patch_jump(jump));
+      self.compile_stmt(ast, case.body);
+    }
+
+    end_jumps.into_iter().for_each(|jump| self.patch_jump(jump));
+  }
+
+  fn visit_break(&mut self, ast: &Ast, id: StmtId) {
+    let jump = self.emit(Instruction::Jump { target: 0 }, ast[id].span);
+    self.current().loops.last_mut().expect("the parser only allows break in loops").breaks.push(jump);
+  }
+
+  fn visit_continue(&mut self, ast: &Ast, id: StmtId) {
+    let jump = self.emit(Instruction::Jump { target: 0 }, ast[id].span);
+    self.current().loops.last_mut().expect("the parser only allows continue in loops").continues.push(jump);
+  }
+
+  // a local function gets its register before its body is compiled, as the resolver defines it
+  fn visit_function(&mut self, ast: &Ast, id: StmtId, name: &str, params: &[String], body: &[StmtId]) {
+    let span = ast[id].span;
+    let mark = self.current().next;
+    let register = self.allocate(span);
+
+    if self.is_global_scope() {
+      let function = self.function(ast, id, name, params, body, FunctionKind::Function);
+      self.emit(Instruction::Closure { dest: register, function }, span);
+
+      let Variable::Global(global) = self.resolve(name, span) else {
+        unreachable!("there are no locals at the top level of the script")
+      };
+      self.emit(Instruction::DefineGlobal { source: register, global }, span);
+      self.current().next = mark;
+    } else {
+      self.add_local(name);
+      let function = self.function(ast, id, name, params, body, FunctionKind::Function);
+      self.emit(Instruction::Closure { dest: register, function }, span);
+    }
+  }
+
+  fn visit_return(&mut self, ast: &Ast, id: StmtId, value: Option<ExprId>) {
+    let span = ast[id].span;
+
+    match value {
+      Some(value) => {
+        let mark = self.current().next;
+        let source = self.operand(ast, value);
+        self.emit(Instruction::Return { source }, span);
+        self.current().next = mark;
+      }
+      None => self.emit_return(span),
+    }
+  }
+
+  fn visit_for_in(&mut self, ast: &Ast, id: StmtId, _: &str, _: ExprId, _: StmtId) {
+    self.unsupported("for-in loops", ast[id].span);
+  }
+
+  fn visit_throw(&mut self, ast: &Ast, id: StmtId, _: ExprId) {
+    self.unsupported("exceptions", ast[id].span);
+  }
+
+  fn visit_try(&mut self, ast: &Ast, id: StmtId, _: StmtId, _: &str, _: StmtId) {
+    self.unsupported("exceptions", ast[id].span);
+  }
+
+  fn visit_import(&mut self, ast: &Ast, id: StmtId, _: &str, _: Option<&[String]>) {
+    self.unsupported("imports", ast[id].span);
+  }
+
+  // the class is defined before its methods are added, the way the stack compiler does it. A
+  // method's closure waits in a temporary until it's added to the class
+  fn visit_class(&mut self, ast: &Ast, id: StmtId, name: &str, superclass: Option<ExprId>, methods: &[StmtId]) {
+    let span = ast[id].span;
+    let mark = self.current().next;
+    let class = self.allocate(span);
+    let name_constant = self.name_constant(name);
+    self.emit(Instruction::Class { dest: class, name: name_constant }, span);
+
+    if self.is_global_scope() {
+      let Variable::Global(global) = self.resolve(name, span) else {
+        unreachable!("there are no locals at the top level of the script")
+      };
+      self.emit(Instruction::DefineGlobal { source: class, global }, span);
+    } else {
+      self.add_local(name);
+    }
+
+    let after_class = self.current().next;
+
+    if let Some(superclass) = superclass {
+      let superclass = self.operand(ast, superclass);
+      self.emit(Instruction::Inherit { class, superclass }, span);
+      self.current().next = after_class;
+    }
+
+    for method in methods {
+      let (method_name, params, body) = match &ast[*method].kind {
+        StmtKind::Function { name, params, body } => (name, params, body),
+        kind => unreachable!("methods are function declarations, not {:?}", kind),
+      };
+      let kind = if method_name == "init" {
+        FunctionKind::Initializer
+      } else {
+        FunctionKind::Method
+      };
+      let method_span = ast[*method].span;
+
+      let function = self.function(ast, *method, method_name, params, body, kind);
+      let closure = self.allocate(method_span);
+      self.emit(Instruction::Closure { dest: closure, function }, method_span);
+      let name = self.name_constant(method_name);
+      self.emit(Instruction::Method { class, method: closure, name }, method_span);
+      self.current().next = after_class;
+    }
+
+    if self.is_global_scope() {
+      self.current().next = mark;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::lexer::Lexer;
+  use crate::parser::Parser;
+  use Instruction::*;
+
+  fn compile(code: &str) -> Result<Prototype, Vec<String>> {
+    let ast = Parser::new(Lexer::new(code).lex().unwrap()).parse().unwrap();
+
+    RegisterCompiler::new(&SourceMap::new(code))
+      .compile(&ast)
+      .map_err(|errors| errors.iter().map(|error| error.to_string()).collect())
+  }
+
+  fn function(script: &Prototype, index: usize) -> &Prototype {
+    &script.functions[index]
+  }
+
+  #[test]
+  fn locals_are_used_where_they_are() {
+    let script = compile("fun f(a, b) { var c = a + b; c = c * a; return c; }").unwrap();
+
+    assert_eq!(
+      function(&script, 0).code,
+      [
+        Add {
+          dest: 3,
+          left: 1,
+          right: 2
+        },
+        Multiply {
+          dest: 3,
+          left: 3,
+          right: 1
+        },
+        Return { source: 3 },
+        LoadNil { dest: 4 },
+        Return { source: 4 },
+      ]
+    );
+  }
+
+  #[test]
+  fn calls_put_their_arguments_after_the_callee() {
+    let script = compile("fun f(n) { return f(n - 1) + 1; }").unwrap();
+
+    assert_eq!(
+      function(&script, 0).code[..6],
+      [
+        GetGlobal { dest: 3, global: 0 },
+        LoadConstant { dest: 5, constant: 0 },
+        Subtract {
+          dest: 4,
+          left: 1,
+          right: 5
+        },
+        Call {
+          callee: 3,
+          argument_count: 1
+        },
+        LoadConstant { dest: 4, constant: 0 },
+        Add {
+          dest: 2,
+          left: 3,
+          right: 4
+        },
+      ]
+    );
+    assert_eq!(function(&script, 0).registers, 6);
+  }
+
+  #[test]
+  fn locals_are_copied_when_the_other_side_could_change_them() {
+    let script = compile("{ var a = 1; print a + (a = 2); }").unwrap();
+
+    assert_eq!(
+      script.code[1..6],
+      [
+        Move { dest: 3, source: 1 },
+        LoadConstant { dest: 1, constant: 1 },
+        Move { dest: 4, source: 1 },
+        Add {
+          dest: 2,
+          left: 3,
+          right: 4
+        },
+        Print { source: 2 },
+      ]
+    );
+  }
+
+  #[test]
+  fn methods_are_invoked_on_their_receiver() {
+    let script = compile("class A { f(n) { return this.f(n); } }").unwrap();
+
+    assert_eq!(
+      function(&script, 0).code[..4],
+      [
+        Move { dest: 2, source: 0 },
+        Move { dest: 3, source: 1 },
+        Invoke {
+          callee: 2,
+          name: 0,
+          argument_count: 1
+        },
+        Return { source: 2 },
+      ]
+    );
+  }
+
+  #[test]
+  fn what_the_register_vm_cant_run_is_reported() {
+    let code = "fun outer() { var a = 1; fun inner() { return a; } } class A < B { f() { super.f(); } }
+      for (var x in [1]) {} print {}; var m; m[0]; throw 1; import \"m.lox\";";
+    let errors = compile(code).unwrap_err();
+
+    assert_eq!(
+      errors,
+      [
+        "closures capturing variables aren't supported by the register vm",
+        "super calls aren't supported by the register vm",
+        "for-in loops aren't supported by the register vm",
+        "maps aren't supported by the register vm",
+        "lists and maps aren't supported by the register vm",
+        "exceptions aren't supported by the register vm",
+        "imports aren't supported by the register vm",
+      ]
+    );
+  }
+}
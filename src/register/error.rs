@@ -0,0 +1,30 @@
+use std::error;
+use std::fmt;
+
+use crate::lexer::Span;
+
+#[derive(Debug, Clone)]
+pub enum CompileError {
+  TooManyRegisters { span: Span },
+  // something the register vm can't run yet, named the way the message shows it
+  Unsupported { span: Span, feature: &'static str },
+}
+
+impl CompileError {
+  pub fn span(&self) -> Span {
+    match self {
+      CompileError::TooManyRegisters { span } | CompileError::Unsupported { span, .. } => *span,
+    }
+  }
+}
+
+impl fmt::Display for CompileError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      CompileError::TooManyRegisters { .. } => write!(f, "too many registers in function"),
+      CompileError::Unsupported { feature, .. } => write!(f, "{} aren't supported by the register vm", feature),
+    }
+  }
+}
+
+impl error::Error for CompileError {}
@@ -0,0 +1,134 @@
+// an experimental backend to see whether registers beat the stack vm. Values, the heap and the
+// collector are the stack vm's, only the instructions and the way they're run differ: an
+// instruction names the registers it reads and the one it writes, so `a = a + b;` on locals is one
+// instruction instead of five.
+//
+// Functions can't capture variables of the functions around them, so there are no super calls
+// either, and lists, maps, for-in loops, exceptions and imports are missing. The compiler
+// reports those as unsupported. Classes, methods and fields work, which is everything the
+// programs of `cargo bench --features register-vm --bench backends` use. There it runs fib about
+// 1.3x as fast as the stack vm and string_churn no faster, as the time goes on joining and
+// interning strings either way. The class programs run at 0.7x to 0.8x: properties are looked
+// up in the class's tables every time, without the stack vm's inline caches
+use std::fmt;
+use std::rc::Rc;
+
+use crate::chunk;
+use crate::vm::{ObjRef, Value};
+
+mod compiler;
+mod error;
+mod vm;
+
+pub use compiler::RegisterCompiler;
+pub use error::CompileError;
+pub use vm::RegisterVm;
+
+// registers are numbered from the start of the call's window. Register 0 holds the function
+// being called and the parameters follow it, the way slots do on the stack vm
+pub type Register = u8;
+
+// jumps hold the index of the instruction they go to, constants, globals and functions their
+// index in the prototype's lists of them
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Instruction {
+  LoadConstant { dest: Register, constant: usize },
+  LoadNil { dest: Register },
+  LoadBool { dest: Register, value: bool },
+  Move { dest: Register, source: Register },
+  GetGlobal { dest: Register, global: usize },
+  DefineGlobal { source: Register, global: usize },
+  SetGlobal { source: Register, global: usize },
+  Equal { dest: Register, left: Register, right: Register },
+  Greater { dest: Register, left: Register, right: Register },
+  GreaterEqual { dest: Register, left: Register, right: Register },
+  Less { dest: Register, left: Register, right: Register },
+  LessEqual { dest: Register, left: Register, right: Register },
+  Add { dest: Register, left: Register, right: Register },
+  Subtract { dest: Register, left: Register, right: Register },
+  Multiply { dest: Register, left: Register, right: Register },
+  Divide { dest: Register, left: Register, right: Register },
+  Remainder { dest: Register, left: Register, right: Register },
+  BitAnd { dest: Register, left: Register, right: Register },
+  BitOr { dest: Register, left: Register, right: Register },
+  BitXor { dest: Register, left: Register, right: Register },
+  ShiftLeft { dest: Register, left: Register, right: Register },
+  ShiftRight { dest: Register, left: Register, right: Register },
+  Not { dest: Register, source: Register },
+  Negate { dest: Register, source: Register },
+  BitNot { dest: Register, source: Register },
+  // joins count registers from first up
+  Interpolate { dest: Register, first: Register, count: Register },
+  Print { source: Register },
+  Jump { target: usize },
+  JumpIfFalse { condition: Register, target: usize },
+  JumpIfTrue { condition: Register, target: usize },
+  JumpIfNotNil { condition: Register, target: usize },
+  // the arguments are in the registers after the callee, the result replaces the callee
+  Call { callee: Register, argument_count: Register },
+  Closure { dest: Register, function: usize },
+  Return { source: Register },
+  // names are the index of the string constant holding them
+  Class { dest: Register, name: usize },
+  // copies the superclass's methods into the class
+  Inherit { class: Register, superclass: Register },
+  Method { class: Register, method: Register, name: usize },
+  GetProperty { dest: Register, object: Register, name: usize },
+  SetProperty { object: Register, name: usize, source: Register },
+  // a method called on the receiver in callee without binding it first, laid out like Call
+  Invoke { callee: Register, name: usize, argument_count: Register },
+}
+
+// a compiled function. Constants are only ever numbers, strings, booleans and nil, the
+// functions declared inside this one, methods included, have a list of their own
+#[derive(Debug, Clone, PartialEq)]
+pub struct Prototype {
+  pub name: String,
+  pub arity: usize,
+  // how many registers a call needs, the function and its parameters included
+  pub registers: usize,
+  pub code: Vec<Instruction>,
+  // the line each instruction came from
+  pub lines: Vec<usize>,
+  pub constants: Vec<chunk::Value>,
+  // the names of the globals the code uses
+  pub globals: Vec<String>,
+  pub functions: Vec<Rc<Prototype>>,
+}
+
+impl Prototype {
+  fn new(name: &str, arity: usize) -> Self {
+    Self {
+      name: String::from(name),
+      arity,
+      registers: 1 + arity,
+      code: vec![],
+      lines: vec![],
+      constants: vec![],
+      globals: vec![],
+      functions: vec![],
+    }
+  }
+}
+
+// shown the same as the stack vm's functions
+impl fmt::Display for Prototype {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    if self.name.is_empty() {
+      write!(f, "<script>")
+    } else {
+      write!(f, "<fn {}>", self.name)
+    }
+  }
+}
+
+// a prototype loaded onto the heap. The constants are values the vm can use, the globals the
+// slots of the names and the functions loaded prototypes themselves, a closure is made of one
+// every time its declaration runs
+#[derive(Debug)]
+pub struct FunctionObject {
+  pub prototype: Rc<Prototype>,
+  pub constants: Vec<Value>,
+  pub globals: Vec<usize>,
+  pub functions: Vec<ObjRef>,
+}
@@ -0,0 +1,905 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use crate::chunk;
+use crate::system::{self, NativeError};
+use crate::vm::{
+  self, BoundMethodObject, ClassObject, ClosureObject, Heap, InstanceObject, NativeFunction, ObjRef, Object,
+  RuntimeError, StackFrame, Value, ValueKind,
+};
+
+use super::{FunctionObject, Instruction, Prototype, Register};
+
+// a call in progress, its registers start at base. The prototype is kept alongside the closure
+// so reading an instruction doesn't go through the heap
+#[derive(Debug)]
+struct CallFrame {
+  closure: ObjRef,
+  function: ObjRef,
+  code: Rc<Prototype>,
+  ip: usize,
+  base: usize,
+}
+
+// bitwise operators work on the integer part of their operands
+fn integer(value: f64) -> i64 {
+  value as i64
+}
+
+// runs what the register compiler produces, with the same results and errors as the stack vm.
+// The registers of every call are windows onto one vector, a call's window starting at the
+// register of the callee in the window of its caller
+pub struct RegisterVm {
+  frames: Vec<CallFrame>,
+  registers: Vec<Value>,
+  // the slot of each global by its interned name, resolved once when a function is loaded
+  globals: HashMap<ObjRef, usize>,
+  global_values: Vec<Option<Value>>,
+  heap: Heap,
+  // the name classes keep their initializer under
+  init_string: ObjRef,
+  out: Box<dyn Write>,
+}
+
+impl Default for RegisterVm {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl RegisterVm {
+  pub fn new() -> Self {
+    Self::with_output(Box::new(io::stdout()))
+  }
+
+  // print writes to out instead of stdout
+  pub fn with_output(out: Box<dyn Write>) -> Self {
+    let mut heap = Heap::new();
+    let init_string = heap.allocate(Object::String(Rc::from("init")));
+    let mut vm = Self {
+      frames: vec![],
+      registers: vec![],
+      globals: HashMap::new(),
+      global_values: vec![],
+      heap,
+      init_string,
+      out,
+    };
+
+    for native in vm::natives::natives() {
+      vm.define_native(native);
+    }
+
+    for (name, value) in vm::natives::CONSTANTS {
+      vm.define_constant(name, value);
+    }
+
+    vm
+  }
+
+  // collect garbage before every allocation, like the stack vm's --stress-gc
+  pub fn set_stress_gc(&mut self, stress: bool) {
+    self.heap.set_stress(stress);
+  }
+
+  pub fn set_generational_gc(&mut self, generational: bool) {
+    self.heap.set_generational(generational);
+  }
+
+  // the name waits in a register while the native is allocated
+  fn define_native(&mut self, native: NativeFunction) {
+    let name = self.allocate_string(String::from(native.name));
+    self.registers.push(Value::object(name));
+    let native = self.allocate(Object::Native(native));
+    self.registers.pop();
+
+    let slot = self.global_slot(name);
+    self.global_values[slot] = Some(Value::object(native));
+  }
+
+  // the name is a root as soon as it has a slot
+  fn define_constant(&mut self, name: &str, value: f64) {
+    let name = self.allocate_string(String::from(name));
+    let slot = self.global_slot(name);
+    self.global_values[slot] = Some(Value::number(value));
+  }
+
+  // everything reachable from the registers, the calls running and the globals is kept
+  fn collect_garbage(&mut self) {
+    self.heap.begin_collection();
+    self.heap.mark_object(self.init_string);
+
+    for value in &self.registers {
+      self.heap.mark_value(*value);
+    }
+
+    for frame in &self.frames {
+      self.heap.mark_object(frame.closure);
+    }
+
+    for name in self.globals.keys() {
+      self.heap.mark_object(*name);
+    }
+
+    for value in self.global_values.iter().flatten() {
+      self.heap.mark_value(*value);
+    }
+
+    self.heap.collect();
+  }
+
+  // anything the object refers to has to be reachable from a root already, as this may collect
+  fn allocate(&mut self, object: Object) -> ObjRef {
+    if self.heap.should_collect() {
+      self.collect_garbage();
+    }
+
+    self.heap.allocate(object)
+  }
+
+  fn allocate_string(&mut self, text: String) -> ObjRef {
+    match self.heap.find_string(&text) {
+      Some(string) => string,
+      None => self.allocate(Object::String(Rc::from(text))),
+    }
+  }
+
+  // the global's slot, given one the first time the name comes up
+  fn global_slot(&mut self, name: ObjRef) -> usize {
+    let next = self.global_values.len();
+    let slot = *self.globals.entry(name).or_insert(next);
+
+    if slot == next {
+      self.global_values.push(None);
+    }
+
+    slot
+  }
+
+  // puts a prototype, its constants and the functions declared in it on the heap. What's loaded
+  // waits in registers past the ones in use until the function object holds on to it
+  fn load(&mut self, prototype: Rc<Prototype>) -> ObjRef {
+    let base = self.registers.len();
+
+    for constant in &prototype.constants {
+      let value = match constant {
+        chunk::Value::Number(value) => Value::number(*value),
+        chunk::Value::Bool(value) => Value::bool(*value),
+        chunk::Value::Nil => Value::nil(),
+        chunk::Value::String(value) => Value::object(self.allocate_string(value.to_string())),
+        chunk::Value::Function(function) => unreachable!("{} is in the functions of a prototype", function),
+      };
+      self.registers.push(value);
+    }
+
+    for function in &prototype.functions {
+      let function = self.load(Rc::clone(function));
+      self.registers.push(Value::object(function));
+    }
+
+    let globals = prototype
+      .globals
+      .iter()
+      .map(|name| {
+        let name = self.allocate_string(name.clone());
+        self.global_slot(name)
+      })
+      .collect();
+    let (constants, functions) = self.registers[base..].split_at(prototype.constants.len());
+    let function = FunctionObject {
+      constants: constants.to_vec(),
+      functions: functions.iter().map(|function| Self::object_of(*function)).collect(),
+      globals,
+      prototype,
+    };
+    let function = self.allocate(Object::RegisterFunction(function));
+    self.registers.truncate(base);
+
+    function
+  }
+
+  // the function waits in a register while its closure is allocated
+  fn allocate_closure(&mut self, function: ObjRef) -> ObjRef {
+    self.registers.push(Value::object(function));
+    let closure = self.allocate(Object::Closure(ClosureObject {
+      function,
+      upvalues: vec![],
+      module: 0,
+    }));
+    self.registers.pop();
+
+    closure
+  }
+
+  // runs a compiled script, globals it defines are kept for the next one. After an error the
+  // registers are emptied so the vm can carry on with another script
+  pub fn interpret(&mut self, script: Prototype) -> Result<(), RuntimeError> {
+    let code = Rc::new(script);
+    let function = self.load(Rc::clone(&code));
+    let closure = self.allocate_closure(function);
+    self.registers.push(Value::object(closure));
+    self.registers.resize(code.registers, Value::nil());
+    self.frames.push(CallFrame {
+      closure,
+      function,
+      code,
+      ip: 0,
+      base: 0,
+    });
+
+    let result = self.run();
+
+    if result.is_err() {
+      self.registers.clear();
+      self.frames.clear();
+    }
+
+    result
+  }
+
+  fn object_of(value: Value) -> ObjRef {
+    match value.kind() {
+      ValueKind::Object(object) => object,
+      value => unreachable!("expected an object, found {:?}", value),
+    }
+  }
+
+  fn function_object(&self, function: ObjRef) -> &FunctionObject {
+    match self.heap.get(function) {
+      Object::RegisterFunction(function) => function,
+      object => unreachable!("calls are always to register functions, not {:?}", object),
+    }
+  }
+
+  // the line every call had reached is in the trace
+  fn error(&self, message: impl Into<String>) -> RuntimeError {
+    let trace = self
+      .frames
+      .iter()
+      .rev()
+      .map(|frame| StackFrame {
+        function: frame.code.name.clone(),
+        line: frame.code.lines[frame.ip.saturating_sub(1)],
+      })
+      .collect();
+
+    RuntimeError {
+      message: message.into(),
+      trace,
+      exit: None,
+    }
+  }
+
+  // a native's error, or the script stopping when the native was exit()
+  fn native_error(&self, error: NativeError) -> RuntimeError {
+    match error {
+      NativeError::Message(message) => self.error(message),
+      NativeError::Exit(status) => RuntimeError {
+        exit: Some(status),
+        ..self.error(system::exited(status))
+      },
+    }
+  }
+
+  fn number(&self, value: Value) -> Result<f64, RuntimeError> {
+    match value.kind() {
+      ValueKind::Number(value) => Ok(value),
+      _ => Err(self.error("Operand must be a number.")),
+    }
+  }
+
+  fn numbers(&self, left: Value, right: Value) -> Result<(f64, f64), RuntimeError> {
+    match (left.kind(), right.kind()) {
+      (ValueKind::Number(left), ValueKind::Number(right)) => Ok((left, right)),
+      _ => Err(self.error("Operands must be numbers.")),
+    }
+  }
+
+  // numbers add up and strings join, the operands are still in their registers while the
+  // result is allocated
+  fn add(&mut self, left: Value, right: Value) -> Result<Value, RuntimeError> {
+    if let (ValueKind::Number(left), ValueKind::Number(right)) = (left.kind(), right.kind()) {
+      return Ok(Value::number(left + right));
+    }
+
+    match (self.heap.as_string(left), self.heap.as_string(right)) {
+      (Some(left), Some(right)) => {
+        let text = format!("{}{}", left, right);
+        Ok(Value::object(self.allocate_string(text)))
+      }
+      _ => Err(self.error("Operands must be two numbers or two strings.")),
+    }
+  }
+
+  fn check_arity(&self, arity: usize, argument_count: usize) -> Result<(), RuntimeError> {
+    if argument_count != arity {
+      return Err(self.error(format!("Expected {} arguments but got {}.", arity, argument_count)));
+    }
+
+    Ok(())
+  }
+
+  // starts a new window at slot, where the callee or the receiver of a method is
+  fn call(&mut self, slot: usize, closure: ObjRef, argument_count: usize) -> Result<(), RuntimeError> {
+    let function = match self.heap.get(closure) {
+      Object::Closure(closure) => closure.function,
+      object => unreachable!("expected a closure, found {:?}", object),
+    };
+    let code = Rc::clone(&self.function_object(function).prototype);
+    self.check_arity(code.arity, argument_count)?;
+
+    if self.frames.len() == vm::FRAMES_MAX {
+      return Err(self.error("Stack overflow."));
+    }
+
+    self.registers.resize(slot + code.registers, Value::nil());
+    self.frames.push(CallFrame {
+      closure,
+      function,
+      code,
+      ip: 0,
+      base: slot,
+    });
+
+    Ok(())
+  }
+
+  // the callee is in the register at slot with its arguments after it. A closure starts a new
+  // window there, a native's result replaces it straight away
+  fn call_value(&mut self, slot: usize, argument_count: usize) -> Result<(), RuntimeError> {
+    let callee = match self.registers[slot].kind() {
+      ValueKind::Object(callee) => callee,
+      _ => return Err(self.error("Can only call functions and classes.")),
+    };
+
+    match self.heap.get(callee) {
+      Object::Closure(_) => self.call(slot, callee, argument_count)?,
+      // the new instance takes the class's register, where init finds it as this
+      Object::Class(class) => {
+        let initializer = class.methods.get(&self.init_string).copied();
+        let instance = self.allocate(Object::Instance(InstanceObject {
+          class: callee,
+          fields: vec![],
+        }));
+        self.registers[slot] = Value::object(instance);
+
+        match initializer {
+          Some(initializer) => self.call(slot, Self::object_of(initializer), argument_count)?,
+          None => self.check_arity(0, argument_count)?,
+        }
+      }
+      Object::BoundMethod(bound) => {
+        let (receiver, method) = (bound.receiver, bound.method);
+        self.registers[slot] = receiver;
+        self.call(slot, method, argument_count)?;
+      }
+      Object::Native(native) => {
+        let function = native.function;
+
+        if native.arity != NativeFunction::VARIADIC {
+          self.check_arity(native.arity, argument_count)?;
+        }
+
+        let arguments = &self.registers[slot + 1..slot + 1 + argument_count];
+        self.registers[slot] = function(&mut self.heap, arguments).map_err(|error| self.native_error(error))?;
+      }
+      _ => return Err(self.error("Can only call functions and classes.")),
+    }
+
+    Ok(())
+  }
+
+  fn instance(&self, value: Value) -> Option<ObjRef> {
+    match value.kind() {
+      ValueKind::Object(object) if matches!(self.heap.get(object), Object::Instance(_)) => Some(object),
+      _ => None,
+    }
+  }
+
+  // the string constant the instruction names something with
+  fn name(&self, name: usize) -> ObjRef {
+    let function = self.frames.last().expect("code only runs inside a call").function;
+
+    Self::object_of(self.function_object(function).constants[name])
+  }
+
+  fn undefined_property(&self, name: ObjRef) -> RuntimeError {
+    self.error(format!("Undefined property '{}'.", self.heap.string(name)))
+  }
+
+  // fields shadow methods
+  fn property(&self, instance: ObjRef, name: ObjRef) -> Option<Result<Value, Value>> {
+    let Object::Instance(instance) = self.heap.get(instance) else {
+      unreachable!("properties are only looked up on instances")
+    };
+
+    match self.heap.get(instance.class) {
+      Object::Class(class) => {
+        let field = class.layout.get(&name).and_then(|index| instance.field(*index));
+        field.map(Ok).or_else(|| class.methods.get(&name).map(|method| Err(*method)))
+      }
+      object => unreachable!("expected a class, found {:?}", object),
+    }
+  }
+
+  fn run(&mut self) -> Result<(), RuntimeError> {
+    loop {
+      let frame = self.frames.last_mut().expect("code only runs inside a call");
+      let instruction = frame.code.code[frame.ip];
+      let base = frame.base;
+      frame.ip += 1;
+
+      let register = |register: Register| base + register as usize;
+
+      match instruction {
+        Instruction::LoadConstant { dest, constant } => {
+          let function = self.frames.last().expect("code only runs inside a call").function;
+          self.registers[register(dest)] = self.function_object(function).constants[constant];
+        }
+        Instruction::LoadNil { dest } => self.registers[register(dest)] = Value::nil(),
+        Instruction::LoadBool { dest, value } => self.registers[register(dest)] = Value::bool(value),
+        Instruction::Move { dest, source } => self.registers[register(dest)] = self.registers[register(source)],
+        Instruction::GetGlobal { dest, global } => {
+          let function = self.frames.last().expect("code only runs inside a call").function;
+          let function = self.function_object(function);
+          let slot = function.globals[global];
+
+          match self.global_values[slot] {
+            Some(value) => self.registers[register(dest)] = value,
+            None => {
+              let name = &function.prototype.globals[global];
+              return Err(self.error(format!("Undefined variable '{}'.", name)));
+            }
+          }
+        }
+        // redefining a global replaces it, as Lox allows at the top level
+        Instruction::DefineGlobal { source, global } => {
+          let function = self.frames.last().expect("code only runs inside a call").function;
+          let slot = self.function_object(function).globals[global];
+          self.global_values[slot] = Some(self.registers[register(source)]);
+        }
+        // assigning never creates a variable
+        Instruction::SetGlobal { source, global } => {
+          let function = self.frames.last().expect("code only runs inside a call").function;
+          let function = self.function_object(function);
+          let slot = function.globals[global];
+
+          match self.global_values[slot] {
+            Some(_) => self.global_values[slot] = Some(self.registers[register(source)]),
+            None => {
+              let name = &function.prototype.globals[global];
+              return Err(self.error(format!("Undefined variable '{}'.", name)));
+            }
+          }
+        }
+        Instruction::Equal { dest, left, right } => {
+          let value = self.registers[register(left)] == self.registers[register(right)];
+          self.registers[register(dest)] = Value::bool(value);
+        }
+        Instruction::Greater { dest, left, right } => {
+          let (left, right) = self.numbers(self.registers[register(left)], self.registers[register(right)])?;
+          self.registers[register(dest)] = Value::bool(left > right);
+        }
+        Instruction::GreaterEqual { dest, left, right } => {
+          let (left, right) = self.numbers(self.registers[register(left)], self.registers[register(right)])?;
+          self.registers[register(dest)] = Value::bool(left >= right);
+        }
+        Instruction::Less { dest, left, right } => {
+          let (left, right) = self.numbers(self.registers[register(left)], self.registers[register(right)])?;
+          self.registers[register(dest)] = Value::bool(left < right);
+        }
+        Instruction::LessEqual { dest, left, right } => {
+          let (left, right) = self.numbers(self.registers[register(left)], self.registers[register(right)])?;
+          self.registers[register(dest)] = Value::bool(left <= right);
+        }
+        Instruction::Add { dest, left, right } => {
+          let value = self.add(self.registers[register(left)], self.registers[register(right)])?;
+          self.registers[register(dest)] = value;
+        }
+        Instruction::Subtract { dest, left, right } => {
+          let (left, right) = self.numbers(self.registers[register(left)], self.registers[register(right)])?;
+          self.registers[register(dest)] = Value::number(left - right);
+        }
+        Instruction::Multiply { dest, left, right } => {
+          let (left, right) = self.numbers(self.registers[register(left)], self.registers[register(right)])?;
+          self.registers[register(dest)] = Value::number(left * right);
+        }
+        Instruction::Divide { dest, left, right } => {
+          let (left, right) = self.numbers(self.registers[register(left)], self.registers[register(right)])?;
+          self.registers[register(dest)] = Value::number(left / right);
+        }
+        Instruction::Remainder { dest, left, right } => {
+          let (left, right) = self.numbers(self.registers[register(left)], self.registers[register(right)])?;
+          self.registers[register(dest)] = Value::number(left % right);
+        }
+        Instruction::BitAnd { dest, left, right } => {
+          let (left, right) = self.numbers(self.registers[register(left)], self.registers[register(right)])?;
+          self.registers[register(dest)] = Value::number((integer(left) & integer(right)) as f64);
+        }
+        Instruction::BitOr { dest, left, right } => {
+          let (left, right) = self.numbers(self.registers[register(left)], self.registers[register(right)])?;
+          self.registers[register(dest)] = Value::number((integer(left) | integer(right)) as f64);
+        }
+        Instruction::BitXor { dest, left, right } => {
+          let (left, right) = self.numbers(self.registers[register(left)], self.registers[register(right)])?;
+          self.registers[register(dest)] = Value::number((integer(left) ^ integer(right)) as f64);
+        }
+        // shift counts wrap at 64 the way the hardware does
+        Instruction::ShiftLeft { dest, left, right } => {
+          let (left, right) = self.numbers(self.registers[register(left)], self.registers[register(right)])?;
+          self.registers[register(dest)] = Value::number(integer(left).wrapping_shl(integer(right) as u32) as f64);
+        }
+        Instruction::ShiftRight { dest, left, right } => {
+          let (left, right) = self.numbers(self.registers[register(left)], self.registers[register(right)])?;
+          self.registers[register(dest)] = Value::number(integer(left).wrapping_shr(integer(right) as u32) as f64);
+        }
+        Instruction::Not { dest, source } => {
+          self.registers[register(dest)] = Value::bool(!self.registers[register(source)].is_truthy())
+        }
+        Instruction::Negate { dest, source } => {
+          let value = self.number(self.registers[register(source)])?;
+          self.registers[register(dest)] = Value::number(-value);
+        }
+        Instruction::BitNot { dest, source } => {
+          let value = self.number(self.registers[register(source)])?;
+          self.registers[register(dest)] = Value::number(!integer(value) as f64);
+        }
+        // every part is stringified the way print would show it
+        Instruction::Interpolate { dest, first, count } => {
+          let parts = register(first)..register(first) + count as usize;
+          let text = self.registers[parts]
+            .iter()
+            .map(|part| self.heap.display(*part).to_string())
+            .collect::<String>();
+          self.registers[register(dest)] = Value::object(self.allocate_string(text));
+        }
+        Instruction::Print { source } => {
+          // a closed stdout is no reason to stop the script
+          let _ = writeln!(self.out, "{}", self.heap.display(self.registers[register(source)]));
+        }
+        Instruction::Jump { target } => self.frames.last_mut().expect("code only runs inside a call").ip = target,
+        Instruction::JumpIfFalse { condition, target } => {
+          if !self.registers[register(condition)].is_truthy() {
+            self.frames.last_mut().expect("code only runs inside a call").ip = target;
+          }
+        }
+        Instruction::JumpIfTrue { condition, target } => {
+          if self.registers[register(condition)].is_truthy() {
+            self.frames.last_mut().expect("code only runs inside a call").ip = target;
+          }
+        }
+        Instruction::JumpIfNotNil { condition, target } => {
+          if self.registers[register(condition)].kind() != ValueKind::Nil {
+            self.frames.last_mut().expect("code only runs inside a call").ip = target;
+          }
+        }
+        Instruction::Call { callee, argument_count } => {
+          self.call_value(register(callee), argument_count as usize)?;
+        }
+        Instruction::Closure { dest, function } => {
+          let frame = self.frames.last().expect("code only runs inside a call");
+          let function = self.function_object(frame.function).functions[function];
+          let closure = self.allocate_closure(function);
+          self.registers[register(dest)] = Value::object(closure);
+        }
+        // the result takes the callee's register in the caller, whose window is all that's left
+        Instruction::Return { source } => {
+          let result = self.registers[register(source)];
+          self.frames.pop();
+
+          let Some(caller) = self.frames.last() else {
+            self.registers.clear();
+            return Ok(());
+          };
+
+          let end = caller.base + caller.code.registers;
+          self.registers[base] = result;
+          self.registers.resize(end, Value::nil());
+        }
+        Instruction::Class { dest, name } => {
+          let name = self.name(name);
+          let class = self.allocate(Object::Class(ClassObject {
+            name,
+            methods: HashMap::new(),
+            layout: HashMap::new(),
+          }));
+          self.registers[register(dest)] = Value::object(class);
+        }
+        Instruction::Inherit { class, superclass } => {
+          let methods = match self.registers[register(superclass)].kind() {
+            ValueKind::Object(object) => match self.heap.get(object) {
+              Object::Class(superclass) => superclass.methods.clone(),
+              _ => return Err(self.error("Superclass must be a class.")),
+            },
+            _ => return Err(self.error("Superclass must be a class.")),
+          };
+
+          if let Object::Class(class) = self.heap.get_mut(Self::object_of(self.registers[register(class)])) {
+            class.methods.extend(methods);
+          }
+        }
+        Instruction::Method { class, method, name } => {
+          let (name, method) = (self.name(name), self.registers[register(method)]);
+
+          if let Object::Class(class) = self.heap.get_mut(Self::object_of(self.registers[register(class)])) {
+            class.methods.insert(name, method);
+          }
+        }
+        // a method is bound to the instance, which stays in its register while that's allocated
+        Instruction::GetProperty { dest, object, name } => {
+          let name = self.name(name);
+          let receiver = self.registers[register(object)];
+          let Some(instance) = self.instance(receiver) else {
+            return Err(self.error("Only instances have properties."));
+          };
+
+          let value = match self.property(instance, name) {
+            Some(Ok(field)) => field,
+            Some(Err(method)) => {
+              let method = Self::object_of(method);
+              Value::object(self.allocate(Object::BoundMethod(BoundMethodObject { receiver, method })))
+            }
+            None => return Err(self.undefined_property(name)),
+          };
+          self.registers[register(dest)] = value;
+        }
+        // every field name ever set on an instance of the class gets an index in its layout
+        Instruction::SetProperty { object, name, source } => {
+          let name = self.name(name);
+          let Some(instance) = self.instance(self.registers[register(object)]) else {
+            return Err(self.error("Only instances have fields."));
+          };
+          let class = match self.heap.get(instance) {
+            Object::Instance(instance) => instance.class,
+            object => unreachable!("expected an instance, found {:?}", object),
+          };
+          let index = match self.heap.get_mut(class) {
+            Object::Class(class) => {
+              let next = class.layout.len();
+              *class.layout.entry(name).or_insert(next)
+            }
+            object => unreachable!("expected a class, found {:?}", object),
+          };
+          let value = self.registers[register(source)];
+
+          if let Object::Instance(instance) = self.heap.get_mut(instance) {
+            instance.set_field(index, value);
+          }
+        }
+        // a field holding a function is called like any other value
+        Instruction::Invoke {
+          callee,
+          name,
+          argument_count,
+        } => {
+          let name = self.name(name);
+          let Some(instance) = self.instance(self.registers[register(callee)]) else {
+            return Err(self.error("Only instances have properties."));
+          };
+
+          match self.property(instance, name) {
+            Some(Ok(field)) => {
+              self.registers[register(callee)] = field;
+              self.call_value(register(callee), argument_count as usize)?;
+            }
+            Some(Err(method)) => self.call(register(callee), Self::object_of(method), argument_count as usize)?,
+            None => return Err(self.undefined_property(name)),
+          }
+        }
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::cell::RefCell;
+
+  use super::*;
+  use crate::lexer::{Lexer, SourceMap};
+  use crate::parser::Parser;
+  use crate::register::RegisterCompiler;
+  use crate::vm::Vm;
+
+  // collects what print writes so tests can look at it
+  #[derive(Clone, Default)]
+  struct Output(Rc<RefCell<Vec<u8>>>);
+
+  impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+      self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+      Ok(())
+    }
+  }
+
+  fn run_with_gc(code: &str, stress: bool, generational: bool) -> Result<String, String> {
+    let ast = Parser::new(Lexer::new(code).lex().unwrap()).parse().unwrap();
+    let script = RegisterCompiler::new(&SourceMap::new(code)).compile(&ast).unwrap();
+    let output = Output::default();
+    let mut vm = RegisterVm::with_output(Box::new(output.clone()));
+    vm.set_stress_gc(stress);
+    vm.set_generational_gc(generational);
+    let result = vm.interpret(script);
+    let printed = String::from_utf8(output.0.take()).unwrap();
+
+    result.map(|_| printed).map_err(|error| error.to_string())
+  }
+
+  // every program runs again collecting at every allocation, with and without generations,
+  // and has to do what it does on the stack vm
+  fn run(code: &str) -> Result<String, String> {
+    let result = run_with_gc(code, false, false);
+    assert_eq!(run_with_gc(code, true, false), result);
+    assert_eq!(run_with_gc(code, true, true), result);
+
+    let ast = Parser::new(Lexer::new(code).lex().unwrap()).parse().unwrap();
+    let script = crate::compiler::Compiler::new(&SourceMap::new(code)).compile(&ast).unwrap();
+    let output = Output::default();
+    let stack = Vm::with_output(Box::new(output.clone())).interpret(script);
+    let printed = String::from_utf8(output.0.take()).unwrap();
+    assert_eq!(stack.map(|_| printed).map_err(|error| error.to_string()), result, "{}", code);
+
+    result
+  }
+
+  #[test]
+  fn expressions() {
+    assert_eq!(run("print 1 + 2 * 3 - 4 / 2;"), Ok(String::from("5\n")));
+    assert_eq!(run("print 1 < 2 == !(2 <= 1); print 1 != 2;"), Ok(String::from("true\ntrue\n")));
+    assert_eq!(run("print \"foo\" + \"bar\";"), Ok(String::from("foobar\n")));
+    assert_eq!(run("print 1 << 4 | 1 ^ 3; print ~5 % 4;"), Ok(String::from("18\n-2\n")));
+    assert_eq!(run("print \"1 + 1 = ${1 + 1}, ${nil}\";"), Ok(String::from("1 + 1 = 2, nil\n")));
+    assert_eq!(run("print nil or \"default\"; print false and -nil;"), Ok(String::from("default\nfalse\n")));
+    assert_eq!(run("print nil ?? \"default\"; print false ?? -nil; var a; print a ?? a ?? 0;"), Ok(String::from("default\nfalse\n0\n")));
+  }
+
+  #[test]
+  fn bitwise_operators_truncate_fractions_towards_zero() {
+    let code = "print -2.7 & -1; print 5.9 >> 0.9; print ~2.5; print 0.5 | 0.5; print (0 / 0) | 1;";
+    assert_eq!(run(code), Ok(String::from("-2\n5\n-3\n0\n1\n")));
+    assert!(run("print \"1\" & 1;").is_err());
+    assert!(run("print ~nil;").is_err());
+  }
+
+  #[test]
+  fn remainder_takes_the_sign_of_the_dividend() {
+    assert_eq!(run("print -7 % 3; print 7 % -3; print 5.5 % 2; print 1 % 0;"), Ok(String::from("-1\n1\n1.5\nNaN\n")));
+    assert!(run("print \"7\" % 3;").is_err());
+  }
+
+  #[test]
+  fn variables_and_scopes() {
+    let code = "var a = \"global\"; var b = 1; { var a = \"inner\"; b = 2; print a; } print a; print b;";
+    assert_eq!(run(code), Ok(String::from("inner\nglobal\n2\n")));
+
+    let code = "{ var a = 1; var b = a or 2; a = a and b + 1; print a + (a = 5); print b; }";
+    assert_eq!(run(code), Ok(String::from("7\n1\n")));
+  }
+
+  #[test]
+  fn control_flow() {
+    assert_eq!(run("if (nil) print 1; else print 2; if (0) print 3;"), Ok(String::from("2\n3\n")));
+
+    let code = "var a = 0; var b = 1; for (var i = 0; i < 10; i += 1) { var t = a; a = b; b += t; } print a;";
+    assert_eq!(run(code), Ok(String::from("55\n")));
+  }
+
+  #[test]
+  fn lambdas() {
+    let code = "fun apply(f, x) { return f(x); } var double = fun (x) { return x * 2; };
+      print apply(double, 4); print (fun (a, b) { return a - b; })(5, 3); print apply(fun (x) { return x + 1; }, 1);";
+    assert_eq!(run(code), Ok(String::from("8\n2\n2\n")));
+  }
+
+  #[test]
+  fn switches() {
+    // the case assigning to a is compared with the value a had before it
+    let code = "fun name(n) { switch (n) { case 1: return \"one\"; case 2, 3: return \"few\"; default: return \"many\"; } }
+      print name(1); print name(3); print name(7);
+      for (var i = 0; i < 5; i += 1) { var a = i; switch (a) { case 1: continue; case a = 3: var b = a; break; } print a; }";
+    assert_eq!(run(code), Ok(String::from("one\nfew\nmany\n3\n3\n")));
+  }
+
+  #[test]
+  fn break_and_continue() {
+    let code = "for (var i = 0; i < 6; i += 1) { if (i == 1) continue; if (i == 4) break; print i; }
+      var n = 0; while (true) { n += 1; if (n < 3) continue; break; } print n;";
+    assert_eq!(run(code), Ok(String::from("0\n2\n3\n3\n")));
+
+    let code = "fun f() { var total = 0; for (var i = 0; i < 3; i += 1) { var a = i; for (var j = 0; j < 3; j += 1) {
+      var b = a * 10 + j; if (j == 2) break; { var c = b; if (c % 2 == 1) continue; total += c; } } } var after = total; return after; }
+      print f();";
+    assert_eq!(run(code), Ok(String::from("30\n")));
+  }
+
+  #[test]
+  fn functions() {
+    let code = "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); } print fib(10);";
+    assert_eq!(run(code), Ok(String::from("55\n")));
+
+    let code = "fun greet(name) { print \"hi ${name}\"; } print greet(\"bob\"); print greet; print clock;";
+    assert_eq!(run(code), Ok(String::from("hi bob\nnil\n<fn greet>\n<native fn>\n")));
+
+    let code = "fun outer() { fun inner(a) { return a * 2; } return inner(21); } print outer();";
+    assert_eq!(run(code), Ok(String::from("42\n")));
+
+    let code = "fun make() { fun f() {} return f; } print make() == make(); var f = make(); print f == f;";
+    assert_eq!(run(code), Ok(String::from("false\ntrue\n")));
+
+    let code = "fun fib(n) { if (n < 2) return n; return fib(n - 2) + fib(n - 1); }
+      var start = clock(); print fib(15) == 610; print clock() - start >= 0 and start > 0;";
+    assert_eq!(run(code), Ok(String::from("true\ntrue\n")));
+
+    let code = "print toUpper(substring(\"register\", 0, 3)); print floor(PI * 100) + max(1, 2);";
+    assert_eq!(run(code), Ok(String::from("REG\n316\n")));
+  }
+
+  #[test]
+  fn classes() {
+    let code = "class A { init(n) { this.n = n; } get() { return this.n; } add(m) { this.n += m; return this; } }
+      var a = A(1); print a; print A; print a.get; a.n = 2; print a.get(); var get = a.get; print get();
+      print a.add(3).add(4).n; print a.init(5).n; print A(6).get();";
+    assert_eq!(run(code), Ok(String::from("A instance\nA\n<fn get>\n2\n2\n9\n5\n6\n")));
+
+    // fields shadow methods, and a function in a field is called without this
+    let code = "class A { f() { return \"method\"; } } var a = A(); fun field() { return \"field\"; } a.f = field;
+      print a.f(); class B < A { g() { return this.f(); } } var b = B(); print b.g(); { class C {} print C(); }";
+    assert_eq!(run(code), Ok(String::from("field\nmethod\nC instance\n")));
+
+    let code = "class Tree { init(depth) { if (depth > 0) { this.left = Tree(depth - 1); this.right = Tree(depth - 1); }
+      else { this.left = nil; this.right = nil; } } check() { if (this.left == nil) return 1;
+      return 1 + this.left.check() + this.right.check(); } } print Tree(6).check();";
+    assert_eq!(run(code), Ok(String::from("127\n")));
+  }
+
+  #[test]
+  fn class_errors() {
+    assert_eq!(run("class A {} A(1);"), Err(String::from("Expected 0 arguments but got 1.")));
+    assert_eq!(run("class A { init(a) {} } A();"), Err(String::from("Expected 1 arguments but got 0.")));
+    assert_eq!(run("class A {} print A().x;"), Err(String::from("Undefined property 'x'.")));
+    assert_eq!(run("class A {} A().f();"), Err(String::from("Undefined property 'f'.")));
+    assert_eq!(run("var a = 1; print a.x;"), Err(String::from("Only instances have properties.")));
+    assert_eq!(run("var a = 1; a.f();"), Err(String::from("Only instances have properties.")));
+    assert_eq!(run("var a = 1; a.x = 2;"), Err(String::from("Only instances have fields.")));
+    assert_eq!(run("var B = 1; class A < B {}"), Err(String::from("Superclass must be a class.")));
+  }
+
+  #[test]
+  fn runtime_errors() {
+    assert_eq!(run("print -\"a\";"), Err(String::from("Operand must be a number.")));
+    assert_eq!(run("print 1 < nil;"), Err(String::from("Operands must be numbers.")));
+    assert_eq!(run("print 1 + \"a\";"), Err(String::from("Operands must be two numbers or two strings.")));
+    assert_eq!(run("print a;"), Err(String::from("Undefined variable 'a'.")));
+    assert_eq!(run("a = 1;"), Err(String::from("Undefined variable 'a'.")));
+    assert_eq!(run("\"a\"();"), Err(String::from("Can only call functions and classes.")));
+    assert_eq!(run("fun f(a) {} f();"), Err(String::from("Expected 1 arguments but got 0.")));
+    assert_eq!(run("fun f() { f(); } f();"), Err(String::from("Stack overflow.")));
+  }
+
+  #[test]
+  fn errors_carry_the_calls_they_happened_in() {
+    let code = "fun inner() {\n  return -nil;\n}\nfun outer() {\n  inner();\n}\nouter();";
+    let ast = Parser::new(Lexer::new(code).lex().unwrap()).parse().unwrap();
+    let script = RegisterCompiler::new(&SourceMap::new(code)).compile(&ast).unwrap();
+    let error = RegisterVm::with_output(Box::new(io::sink())).interpret(script).unwrap_err();
+
+    assert_eq!(error.stack_trace(), ["[line 2] in inner()", "[line 5] in outer()", "[line 7] in script"]);
+  }
+
+  #[test]
+  fn exit_stops_the_script_with_its_status() {
+    let code = "fun f(status) { exit(status); } print 1; f(3); print 2;";
+    assert_eq!(run(code), Err(String::from("Exited with status 3.")));
+
+    let ast = Parser::new(Lexer::new(code).lex().unwrap()).parse().unwrap();
+    let script = RegisterCompiler::new(&SourceMap::new(code)).compile(&ast).unwrap();
+    let error = RegisterVm::with_output(Box::new(io::sink())).interpret(script).unwrap_err();
+
+    assert_eq!(error.exit, Some(3));
+    assert_eq!(run("exit(\"1\");").unwrap_err(), "Argument to exit() must be a whole number from 0 to 255.");
+  }
+}
@@ -0,0 +1,77 @@
+use std::error;
+use std::fmt;
+
+use crate::lexer::Span;
+
+#[derive(Debug, Clone)]
+pub enum ResolveError {
+  ReadInOwnInitializer {
+    span: Span,
+    name: String,
+  },
+  AlreadyDeclared {
+    span: Span,
+    name: String,
+  },
+  ReturnAtTopLevel {
+    span: Span,
+  },
+  ReturnValueFromInitializer {
+    span: Span,
+  },
+  ThisOutsideClass {
+    span: Span,
+  },
+  SuperOutsideClass {
+    span: Span,
+  },
+  SuperWithoutSuperclass {
+    span: Span,
+  },
+  ImportOutsideTopLevel {
+    span: Span,
+  },
+  // the tree was parsed without going through the loader, so nothing was read for the import
+  ModuleNotLoaded {
+    span: Span,
+    path: String,
+  },
+}
+
+impl ResolveError {
+  pub fn span(&self) -> Span {
+    match self {
+      ResolveError::ReadInOwnInitializer { span, .. }
+      | ResolveError::AlreadyDeclared { span, .. }
+      | ResolveError::ReturnAtTopLevel { span }
+      | ResolveError::ReturnValueFromInitializer { span }
+      | ResolveError::ThisOutsideClass { span }
+      | ResolveError::SuperOutsideClass { span }
+      | ResolveError::SuperWithoutSuperclass { span }
+      | ResolveError::ImportOutsideTopLevel { span }
+      | ResolveError::ModuleNotLoaded { span, .. } => *span,
+    }
+  }
+}
+
+impl fmt::Display for ResolveError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ResolveError::ReadInOwnInitializer { name, .. } => {
+        write!(f, "can't read local variable '{}' in its own initializer", name)
+      }
+      ResolveError::AlreadyDeclared { name, .. } => write!(f, "'{}' is already declared in this scope", name),
+      ResolveError::ReturnAtTopLevel { .. } => write!(f, "can't return from top-level code"),
+      ResolveError::ReturnValueFromInitializer { .. } => write!(f, "can't return a value from an initializer"),
+      ResolveError::ThisOutsideClass { .. } => write!(f, "can't use 'this' outside of a class"),
+      ResolveError::SuperOutsideClass { .. } => write!(f, "can't use 'super' outside of a class"),
+      ResolveError::SuperWithoutSuperclass { .. } => {
+        write!(f, "can't use 'super' in a class with no superclass")
+      }
+      ResolveError::ImportOutsideTopLevel { .. } => write!(f, "can only import at the top level"),
+      ResolveError::ModuleNotLoaded { path, .. } => write!(f, "module '{}' was never loaded", path),
+    }
+  }
+}
+
+impl error::Error for ResolveError {}
@@ -0,0 +1,475 @@
+use std::collections::{HashMap, HashSet};
+use std::mem;
+
+use crate::ast::{
+  Ast, BinaryOperator, Case, ExprId, ExprVisitor, Literal, LogicalOperator, ModuleId, StmtId, StmtKind, StmtVisitor,
+  UnaryOperator,
+};
+use crate::lexer::Span;
+
+mod error;
+
+pub use error::ResolveError;
+
+// how many scopes out from the reference each local variable was declared, references missing
+// from the table are globals
+pub type Locals = HashMap<ExprId, usize>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FunctionKind {
+  None,
+  Function,
+  Method,
+  Initializer,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ClassKind {
+  None,
+  Class,
+  Subclass,
+}
+
+// walks the tree once before it runs, binding every variable reference to the scope that
+// declares it so closures keep seeing the variables they saw when they were made, and
+// catching mistakes that don't need the program to run
+pub struct Resolver {
+  // the block scopes around the current node, innermost last. A name maps to whether its
+  // initializer has finished, the top level isn't tracked since globals are looked up by name
+  scopes: Vec<HashMap<String, bool>>,
+  locals: Locals,
+  errors: Vec<ResolveError>,
+  function: FunctionKind,
+  class: ClassKind,
+  // modules already resolved, a file imported from several places is only gone through once
+  modules: HashSet<ModuleId>,
+}
+
+impl Default for Resolver {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Resolver {
+  pub fn new() -> Self {
+    Self {
+      scopes: vec![],
+      locals: Locals::new(),
+      errors: vec![],
+      function: FunctionKind::None,
+      class: ClassKind::None,
+      modules: HashSet::new(),
+    }
+  }
+
+  // every error in the program, not just the first one
+  pub fn resolve(mut self, ast: &Ast) -> Result<Locals, Vec<ResolveError>> {
+    self.resolve_statements(ast, ast.program());
+
+    if self.errors.is_empty() {
+      Ok(self.locals)
+    } else {
+      Err(self.errors)
+    }
+  }
+
+  fn resolve_statements(&mut self, ast: &Ast, statements: &[StmtId]) {
+    statements.iter().for_each(|statement| ast.visit_stmt(*statement, self));
+  }
+
+  fn resolve_expr(&mut self, ast: &Ast, expr: ExprId) {
+    ast.visit_expr(expr, self)
+  }
+
+  fn begin_scope(&mut self) {
+    self.scopes.push(HashMap::new());
+  }
+
+  fn end_scope(&mut self) {
+    self.scopes.pop();
+  }
+
+  fn declare(&mut self, name: &str, span: Span) {
+    if let Some(scope) = self.scopes.last_mut() {
+      if scope.insert(String::from(name), false).is_some() {
+        self.errors.push(ResolveError::AlreadyDeclared {
+          span,
+          name: String::from(name),
+        });
+      }
+    }
+  }
+
+  fn define(&mut self, name: &str) {
+    if let Some(scope) = self.scopes.last_mut() {
+      scope.insert(String::from(name), true);
+    }
+  }
+
+  fn resolve_local(&mut self, expr: ExprId, name: &str) {
+    if let Some(depth) = self.scopes.iter().rev().position(|scope| scope.contains_key(name)) {
+      self.locals.insert(expr, depth);
+    }
+  }
+
+  // parameters and body share the one scope, as they do when the function is called
+  fn resolve_function(&mut self, ast: &Ast, declaration: StmtId, kind: FunctionKind) {
+    let (params, body) = match &ast[declaration].kind {
+      StmtKind::Function { params, body, .. } => (params, body),
+      kind => unreachable!("only function declarations are resolved as functions, not {:?}", kind),
+    };
+
+    let enclosing = mem::replace(&mut self.function, kind);
+    self.begin_scope();
+
+    for param in params {
+      self.declare(param, ast[declaration].span);
+      self.define(param);
+    }
+
+    self.resolve_statements(ast, body);
+    self.end_scope();
+    self.function = enclosing;
+  }
+}
+
+impl ExprVisitor<()> for Resolver {
+  fn visit_literal(&mut self, _: &Ast, _: ExprId, _: &Literal) {}
+
+  fn visit_interpolation(&mut self, ast: &Ast, _: ExprId, parts: &[ExprId]) {
+    parts.iter().for_each(|part| self.resolve_expr(ast, *part));
+  }
+
+  fn visit_grouping(&mut self, ast: &Ast, _: ExprId, inner: ExprId) {
+    self.resolve_expr(ast, inner);
+  }
+
+  fn visit_unary(&mut self, ast: &Ast, _: ExprId, _: UnaryOperator, operand: ExprId) {
+    self.resolve_expr(ast, operand);
+  }
+
+  fn visit_binary(&mut self, ast: &Ast, _: ExprId, left: ExprId, _: BinaryOperator, right: ExprId) {
+    self.resolve_expr(ast, left);
+    self.resolve_expr(ast, right);
+  }
+
+  fn visit_logical(&mut self, ast: &Ast, _: ExprId, left: ExprId, _: LogicalOperator, right: ExprId) {
+    self.resolve_expr(ast, left);
+    self.resolve_expr(ast, right);
+  }
+
+  fn visit_variable(&mut self, ast: &Ast, id: ExprId, name: &str) {
+    if self.scopes.last().and_then(|scope| scope.get(name)) == Some(&false) {
+      self.errors.push(ResolveError::ReadInOwnInitializer {
+        span: ast[id].span,
+        name: String::from(name),
+      });
+    }
+
+    self.resolve_local(id, name);
+  }
+
+  fn visit_assign(&mut self, ast: &Ast, id: ExprId, name: &str, value: ExprId) {
+    self.resolve_expr(ast, value);
+    self.resolve_local(id, name);
+  }
+
+  fn visit_call(&mut self, ast: &Ast, _: ExprId, callee: ExprId, arguments: &[ExprId]) {
+    self.resolve_expr(ast, callee);
+    arguments.iter().for_each(|argument| self.resolve_expr(ast, *argument));
+  }
+
+  // properties are looked up dynamically, only the object is resolved
+  fn visit_get(&mut self, ast: &Ast, _: ExprId, object: ExprId, _: &str) {
+    self.resolve_expr(ast, object);
+  }
+
+  fn visit_set(&mut self, ast: &Ast, _: ExprId, object: ExprId, _: &str, _: Option<BinaryOperator>, value: ExprId) {
+    self.resolve_expr(ast, value);
+    self.resolve_expr(ast, object);
+  }
+
+  fn visit_this(&mut self, ast: &Ast, id: ExprId) {
+    if self.class == ClassKind::None {
+      self.errors.push(ResolveError::ThisOutsideClass { span: ast[id].span });
+    }
+
+    self.resolve_local(id, "this");
+  }
+
+  fn visit_super(&mut self, ast: &Ast, id: ExprId, _: &str) {
+    match self.class {
+      ClassKind::None => self.errors.push(ResolveError::SuperOutsideClass { span: ast[id].span }),
+      ClassKind::Class => self.errors.push(ResolveError::SuperWithoutSuperclass { span: ast[id].span }),
+      ClassKind::Subclass => (),
+    }
+
+    self.resolve_local(id, "super");
+  }
+
+  fn visit_list(&mut self, ast: &Ast, _: ExprId, elements: &[ExprId]) {
+    elements.iter().for_each(|element| self.resolve_expr(ast, *element));
+  }
+
+  fn visit_map(&mut self, ast: &Ast, _: ExprId, entries: &[(ExprId, ExprId)]) {
+    for (key, value) in entries {
+      self.resolve_expr(ast, *key);
+      self.resolve_expr(ast, *value);
+    }
+  }
+
+  fn visit_index(&mut self, ast: &Ast, _: ExprId, object: ExprId, index: ExprId) {
+    self.resolve_expr(ast, object);
+    self.resolve_expr(ast, index);
+  }
+
+  fn visit_set_index(
+    &mut self,
+    ast: &Ast,
+    _: ExprId,
+    object: ExprId,
+    index: ExprId,
+    _: Option<BinaryOperator>,
+    value: ExprId,
+  ) {
+    self.resolve_expr(ast, object);
+    self.resolve_expr(ast, index);
+    self.resolve_expr(ast, value);
+  }
+
+  fn visit_slice(&mut self, ast: &Ast, _: ExprId, object: ExprId, start: Option<ExprId>, end: Option<ExprId>) {
+    self.resolve_expr(ast, object);
+    start.into_iter().chain(end).for_each(|bound| self.resolve_expr(ast, bound));
+  }
+
+  fn visit_lambda(&mut self, ast: &Ast, _: ExprId, declaration: StmtId) {
+    self.resolve_function(ast, declaration, FunctionKind::Function);
+  }
+}
+
+impl StmtVisitor<()> for Resolver {
+  fn visit_expression_stmt(&mut self, ast: &Ast, _: StmtId, expr: ExprId) {
+    self.resolve_expr(ast, expr);
+  }
+
+  fn visit_print(&mut self, ast: &Ast, _: StmtId, expr: ExprId) {
+    self.resolve_expr(ast, expr);
+  }
+
+  // declared before the initializer and defined after it, so the initializer can't see it
+  fn visit_var(&mut self, ast: &Ast, id: StmtId, name: &str, initializer: Option<ExprId>) {
+    self.declare(name, ast[id].span);
+
+    if let Some(initializer) = initializer {
+      self.resolve_expr(ast, initializer);
+    }
+
+    self.define(name);
+  }
+
+  fn visit_block(&mut self, ast: &Ast, _: StmtId, statements: &[StmtId]) {
+    self.begin_scope();
+    self.resolve_statements(ast, statements);
+    self.end_scope();
+  }
+
+  fn visit_if(
+    &mut self,
+    ast: &Ast,
+    _: StmtId,
+    condition: ExprId,
+    then_branch: StmtId,
+    else_branch: Option<StmtId>,
+  ) {
+    self.resolve_expr(ast, condition);
+    ast.visit_stmt(then_branch, self);
+
+    if let Some(else_branch) = else_branch {
+      ast.visit_stmt(else_branch, self);
+    }
+  }
+
+  fn visit_while(&mut self, ast: &Ast, _: StmtId, condition: ExprId, body: StmtId, increment: Option<ExprId>) {
+    self.resolve_expr(ast, condition);
+    ast.visit_stmt(body, self);
+
+    if let Some(increment) = increment {
+      self.resolve_expr(ast, increment);
+    }
+  }
+
+  // the variable is in a scope of its own around the body, as the interpreter binds each element
+  fn visit_for_in(&mut self, ast: &Ast, id: StmtId, name: &str, iterable: ExprId, body: StmtId) {
+    self.resolve_expr(ast, iterable);
+    self.begin_scope();
+    self.declare(name, ast[id].span);
+    self.define(name);
+    ast.visit_stmt(body, self);
+    self.end_scope();
+  }
+
+  fn visit_switch(&mut self, ast: &Ast, _: StmtId, value: ExprId, cases: &[Case], default: Option<StmtId>) {
+    self.resolve_expr(ast, value);
+
+    for case in cases {
+      case.values.iter().for_each(|value| self.resolve_expr(ast, *value));
+      ast.visit_stmt(case.body, self);
+    }
+
+    if let Some(default) = default {
+      ast.visit_stmt(default, self);
+    }
+  }
+
+  fn visit_break(&mut self, _: &Ast, _: StmtId) {}
+
+  fn visit_continue(&mut self, _: &Ast, _: StmtId) {}
+
+  fn visit_throw(&mut self, ast: &Ast, _: StmtId, value: ExprId) {
+    self.resolve_expr(ast, value);
+  }
+
+  // the caught value gets a scope around the handler the way a for-in loop's variable does
+  fn visit_try(&mut self, ast: &Ast, id: StmtId, body: StmtId, name: &str, handler: StmtId) {
+    ast.visit_stmt(body, self);
+    self.begin_scope();
+    self.declare(name, ast[id].span);
+    self.define(name);
+    ast.visit_stmt(handler, self);
+    self.end_scope();
+  }
+
+  // the module's statements are resolved where it's first imported, at the top level like the
+  // importer's
+  fn visit_import(&mut self, ast: &Ast, id: StmtId, path: &str, _: Option<&[String]>) {
+    if !self.scopes.is_empty() {
+      self.errors.push(ResolveError::ImportOutsideTopLevel { span: ast[id].span });
+      return;
+    }
+
+    match ast.imported(id) {
+      Some(module) => {
+        if self.modules.insert(module) {
+          self.resolve_statements(ast, &ast.module(module).body);
+        }
+      }
+      None => self.errors.push(ResolveError::ModuleNotLoaded {
+        span: ast[id].span,
+        path: String::from(path),
+      }),
+    }
+  }
+
+  // defined straight away so the function can call itself
+  fn visit_function(&mut self, ast: &Ast, id: StmtId, name: &str, _: &[String], _: &[StmtId]) {
+    self.declare(name, ast[id].span);
+    self.define(name);
+    self.resolve_function(ast, id, FunctionKind::Function);
+  }
+
+  fn visit_return(&mut self, ast: &Ast, id: StmtId, value: Option<ExprId>) {
+    match (self.function, value) {
+      (FunctionKind::None, _) => self.errors.push(ResolveError::ReturnAtTopLevel { span: ast[id].span }),
+      (FunctionKind::Initializer, Some(_)) => {
+        self.errors.push(ResolveError::ReturnValueFromInitializer { span: ast[id].span })
+      }
+      _ => (),
+    }
+
+    if let Some(value) = value {
+      self.resolve_expr(ast, value);
+    }
+  }
+
+  // methods sit inside a scope holding `this`, and of one holding `super` for subclasses,
+  // mirroring the scopes the interpreter creates when it binds them
+  fn visit_class(
+    &mut self,
+    ast: &Ast,
+    id: StmtId,
+    name: &str,
+    superclass: Option<ExprId>,
+    methods: &[StmtId],
+  ) {
+    let enclosing = mem::replace(&mut self.class, ClassKind::Class);
+    self.declare(name, ast[id].span);
+    self.define(name);
+
+    if let Some(superclass) = superclass {
+      self.class = ClassKind::Subclass;
+      self.resolve_expr(ast, superclass);
+      self.begin_scope();
+      self.define("super");
+    }
+
+    self.begin_scope();
+    self.define("this");
+
+    for method in methods {
+      let kind = match &ast[*method].kind {
+        StmtKind::Function { name, .. } if name == "init" => FunctionKind::Initializer,
+        _ => FunctionKind::Method,
+      };
+
+      self.resolve_function(ast, *method, kind);
+    }
+
+    self.end_scope();
+
+    if superclass.is_some() {
+      self.end_scope();
+    }
+
+    self.class = enclosing;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::lexer::Lexer;
+  use crate::parser::Parser;
+
+  fn resolve(code: &str) -> Result<Locals, Vec<String>> {
+    let ast = Parser::new(Lexer::new(code).lex().unwrap()).parse().unwrap();
+
+    Resolver::new()
+      .resolve(&ast)
+      .map_err(|errors| errors.iter().map(|error| error.to_string()).collect())
+  }
+
+  fn errors(code: &str) -> Vec<String> {
+    resolve(code).err().unwrap_or_default()
+  }
+
+  #[test]
+  fn locals_record_their_depth_and_globals_are_left_out() {
+    let locals = resolve("var g; { var a; { a; g; } }").unwrap();
+
+    assert_eq!(locals.values().copied().collect::<Vec<_>>(), vec![1]);
+  }
+
+  #[test]
+  fn static_errors() {
+    assert_eq!(errors("{ var a = a; }"), vec!["can't read local variable 'a' in its own initializer"]);
+    assert_eq!(errors("{ var a; var a; }"), vec!["'a' is already declared in this scope"]);
+    assert_eq!(errors("fun f(a, a) {}"), vec!["'a' is already declared in this scope"]);
+    assert_eq!(errors("return 1;"), vec!["can't return from top-level code"]);
+    assert_eq!(errors("class A { init() { return 1; } }"), vec!["can't return a value from an initializer"]);
+    assert_eq!(errors("print this;"), vec!["can't use 'this' outside of a class"]);
+    assert_eq!(errors("fun f() { super.g(); }"), vec!["can't use 'super' outside of a class"]);
+    assert_eq!(
+      errors("class A { f() { super.f(); } }"),
+      vec!["can't use 'super' in a class with no superclass"]
+    );
+    assert_eq!(errors("fun f() { import \"m.lox\"; }"), vec!["can only import at the top level"]);
+    assert_eq!(errors("import \"m.lox\";"), vec!["module 'm.lox' was never loaded"]);
+  }
+
+  #[test]
+  fn globals_may_be_redeclared_and_read_in_their_initializer() {
+    assert!(errors("var a = 1; var a = a;").is_empty());
+    assert!(errors("class A { init() { return; } f() { return this; } }").is_empty());
+  }
+}
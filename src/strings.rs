@@ -0,0 +1,248 @@
+// the string handling behind the natives, shared by the tree-walker and the vm so both agree on
+// what an index is. Indices count characters rather than bytes, so they never land inside one
+
+pub fn len(text: &str) -> usize {
+  text.chars().count()
+}
+
+// the characters from start up to but not including end
+pub fn substring(text: &str, start: f64, end: f64) -> Result<String, String> {
+  let len = len(text);
+
+  match (index(start, len), index(end, len)) {
+    (Some(start), Some(end)) if start <= end => Ok(text.chars().skip(start).take(end - start).collect()),
+    _ => Err(format!("Substring range {}..{} is out of bounds for a string of length {}.", start, end, len)),
+  }
+}
+
+// where needle first starts in text, -1 when it doesn't
+pub fn index_of(text: &str, needle: &str) -> f64 {
+  match text.find(needle) {
+    Some(byte) => len(&text[..byte]) as f64,
+    None => -1.0,
+  }
+}
+
+// the text between each separator, an empty separator splits it into its characters
+pub fn split(text: &str, separator: &str) -> Vec<String> {
+  match separator {
+    "" => text.chars().map(String::from).collect(),
+    _ => text.split(separator).map(String::from).collect(),
+  }
+}
+
+// the number text spells the way a Lox literal would, a minus sign and the spaces around it
+// allowed. Anything else, exponents and infinity included, isn't one
+pub fn parse_number(text: &str) -> Option<f64> {
+  let text = text.trim();
+  let digits = text.strip_prefix('-').unwrap_or(text);
+
+  let spelled = digits.chars().all(|c| c.is_ascii_digit() || c == '.');
+
+  if !spelled || !digits.starts_with(|c: char| c.is_ascii_digit()) || !digits.ends_with(|c: char| c.is_ascii_digit()) {
+    return None;
+  }
+
+  text.parse().ok()
+}
+
+// the character with the code, if it's a whole number that's a valid one
+pub fn chr(code: f64) -> Option<char> {
+  if code.fract() != 0.0 || !(0.0..=u32::MAX as f64).contains(&code) {
+    return None;
+  }
+
+  char::from_u32(code as u32)
+}
+
+// the code of the only character in text
+pub fn ord(text: &str) -> Option<u32> {
+  let mut chars = text.chars();
+
+  match (chars.next(), chars.next()) {
+    (Some(char), None) => Some(char as u32),
+    _ => None,
+  }
+}
+
+// a piece of the template format() is given, the text between placeholders or one of them
+#[derive(Debug, Clone, PartialEq)]
+pub enum Piece {
+  Text(String),
+  // the argument at index, with that many decimals when there's a precision
+  Argument { index: usize, precision: Option<usize> },
+}
+
+// the template split at its placeholders, checked against how many arguments there are to fill
+// them. `{}` is the argument after the one before it, `{1}` the one at that index counted from
+// 0, and either can end in `:.2` for a number with two decimals. `{{` and `}}` are braces
+pub fn parse_template(template: &str, count: usize) -> Result<Vec<Piece>, String> {
+  let mut pieces = vec![];
+  let mut text = String::new();
+  let mut next = 0;
+  let mut chars = template.chars().peekable();
+
+  while let Some(char) = chars.next() {
+    match char {
+      '{' | '}' if chars.peek() == Some(&char) => {
+        chars.next();
+        text.push(char);
+      }
+      '}' => return Err(String::from("Unmatched '}' in format string.")),
+      '{' => {
+        let mut spec = String::new();
+
+        loop {
+          match chars.next() {
+            Some('}') => break,
+            Some(char) => spec.push(char),
+            None => return Err(String::from("Unmatched '{' in format string.")),
+          }
+        }
+
+        let (index, precision) = placeholder(&spec, &mut next)
+          .ok_or_else(|| format!("Invalid placeholder '{{{}' in format string.", spec))?;
+
+        if index >= count {
+          return Err(format!("Format string needs at least {} arguments but got {}.", index + 1, count));
+        }
+
+        pieces.push(Piece::Text(std::mem::take(&mut text)));
+        pieces.push(Piece::Argument { index, precision });
+      }
+      _ => text.push(char),
+    }
+  }
+
+  pieces.push(Piece::Text(text));
+  pieces.retain(|piece| *piece != Piece::Text(String::new()));
+
+  Ok(pieces)
+}
+
+pub fn precision_needs_a_number() -> String {
+  String::from("Only numbers can be formatted with a precision.")
+}
+
+// the number with exactly that many decimals, rounded
+pub fn fixed(value: f64, precision: usize) -> String {
+  format!("{:.*}", precision, value)
+}
+
+// the index and precision the text between a placeholder's braces asks for
+fn placeholder(spec: &str, next: &mut usize) -> Option<(usize, Option<usize>)> {
+  let (position, precision) = match spec.split_once(':') {
+    Some((position, precision)) => (position, Some(precision.strip_prefix('.')?)),
+    None => (spec, None),
+  };
+
+  let digits = |text: &str| text.chars().all(|char| char.is_ascii_digit()).then(|| text.parse().ok()).flatten();
+
+  let index = match position {
+    "" => {
+      *next += 1;
+      *next - 1
+    }
+    position => digits(position)?,
+  };
+
+  match precision {
+    Some(precision) => Some((index, Some(digits(precision)?))),
+    None => Some((index, None)),
+  }
+}
+
+// a whole number from 0 up to len, the end of the string included
+fn index(value: f64, len: usize) -> Option<usize> {
+  (value.fract() == 0.0 && value >= 0.0 && value <= len as f64).then_some(value as usize)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn indices_count_characters() {
+    assert_eq!(len("héllo"), 5);
+    assert_eq!(substring("héllo", 1.0, 3.0), Ok(String::from("él")));
+    assert_eq!(index_of("héllo", "llo"), 2.0);
+    assert_eq!(index_of("héllo", "x"), -1.0);
+  }
+
+  #[test]
+  fn split_keeps_the_empty_pieces() {
+    assert_eq!(split("a,b,,c", ","), ["a", "b", "", "c"]);
+    assert_eq!(split("héllo", ""), ["h", "é", "l", "l", "o"]);
+    assert_eq!(split("", ","), [""]);
+  }
+
+  #[test]
+  fn numbers_are_parsed_the_way_literals_are() {
+    assert_eq!(parse_number("42"), Some(42.0));
+    assert_eq!(parse_number(" -1.5\t"), Some(-1.5));
+
+    for text in ["", "-", ".5", "1.", "1.2.3", "1e5", "inf", "NaN", "+1", "12abc"] {
+      assert_eq!(parse_number(text), None, "{}", text);
+    }
+  }
+
+  #[test]
+  fn characters_and_their_codes() {
+    assert_eq!(chr(65.0), Some('A'));
+    assert_eq!(chr(233.0), Some('é'));
+    assert_eq!(ord("é"), Some(233));
+
+    for code in [-1.0, 65.5, 0xd800 as f64, 1e20] {
+      assert_eq!(chr(code), None, "{}", code);
+    }
+
+    assert_eq!(ord(""), None);
+    assert_eq!(ord("ab"), None);
+  }
+
+  #[test]
+  fn templates_are_split_at_their_placeholders() {
+    let text = |text: &str| Piece::Text(String::from(text));
+    let argument = |index, precision| Piece::Argument { index, precision };
+
+    assert_eq!(
+      parse_template("x = {}, y = {:.2}{{}}", 2),
+      Ok(vec![text("x = "), argument(0, None), text(", y = "), argument(1, Some(2)), text("{}")])
+    );
+    assert_eq!(parse_template("{1}{0}{}", 2), Ok(vec![argument(1, None), argument(0, None), argument(0, None)]));
+    assert_eq!(parse_template("", 0), Ok(vec![]));
+  }
+
+  #[test]
+  fn bad_templates_are_errors() {
+    assert_eq!(parse_template("{} {}", 1), Err(String::from("Format string needs at least 2 arguments but got 1.")));
+    assert_eq!(parse_template("a }", 0), Err(String::from("Unmatched '}' in format string.")));
+    assert_eq!(parse_template("{0", 1), Err(String::from("Unmatched '{' in format string.")));
+
+    for template in ["{x}", "{:2}", "{:.}", "{-1}", "{0:.2x}"] {
+      assert!(parse_template(template, 1).unwrap_err().starts_with("Invalid placeholder"), "{}", template);
+    }
+  }
+
+  #[test]
+  fn numbers_are_fixed_to_their_precision() {
+    assert_eq!(fixed(1.23456, 2), "1.23");
+    assert_eq!(fixed(2.0, 3), "2.000");
+    assert_eq!(fixed(-0.6, 0), "-1");
+  }
+
+  #[test]
+  fn substrings_stay_inside_the_string() {
+    assert_eq!(substring("abc", 0.0, 3.0), Ok(String::from("abc")));
+    assert_eq!(substring("abc", 3.0, 3.0), Ok(String::new()));
+
+    for (start, end) in [(2.0, 1.0), (0.0, 4.0), (-1.0, 2.0), (0.5, 2.0)] {
+      assert!(substring("abc", start, end).is_err(), "{}..{}", start, end);
+    }
+
+    assert_eq!(
+      substring("abc", 1.0, 5.0),
+      Err(String::from("Substring range 1..5 is out of bounds for a string of length 3."))
+    );
+  }
+}
@@ -0,0 +1,98 @@
+// what a script can learn about the process running it, for the system natives of every
+// backend. The arguments are the ones after `--` on the command line, kept one list per thread
+// like the random generator's state
+use std::cell::RefCell;
+use std::env;
+use std::fmt;
+
+thread_local! {
+  static ARGS: RefCell<Vec<String>> = const { RefCell::new(vec![]) };
+}
+
+// why a native of any backend returned no value. A message is a runtime error the script can
+// catch, exit() stops the script with the status without reporting anything
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NativeError {
+  Message(String),
+  Exit(i32),
+}
+
+// natives fail with the messages of the helpers they share through `?`
+impl From<String> for NativeError {
+  fn from(message: String) -> Self {
+    NativeError::Message(message)
+  }
+}
+
+impl fmt::Display for NativeError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      NativeError::Message(message) => write!(f, "{}", message),
+      NativeError::Exit(status) => write!(f, "{}", exited(*status)),
+    }
+  }
+}
+
+pub fn set_args(args: Vec<String>) {
+  ARGS.with(|current| *current.borrow_mut() = args);
+}
+
+pub fn args() -> Vec<String> {
+  ARGS.with(|args| args.borrow().clone())
+}
+
+// None when the variable isn't set or isn't valid unicode
+pub fn get_env(name: &str) -> Option<String> {
+  env::var(name).ok()
+}
+
+// the name Rust gives the operating system, like "linux", "macos" or "windows"
+pub fn platform() -> &'static str {
+  env::consts::OS
+}
+
+pub fn invalid_status() -> String {
+  String::from("Argument to exit() must be a whole number from 0 to 255.")
+}
+
+pub fn exited(status: i32) -> String {
+  format!("Exited with status {}.", status)
+}
+
+// the error for exit() to fail with, a status out of range is an error like any other
+pub fn exit(status: f64) -> NativeError {
+  if status.fract() != 0.0 || !(0.0..=255.0).contains(&status) {
+    return NativeError::Message(invalid_status());
+  }
+
+  NativeError::Exit(status as i32)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn args_are_the_ones_last_set() {
+    assert_eq!(args(), Vec::<String>::new());
+
+    set_args(vec![String::from("a"), String::from("b c")]);
+    assert_eq!(args(), ["a", "b c"]);
+  }
+
+  #[test]
+  fn exit_fails_with_the_status() {
+    assert_eq!(exit(3.0), NativeError::Exit(3));
+    assert_eq!(exit(3.0).to_string(), "Exited with status 3.");
+
+    for status in [-1.0, 256.0, 1.5, f64::NAN] {
+      assert_eq!(exit(status), NativeError::Message(invalid_status()), "{}", status);
+    }
+  }
+
+  #[test]
+  fn unset_variables_are_none() {
+    assert_eq!(get_env("RSLOX_NOT_A_VARIABLE"), None);
+    assert!(!platform().is_empty());
+  }
+}
@@ -0,0 +1,40 @@
+use super::{ObjRef, Value};
+
+// what an instruction found the last time it ran, kept by the function it's in at the
+// instruction's offset. An instruction only remembers one class, the next one it sees replaces it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InlineCache {
+  Empty,
+  // the global's slot, which never changes once the name has one
+  Global(usize),
+  // the field's index in instances of the class
+  Field { class: ObjRef, index: usize },
+  // the class's method, for as long as the class has as many field names as when it was found.
+  // A field of the same name would shadow it, and adding one grows the layout
+  Method { class: ObjRef, fields: usize, method: Value },
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CacheCounts {
+  pub hits: usize,
+  pub misses: usize,
+}
+
+impl CacheCounts {
+  pub fn record(&mut self, hit: bool) {
+    if hit {
+      self.hits += 1;
+    } else {
+      self.misses += 1;
+    }
+  }
+}
+
+// how often each kind of instruction found what it was after in its cache, for --vm-stats
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct VmStats {
+  pub globals: CacheCounts,
+  pub get_property: CacheCounts,
+  pub set_property: CacheCounts,
+  pub invoke: CacheCounts,
+}
@@ -0,0 +1,49 @@
+use std::error;
+use std::fmt;
+
+// a call that was running when the error happened, and the line it had got to
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackFrame {
+  // empty for the top level of the script
+  pub function: String,
+  pub line: usize,
+}
+
+// chunks only know the line each instruction came from, so unlike the tree-walker's errors
+// these carry lines rather than spans. The messages match the tree-walker's word for word
+#[derive(Debug, Clone)]
+pub struct RuntimeError {
+  pub message: String,
+  // innermost first, ending with the script
+  pub trace: Vec<StackFrame>,
+  // the status when this is exit() stopping the script, which no try catches
+  pub exit: Option<i32>,
+}
+
+impl RuntimeError {
+  // one line per frame in the style of clox. The top level of an imported file is named after
+  // it in angle brackets
+  //
+  //   [line 3] in fibonacci()
+  //   [line 1] in <lib/math.lox>
+  //   [line 7] in script
+  pub fn stack_trace(&self) -> Vec<String> {
+    self
+      .trace
+      .iter()
+      .map(|frame| match frame.function.as_str() {
+        "" => format!("[line {}] in script", frame.line),
+        function if function.starts_with('<') => format!("[line {}] in {}", frame.line, function),
+        function => format!("[line {}] in {}()", frame.line, function),
+      })
+      .collect()
+  }
+}
+
+impl fmt::Display for RuntimeError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.message)
+  }
+}
+
+impl error::Error for RuntimeError {}
@@ -0,0 +1,643 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use super::object::{MapKey, MapObject, Object, UpvalueObject};
+use super::{InlineCache, Value, ValueKind};
+
+// the first collection happens once this much is allocated, later ones when the heap has grown
+// by GROW_FACTOR since the last
+const INITIAL_NEXT_GC: usize = 1024 * 1024;
+const GROW_FACTOR: usize = 2;
+// in the generational mode, a minor collection happens once this much has been allocated since
+// the last collection
+const NURSERY_SIZE: usize = 256 * 1024;
+
+// a handle to an object on the heap, only valid for as long as the object is reachable
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObjRef(pub(super) usize);
+
+// the header every object carries, marked is set while collecting for the objects still in use
+#[derive(Debug)]
+struct Slot {
+  marked: bool,
+  // survived a collection in the generational mode, minor collections leave it alone
+  old: bool,
+  // old and changed since the last collection, so it's in the remembered set
+  remembered: bool,
+  size: usize,
+  object: Object,
+}
+
+// how well interning is doing, for the --intern-stats flag
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct InternStats {
+  // strings in the table right now
+  pub strings: usize,
+  // times a string was about to be allocated, and how many of those found it already there
+  pub lookups: usize,
+  pub hits: usize,
+}
+
+// how many collections of each kind there have been, how long the vm stopped for them and the
+// bytes they freed and left, for the gcStats native. Every collection is major unless the
+// generational mode is on
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GcStats {
+  pub minor: usize,
+  pub major: usize,
+  pub longest_pause: Duration,
+  pub total_pause: Duration,
+  pub bytes_allocated: usize,
+  pub bytes_freed: usize,
+}
+
+impl GcStats {
+  // the entries of the map gcStats returns, the pauses in milliseconds
+  pub fn fields(&self) -> [(&'static str, f64); 7] {
+    [
+      ("collections", (self.minor + self.major) as f64),
+      ("minor", self.minor as f64),
+      ("major", self.major as f64),
+      ("bytesAllocated", self.bytes_allocated as f64),
+      ("bytesFreed", self.bytes_freed as f64),
+      ("longestPause", self.longest_pause.as_secs_f64() * 1000.0),
+      ("totalPause", self.total_pause.as_secs_f64() * 1000.0),
+    ]
+  }
+}
+
+// every object the vm allocates, freed by mark and sweep. The heap doesn't know the roots, the
+// vm calls begin_collection, marks them and then calls collect.
+//
+// In the generational mode objects start out young and become old once they survive a
+// collection. Most collections are minor: they trace from the roots and from the old objects
+// changed since the last collection, the only ones that can point at young objects, and sweep
+// only the young. Old objects are left for a major collection once the heap has grown by
+// GROW_FACTOR, so the vm stops for a pass over the whole heap much less often
+#[derive(Debug)]
+pub struct Heap {
+  slots: Vec<Option<Slot>>,
+  // every string on the heap by its text, so each text is allocated once and strings can be
+  // compared by handle. Strings only the table refers to are still collected
+  strings: HashMap<Rc<str>, ObjRef>,
+  intern_lookups: usize,
+  intern_hits: usize,
+  // slots freed by the last sweep, reused before the heap grows
+  free: Vec<usize>,
+  // marked objects whose references haven't been marked yet
+  gray: Vec<ObjRef>,
+  bytes_allocated: usize,
+  next_gc: usize,
+  // collect before every allocation, so a missing root shows up straight away in tests
+  stress: bool,
+  generational: bool,
+  // allocated since the last collection, and how much they take up
+  young: Vec<ObjRef>,
+  young_bytes: usize,
+  // old objects changed since the last collection
+  remembered: Vec<ObjRef>,
+  // whether the collection under way is minor, and when it started
+  minor: bool,
+  started: Option<Instant>,
+  gc_stats: GcStats,
+}
+
+impl Default for Heap {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Heap {
+  pub fn new() -> Self {
+    Self {
+      slots: vec![],
+      strings: HashMap::new(),
+      intern_lookups: 0,
+      intern_hits: 0,
+      free: vec![],
+      gray: vec![],
+      bytes_allocated: 0,
+      next_gc: INITIAL_NEXT_GC,
+      stress: false,
+      generational: false,
+      young: vec![],
+      young_bytes: 0,
+      remembered: vec![],
+      minor: false,
+      started: None,
+      gc_stats: GcStats::default(),
+    }
+  }
+
+  pub fn set_stress(&mut self, stress: bool) {
+    self.stress = stress;
+  }
+
+  // objects already on the heap only become old once they survive a collection
+  pub fn set_generational(&mut self, generational: bool) {
+    self.generational = generational;
+  }
+
+  // whether the vm should mark its roots and collect before allocating again
+  pub fn should_collect(&self) -> bool {
+    self.stress || self.bytes_allocated > self.next_gc || (self.generational && self.young_bytes > NURSERY_SIZE)
+  }
+
+  pub fn gc_stats(&self) -> GcStats {
+    GcStats {
+      bytes_allocated: self.bytes_allocated,
+      ..self.gc_stats
+    }
+  }
+
+  pub fn bytes_allocated(&self) -> usize {
+    self.bytes_allocated
+  }
+
+  pub fn object_count(&self) -> usize {
+    self.slots.len() - self.free.len()
+  }
+
+  pub fn intern_stats(&self) -> InternStats {
+    InternStats {
+      strings: self.strings.len(),
+      lookups: self.intern_lookups,
+      hits: self.intern_hits,
+    }
+  }
+
+  // the string already holding the text, to use instead of allocating another
+  pub fn find_string(&mut self, text: &str) -> Option<ObjRef> {
+    self.intern_lookups += 1;
+    let string = self.strings.get(text).copied();
+
+    if string.is_some() {
+      self.intern_hits += 1;
+    }
+
+    string
+  }
+
+  // strings have to be looked up with find_string first, there's only ever one of each
+  pub fn allocate(&mut self, object: Object) -> ObjRef {
+    let size = object.size();
+    let string = match &object {
+      Object::String(value) => Some(Rc::clone(value)),
+      _ => None,
+    };
+    let slot = Slot {
+      marked: false,
+      old: false,
+      remembered: false,
+      size,
+      object,
+    };
+    self.bytes_allocated += size;
+
+    let reference = match self.free.pop() {
+      Some(index) => {
+        self.slots[index] = Some(slot);
+        ObjRef(index)
+      }
+      None => {
+        self.slots.push(Some(slot));
+        ObjRef(self.slots.len() - 1)
+      }
+    };
+
+    if let Some(string) = string {
+      self.strings.insert(string, reference);
+    }
+
+    if self.generational {
+      self.young.push(reference);
+      self.young_bytes += size;
+    }
+
+    reference
+  }
+
+  fn slot(&self, object: ObjRef) -> &Slot {
+    self.slots[object.0].as_ref().expect("objects in use are never freed")
+  }
+
+  pub fn get(&self, object: ObjRef) -> &Object {
+    &self.slot(object).object
+  }
+
+  // changing an old object is the write barrier, it goes in the remembered set in case what
+  // it's changed to refers to a young one
+  pub fn get_mut(&mut self, object: ObjRef) -> &mut Object {
+    let slot = self.slots[object.0].as_mut().expect("objects in use are never freed");
+
+    if slot.old && !slot.remembered {
+      slot.remembered = true;
+      self.remembered.push(object);
+    }
+
+    &mut slot.object
+  }
+
+  pub fn string(&self, object: ObjRef) -> &str {
+    match self.get(object) {
+      Object::String(value) => value,
+      object => unreachable!("expected a string, found {:?}", object),
+    }
+  }
+
+  pub fn as_string(&self, value: Value) -> Option<&str> {
+    match value.kind() {
+      ValueKind::Object(object) => match self.get(object) {
+        Object::String(value) => Some(value),
+        _ => None,
+      },
+      _ => None,
+    }
+  }
+
+  pub fn as_list(&self, value: Value) -> Option<&[Value]> {
+    match value.kind() {
+      ValueKind::Object(object) => match self.get(object) {
+        Object::List(elements) => Some(elements),
+        _ => None,
+      },
+      _ => None,
+    }
+  }
+
+  pub fn as_list_mut(&mut self, value: Value) -> Option<&mut Vec<Value>> {
+    match value.kind() {
+      ValueKind::Object(object) if matches!(self.get(object), Object::List(_)) => match self.get_mut(object) {
+        Object::List(elements) => Some(elements),
+        _ => None,
+      },
+      _ => None,
+    }
+  }
+
+  pub fn as_map(&self, value: Value) -> Option<&MapObject> {
+    match value.kind() {
+      ValueKind::Object(object) => match self.get(object) {
+        Object::Map(map) => Some(map),
+        _ => None,
+      },
+      _ => None,
+    }
+  }
+
+  pub fn as_map_mut(&mut self, value: Value) -> Option<&mut MapObject> {
+    match value.kind() {
+      ValueKind::Object(object) if matches!(self.get(object), Object::Map(_)) => match self.get_mut(object) {
+        Object::Map(map) => Some(map),
+        _ => None,
+      },
+      _ => None,
+    }
+  }
+
+  // only strings and numbers can be keys, strings by their handle as they're interned
+  pub fn to_key(&self, value: Value) -> Option<MapKey> {
+    match value.kind() {
+      ValueKind::Number(value) => Some(MapKey::number(value)),
+      ValueKind::Object(object) if matches!(self.get(object), Object::String(_)) => Some(MapKey::String(object)),
+      _ => None,
+    }
+  }
+
+  pub fn from_key(key: MapKey) -> Value {
+    match key {
+      MapKey::String(string) => Value::object(string),
+      MapKey::Number(bits) => Value::number(f64::from_bits(bits)),
+    }
+  }
+
+  pub fn mark_value(&mut self, value: Value) {
+    if let ValueKind::Object(object) = value.kind() {
+      self.mark_object(object);
+    }
+  }
+
+  // old objects are taken to be alive in a minor collection
+  pub fn mark_object(&mut self, object: ObjRef) {
+    let slot = self.slots[object.0].as_mut().expect("objects in use are never freed");
+    let skipped = slot.marked || (self.minor && slot.old);
+
+    if !skipped {
+      slot.marked = true;
+      self.gray.push(object);
+    }
+  }
+
+  // marks everything the object refers to
+  fn blacken(&mut self, object: ObjRef) {
+    match self.get(object) {
+      // a cache holding on to a class keeps it, so its handle can't be reused by another one
+      Object::Function(function) => {
+        let caches = function.caches.clone();
+
+        for value in function.constants.clone() {
+          self.mark_value(value);
+        }
+
+        for cache in caches {
+          match cache {
+            InlineCache::Field { class, .. } => self.mark_object(class),
+            InlineCache::Method { class, method, .. } => {
+              self.mark_object(class);
+              self.mark_value(method);
+            }
+            InlineCache::Empty | InlineCache::Global(_) => (),
+          }
+        }
+      }
+      Object::Closure(closure) => {
+        let (function, upvalues) = (closure.function, closure.upvalues.clone());
+        self.mark_object(function);
+        upvalues.into_iter().for_each(|upvalue| self.mark_object(upvalue));
+      }
+      Object::Class(class) => {
+        let name = class.name;
+        let methods = class.methods.values().copied().collect::<Vec<_>>();
+        let fields = class.layout.keys().copied().collect::<Vec<_>>();
+        self.mark_object(name);
+        methods.into_iter().for_each(|method| self.mark_value(method));
+        fields.into_iter().for_each(|field| self.mark_object(field));
+      }
+      Object::Instance(instance) => {
+        let class = instance.class;
+        let fields = instance.fields.iter().flatten().copied().collect::<Vec<_>>();
+        self.mark_object(class);
+        fields.into_iter().for_each(|value| self.mark_value(value));
+      }
+      Object::BoundMethod(bound) => {
+        let (receiver, method) = (bound.receiver, bound.method);
+        self.mark_value(receiver);
+        self.mark_object(method);
+      }
+      Object::List(elements) => {
+        for element in elements.clone() {
+          self.mark_value(element);
+        }
+      }
+      Object::Map(map) => {
+        for (key, value) in map.entries().to_vec() {
+          self.mark_value(Heap::from_key(key));
+          self.mark_value(value);
+        }
+      }
+      #[cfg(feature = "register-vm")]
+      Object::RegisterFunction(function) => {
+        let functions = function.functions.clone();
+
+        for value in function.constants.clone() {
+          self.mark_value(value);
+        }
+
+        functions.into_iter().for_each(|function| self.mark_object(function));
+      }
+      // open upvalues point at the stack, which is a root already
+      Object::Upvalue(UpvalueObject::Closed(value)) => self.mark_value(*value),
+      Object::String(_) | Object::Upvalue(UpvalueObject::Open(_)) | Object::Native(_) => (),
+    }
+  }
+
+  // decides whether the collection about to happen is minor, before the vm marks its roots
+  pub fn begin_collection(&mut self) {
+    self.minor = self.generational && self.bytes_allocated <= self.next_gc;
+    self.started = Some(Instant::now());
+  }
+
+  // frees every object that wasn't marked from a root, and clears the marks on the rest for
+  // the next collection. Whatever survives is old from then on in the generational mode
+  pub fn collect(&mut self) {
+    let started = self.started.take().unwrap_or_else(Instant::now);
+
+    if self.minor {
+      for object in mem::take(&mut self.remembered) {
+        self.slots[object.0].as_mut().expect("objects in use are never freed").remembered = false;
+        self.blacken(object);
+      }
+    }
+
+    while let Some(object) = self.gray.pop() {
+      self.blacken(object);
+    }
+
+    if self.minor {
+      for object in mem::take(&mut self.young) {
+        self.sweep(object.0);
+      }
+
+      self.gc_stats.minor += 1;
+    } else {
+      for index in 0..self.slots.len() {
+        self.sweep(index);
+      }
+
+      self.young.clear();
+      self.remembered.clear();
+      self.next_gc = (self.bytes_allocated * GROW_FACTOR).max(INITIAL_NEXT_GC);
+      self.gc_stats.major += 1;
+    }
+
+    self.young_bytes = 0;
+    self.minor = false;
+
+    let pause = started.elapsed();
+    self.gc_stats.longest_pause = self.gc_stats.longest_pause.max(pause);
+    self.gc_stats.total_pause += pause;
+  }
+
+  fn sweep(&mut self, index: usize) {
+    let entry = &mut self.slots[index];
+
+    match entry {
+      Some(slot) if slot.marked => {
+        slot.marked = false;
+        slot.old = self.generational;
+        slot.remembered = false;
+      }
+      Some(slot) => {
+        if let Object::String(value) = &slot.object {
+          self.strings.remove(value);
+        }
+
+        self.bytes_allocated -= slot.size;
+        self.gc_stats.bytes_freed += slot.size;
+        *entry = None;
+        self.free.push(index);
+      }
+      None => (),
+    }
+  }
+
+  // shown the way the tree-walker prints values so both backends give the same output
+  pub fn display(&self, value: Value) -> Display<'_> {
+    Display { heap: self, value }
+  }
+}
+
+pub struct Display<'h> {
+  heap: &'h Heap,
+  value: Value,
+}
+
+impl fmt::Display for Display<'_> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self.value.kind() {
+      ValueKind::Number(value) => write!(f, "{}", value),
+      ValueKind::Bool(value) => write!(f, "{}", value),
+      ValueKind::Nil => write!(f, "nil"),
+      ValueKind::Object(object) => match self.heap.get(object) {
+        Object::String(value) => write!(f, "{}", value),
+        Object::Function(function) => write!(f, "{}", function.function),
+        Object::Closure(closure) => write!(f, "{}", self.heap.display(Value::object(closure.function))),
+        Object::Upvalue(_) => write!(f, "upvalue"),
+        Object::Native(_) => write!(f, "<native fn>"),
+        Object::Class(class) => write!(f, "{}", self.heap.string(class.name)),
+        Object::Instance(instance) => match self.heap.get(instance.class) {
+          Object::Class(class) => write!(f, "{} instance", self.heap.string(class.name)),
+          object => unreachable!("an instance's class is a class, not {:?}", object),
+        },
+        Object::BoundMethod(bound) => write!(f, "{}", self.heap.display(Value::object(bound.method))),
+        Object::List(_) | Object::Map(_) => self.write_collection(f, self.value, &mut vec![]),
+        #[cfg(feature = "register-vm")]
+        Object::RegisterFunction(function) => write!(f, "{}", function.prototype),
+      },
+    }
+  }
+}
+
+impl Display<'_> {
+  // the elements the way print shows them, a list or map met again inside itself shows as [...]
+  // or {...}
+  fn write_collection(&self, f: &mut fmt::Formatter, value: Value, open: &mut Vec<ObjRef>) -> fmt::Result {
+    let (object, brackets) = match value.kind() {
+      ValueKind::Object(object) => match self.heap.get(object) {
+        Object::List(_) => (object, ("[", "]")),
+        Object::Map(_) => (object, ("{", "}")),
+        _ => return write!(f, "{}", self.heap.display(value)),
+      },
+      _ => return write!(f, "{}", self.heap.display(value)),
+    };
+
+    if open.contains(&object) {
+      return write!(f, "{}...{}", brackets.0, brackets.1);
+    }
+
+    open.push(object);
+    write!(f, "{}", brackets.0)?;
+
+    match self.heap.get(object) {
+      Object::List(elements) => {
+        for (index, element) in elements.iter().enumerate() {
+          if index > 0 {
+            write!(f, ", ")?;
+          }
+
+          self.write_collection(f, *element, open)?;
+        }
+      }
+      Object::Map(map) => {
+        for (index, (key, value)) in map.entries().iter().enumerate() {
+          if index > 0 {
+            write!(f, ", ")?;
+          }
+
+          write!(f, "{}: ", self.heap.display(Heap::from_key(*key)))?;
+          self.write_collection(f, *value, open)?;
+        }
+      }
+      _ => {}
+    }
+
+    open.pop();
+    write!(f, "{}", brackets.1)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn unmarked_objects_are_freed_and_their_slots_reused() {
+    let mut heap = Heap::new();
+    let kept = heap.allocate(Object::String(Rc::from("kept")));
+    heap.allocate(Object::String(Rc::from("garbage")));
+
+    heap.mark_object(kept);
+    heap.collect();
+
+    assert_eq!(heap.object_count(), 1);
+    assert_eq!(heap.bytes_allocated(), Object::String(Rc::from("kept")).size());
+    assert_eq!(heap.string(kept), "kept");
+    assert_eq!(heap.allocate(Object::String(Rc::from("new"))), ObjRef(1));
+
+    heap.collect();
+    assert_eq!((heap.object_count(), heap.bytes_allocated()), (0, 0));
+  }
+
+  #[test]
+  fn minor_collections_only_sweep_the_young() {
+    let mut heap = Heap::new();
+    heap.set_generational(true);
+    let old = heap.allocate(Object::Upvalue(UpvalueObject::Closed(Value::nil())));
+    heap.begin_collection();
+    heap.mark_object(old);
+    heap.collect();
+
+    // nothing marks the old object this time, it's kept anyway
+    heap.allocate(Object::String(Rc::from("young")));
+    heap.allocate(Object::String(Rc::from("garbage")));
+    heap.begin_collection();
+    heap.collect();
+
+    assert_eq!(heap.object_count(), 1);
+    assert_eq!(heap.gc_stats().minor, 2);
+    assert_eq!(heap.find_string("young"), None);
+  }
+
+  #[test]
+  fn old_objects_changed_since_the_last_collection_keep_young_ones_alive() {
+    let mut heap = Heap::new();
+    heap.set_generational(true);
+    let old = heap.allocate(Object::Upvalue(UpvalueObject::Closed(Value::nil())));
+    heap.begin_collection();
+    heap.mark_object(old);
+    heap.collect();
+
+    let young = heap.allocate(Object::String(Rc::from("young")));
+    *heap.get_mut(old) = Object::Upvalue(UpvalueObject::Closed(Value::object(young)));
+    heap.begin_collection();
+    heap.collect();
+
+    assert_eq!(heap.string(young), "young");
+    assert_eq!(heap.object_count(), 2);
+  }
+
+  #[test]
+  fn collected_strings_leave_the_table() {
+    let mut heap = Heap::new();
+    let kept = heap.allocate(Object::String(Rc::from("kept")));
+    heap.allocate(Object::String(Rc::from("garbage")));
+
+    heap.mark_object(kept);
+    heap.collect();
+
+    assert_eq!(heap.find_string("kept"), Some(kept));
+    assert_eq!(heap.find_string("garbage"), None);
+    assert_eq!(
+      heap.intern_stats(),
+      InternStats {
+        strings: 1,
+        lookups: 2,
+        hits: 1
+      }
+    );
+  }
+}
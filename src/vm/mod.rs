@@ -0,0 +1,1990 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use crate::chunk::{self, Function, OpCode};
+use crate::debug;
+use crate::lists;
+use crate::maps;
+use crate::system::{self, NativeError};
+
+mod cache;
+mod error;
+mod heap;
+pub(crate) mod natives;
+mod object;
+mod value;
+
+pub use cache::{CacheCounts, InlineCache, VmStats};
+pub use error::{RuntimeError, StackFrame};
+pub use heap::{GcStats, Heap, InternStats, ObjRef};
+pub use object::{
+  BoundMethodObject, ClassObject, ClosureObject, FunctionObject, InstanceObject, MapKey, MapObject, NativeFunction,
+  Object, UpvalueObject,
+};
+pub use value::{Value, ValueKind};
+
+// deep enough for any reasonable recursion, a runaway one is reported rather than taking the
+// process down with it
+pub(crate) const FRAMES_MAX: usize = 1024;
+
+// a call in progress, its locals start at slots in the vm's stack. The closure's function and
+// code are kept alongside it so reading an instruction doesn't go through the heap. The ip is
+// only up to date once another call has started on top of this one, the running call's is the
+// vm's
+#[derive(Debug)]
+struct CallFrame {
+  closure: ObjRef,
+  function: ObjRef,
+  code: Rc<Function>,
+  ip: usize,
+  slots: usize,
+  // whose globals the code uses, the closure's module
+  module: usize,
+}
+
+// a try block that's running: how many calls there were and how high the stack was when it
+// started, and where its catch clause is in the code of the call that started it
+#[derive(Debug, Clone, Copy)]
+struct Handler {
+  frames: usize,
+  stack: usize,
+  catch: usize,
+}
+
+// what a property access found on an instance
+#[derive(Debug, Clone, Copy)]
+enum Property {
+  Field(Value),
+  Method(Value),
+  Missing,
+}
+
+// bitwise operators work on the integer part of their operands
+fn integer(value: f64) -> i64 {
+  value as i64
+}
+
+// runs the functions the compiler produces, with the same results as the tree-walker
+pub struct Vm {
+  frames: Vec<CallFrame>,
+  // the code of the running call and where it's up to. Every instruction reads them, so they're
+  // kept here rather than behind the last frame
+  code: Rc<Function>,
+  ip: usize,
+  stack: Vec<Value>,
+  // the slot of each global by its interned name, a table for the script and one for every
+  // module it imported. Slots are never given up, so instructions can cache them, and hold None
+  // for names used before they're defined
+  globals: Vec<HashMap<ObjRef, usize>>,
+  global_values: Vec<Option<Value>>,
+  // the natives and constants every module starts out with, in slots of its own
+  builtins: Vec<(ObjRef, Value)>,
+  // the number of each module that has run, by the name of its top level function
+  modules: HashMap<String, usize>,
+  // the upvalues still pointing into the stack, ordered by their slot
+  open_upvalues: Vec<ObjRef>,
+  // the try blocks that are running, innermost last
+  handlers: Vec<Handler>,
+  // the name initializers are looked up by when a class is called
+  init_string: ObjRef,
+  heap: Heap,
+  stats: VmStats,
+  // print the stack and the instruction about to run before every instruction
+  trace: bool,
+  out: Box<dyn Write>,
+}
+
+impl Default for Vm {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Vm {
+  pub fn new() -> Self {
+    Self::with_output(Box::new(io::stdout()))
+  }
+
+  // print writes to out instead of stdout
+  pub fn with_output(out: Box<dyn Write>) -> Self {
+    let mut heap = Heap::new();
+    let init_string = heap.allocate(Object::String(Rc::from("init")));
+    let mut vm = Self {
+      frames: vec![],
+      code: Rc::default(),
+      ip: 0,
+      stack: vec![],
+      globals: vec![HashMap::new()],
+      global_values: vec![],
+      builtins: vec![],
+      modules: HashMap::new(),
+      open_upvalues: vec![],
+      handlers: vec![],
+      init_string,
+      heap,
+      stats: VmStats::default(),
+      trace: false,
+      out,
+    };
+
+    for native in natives::natives() {
+      vm.define_native(native);
+    }
+
+    for (name, value) in natives::CONSTANTS {
+      vm.define_constant(name, value);
+    }
+
+    vm
+  }
+
+  // collect garbage before every allocation instead of only once the heap has grown, for
+  // flushing out objects the vm forgets to mark
+  pub fn set_stress_gc(&mut self, stress: bool) {
+    self.heap.set_stress(stress);
+  }
+
+  // most collections only look at what was allocated since the last one, see Heap
+  pub fn set_generational_gc(&mut self, generational: bool) {
+    self.heap.set_generational(generational);
+  }
+
+  // the trace goes to the same output as print, so the two stay in order
+  pub fn set_trace_execution(&mut self, trace: bool) {
+    self.trace = trace;
+  }
+
+  pub fn intern_stats(&self) -> InternStats {
+    self.heap.intern_stats()
+  }
+
+  pub fn vm_stats(&self) -> VmStats {
+    self.stats
+  }
+
+  // the name waits on the stack while the native is allocated
+  fn define_native(&mut self, native: NativeFunction) {
+    let name = self.allocate_string(String::from(native.name));
+    self.push(Value::object(name));
+    let native = self.allocate(Object::Native(native));
+    self.pop();
+
+    let slot = self.global_slot(name);
+    self.global_values[slot] = Some(Value::object(native));
+    self.builtins.push((name, Value::object(native)));
+  }
+
+  // the name is a root as soon as it has a slot
+  fn define_constant(&mut self, name: &str, value: f64) {
+    let name = self.allocate_string(String::from(name));
+    let slot = self.global_slot(name);
+    self.global_values[slot] = Some(Value::number(value));
+    self.builtins.push((name, Value::number(value)));
+  }
+
+  // everything reachable from the stack, the calls running and the globals is kept
+  fn collect_garbage(&mut self) {
+    self.heap.begin_collection();
+
+    for value in &self.stack {
+      self.heap.mark_value(*value);
+    }
+
+    for frame in &self.frames {
+      self.heap.mark_object(frame.closure);
+    }
+
+    for upvalue in &self.open_upvalues {
+      self.heap.mark_object(*upvalue);
+    }
+
+    for name in self.globals.iter().flat_map(HashMap::keys) {
+      self.heap.mark_object(*name);
+    }
+
+    for value in self.global_values.iter().flatten() {
+      self.heap.mark_value(*value);
+    }
+
+    for (name, value) in &self.builtins {
+      self.heap.mark_object(*name);
+      self.heap.mark_value(*value);
+    }
+
+    self.heap.mark_object(self.init_string);
+    self.heap.collect();
+  }
+
+  // anything the object refers to has to be reachable from a root already, as this may collect
+  fn allocate(&mut self, object: Object) -> ObjRef {
+    if self.heap.should_collect() {
+      self.collect_garbage();
+    }
+
+    self.heap.allocate(object)
+  }
+
+  fn allocate_string(&mut self, text: String) -> ObjRef {
+    match self.heap.find_string(&text) {
+      Some(string) => string,
+      None => self.allocate(Object::String(Rc::from(text))),
+    }
+  }
+
+  // puts a compiled function and everything in its constants on the heap. The constants wait
+  // on the stack while the rest are loaded so a collection in between doesn't free them
+  fn load(&mut self, function: Rc<Function>) -> ObjRef {
+    let base = self.stack.len();
+
+    for constant in function.chunk.constants() {
+      let value = match constant {
+        chunk::Value::Number(value) => Value::number(*value),
+        chunk::Value::Bool(value) => Value::bool(*value),
+        chunk::Value::Nil => Value::nil(),
+        chunk::Value::String(value) => Value::object(self.allocate_string(value.to_string())),
+        chunk::Value::Function(function) => Value::object(self.load(Rc::clone(function))),
+      };
+      self.push(value);
+    }
+
+    let constants = self.stack[base..].to_vec();
+    let caches = vec![InlineCache::Empty; function.chunk.code().len()];
+    let function = self.allocate(Object::Function(FunctionObject {
+      function,
+      constants,
+      caches,
+    }));
+    self.stack.truncate(base);
+
+    function
+  }
+
+  // the function waits on the stack while its closure is allocated
+  fn allocate_closure(&mut self, function: ObjRef, module: usize) -> ObjRef {
+    self.push(Value::object(function));
+    let closure = self.allocate(Object::Closure(ClosureObject {
+      function,
+      upvalues: vec![],
+      module,
+    }));
+    self.pop();
+
+    closure
+  }
+
+  // runs a compiled script, globals it defines are kept for the next one. After an error the
+  // stack is emptied so the vm can carry on with another script
+  pub fn interpret(&mut self, script: Function) -> Result<(), RuntimeError> {
+    let code = Rc::new(script);
+    let function = self.load(Rc::clone(&code));
+    let closure = self.allocate_closure(function, 0);
+    self.stack.push(Value::object(closure));
+    self.frames.push(CallFrame {
+      closure,
+      function,
+      code: Rc::clone(&code),
+      ip: 0,
+      slots: 0,
+      module: 0,
+    });
+    self.code = code;
+    self.ip = 0;
+
+    let result = self.run();
+
+    if result.is_err() {
+      self.stack.clear();
+      self.frames.clear();
+      self.open_upvalues.clear();
+      self.handlers.clear();
+    }
+
+    result
+  }
+
+  fn frame(&mut self) -> &mut CallFrame {
+    self.frames.last_mut().expect("code only runs inside a call")
+  }
+
+  fn read_byte(&mut self) -> u8 {
+    let byte = self.code.chunk.code()[self.ip];
+    self.ip += 1;
+
+    byte
+  }
+
+  fn read_short(&mut self) -> u16 {
+    u16::from_be_bytes([self.read_byte(), self.read_byte()])
+  }
+
+  // long instructions index the constants with three bytes instead of one
+  fn read_constant(&mut self, long: bool) -> Value {
+    let index = if long {
+      u32::from_be_bytes([0, self.read_byte(), self.read_byte(), self.read_byte()]) as usize
+    } else {
+      self.read_byte() as usize
+    };
+
+    let function = self.frame().function;
+
+    match self.heap.get(function) {
+      Object::Function(function) => function.constants[index],
+      object => unreachable!("calls are always to functions, not {:?}", object),
+    }
+  }
+
+  // names are always string constants
+  fn read_name(&mut self, long: bool) -> ObjRef {
+    match self.read_constant(long).kind() {
+      ValueKind::Object(name) => name,
+      value => unreachable!("expected a name, found {:?}", value),
+    }
+  }
+
+  fn push(&mut self, value: Value) {
+    self.stack.push(value);
+  }
+
+  fn pop(&mut self) -> Value {
+    self.stack.pop().expect("the compiler never pops more than it pushed")
+  }
+
+  fn peek(&self, distance: usize) -> Value {
+    self.stack[self.stack.len() - 1 - distance]
+  }
+
+  // the line every call on the stack had reached is in the trace
+  fn error(&self, message: impl Into<String>) -> RuntimeError {
+    let trace = self
+      .frames
+      .iter()
+      .rev()
+      .enumerate()
+      .map(|(depth, frame)| {
+        let ip = if depth == 0 { self.ip } else { frame.ip };
+
+        StackFrame {
+          function: frame.code.name.clone(),
+          line: frame.code.chunk.get_line(ip.saturating_sub(1)),
+        }
+      })
+      .collect();
+
+    RuntimeError {
+      message: message.into(),
+      trace,
+      exit: None,
+    }
+  }
+
+  // a native's error, or the script stopping when the native was exit()
+  fn native_error(&self, error: NativeError) -> RuntimeError {
+    match error {
+      NativeError::Message(message) => self.error(message),
+      NativeError::Exit(status) => RuntimeError {
+        exit: Some(status),
+        ..self.error(system::exited(status))
+      },
+    }
+  }
+
+  fn number_operand(&mut self) -> Result<f64, RuntimeError> {
+    match self.peek(0).kind() {
+      ValueKind::Number(value) => {
+        self.pop();
+        Ok(value)
+      }
+      _ => Err(self.error("Operand must be a number.")),
+    }
+  }
+
+  // the operands are left on the stack when they aren't both numbers
+  fn number_operands(&mut self) -> Result<(f64, f64), RuntimeError> {
+    match (self.peek(1).kind(), self.peek(0).kind()) {
+      (ValueKind::Number(left), ValueKind::Number(right)) => {
+        self.pop();
+        self.pop();
+        Ok((left, right))
+      }
+      _ => Err(self.error("Operands must be numbers.")),
+    }
+  }
+
+  fn list_index(&self, index: Value) -> Result<f64, RuntimeError> {
+    match index.kind() {
+      ValueKind::Number(index) => Ok(index),
+      _ => Err(self.error("List index must be a number.")),
+    }
+  }
+
+  fn map_key(&self, key: Value) -> Result<MapKey, RuntimeError> {
+    self.heap.to_key(key).ok_or_else(|| self.error(maps::invalid_key()))
+  }
+
+  fn slice_bound(&self, distance: usize) -> Result<Option<f64>, RuntimeError> {
+    match self.peek(distance).kind() {
+      ValueKind::Number(bound) => Ok(Some(bound)),
+      ValueKind::Nil => Ok(None),
+      _ => Err(self.error("Slice bounds must be numbers.")),
+    }
+  }
+
+  fn check_arity(&self, arity: usize, argument_count: usize) -> Result<(), RuntimeError> {
+    if argument_count != arity {
+      return Err(self.error(format!("Expected {} arguments but got {}.", arity, argument_count)));
+    }
+
+    Ok(())
+  }
+
+  // starts running the closure, its arguments are on top of the stack with slot 0 under them
+  fn call(&mut self, closure: ObjRef, argument_count: usize) -> Result<(), RuntimeError> {
+    let (function, module) = match self.heap.get(closure) {
+      Object::Closure(closure) => (closure.function, closure.module),
+      object => unreachable!("expected a closure, found {:?}", object),
+    };
+    let code = match self.heap.get(function) {
+      Object::Function(function) => Rc::clone(&function.function),
+      object => unreachable!("closures always hold functions, not {:?}", object),
+    };
+    self.check_arity(code.arity, argument_count)?;
+
+    if self.frames.len() == FRAMES_MAX {
+      return Err(self.error("Stack overflow."));
+    }
+
+    self.frame().ip = self.ip;
+    self.frames.push(CallFrame {
+      closure,
+      function,
+      code: Rc::clone(&code),
+      ip: 0,
+      slots: self.stack.len() - argument_count - 1,
+      module,
+    });
+    self.code = code;
+    self.ip = 0;
+
+    Ok(())
+  }
+
+  // for values the compiler guarantees are objects, like methods and classes
+  fn object_of(value: Value) -> ObjRef {
+    match value.kind() {
+      ValueKind::Object(object) => object,
+      value => unreachable!("expected an object, found {:?}", value),
+    }
+  }
+
+  // the callee and its arguments are on top of the stack
+  fn call_value(&mut self, argument_count: usize) -> Result<(), RuntimeError> {
+    let callee = match self.peek(argument_count).kind() {
+      ValueKind::Object(callee) => callee,
+      _ => return Err(self.error("Can only call functions and classes.")),
+    };
+
+    match self.heap.get(callee) {
+      Object::Closure(_) => self.call(callee, argument_count)?,
+      // the new instance takes the class's slot, where init finds it as this
+      Object::Class(class) => {
+        let initializer = class.methods.get(&self.init_string).copied();
+        let instance = self.allocate(Object::Instance(InstanceObject {
+          class: callee,
+          fields: vec![],
+        }));
+        let slot = self.stack.len() - argument_count - 1;
+        self.stack[slot] = Value::object(instance);
+
+        match initializer {
+          Some(initializer) => self.call(Self::object_of(initializer), argument_count)?,
+          None => self.check_arity(0, argument_count)?,
+        }
+      }
+      Object::BoundMethod(bound) => {
+        let (receiver, method) = (bound.receiver, bound.method);
+        let slot = self.stack.len() - argument_count - 1;
+        self.stack[slot] = receiver;
+        self.call(method, argument_count)?;
+      }
+      Object::Native(native) => {
+        let function = native.function;
+
+        if native.arity != NativeFunction::VARIADIC {
+          self.check_arity(native.arity, argument_count)?;
+        }
+
+        let arguments = self.stack.len() - argument_count;
+        let result = function(&mut self.heap, &self.stack[arguments..]).map_err(|error| self.native_error(error))?;
+        self.stack.truncate(arguments - 1);
+        self.push(result);
+      }
+      _ => return Err(self.error("Can only call functions and classes.")),
+    }
+
+    Ok(())
+  }
+
+  fn instance(&self, value: Value) -> Option<ObjRef> {
+    match value.kind() {
+      ValueKind::Object(object) if matches!(self.heap.get(object), Object::Instance(_)) => Some(object),
+      _ => None,
+    }
+  }
+
+  fn undefined_property(&self, name: ObjRef) -> RuntimeError {
+    self.error(format!("Undefined property '{}'.", self.heap.string(name)))
+  }
+
+  fn method(&self, class: ObjRef, name: ObjRef) -> Result<Value, RuntimeError> {
+    let method = match self.heap.get(class) {
+      Object::Class(class) => class.methods.get(&name).copied(),
+      object => unreachable!("expected a class, found {:?}", object),
+    };
+
+    method.ok_or_else(|| self.undefined_property(name))
+  }
+
+  // replaces the instance on top of the stack with the method bound to it
+  fn bind_method(&mut self, method: Value) {
+    let bound = self.allocate(Object::BoundMethod(BoundMethodObject {
+      receiver: self.peek(0),
+      method: Self::object_of(method),
+    }));
+    self.pop();
+    self.push(Value::object(bound));
+  }
+
+  // calls the method straight from the class, the receiver already sits in slot 0
+  fn invoke_from_class(&mut self, class: ObjRef, name: ObjRef, argument_count: usize) -> Result<(), RuntimeError> {
+    let method = self.method(class, name)?;
+    self.call(Self::object_of(method), argument_count)
+  }
+
+  // a field holding a function is called like any other value, fields shadow methods
+  fn invoke(&mut self, offset: usize, name: ObjRef, argument_count: usize) -> Result<(), RuntimeError> {
+    let instance = match self.instance(self.peek(argument_count)) {
+      Some(instance) => instance,
+      None => return Err(self.error("Only instances have properties.")),
+    };
+    let (property, hit) = self.property(offset, instance, name);
+    self.stats.invoke.record(hit);
+
+    match property {
+      Property::Field(field) => {
+        let slot = self.stack.len() - argument_count - 1;
+        self.stack[slot] = field;
+        self.call_value(argument_count)
+      }
+      Property::Method(method) => self.call(Self::object_of(method), argument_count),
+      Property::Missing => Err(self.undefined_property(name)),
+    }
+  }
+
+  // numbers add up and strings join, the operands stay on the stack if they're neither
+  fn add(&mut self) -> Result<(), RuntimeError> {
+    match (self.peek(1).kind(), self.peek(0).kind()) {
+      (ValueKind::Number(_), ValueKind::Number(_)) => {
+        let (left, right) = self.number_operands()?;
+        self.push(Value::number(left + right));
+      }
+      _ => match (self.heap.as_string(self.peek(1)), self.heap.as_string(self.peek(0))) {
+        (Some(left), Some(right)) => {
+          let value = Value::object(self.allocate_string(format!("{}{}", left, right)));
+          self.pop();
+          self.pop();
+          self.push(value);
+        }
+        _ => return Err(self.error("Operands must be two numbers or two strings.")),
+      },
+    }
+
+    Ok(())
+  }
+
+  // the cache of the instruction at offset in the function running
+  fn cache(&self, offset: usize) -> InlineCache {
+    let frame = self.frames.last().expect("code only runs inside a call");
+
+    match self.heap.get(frame.function) {
+      Object::Function(function) => function.caches[offset],
+      object => unreachable!("calls are always to functions, not {:?}", object),
+    }
+  }
+
+  fn set_cache(&mut self, offset: usize, cache: InlineCache) {
+    let function = self.frame().function;
+
+    if let Object::Function(function) = self.heap.get_mut(function) {
+      function.caches[offset] = cache;
+    }
+  }
+
+  // the global's slot in the running code's module, given one the first time the name comes up
+  fn global_slot(&mut self, name: ObjRef) -> usize {
+    let module = self.frames.last().map_or(0, |frame| frame.module);
+    let next = self.global_values.len();
+    let slot = *self.globals[module].entry(name).or_insert(next);
+
+    if slot == next {
+      self.global_values.push(None);
+    }
+
+    slot
+  }
+
+  // a table of globals for a module about to run, with the builtins in slots of its own
+  fn new_module(&mut self) -> usize {
+    let mut globals = HashMap::new();
+
+    for (name, value) in &self.builtins {
+      globals.insert(*name, self.global_values.len());
+      self.global_values.push(Some(*value));
+    }
+
+    self.globals.push(globals);
+    self.globals.len() - 1
+  }
+
+  // the slot the global instruction at offset is for, from its cache once it has run before
+  fn cached_global_slot(&mut self, offset: usize, name: ObjRef) -> usize {
+    if let InlineCache::Global(slot) = self.cache(offset) {
+      self.stats.globals.record(true);
+      return slot;
+    }
+
+    self.stats.globals.record(false);
+    let slot = self.global_slot(name);
+    self.set_cache(offset, InlineCache::Global(slot));
+
+    slot
+  }
+
+  // the field or method the name refers to on the instance, and whether the cache of the
+  // instruction at offset already knew where it was. A miss fills the cache for next time
+  fn property(&mut self, offset: usize, instance: ObjRef, name: ObjRef) -> (Property, bool) {
+    let instance = self.instance_object(instance);
+    let class = self.class_object(instance.class);
+
+    match self.cache(offset) {
+      InlineCache::Field { class: cached, index } if cached == instance.class => {
+        if let Some(value) = instance.field(index) {
+          return (Property::Field(value), true);
+        }
+      }
+      InlineCache::Method {
+        class: cached,
+        fields,
+        method,
+      } if cached == instance.class && fields == class.layout.len() => return (Property::Method(method), true),
+      _ => (),
+    }
+
+    let method = class.methods.get(&name).map_or(Property::Missing, |method| Property::Method(*method));
+    let (property, cache) = match class.layout.get(&name) {
+      Some(&index) => {
+        let cache = InlineCache::Field {
+          class: instance.class,
+          index,
+        };
+        (instance.field(index).map_or(method, Property::Field), cache)
+      }
+      None => match method {
+        Property::Method(method) => {
+          let cache = InlineCache::Method {
+            class: instance.class,
+            fields: class.layout.len(),
+            method,
+          };
+          (Property::Method(method), cache)
+        }
+        _ => (Property::Missing, InlineCache::Empty),
+      },
+    };
+
+    if cache != InlineCache::Empty {
+      self.set_cache(offset, cache);
+    }
+
+    (property, false)
+  }
+
+  // the index of the field in instances of the class, from the cache of the instruction at
+  // offset when it's seen the class before. A name no instance of the class had yet is added
+  // to its layout
+  fn field_index(&mut self, offset: usize, class: ObjRef, name: ObjRef) -> usize {
+    if let InlineCache::Field { class: cached, index } = self.cache(offset) {
+      if cached == class {
+        self.stats.set_property.record(true);
+        return index;
+      }
+    }
+
+    self.stats.set_property.record(false);
+    let index = match self.heap.get_mut(class) {
+      Object::Class(class) => {
+        let next = class.layout.len();
+        *class.layout.entry(name).or_insert(next)
+      }
+      object => unreachable!("expected a class, found {:?}", object),
+    };
+    self.set_cache(offset, InlineCache::Field { class, index });
+
+    index
+  }
+
+  fn instance_object(&self, instance: ObjRef) -> &InstanceObject {
+    match self.heap.get(instance) {
+      Object::Instance(instance) => instance,
+      object => unreachable!("expected an instance, found {:?}", object),
+    }
+  }
+
+  fn class_object(&self, class: ObjRef) -> &ClassObject {
+    match self.heap.get(class) {
+      Object::Class(class) => class,
+      object => unreachable!("expected a class, found {:?}", object),
+    }
+  }
+
+  // the upvalue for the slot, shared with every closure that already captured it
+  fn capture_upvalue(&mut self, slot: usize) -> ObjRef {
+    let position = self.open_upvalues.partition_point(|upvalue| self.open_slot(*upvalue) < slot);
+
+    if let Some(upvalue) = self.open_upvalues.get(position) {
+      if self.open_slot(*upvalue) == slot {
+        return *upvalue;
+      }
+    }
+
+    let upvalue = self.allocate(Object::Upvalue(UpvalueObject::Open(slot)));
+    self.open_upvalues.insert(position, upvalue);
+
+    upvalue
+  }
+
+  fn open_slot(&self, upvalue: ObjRef) -> usize {
+    match self.heap.get(upvalue) {
+      Object::Upvalue(UpvalueObject::Open(slot)) => *slot,
+      object => unreachable!("expected an open upvalue, found {:?}", object),
+    }
+  }
+
+  // moves the variables in slots from first up into their upvalues, as the slots are about to go
+  fn close_upvalues(&mut self, first: usize) {
+    let position = self.open_upvalues.partition_point(|upvalue| self.open_slot(*upvalue) < first);
+
+    for upvalue in self.open_upvalues.split_off(position) {
+      let value = self.stack[self.open_slot(upvalue)];
+      *self.heap.get_mut(upvalue) = Object::Upvalue(UpvalueObject::Closed(value));
+    }
+  }
+
+  // the index'th upvalue of the closure running
+  fn upvalue(&self, index: usize) -> ObjRef {
+    let frame = self.frames.last().expect("code only runs inside a call");
+
+    match self.heap.get(frame.closure) {
+      Object::Closure(closure) => closure.upvalues[index],
+      object => unreachable!("calls are always to closures, not {:?}", object),
+    }
+  }
+
+  // the whole stack on one line and the instruction under it, the way clox traces
+  //
+  //             [ <script> ][ 1 ]
+  //   0002    | OP_PRINT
+  fn trace_instruction(&mut self) {
+    let mut stack = String::from("          ");
+
+    for value in &self.stack {
+      stack.push_str(&format!("[ {} ]", self.heap.display(*value)));
+    }
+
+    let (instruction, _) = debug::disassemble_instruction(&self.code.chunk, self.ip);
+    let _ = writeln!(self.out, "{}\n{}", stack, instruction);
+  }
+
+  // runs until the script returns. A runtime error inside a try block goes to its catch clause
+  // as the error's message, with the instructions carrying on from there. exit() never does
+  fn run(&mut self) -> Result<(), RuntimeError> {
+    loop {
+      let error = match self.execute() {
+        Ok(()) => return Ok(()),
+        Err(error) if self.handlers.is_empty() || error.exit.is_some() => return Err(error),
+        Err(error) => error,
+      };
+
+      let message = Value::object(self.allocate_string(error.message));
+      self.catch(message);
+    }
+  }
+
+  // leaves every call and everything on the stack since the innermost try started, closing the
+  // upvalues pointing into it, and goes to its catch clause with the exception
+  fn catch(&mut self, exception: Value) {
+    let handler = self.handlers.pop().expect("only caught inside a try");
+    self.close_upvalues(handler.stack);
+    self.frames.truncate(handler.frames);
+    self.stack.truncate(handler.stack);
+
+    self.code = Rc::clone(&self.frame().code);
+    self.ip = handler.catch;
+    self.push(exception);
+  }
+
+  // one match on the opcode, which compiles to a jump table. Reading an instruction used to find
+  // the last frame and follow it to the code for every byte, with the running call's code and
+  // ip kept on the vm `cargo bench --bench vm` went from 59ms to 41ms on fib, 719ms to 577ms on
+  // equality and 623ms to 472ms on string_equality. Keeping the slots there as well made no
+  // difference, neither did leaving out the check for tracing
+  fn execute(&mut self) -> Result<(), RuntimeError> {
+    loop {
+      if self.trace {
+        self.trace_instruction();
+      }
+
+      let byte = self.read_byte();
+      let op = OpCode::try_from(byte).unwrap_or_else(|byte| unreachable!("{} is not an instruction", byte));
+
+      match op {
+        OpCode::Constant | OpCode::ConstantLong => {
+          let constant = self.read_constant(op == OpCode::ConstantLong);
+          self.push(constant);
+        }
+        OpCode::Nil => self.push(Value::nil()),
+        OpCode::True => self.push(Value::bool(true)),
+        OpCode::False => self.push(Value::bool(false)),
+        OpCode::Pop => {
+          self.pop();
+        }
+        OpCode::Dup => {
+          let count = self.read_byte() as usize;
+          let start = self.stack.len() - count;
+          self.stack.extend_from_within(start..);
+        }
+        OpCode::GetLocal => {
+          let slot = self.frame().slots + self.read_byte() as usize;
+          self.push(self.stack[slot]);
+        }
+        OpCode::SetLocal => {
+          let slot = self.frame().slots + self.read_byte() as usize;
+          self.stack[slot] = self.peek(0);
+        }
+        OpCode::GetGlobal | OpCode::GetGlobalLong => {
+          let offset = self.ip - 1;
+          let name = self.read_name(op == OpCode::GetGlobalLong);
+          let slot = self.cached_global_slot(offset, name);
+
+          match self.global_values[slot] {
+            Some(value) => self.push(value),
+            None => return Err(self.error(format!("Undefined variable '{}'.", self.heap.string(name)))),
+          }
+        }
+        // redefining a global replaces it, as Lox allows at the top level
+        OpCode::DefineGlobal | OpCode::DefineGlobalLong => {
+          let offset = self.ip - 1;
+          let name = self.read_name(op == OpCode::DefineGlobalLong);
+          let slot = self.cached_global_slot(offset, name);
+          self.global_values[slot] = Some(self.pop());
+        }
+        // assigning never creates a variable
+        OpCode::SetGlobal | OpCode::SetGlobalLong => {
+          let offset = self.ip - 1;
+          let name = self.read_name(op == OpCode::SetGlobalLong);
+          let slot = self.cached_global_slot(offset, name);
+          let value = self.peek(0);
+
+          match &mut self.global_values[slot] {
+            Some(global) => *global = value,
+            None => return Err(self.error(format!("Undefined variable '{}'.", self.heap.string(name)))),
+          }
+        }
+        OpCode::Equal => {
+          let right = self.pop();
+          let left = self.pop();
+          self.push(Value::bool(left == right));
+        }
+        OpCode::Greater => {
+          let (left, right) = self.number_operands()?;
+          self.push(Value::bool(left > right));
+        }
+        OpCode::GreaterEqual => {
+          let (left, right) = self.number_operands()?;
+          self.push(Value::bool(left >= right));
+        }
+        OpCode::Less => {
+          let (left, right) = self.number_operands()?;
+          self.push(Value::bool(left < right));
+        }
+        OpCode::LessEqual => {
+          let (left, right) = self.number_operands()?;
+          self.push(Value::bool(left <= right));
+        }
+        // the operands stay on the stack while the result is allocated
+        OpCode::Add => self.add()?,
+        OpCode::AddLocals => {
+          let slots = self.frame().slots;
+          let (left, right) = (self.read_byte() as usize, self.read_byte() as usize);
+          self.push(self.stack[slots + left]);
+          self.push(self.stack[slots + right]);
+          self.add()?;
+        }
+        OpCode::Subtract => {
+          let (left, right) = self.number_operands()?;
+          self.push(Value::number(left - right));
+        }
+        OpCode::Multiply => {
+          let (left, right) = self.number_operands()?;
+          self.push(Value::number(left * right));
+        }
+        OpCode::Divide => {
+          let (left, right) = self.number_operands()?;
+          self.push(Value::number(left / right));
+        }
+        OpCode::Remainder => {
+          let (left, right) = self.number_operands()?;
+          self.push(Value::number(left % right));
+        }
+        OpCode::BitAnd => {
+          let (left, right) = self.number_operands()?;
+          self.push(Value::number((integer(left) & integer(right)) as f64));
+        }
+        OpCode::BitOr => {
+          let (left, right) = self.number_operands()?;
+          self.push(Value::number((integer(left) | integer(right)) as f64));
+        }
+        OpCode::BitXor => {
+          let (left, right) = self.number_operands()?;
+          self.push(Value::number((integer(left) ^ integer(right)) as f64));
+        }
+        // shift counts wrap at 64 the way the hardware does
+        OpCode::ShiftLeft => {
+          let (left, right) = self.number_operands()?;
+          self.push(Value::number(integer(left).wrapping_shl(integer(right) as u32) as f64));
+        }
+        OpCode::ShiftRight => {
+          let (left, right) = self.number_operands()?;
+          self.push(Value::number(integer(left).wrapping_shr(integer(right) as u32) as f64));
+        }
+        OpCode::Not => {
+          let value = self.pop();
+          self.push(Value::bool(!value.is_truthy()));
+        }
+        OpCode::Negate => {
+          let value = self.number_operand()?;
+          self.push(Value::number(-value));
+        }
+        OpCode::BitNot => {
+          let value = self.number_operand()?;
+          self.push(Value::number(!integer(value) as f64));
+        }
+        // every part is stringified the way print would show it
+        OpCode::Interpolate => {
+          let count = self.read_byte() as usize;
+          let parts = self.stack.len() - count;
+          let text = self.stack[parts..]
+            .iter()
+            .map(|part| self.heap.display(*part).to_string())
+            .collect::<String>();
+          let value = Value::object(self.allocate_string(text));
+          self.stack.truncate(parts);
+          self.push(value);
+        }
+        OpCode::Print => {
+          let value = self.pop();
+          // a closed stdout is no reason to stop the script
+          let _ = writeln!(self.out, "{}", self.heap.display(value));
+        }
+        OpCode::Jump => {
+          let distance = self.read_short() as usize;
+          self.ip += distance;
+        }
+        OpCode::JumpIfFalse => {
+          let distance = self.read_short() as usize;
+
+          if !self.peek(0).is_truthy() {
+            self.ip += distance;
+          }
+        }
+        OpCode::JumpIfNotNil => {
+          let distance = self.read_short() as usize;
+
+          if self.peek(0).kind() != ValueKind::Nil {
+            self.ip += distance;
+          }
+        }
+        OpCode::Case => {
+          let distance = self.read_short() as usize;
+          let candidate = self.pop();
+
+          if self.peek(0) == candidate {
+            self.pop();
+            self.ip += distance;
+          }
+        }
+        OpCode::Loop => {
+          let distance = self.read_short() as usize;
+          self.ip -= distance;
+        }
+        OpCode::Call => {
+          let argument_count = self.read_byte() as usize;
+          self.call_value(argument_count)?;
+        }
+        // the closure goes on the stack before it captures anything, so it's a root while the
+        // upvalues are allocated
+        OpCode::Closure | OpCode::ClosureLong => {
+          let function = match self.read_constant(op == OpCode::ClosureLong).kind() {
+            ValueKind::Object(function) => function,
+            value => unreachable!("expected a function, found {:?}", value),
+          };
+          let upvalue_count = match self.heap.get(function) {
+            Object::Function(function) => function.function.upvalue_count,
+            object => unreachable!("expected a function, found {:?}", object),
+          };
+          let module = self.frame().module;
+          let closure = self.allocate_closure(function, module);
+          self.push(Value::object(closure));
+
+          for _ in 0..upvalue_count {
+            let is_local = self.read_byte() == 1;
+            let index = self.read_byte() as usize;
+            let upvalue = if is_local {
+              let slot = self.frame().slots + index;
+              self.capture_upvalue(slot)
+            } else {
+              self.upvalue(index)
+            };
+
+            if let Object::Closure(closure) = self.heap.get_mut(closure) {
+              closure.upvalues.push(upvalue);
+            }
+          }
+        }
+        OpCode::GetUpvalue => {
+          let index = self.read_byte() as usize;
+          let upvalue = self.upvalue(index);
+          let value = match self.heap.get(upvalue) {
+            Object::Upvalue(UpvalueObject::Open(slot)) => self.stack[*slot],
+            Object::Upvalue(UpvalueObject::Closed(value)) => *value,
+            object => unreachable!("expected an upvalue, found {:?}", object),
+          };
+          self.push(value);
+        }
+        OpCode::SetUpvalue => {
+          let index = self.read_byte() as usize;
+          let upvalue = self.upvalue(index);
+          let value = self.peek(0);
+
+          match self.heap.get_mut(upvalue) {
+            Object::Upvalue(UpvalueObject::Open(slot)) => {
+              let slot = *slot;
+              self.stack[slot] = value;
+            }
+            Object::Upvalue(UpvalueObject::Closed(closed)) => *closed = value,
+            object => unreachable!("expected an upvalue, found {:?}", object),
+          }
+        }
+        OpCode::GetProperty | OpCode::GetPropertyLong => {
+          let offset = self.ip - 1;
+          let name = self.read_name(op == OpCode::GetPropertyLong);
+          let instance = match self.instance(self.peek(0)) {
+            Some(instance) => instance,
+            None => return Err(self.error("Only instances have properties.")),
+          };
+          let (property, hit) = self.property(offset, instance, name);
+          self.stats.get_property.record(hit);
+
+          match property {
+            Property::Field(value) => {
+              self.pop();
+              self.push(value);
+            }
+            Property::Method(method) => self.bind_method(method),
+            Property::Missing => return Err(self.undefined_property(name)),
+          }
+        }
+        OpCode::SetProperty | OpCode::SetPropertyLong => {
+          let offset = self.ip - 1;
+          let name = self.read_name(op == OpCode::SetPropertyLong);
+          let instance = match self.instance(self.peek(1)) {
+            Some(instance) => instance,
+            None => return Err(self.error("Only instances have fields.")),
+          };
+          let class = self.instance_object(instance).class;
+          let index = self.field_index(offset, class, name);
+          let value = self.pop();
+
+          if let Object::Instance(instance) = self.heap.get_mut(instance) {
+            instance.set_field(index, value);
+          }
+
+          self.pop();
+          self.push(value);
+        }
+        // the superclass stays reachable through the variable it was loaded from
+        OpCode::GetSuper | OpCode::GetSuperLong => {
+          let name = self.read_name(op == OpCode::GetSuperLong);
+          let superclass = Self::object_of(self.pop());
+          let method = self.method(superclass, name)?;
+          self.bind_method(method);
+        }
+        OpCode::Invoke | OpCode::InvokeLong => {
+          let offset = self.ip - 1;
+          let name = self.read_name(op == OpCode::InvokeLong);
+          let argument_count = self.read_byte() as usize;
+          self.invoke(offset, name, argument_count)?;
+        }
+        OpCode::SuperInvoke | OpCode::SuperInvokeLong => {
+          let name = self.read_name(op == OpCode::SuperInvokeLong);
+          let argument_count = self.read_byte() as usize;
+          let superclass = Self::object_of(self.pop());
+          self.invoke_from_class(superclass, name, argument_count)?;
+        }
+        OpCode::CloseUpvalue => {
+          self.close_upvalues(self.stack.len() - 1);
+          self.pop();
+        }
+        // the call's slots, the callee among them, are replaced by its result. Any of them
+        // closures captured are closed first
+        OpCode::Return => {
+          let result = self.pop();
+          let frame = self.frames.pop().expect("returns only happen inside a call");
+          self.close_upvalues(frame.slots);
+          self.stack.truncate(frame.slots);
+          // a return from inside a try leaves it
+          while self.handlers.last().is_some_and(|handler| handler.frames > self.frames.len()) {
+            self.handlers.pop();
+          }
+
+          let Some(caller) = self.frames.last() else {
+            return Ok(());
+          };
+          self.code = Rc::clone(&caller.code);
+          self.ip = caller.ip;
+          self.push(result);
+        }
+        // modules are told apart by their function's name, which is their file's
+        OpCode::Import | OpCode::ImportLong => {
+          let function = Self::object_of(self.read_constant(op == OpCode::ImportLong));
+          let name = match self.heap.get(function) {
+            Object::Function(function) => function.function.name.clone(),
+            object => unreachable!("expected a function, found {:?}", object),
+          };
+
+          match self.modules.get(&name) {
+            Some(module) => {
+              self.push(Value::number(*module as f64));
+              self.push(Value::nil());
+            }
+            None => {
+              let module = self.new_module();
+              self.modules.insert(name, module);
+              self.push(Value::number(module as f64));
+              let closure = self.allocate_closure(function, module);
+              self.push(Value::object(closure));
+              self.call(closure, 0)?;
+            }
+          }
+        }
+        OpCode::ImportName | OpCode::ImportNameLong => {
+          let name = self.read_name(op == OpCode::ImportNameLong);
+          let module = match self.peek(0).kind() {
+            ValueKind::Number(module) => module as usize,
+            value => unreachable!("expected a module number, found {:?}", value),
+          };
+          let value = self.globals[module].get(&name).and_then(|slot| self.global_values[*slot]);
+          let slot = self.global_slot(name);
+          self.global_values[slot] = Some(value.unwrap_or(Value::nil()));
+        }
+        OpCode::Class | OpCode::ClassLong => {
+          let name = self.read_name(op == OpCode::ClassLong);
+          let class = self.allocate(Object::Class(ClassObject {
+            name,
+            methods: HashMap::new(),
+            layout: HashMap::new(),
+          }));
+          self.push(Value::object(class));
+        }
+        OpCode::Inherit => {
+          let methods = match self.peek(1).kind() {
+            ValueKind::Object(object) => match self.heap.get(object) {
+              Object::Class(superclass) => superclass.methods.clone(),
+              _ => return Err(self.error("Superclass must be a class.")),
+            },
+            _ => return Err(self.error("Superclass must be a class.")),
+          };
+          let subclass = Self::object_of(self.pop());
+
+          if let Object::Class(subclass) = self.heap.get_mut(subclass) {
+            subclass.methods.extend(methods);
+          }
+        }
+        OpCode::Method | OpCode::MethodLong => {
+          let name = self.read_name(op == OpCode::MethodLong);
+          let method = self.peek(0);
+          let class = Self::object_of(self.peek(1));
+
+          if let Object::Class(class) = self.heap.get_mut(class) {
+            class.methods.insert(name, method);
+          }
+
+          self.pop();
+        }
+        // the elements stay on the stack while the list is allocated
+        OpCode::BuildList => {
+          let count = self.read_short() as usize;
+          let elements = self.stack.len() - count;
+          let list = self.allocate(Object::List(self.stack[elements..].to_vec()));
+          self.stack.truncate(elements);
+          self.push(Value::object(list));
+        }
+        // the keys and values stay on the stack while the map is allocated
+        OpCode::BuildMap => {
+          let count = self.read_short() as usize;
+          let entries = self.stack.len() - 2 * count;
+          let mut map = MapObject::new();
+
+          for entry in self.stack[entries..].chunks(2) {
+            map.set(self.map_key(entry[0])?, entry[1]);
+          }
+
+          let map = self.allocate(Object::Map(map));
+          self.stack.truncate(entries);
+          self.push(Value::object(map));
+        }
+        OpCode::GetIndex => {
+          let (object, index) = (self.peek(1), self.peek(0));
+          let element = match (self.heap.as_list(object), self.heap.as_map(object)) {
+            (Some(list), _) => lists::get(list, self.list_index(index)?).map_err(|message| self.error(message))?,
+            (_, Some(map)) => match map.get(&self.map_key(index)?) {
+              Some(value) => *value,
+              None => return Err(self.error(maps::undefined_key(self.heap.display(index)))),
+            },
+            _ => return Err(self.error("Only lists and maps can be indexed.")),
+          };
+          self.pop();
+          self.pop();
+          self.push(element);
+        }
+        OpCode::SetIndex => {
+          let (object, index, value) = (self.peek(2), self.peek(1), self.peek(0));
+
+          if self.heap.as_list(object).is_some() {
+            let index = self.list_index(index)?;
+
+            if let Err(message) = lists::set(self.heap.as_list_mut(object).expect("checked above"), index, value) {
+              return Err(self.error(message));
+            }
+          } else if self.heap.as_map(object).is_some() {
+            let key = self.map_key(index)?;
+            self.heap.as_map_mut(object).expect("checked above").set(key, value);
+          } else {
+            return Err(self.error("Only lists and maps can be indexed."));
+          }
+
+          self.stack.truncate(self.stack.len() - 3);
+          self.push(value);
+        }
+        // the list and its bounds stay on the stack while the slice is allocated
+        OpCode::Slice => {
+          if self.heap.as_list(self.peek(2)).is_none() {
+            return Err(self.error("Only lists can be sliced."));
+          }
+          let (start, end) = (self.slice_bound(1)?, self.slice_bound(0)?);
+          let list = self.heap.as_list(self.peek(2)).unwrap_or_default();
+          let elements = lists::slice(list, start, end).map_err(|message| self.error(message))?;
+          let slice = self.allocate(Object::List(elements));
+          self.stack.truncate(self.stack.len() - 3);
+          self.push(Value::object(slice));
+        }
+        OpCode::Try => {
+          let distance = self.read_short() as usize;
+          self.handlers.push(Handler {
+            frames: self.frames.len(),
+            stack: self.stack.len(),
+            catch: self.ip + distance,
+          });
+        }
+        OpCode::EndTry => {
+          self.handlers.pop();
+        }
+        OpCode::Throw => {
+          let exception = self.pop();
+
+          if self.handlers.is_empty() {
+            return Err(self.error(format!("Uncaught exception: {}", self.heap.display(exception))));
+          }
+
+          self.catch(exception);
+        }
+        // whether a for-in loop has an element left at its index
+        OpCode::HasElement => {
+          let Some(list) = self.heap.as_list(self.peek(1)) else {
+            return Err(self.error("Can only iterate over lists."));
+          };
+          let more = matches!(self.peek(0).kind(), ValueKind::Number(index) if index < list.len() as f64);
+          self.pop();
+          self.pop();
+          self.push(Value::bool(more));
+        }
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::cell::RefCell;
+
+  use super::*;
+  use crate::compiler::Compiler;
+  use crate::lexer::{Lexer, SourceMap};
+  use crate::modules::{load_imports, Sources};
+  use crate::parser::Parser;
+
+  // collects what print writes so tests can look at it
+  #[derive(Clone, Default)]
+  struct Output(Rc<RefCell<Vec<u8>>>);
+
+  impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+      self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+      Ok(())
+    }
+  }
+
+  fn compile(code: &str) -> Function {
+    let ast = Parser::new(Lexer::new(code).lex().unwrap()).parse().unwrap();
+
+    Compiler::new(&SourceMap::new(code)).compile(&ast).unwrap()
+  }
+
+  fn run_with_gc(code: &str, stress: bool, generational: bool) -> Result<String, String> {
+    let output = Output::default();
+    let mut vm = Vm::with_output(Box::new(output.clone()));
+    vm.set_stress_gc(stress);
+    vm.set_generational_gc(generational);
+    let result = vm.interpret(compile(code));
+    let printed = String::from_utf8(output.0.take()).unwrap();
+
+    result.map(|_| printed).map_err(|error| error.to_string())
+  }
+
+  // every program is run again collecting at every allocation, with and without generations,
+  // which has to make no difference to what it does
+  fn run(code: &str) -> Result<String, String> {
+    let result = run_with_gc(code, false, false);
+    assert_eq!(run_with_gc(code, true, false), result);
+    assert_eq!(run_with_gc(code, true, true), result);
+
+    result
+  }
+
+  #[test]
+  fn expressions() {
+    assert_eq!(run("print 1 + 2 * 3 - 4 / 2;"), Ok(String::from("5\n")));
+    assert_eq!(run("print 1 < 2 == !(2 <= 1);"), Ok(String::from("true\n")));
+    assert_eq!(run("print \"foo\" + \"bar\";"), Ok(String::from("foobar\n")));
+    assert_eq!(run("print 1 << 4 | 1 ^ 3;"), Ok(String::from("18\n")));
+    assert_eq!(run("print \"1 + 1 = ${1 + 1}, ${nil}\";"), Ok(String::from("1 + 1 = 2, nil\n")));
+    assert_eq!(run("print nil or \"default\"; print false and -nil;"), Ok(String::from("default\nfalse\n")));
+    assert_eq!(run("print nil ?? \"default\"; print false ?? -nil; var a; print a ?? a ?? 0;"), Ok(String::from("default\nfalse\n0\n")));
+  }
+
+  #[test]
+  fn bitwise_operators_truncate_fractions_towards_zero() {
+    let code = "print -2.7 & -1; print 5.9 >> 0.9; print ~2.5; print 0.5 | 0.5; print (0 / 0) | 1;";
+    assert_eq!(run(code), Ok(String::from("-2\n5\n-3\n0\n1\n")));
+    assert_eq!(run("print \"1\" & 1;"), Err(String::from("Operands must be numbers.")));
+    assert_eq!(run("print ~nil;"), Err(String::from("Operand must be a number.")));
+  }
+
+  #[test]
+  fn remainder_takes_the_sign_of_the_dividend() {
+    assert_eq!(run("print -7 % 3; print 7 % -3; print -7 % -3;"), Ok(String::from("-1\n1\n-1\n")));
+    assert_eq!(run("print 5.5 % 2; print 1 % 0;"), Ok(String::from("1.5\nNaN\n")));
+    assert_eq!(run("print \"7\" % 3;"), Err(String::from("Operands must be numbers.")));
+  }
+
+  #[test]
+  fn variables_and_scopes() {
+    let code = "var a = \"global\"; var b = 1; { var a = \"inner\"; b = 2; print a; } print a; print b;";
+
+    assert_eq!(run(code), Ok(String::from("inner\nglobal\n2\n")));
+  }
+
+  #[test]
+  fn control_flow() {
+    assert_eq!(run("if (nil) print 1; else print 2; if (0) print 3;"), Ok(String::from("2\n3\n")));
+
+    let code = "var a = 0; var b = 1; for (var i = 0; i < 10; i += 1) { var t = a; a = b; b += t; } print a;";
+    assert_eq!(run(code), Ok(String::from("55\n")));
+  }
+
+  #[test]
+  fn switches() {
+    let code = "fun name(n) { switch (n) { case 1: return \"one\"; case 2, 3: return \"few\"; default: return \"many\"; } }
+      print name(1); print name(3); print name(7);
+      switch (\"b\") { case \"a\": print \"a\"; case \"b\": var x = \"bee\"; print x; case \"b\": print \"again\"; }
+      switch (nil) { default: print \"default\"; case nil: print \"nil\"; } switch (1) { case 2: print 2; }";
+    assert_eq!(run(code), Ok(String::from("one\nfew\nmany\nbee\nnil\n")));
+
+    // the values after the one that matched aren't evaluated, a break or continue is the loop's
+    let code = "fun two() { print \"two\"; return 2; } switch (1) { case 1: print 1; case two(): print 2; }
+      for (var i = 0; i < 5; i += 1) { var a = i; switch (a) { case 1: continue; case 3: var b = a; break; } print a; }";
+    assert_eq!(run(code), Ok(String::from("1\n0\n2\n")));
+  }
+
+  #[test]
+  fn break_and_continue() {
+    let code = "for (var i = 0; i < 6; i += 1) { if (i == 1) continue; if (i == 4) break; print i; }
+      var n = 0; while (true) { n += 1; if (n < 3) continue; break; } print n;
+      for (var x in [1, 2, 3, 4]) { if (x == 2) continue; if (x == 4) break; print x; }";
+    assert_eq!(run(code), Ok(String::from("0\n2\n3\n3\n1\n3\n")));
+
+    // only the innermost loop is left, with the locals declared in it gone
+    let code = "fun f() { var total = 0; for (var i = 0; i < 3; i += 1) { var a = i; for (var j = 0; j < 3; j += 1) {
+      var b = a * 10 + j; if (j == 2) break; { var c = b; if (c % 2 == 1) continue; total += c; } } } var after = total; return after; }
+      print f();";
+    assert_eq!(run(code), Ok(String::from("30\n")));
+
+    // closures keep the variable of the time round they were made in
+    let code = "var fs = []; for (var x in [1, 2, 3]) { fun f() { return x; } push(fs, f); if (x == 2) continue; }
+      var i = 0; while (i < 3) { var j = i; fun g() { return j; } push(fs, g); i += 1; if (j == 1) break; }
+      for (var f in fs) print f();";
+    assert_eq!(run(code), Ok(String::from("1\n2\n3\n0\n1\n")));
+
+    // a try left by break or continue doesn't catch what's thrown after it
+    let code = "for (var i = 0; i < 2; i += 1) { try { if (i == 0) continue; break; } catch (e) { print \"stale\"; } }
+      throw \"after\";";
+    assert_eq!(run(code), Err(String::from("Uncaught exception: after")));
+  }
+
+  #[test]
+  fn functions() {
+    let code = "fun fib(n) { if (n < 2) return n; return fib(n - 1) + fib(n - 2); } print fib(10);";
+    assert_eq!(run(code), Ok(String::from("55\n")));
+
+    let code = "fun greet(name) { print \"hi ${name}\"; } print greet(\"bob\"); print greet; print clock;";
+    assert_eq!(run(code), Ok(String::from("hi bob\nnil\n<fn greet>\n<native fn>\n")));
+
+    let code = "fun outer() { fun inner(a) { return a * 2; } return inner(21); } print outer();";
+    assert_eq!(run(code), Ok(String::from("42\n")));
+  }
+
+  // the benchmark from the book, timed with clock the way it is there
+  #[test]
+  fn natives() {
+    let code = "fun fib(n) { if (n < 2) return n; return fib(n - 2) + fib(n - 1); }
+      var start = clock(); print fib(15) == 610; var elapsed = clock() - start; print elapsed >= 0 and start > 0;";
+    assert_eq!(run(code), Ok(String::from("true\ntrue\n")));
+    assert_eq!(run("clock(1);"), Err(String::from("Expected 0 arguments but got 1.")));
+
+    let code = "var s = \"Hello, World\"; print len(s); print substring(s, 7, len(s)); print indexOf(s, \"o\");
+      print indexOf(s, \"x\"); print toUpper(s); print toLower(s);";
+    assert_eq!(run(code), Ok(String::from("12\nWorld\n4\n-1\nHELLO, WORLD\nhello, world\n")));
+    assert_eq!(run("len(1);"), Err(String::from("Argument to len() must be a string or a list.")));
+
+    let code = "print sqrt(16); print abs(-2.5); print floor(1.7); print ceil(1.2); print pow(2, 10);
+      print min(3, -1); print max(3, -1); print floor(PI * 100); print floor(E * 100);";
+    assert_eq!(run(code), Ok(String::from("4\n2.5\n1\n2\n1024\n-1\n3\n314\n271\n")));
+    assert_eq!(run("sqrt(\"4\");"), Err(String::from("Argument to sqrt() must be a number.")));
+    assert_eq!(run("pow(2, nil);"), Err(String::from("Arguments to pow() must be numbers.")));
+
+    let code = "seedRandom(42); var a = random(); var b = randomInt(1, 6); seedRandom(42);
+      print a == random() and b == randomInt(1, 6); print a >= 0 and a < 1 and b >= 1 and b <= 6 and b == floor(b);";
+    assert_eq!(run(code), Ok(String::from("true\ntrue\n")));
+    let code = "print str(1.5) + str(nil) + str(clock); print num(\" 42 \") + 1; print num(\"4x\");
+      print chr(72) + chr(105); print ord(\"A\");";
+    assert_eq!(run(code), Ok(String::from("1.5nil<native fn>\n43\nnil\nHi\n65\n")));
+    assert_eq!(run("ord(\"ab\");"), Err(String::from("Argument to ord() must be a string of one character.")));
+    assert_eq!(run("chr(-1);"), Err(String::from("Argument to chr() must be a character code.")));
+
+    let code = "class Point { m() {} } fun f() {} var p = Point();
+      print type(1) + type(\"a\") + type(true) + type(nil); print type(f) + type(clock) + type(p.m);
+      print type(Point) + type(p);";
+    assert_eq!(run(code), Ok(String::from("numberstringboolnil\nfunctionfunctionfunction\nclassPoint\n")));
+
+    assert_eq!(run("assert(1 < 2, \"unused\"); print \"ok\";"), Ok(String::from("ok\n")));
+    assert_eq!(run("var x = 2; assert(x == 1, x);"), Err(String::from("Assertion failed: 2")));
+
+    let code = "print format(\"x = {}, y = {:.2}\", 1, 2); print format(\"{1}{0}{{}}\", nil, [1]) + format(\"\");";
+    assert_eq!(run(code), Ok(String::from("x = 1, y = 2.00\n[1]nil{}\n")));
+    assert_eq!(run("format(1);"), Err(String::from("First argument to format() must be a string.")));
+    assert_eq!(run("format();"), Err(String::from("First argument to format() must be a string.")));
+    assert_eq!(run("format(\"{} {}\", 1);"), Err(String::from("Format string needs at least 2 arguments but got 1.")));
+    assert_eq!(run("format(\"{:.1}\", \"a\");"), Err(String::from("Only numbers can be formatted with a precision.")));
+
+    let code = "var data = jsonParse(\"{\\\"a\\\": [1, true, null], \\\"b\\\": {\\\"c\\\": \\\"d\\\"}}\"); print data[\"a\"][0] + 1;
+      print data[\"b\"][\"c\"]; print data; print jsonStringify(data); print jsonStringify({1: [], \"x\": 0.5}, true);";
+    let printed = "2\nd\n{a: [1, true, nil], b: {c: d}}\n{\"a\":[1,true,null],\"b\":{\"c\":\"d\"}}\n{\n  \"1\": [],\n  \"x\": 0.5\n}\n";
+    assert_eq!(run(code), Ok(String::from(printed)));
+    assert_eq!(run("jsonParse(\"[1,\");"), Err(String::from("Invalid JSON: expected a value at the end.")));
+    assert_eq!(run("var l = [1]; push(l, l); jsonStringify(l);"), Err(String::from("Can't convert a list or map that contains itself to JSON.")));
+    assert_eq!(run("jsonStringify(clock);"), Err(String::from("Only nil, bools, numbers, strings, lists and maps can be converted to JSON.")));
+    assert_eq!(run("jsonStringify(1, 2);"), Err(String::from("Second argument to jsonStringify() must be a bool.")));
+    assert_eq!(run("jsonStringify();"), Err(String::from("Expected 1 or 2 arguments but got 0.")));
+    assert_eq!(run("jsonStringify([1, 1/0]);"), Err(String::from("Can't convert infinity or NaN to JSON.")));
+    assert_eq!(run("jsonStringify(0/0);"), Err(String::from("Can't convert infinity or NaN to JSON.")));
+
+    let nested = |depth| {
+      format!("var l = []; for (var i = 1; i < {}; i += 1) l = [l]; print len(jsonStringify(l));", depth)
+    };
+    assert_eq!(run(&nested(512)), Ok(String::from("1024\n")));
+    let too_deep = "Can't convert lists and maps nested more than 512 deep to JSON.";
+    assert_eq!(run(&nested(513)), Err(String::from(too_deep)));
+
+    crate::system::set_args(vec![String::from("-v"), String::from("in.txt")]);
+    let code = "print args(); print len(args()[1]); print getEnv(\"RSLOX_NOT_A_VARIABLE\"); print type(platform());";
+    assert_eq!(run(code), Ok(String::from("[-v, in.txt]\n6\nnil\nstring\n")));
+    assert_eq!(run("getEnv(1);"), Err(String::from("Argument to getEnv() must be a string.")));
+
+    // exit() goes past the try it's called in, an invalid status is an error like any other
+    let code = "fun f() { try { exit(3); } catch (e) { print \"caught\"; } } f(); print \"after\";";
+    assert_eq!(run(code), Err(String::from("Exited with status 3.")));
+    let code = "try { exit(-1); } catch (e) { print e; }";
+    assert_eq!(run(code), Ok(String::from("Argument to exit() must be a whole number from 0 to 255.\n")));
+
+    assert_eq!(
+      run("randomInt(2, 1);"),
+      Err(String::from("Arguments to randomInt() must be whole numbers, the first no greater than the second."))
+    );
+    assert_eq!(
+      run("substring(\"abc\", 2, 1);"),
+      Err(String::from("Substring range 2..1 is out of bounds for a string of length 3."))
+    );
+  }
+
+  #[test]
+  fn math_natives() {
+    let code = "print floor(1.7); print floor(-1.5); print ceil(1.2); print ceil(-1.5); print sqrt(2.25);
+      print sqrt(-1); print pow(2, 10); print pow(2, -1); print pow(9, 0.5); print abs(-2.5); print abs(3);
+      print min(3, -1); print max(3, -1); print min(2, 2);";
+    let printed = "1\n-2\n2\n-1\n1.5\nNaN\n1024\n0.5\n3\n2.5\n3\n-1\n3\n2\n";
+    assert_eq!(run(code), Ok(String::from(printed)));
+
+    for name in ["floor", "ceil", "sqrt", "abs"] {
+      let error = format!("Argument to {}() must be a number.", name);
+      assert_eq!(run(&format!("{}(\"1\");", name)), Err(error.clone()));
+      assert_eq!(run(&format!("{}(nil);", name)), Err(error));
+      assert_eq!(run(&format!("{}();", name)), Err(String::from("Expected 1 arguments but got 0.")));
+      assert_eq!(run(&format!("{}(1, 2);", name)), Err(String::from("Expected 1 arguments but got 2.")));
+    }
+
+    for name in ["pow", "min", "max"] {
+      let error = format!("Arguments to {}() must be numbers.", name);
+      assert_eq!(run(&format!("{}(\"1\", 2);", name)), Err(error.clone()));
+      assert_eq!(run(&format!("{}(1, true);", name)), Err(error));
+      assert_eq!(run(&format!("{}(1);", name)), Err(String::from("Expected 2 arguments but got 1.")));
+      assert_eq!(run(&format!("{}(1, 2, 3);", name)), Err(String::from("Expected 2 arguments but got 3.")));
+    }
+  }
+
+  #[test]
+  fn type_native() {
+    let code = "print type(1.5); print type(\"a\"); print type(\"\"); print type(false); print type(nil);
+      print type([1]); print type({\"a\": 1});";
+    assert_eq!(run(code), Ok(String::from("number\nstring\nstring\nbool\nnil\nlist\nmap\n")));
+
+    // closures, natives, lambdas and bound methods are all functions
+    let code = "fun f() { var x = 1; fun g() { return x; } return g; } class A { m() {} }
+      print type(f); print type(f()); print type(clock); print type(fun () {}); print type(A().m);";
+    assert_eq!(run(code), Ok(String::from("function\nfunction\nfunction\nfunction\nfunction\n")));
+
+    // an instance is named after its own class, not the one it inherits from
+    let code = "class A {} class B < A {} print type(A); print type(B); print type(A()); print type(B());
+      print type(type(A()));";
+    assert_eq!(run(code), Ok(String::from("class\nclass\nA\nB\nstring\n")));
+    assert_eq!(run("type();"), Err(String::from("Expected 1 arguments but got 0.")));
+  }
+
+  #[test]
+  fn assert_native() {
+    let code = "assert(true, \"unused\"); assert(1, nil); assert(\"\", 0); assert([], 0); print \"passed\";";
+    assert_eq!(run(code), Ok(String::from("passed\n")));
+
+    // the message is shown the way print would show it, whatever kind of value it is
+    let code = "print \"before\"; assert(1 + 1 == 3, \"math is broken\"); print \"after\";";
+    assert_eq!(run(code), Err(String::from("Assertion failed: math is broken")));
+    assert_eq!(run("assert(nil, [1, \"a\"]);"), Err(String::from("Assertion failed: [1, a]")));
+    assert_eq!(run("assert(false, nil);"), Err(String::from("Assertion failed: nil")));
+
+    // the message can't be left out
+    assert_eq!(run("assert(false);"), Err(String::from("Expected 2 arguments but got 1.")));
+    assert_eq!(run("assert(true);"), Err(String::from("Expected 2 arguments but got 1.")));
+  }
+
+  #[cfg(feature = "file-io")]
+  #[test]
+  fn file_natives() {
+    let path = std::env::temp_dir().join(format!("rslox-vm-{}.txt", std::process::id()));
+    let path = path.to_str().unwrap();
+    let code = format!("writeFile(\"{0}\", \"a\"); appendFile(\"{0}\", \"b\"); print readFile(\"{0}\");", path);
+
+    assert_eq!(run(&code), Ok(String::from("ab\n")));
+    std::fs::remove_file(path).unwrap();
+    assert_eq!(run("writeFile(\"a\", 1);"), Err(String::from("Arguments to writeFile() must be two strings.")));
+  }
+
+  #[test]
+  fn closures() {
+    let code = "fun counter() { var i = 0; fun count() { i = i + 1; return i; } return count; }
+      var c = counter(); c(); print c(); print counter()();";
+    assert_eq!(run(code), Ok(String::from("2\n1\n")));
+
+    let code = "var get; var set;
+      { var a = \"before\"; fun g() { return a; } fun s(v) { a = v; } get = g; set = s; }
+      set(\"after\"); print get();";
+    assert_eq!(run(code), Ok(String::from("after\n")));
+
+    let code = "fun outer() { var x = \"outer\"; fun middle() { fun inner() { return x; } return inner; } return middle; }
+      print outer()()(); { var a = 1; fun f() { a = 2; } f(); print a; }";
+    assert_eq!(run(code), Ok(String::from("outer\n2\n")));
+  }
+
+  #[test]
+  fn lambdas() {
+    let code = "fun apply(f, x) { return f(x); } var n = 10; var add = fun (x) { return x + n; }; n = 20;
+      print apply(add, 1); print apply(fun (x) { return x * 2; }, 4); print (fun (a, b) { return a - b; })(5, 3); print add;";
+    assert_eq!(run(code), Ok(String::from("21\n8\n2\n<fn <lambda>>\n")));
+
+    let code = "fun counter() { var i = 0; return fun () { i += 1; return i; }; } var c = counter(); c(); print c();
+      { var a = 1; var f = fun () { return a; }; a = 2; print f(); }";
+    assert_eq!(run(code), Ok(String::from("2\n2\n")));
+  }
+
+  #[test]
+  fn lists() {
+    let code = "var a = [1, \"two\", [3]]; print a; print a[1]; print a[2][0]; a[0] = a[0] + 10; print a[0];
+      print len(a); print a[1:]; print a[:1]; print a[:]; print [];";
+    assert_eq!(run(code), Ok(String::from("[1, two, [3]]\ntwo\n3\n11\n3\n[two, [3]]\n[11]\n[11, two, [3]]\n[]\n")));
+
+    let code = "var a = [3]; push(a, 4); insert(a, 0, 1); insert(a, 1, 2); print a; print pop(a); print remove(a, 0);
+      print a; print split(\"a,b\", \",\"); print type(a); print [1] == [1]; var b = a; print a == b;";
+    assert_eq!(run(code), Ok(String::from("[1, 2, 3, 4]\n4\n1\n[2, 3]\n[a, b]\nlist\nfalse\ntrue\n")));
+
+    // each iteration has a variable of its own for closures to capture
+    let code = "var fs = []; for (var x in [1, 2, 3]) { fun f() { return x; } push(fs, f); }
+      for (var f in fs) print f(); var a = [1]; a[0] = a; print a;";
+    assert_eq!(run(code), Ok(String::from("1\n2\n3\n[[...]]\n")));
+
+    assert_eq!(run("1[0];"), Err(String::from("Only lists and maps can be indexed.")));
+    assert_eq!(run("[1][\"a\"];"), Err(String::from("List index must be a number.")));
+    assert_eq!(run("[1][0.5];"), Err(String::from("List index must be a whole number.")));
+    assert_eq!(run("[1][1] = 2;"), Err(String::from("List index 1 is out of bounds for a list of length 1.")));
+    assert_eq!(run("[1][0:\"a\"];"), Err(String::from("Slice bounds must be numbers.")));
+    assert_eq!(run("[1][:2];"), Err(String::from("Slice range 0..2 is out of bounds for a list of length 1.")));
+    assert_eq!(run("for (var x in \"ab\") {}"), Err(String::from("Can only iterate over lists.")));
+    assert_eq!(run("pop([]);"), Err(String::from("Can't pop from an empty list.")));
+  }
+
+  #[test]
+  fn maps() {
+    let code = "var m = {\"a\": 1, 2: [3]}; print m; print m[\"a\"] + m[2][0]; m[\"b\"] = m; m[\"a\"] = 4; print m;
+      print keys(m)[2]; print values(m)[0]; print has(m, 2) and !has(m, \"c\"); print type(m); print {} == {};";
+    assert_eq!(run(code), Ok(String::from("{a: 1, 2: [3]}\n4\n{a: 4, 2: [3], b: {...}}\nb\n4\ntrue\nmap\nfalse\n")));
+
+    let code = "var m = {1: \"one\", -0: \"zero\"}; print delete(m, 1); print delete(m, 1); m[0] = \"nought\"; print m;";
+    assert_eq!(run(code), Ok(String::from("true\nfalse\n{0: nought}\n")));
+
+    assert_eq!(run("print {}[\"a\"];"), Err(String::from("Undefined key 'a'.")));
+    assert_eq!(run("print {nil: 1};"), Err(String::from("Map keys must be strings or numbers.")));
+    assert_eq!(run("var m = {}; m[true] = 1;"), Err(String::from("Map keys must be strings or numbers.")));
+    assert_eq!(run("has({}, [1]);"), Err(String::from("Map keys must be strings or numbers.")));
+    assert_eq!(run("keys([1]);"), Err(String::from("Argument to keys() must be a map.")));
+    assert_eq!(run("print {}[0:1];"), Err(String::from("Only lists can be sliced.")));
+  }
+
+  #[test]
+  fn exceptions() {
+    let code = "try { throw \"oops\"; print \"skipped\"; } catch (e) { print \"caught \" + e; } print \"after\";";
+    assert_eq!(run(code), Ok(String::from("caught oops\nafter\n")));
+
+    // thrown through calls, and runtime errors caught as their message
+    let code = "fun f(n) { if (n == 0) throw {\"code\": 42}; return f(n - 1); } try { f(3); } catch (e) { print e[\"code\"]; }
+      try { var x = 1 + nil; } catch (e) { print e; } try { [1][5]; } catch (e) { print e; }";
+    assert_eq!(
+      run(code),
+      Ok(String::from(
+        "42\nOperands must be two numbers or two strings.\nList index 5 is out of bounds for a list of length 1.\n"
+      ))
+    );
+
+    let code = "fun g() { try { return \"returned\"; } catch (e) {} } print g();
+      try { try { throw 1; } catch (e) { throw e + 1; } } catch (e) { print e; }
+      var h; try { var local = \"captured\"; fun c() { return local; } h = c; throw nil; } catch (e) { print h(); print e; }
+      var a = 1; { var b = 2; try { var c = 3; throw c; } catch (e) { print a + b + e; } var d = 4; print d + b; }";
+    assert_eq!(run(code), Ok(String::from("returned\n2\ncaptured\nnil\n6\n6\n")));
+
+    assert_eq!(run("throw [1, 2];"), Err(String::from("Uncaught exception: [1, 2]")));
+    // the try g returned out of is over by the time of the second throw
+    let code = "fun g() { try { return 1; } catch (e) { print \"wrong\"; } } g(); throw \"late\";";
+    assert_eq!(run(code), Err(String::from("Uncaught exception: late")));
+  }
+
+  #[test]
+  fn compound_assignments_evaluate_their_target_once() {
+    let code = "var calls = 0; var a = [1, 2]; var m = {\"k\": 2};
+      fun i() { calls += 1; return 1; } fun k() { calls += 1; return \"k\"; }
+      class P {} var p = P(); p.x = 1; fun getP() { calls += 1; return p; }
+      a[i()] += 5; m[k()] *= 3; getP().x += 1; print a; print m; print p.x; print calls;";
+    assert_eq!(run(code), Ok(String::from("[1, 7]\n{k: 6}\n2\n3\n")));
+
+    assert_eq!(run("class P {} P().x += 1;"), Err(String::from("Undefined property 'x'.")));
+    let out_of_bounds = "List index 1 is out of bounds for a list of length 1.";
+    assert_eq!(run("var a = [1]; a[1] -= 1;"), Err(String::from(out_of_bounds)));
+    assert_eq!(run("var a = [nil]; a[0] += 1;"), Err(String::from("Operands must be two numbers or two strings.")));
+  }
+
+  #[test]
+  fn classes() {
+    let code = "class A { init(n) { this.n = n; } get() { return this.n; } }
+var a = A(1);
+print a; print A; print a.get;
+a.n = 2;
+print a.get();
+var get = a.get;
+print get();
+print a.init(3).n;";
+
+    assert_eq!(run(code), Ok(String::from("A instance\nA\n<fn get>\n2\n2\n3\n")));
+  }
+
+  #[test]
+  fn fields_shadow_methods() {
+    let code = "class A { f() { return \"method\"; } }
+var a = A();
+fun field() { return \"field\"; }
+a.f = field;
+print a.f();";
+
+    assert_eq!(run(code), Ok(String::from("field\n")));
+  }
+
+  #[test]
+  fn subclasses_inherit_and_call_up_through_super() {
+    let code = "class A { f() { return \"A\"; } g() { return \"g\"; } }
+class B < A {
+  f() { return \"B\" + super.f(); }
+  h() { return super.g; }
+}
+var b = B();
+print b.f(); print b.g(); print b.h()();";
+
+    assert_eq!(run(code), Ok(String::from("BA\ng\ng\n")));
+  }
+
+  #[test]
+  fn repeated_accesses_hit_the_inline_caches() {
+    let mut vm = Vm::with_output(Box::new(io::sink()));
+    let code = "class P { init() { this.x = 0; } f() {} }
+var p = P();
+for (var i = 0; i < 10; i = i + 1) { p.x = p.x + 1; p.f(); }";
+    vm.interpret(compile(code)).unwrap();
+    let stats = vm.vm_stats();
+
+    assert_eq!(stats.get_property, CacheCounts { hits: 9, misses: 1 });
+    // init's this.x and the loop's p.x are separate instructions with a cache each
+    assert_eq!(stats.set_property, CacheCounts { hits: 9, misses: 2 });
+    assert_eq!(stats.invoke, CacheCounts { hits: 9, misses: 1 });
+  }
+
+  #[test]
+  fn caches_notice_when_what_they_remember_changes() {
+    // the same call sees two classes, then a field that shadows the method it cached
+    let code = "class A { f() { return \"A\"; } }
+class B { f() { return \"B\"; } }
+fun call(o) { return o.f(); }
+fun field() { return \"field\"; }
+var a = A();
+print call(a); print call(B()); print call(a);
+a.f = field;
+print call(a);";
+
+    assert_eq!(run(code), Ok(String::from("A\nB\nA\nfield\n")));
+  }
+
+  #[test]
+  fn traces_show_the_stack_before_each_instruction() {
+    let output = Output::default();
+    let mut vm = Vm::with_output(Box::new(output.clone()));
+    vm.set_trace_execution(true);
+    vm.interpret(compile("print 1;")).unwrap();
+
+    assert_eq!(
+      String::from_utf8(output.0.borrow().clone()).unwrap(),
+      "          [ <script> ]
+0000    1 OP_CONSTANT         0 '1'
+          [ <script> ][ 1 ]
+0002    | OP_PRINT
+1
+          [ <script> ]
+0003    | OP_NIL
+          [ <script> ][ nil ]
+0004    | OP_RETURN
+"
+    );
+  }
+
+  #[test]
+  fn long_constants() {
+    let code = (0..300).map(|n| format!("var v{} = {};", n, n)).collect::<String>() + "v299 = v299 + v1; print v299;";
+
+    assert_eq!(run(&code), Ok(String::from("300\n")));
+  }
+
+  #[test]
+  fn unreachable_objects_are_collected() {
+    let mut vm = Vm::with_output(Box::new(io::sink()));
+    vm.set_stress_gc(true);
+    let code = "var s = \"\"; for (var i = 0; i < 100; i += 1) { s = s + \"x${i}\"; } fun f() {}";
+    vm.interpret(compile(code)).unwrap();
+    vm.collect_garbage();
+
+    // the natives and their names, the constants' names, s and f's closure and function, their
+    // names and init are all that's left
+    assert_eq!(vm.heap.object_count(), 2 * natives::natives().len() + natives::CONSTANTS.len() + 6);
+  }
+
+  #[test]
+  fn lists_and_maps_keep_their_elements_alive() {
+    let output = Output::default();
+    let mut vm = Vm::with_output(Box::new(output.clone()));
+    vm.set_stress_gc(true);
+    vm.set_generational_gc(true);
+    let code = "var a = []; var m = {}; for (var i = 0; i < 20; i += 1) { push(a, \"x${i}\"); a = a[:]; m[\"k${i}\"] = \"v${i}\"; }
+      print a[19] + a[0]; print keys(m)[19] + m[\"k0\"];";
+    vm.interpret(compile(code)).unwrap();
+
+    assert_eq!(String::from_utf8(output.0.take()).unwrap(), "x19x0\nk19v0\n");
+  }
+
+  #[test]
+  fn gc_stats_counts_collections_of_each_kind() {
+    let output = Output::default();
+    let mut vm = Vm::with_output(Box::new(output.clone()));
+    vm.interpret(compile("var s = gcStats(); print keys(s); print s[\"collections\"] + s[\"bytesFreed\"];")).unwrap();
+
+    let keys = "[collections, minor, major, bytesAllocated, bytesFreed, longestPause, totalPause]\n0\n";
+    assert_eq!(String::from_utf8(output.0.take()).unwrap(), keys);
+
+    vm.set_stress_gc(true);
+    vm.set_generational_gc(true);
+    vm.interpret(compile("var s = \"\"; for (var i = 0; i < 10; i = i + 1) s = s + \"x\";")).unwrap();
+    let stats = vm.heap.gc_stats();
+
+    assert!(stats.minor > 10);
+    assert_eq!(stats.major, 0);
+
+    // the strings that survived a minor collection are old, and only a major one frees them
+    vm.set_generational_gc(false);
+    let code = "var s = gcStats(); print s[\"collections\"] == s[\"minor\"] + s[\"major\"]; print s[\"bytesFreed\"] > 0;";
+    vm.interpret(compile(code)).unwrap();
+    assert_eq!(String::from_utf8(output.0.take()).unwrap(), "true\ntrue\n");
+  }
+
+  #[test]
+  fn strings_are_interned() {
+    let mut vm = Vm::with_output(Box::new(io::sink()));
+    vm.interpret(compile("var a = \"ab\"; var b = \"a\" + \"b\";")).unwrap();
+    let stats = vm.intern_stats();
+
+    // init, the natives' and constants' names, a and b, and ab, which the concatenation finds
+    // already there
+    let globals = natives::natives().len() + natives::CONSTANTS.len();
+    assert_eq!(
+      stats,
+      InternStats {
+        strings: globals + 4,
+        lookups: globals + 4,
+        hits: 1
+      }
+    );
+    assert_eq!(run("print \"a\" + \"b\" == \"ab\"; print \"a\" == \"b\";"), Ok(String::from("true\nfalse\n")));
+  }
+
+  #[test]
+  fn errors() {
+    assert_eq!(run("-\"a\";"), Err(String::from("Operand must be a number.")));
+    assert_eq!(run("1 < true;"), Err(String::from("Operands must be numbers.")));
+    assert_eq!(run("1 + \"a\";"), Err(String::from("Operands must be two numbers or two strings.")));
+    assert_eq!(run("print a;"), Err(String::from("Undefined variable 'a'.")));
+    assert_eq!(run("a = 1;"), Err(String::from("Undefined variable 'a'.")));
+    assert_eq!(run("\"not a function\"();"), Err(String::from("Can only call functions and classes.")));
+    assert_eq!(run("fun f(a, b) {} f(1);"), Err(String::from("Expected 2 arguments but got 1.")));
+    assert_eq!(run("fun f() { f(); } f();"), Err(String::from("Stack overflow.")));
+    assert_eq!(run("var A = 1; class B < A {}"), Err(String::from("Superclass must be a class.")));
+    assert_eq!(run("class A {} A().b;"), Err(String::from("Undefined property 'b'.")));
+    assert_eq!(run("class A {} A().b();"), Err(String::from("Undefined property 'b'.")));
+    assert_eq!(run("var a = 1; a.b;"), Err(String::from("Only instances have properties.")));
+    assert_eq!(run("var a = 1; a.b();"), Err(String::from("Only instances have properties.")));
+    assert_eq!(run("true.b = 1;"), Err(String::from("Only instances have fields.")));
+    assert_eq!(run("class A { init(a) {} } A();"), Err(String::from("Expected 1 arguments but got 0.")));
+    assert_eq!(run("class A {} A(1);"), Err(String::from("Expected 0 arguments but got 1.")));
+  }
+
+  #[test]
+  fn errors_carry_the_calls_they_happened_in() {
+    let code = "fun fail(n) {\n  return n + nil;\n}\nfun outer() {\n  fail(1);\n}\nouter();";
+    let error = Vm::with_output(Box::new(io::sink())).interpret(compile(code)).unwrap_err();
+
+    assert_eq!(error.stack_trace(), vec!["[line 2] in fail()", "[line 5] in outer()", "[line 7] in script"]);
+  }
+
+  // the program and the files it imports from a directory of their own, compiled together
+  fn compile_with_imports(directory: &std::path::Path, files: &[(&str, &str)], code: &str) -> Function {
+    std::fs::create_dir_all(directory).unwrap();
+
+    for (name, code) in files {
+      std::fs::write(directory.join(name), code).unwrap();
+    }
+
+    let mut sources = Sources::new(directory.join("main.lox").to_str().unwrap(), code);
+    let mut ast = Parser::new(Lexer::new(code).lex().unwrap()).parse().unwrap();
+    load_imports(&mut ast, &mut sources).unwrap();
+
+    Compiler::new(&sources.source_map()).compile(&ast).unwrap()
+  }
+
+  #[test]
+  fn imported_files_run_once_with_globals_of_their_own() {
+    let directory = std::env::temp_dir().join(format!("rslox-vm-imports-{}", std::process::id()));
+    let counter = "var count = 0;\nfun bump() { count = count + 1; return count; }\nvar clock = 1;\nprint \"loaded\";";
+    let code = "import \"counter.lox\";\nimport bump from \"counter.lox\";\nvar count = 10;\nprint bump();\nprint bump();\nprint count;\nprint clock;";
+
+    for (stress, generational) in [(false, false), (true, false), (true, true)] {
+      let script = compile_with_imports(&directory, &[("counter.lox", counter)], code);
+      let output = Output::default();
+      let mut vm = Vm::with_output(Box::new(output.clone()));
+      vm.set_stress_gc(stress);
+      vm.set_generational_gc(generational);
+      vm.interpret(script).unwrap();
+
+      assert_eq!(String::from_utf8(output.0.take()).unwrap(), "loaded\n1\n2\n10\n1\n");
+    }
+
+    std::fs::remove_dir_all(directory).unwrap();
+  }
+
+  #[test]
+  fn errors_in_imported_files_name_the_file() {
+    let directory = std::env::temp_dir().join(format!("rslox-vm-import-errors-{}", std::process::id()));
+    let broken = "fun fail() {\n  return -nil;\n}\nfail();";
+    let script = compile_with_imports(&directory, &[("broken.lox", broken)], "print 1;\nimport \"broken.lox\";");
+    let error = Vm::with_output(Box::new(io::sink())).interpret(script).unwrap_err();
+
+    assert_eq!(
+      error.stack_trace(),
+      vec![
+        String::from("[line 2] in fail()"),
+        format!("[line 4] in <{}>", directory.join("broken.lox").display()),
+        String::from("[line 2] in script"),
+      ]
+    );
+    std::fs::remove_dir_all(directory).unwrap();
+  }
+}
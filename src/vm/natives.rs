@@ -0,0 +1,710 @@
+use std::f64::consts;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{Heap, MapKey, MapObject, NativeFunction, Object, ObjRef, Value, ValueKind};
+use crate::console;
+use crate::json::{self, Json};
+use crate::lists;
+use crate::maps;
+#[cfg(feature = "file-io")]
+use crate::files;
+use crate::random;
+use crate::strings::{self, Piece};
+use crate::system::{self, NativeError};
+
+// the string object for text, the existing one when it's already interned
+fn intern(heap: &mut Heap, text: String) -> ObjRef {
+  match heap.find_string(&text) {
+    Some(string) => string,
+    None => heap.allocate(Object::String(Rc::from(text))),
+  }
+}
+
+fn string(heap: &mut Heap, text: String) -> Value {
+  Value::object(intern(heap, text))
+}
+
+fn clock(_: &mut Heap, _: &[Value]) -> Result<Value, NativeError> {
+  let elapsed = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+
+  Ok(Value::number(elapsed.as_secs_f64()))
+}
+
+// the collections so far, how long they paused the script and the bytes in use and freed, in
+// a new map every call
+fn gc_stats(heap: &mut Heap, _: &[Value]) -> Result<Value, NativeError> {
+  let mut map = MapObject::new();
+
+  for (name, value) in heap.gc_stats().fields() {
+    let name = intern(heap, String::from(name));
+    map.set(MapKey::String(name), Value::number(value));
+  }
+
+  Ok(Value::object(heap.allocate(Object::Map(map))))
+}
+
+// the arguments have been checked against the arity, so each native can index them
+fn len(heap: &mut Heap, arguments: &[Value]) -> Result<Value, NativeError> {
+  match (heap.as_string(arguments[0]), heap.as_list(arguments[0])) {
+    (Some(text), _) => Ok(Value::number(strings::len(text) as f64)),
+    (_, Some(list)) => Ok(Value::number(list.len() as f64)),
+    _ => Err(String::from("Argument to len() must be a string or a list.").into()),
+  }
+}
+
+fn substring(heap: &mut Heap, arguments: &[Value]) -> Result<Value, NativeError> {
+  let text = match (heap.as_string(arguments[0]), arguments[1].kind(), arguments[2].kind()) {
+    (Some(text), ValueKind::Number(start), ValueKind::Number(end)) => strings::substring(text, start, end)?,
+    _ => return Err(String::from("Arguments to substring() must be a string and two numbers.").into()),
+  };
+
+  Ok(string(heap, text))
+}
+
+fn index_of(heap: &mut Heap, arguments: &[Value]) -> Result<Value, NativeError> {
+  match (heap.as_string(arguments[0]), heap.as_string(arguments[1])) {
+    (Some(text), Some(needle)) => Ok(Value::number(strings::index_of(text, needle))),
+    _ => Err(String::from("Arguments to indexOf() must be two strings.").into()),
+  }
+}
+
+fn to_upper(heap: &mut Heap, arguments: &[Value]) -> Result<Value, NativeError> {
+  match heap.as_string(arguments[0]).map(str::to_uppercase) {
+    Some(text) => Ok(string(heap, text)),
+    None => Err(String::from("Argument to toUpper() must be a string.").into()),
+  }
+}
+
+fn to_lower(heap: &mut Heap, arguments: &[Value]) -> Result<Value, NativeError> {
+  match heap.as_string(arguments[0]).map(str::to_lowercase) {
+    Some(text) => Ok(string(heap, text)),
+    None => Err(String::from("Argument to toLower() must be a string.").into()),
+  }
+}
+
+// the heap never collects inside a native, so the pieces are safe until the list holds them
+fn split(heap: &mut Heap, arguments: &[Value]) -> Result<Value, NativeError> {
+  let pieces = match (heap.as_string(arguments[0]), heap.as_string(arguments[1])) {
+    (Some(text), Some(separator)) => strings::split(text, separator),
+    _ => return Err(String::from("Arguments to split() must be two strings.").into()),
+  };
+  let pieces = pieces.into_iter().map(|piece| string(heap, piece)).collect();
+
+  Ok(Value::object(heap.allocate(Object::List(pieces))))
+}
+
+fn push(heap: &mut Heap, arguments: &[Value]) -> Result<Value, NativeError> {
+  match heap.as_list_mut(arguments[0]) {
+    Some(list) => {
+      list.push(arguments[1]);
+      Ok(Value::nil())
+    }
+    None => Err(String::from("First argument to push() must be a list.").into()),
+  }
+}
+
+fn pop(heap: &mut Heap, arguments: &[Value]) -> Result<Value, NativeError> {
+  match heap.as_list_mut(arguments[0]) {
+    Some(list) => lists::pop(list).map_err(NativeError::from),
+    None => Err(String::from("Argument to pop() must be a list.").into()),
+  }
+}
+
+fn insert(heap: &mut Heap, arguments: &[Value]) -> Result<Value, NativeError> {
+  match (heap.as_list_mut(arguments[0]), arguments[1].kind()) {
+    (Some(list), ValueKind::Number(index)) => {
+      lists::insert(list, index, arguments[2]).map(|_| Value::nil()).map_err(NativeError::from)
+    }
+    _ => Err(String::from("Arguments to insert() must be a list, a number and a value.").into()),
+  }
+}
+
+fn remove(heap: &mut Heap, arguments: &[Value]) -> Result<Value, NativeError> {
+  match (heap.as_list_mut(arguments[0]), arguments[1].kind()) {
+    (Some(list), ValueKind::Number(index)) => lists::remove(list, index).map_err(NativeError::from),
+    _ => Err(String::from("Arguments to remove() must be a list and a number.").into()),
+  }
+}
+
+// in the order they were first set
+fn keys(heap: &mut Heap, arguments: &[Value]) -> Result<Value, NativeError> {
+  let keys = match heap.as_map(arguments[0]) {
+    Some(map) => map.entries().iter().map(|(key, _)| Heap::from_key(*key)).collect(),
+    None => return Err(String::from("Argument to keys() must be a map.").into()),
+  };
+
+  Ok(Value::object(heap.allocate(Object::List(keys))))
+}
+
+fn values(heap: &mut Heap, arguments: &[Value]) -> Result<Value, NativeError> {
+  let values = match heap.as_map(arguments[0]) {
+    Some(map) => map.entries().iter().map(|(_, value)| *value).collect(),
+    None => return Err(String::from("Argument to values() must be a map.").into()),
+  };
+
+  Ok(Value::object(heap.allocate(Object::List(values))))
+}
+
+fn has(heap: &mut Heap, arguments: &[Value]) -> Result<Value, NativeError> {
+  let Some(map) = heap.as_map(arguments[0]) else {
+    return Err(String::from("First argument to has() must be a map.").into());
+  };
+
+  Ok(Value::bool(map.contains(&heap.to_key(arguments[1]).ok_or_else(maps::invalid_key)?)))
+}
+
+// whether the key was there to delete
+fn delete(heap: &mut Heap, arguments: &[Value]) -> Result<Value, NativeError> {
+  if heap.as_map(arguments[0]).is_none() {
+    return Err(String::from("First argument to delete() must be a map.").into());
+  }
+
+  let key = heap.to_key(arguments[1]).ok_or_else(maps::invalid_key)?;
+  let removed = heap.as_map_mut(arguments[0]).and_then(|map| map.remove(&key));
+
+  Ok(Value::bool(removed.is_some()))
+}
+
+// the one number argument of a math native, name is for the message when it isn't one
+fn number(arguments: &[Value], name: &str) -> Result<f64, String> {
+  match arguments[0].kind() {
+    ValueKind::Number(value) => Ok(value),
+    _ => Err(format!("Argument to {}() must be a number.", name)),
+  }
+}
+
+fn numbers(arguments: &[Value], name: &str) -> Result<(f64, f64), String> {
+  match (arguments[0].kind(), arguments[1].kind()) {
+    (ValueKind::Number(left), ValueKind::Number(right)) => Ok((left, right)),
+    _ => Err(format!("Arguments to {}() must be numbers.", name)),
+  }
+}
+
+fn sqrt(_: &mut Heap, arguments: &[Value]) -> Result<Value, NativeError> {
+  Ok(Value::number(number(arguments, "sqrt")?.sqrt()))
+}
+
+fn abs(_: &mut Heap, arguments: &[Value]) -> Result<Value, NativeError> {
+  Ok(Value::number(number(arguments, "abs")?.abs()))
+}
+
+fn floor(_: &mut Heap, arguments: &[Value]) -> Result<Value, NativeError> {
+  Ok(Value::number(number(arguments, "floor")?.floor()))
+}
+
+fn ceil(_: &mut Heap, arguments: &[Value]) -> Result<Value, NativeError> {
+  Ok(Value::number(number(arguments, "ceil")?.ceil()))
+}
+
+fn pow(_: &mut Heap, arguments: &[Value]) -> Result<Value, NativeError> {
+  let (base, exponent) = numbers(arguments, "pow")?;
+
+  Ok(Value::number(base.powf(exponent)))
+}
+
+fn min(_: &mut Heap, arguments: &[Value]) -> Result<Value, NativeError> {
+  let (left, right) = numbers(arguments, "min")?;
+
+  Ok(Value::number(left.min(right)))
+}
+
+fn max(_: &mut Heap, arguments: &[Value]) -> Result<Value, NativeError> {
+  let (left, right) = numbers(arguments, "max")?;
+
+  Ok(Value::number(left.max(right)))
+}
+
+fn random(_: &mut Heap, _: &[Value]) -> Result<Value, NativeError> {
+  Ok(Value::number(random::random()))
+}
+
+fn random_int(_: &mut Heap, arguments: &[Value]) -> Result<Value, NativeError> {
+  let (low, high) = numbers(arguments, "randomInt")?;
+
+  Ok(Value::number(random::random_int(low, high)?))
+}
+
+fn seed_random(_: &mut Heap, arguments: &[Value]) -> Result<Value, NativeError> {
+  random::seed(number(arguments, "seedRandom")?);
+
+  Ok(Value::nil())
+}
+
+// nil once stdin has run out
+fn read_line(heap: &mut Heap, _: &[Value]) -> Result<Value, NativeError> {
+  Ok(console::read_line().map_or(Value::nil(), |line| string(heap, line)))
+}
+
+// nil when the line isn't a number either
+fn read_number(_: &mut Heap, _: &[Value]) -> Result<Value, NativeError> {
+  let number = console::read_line().and_then(|line| strings::parse_number(&line));
+
+  Ok(number.map_or(Value::nil(), Value::number))
+}
+
+// shown the way print would show it
+fn str(heap: &mut Heap, arguments: &[Value]) -> Result<Value, NativeError> {
+  let text = heap.display(arguments[0]).to_string();
+
+  Ok(string(heap, text))
+}
+
+// nil when the string isn't a number spelled the way a Lox literal is
+fn num(heap: &mut Heap, arguments: &[Value]) -> Result<Value, NativeError> {
+  match heap.as_string(arguments[0]) {
+    Some(text) => Ok(strings::parse_number(text).map_or(Value::nil(), Value::number)),
+    None => Err(String::from("Argument to num() must be a string.").into()),
+  }
+}
+
+fn chr(heap: &mut Heap, arguments: &[Value]) -> Result<Value, NativeError> {
+  match strings::chr(number(arguments, "chr")?) {
+    Some(char) => Ok(string(heap, char.to_string())),
+    None => Err(String::from("Argument to chr() must be a character code.").into()),
+  }
+}
+
+fn ord(heap: &mut Heap, arguments: &[Value]) -> Result<Value, NativeError> {
+  match heap.as_string(arguments[0]).and_then(strings::ord) {
+    Some(code) => Ok(Value::number(code as f64)),
+    None => Err(String::from("Argument to ord() must be a string of one character.").into()),
+  }
+}
+
+// what kind of value it is, instances give the name of their class
+fn type_of(heap: &mut Heap, arguments: &[Value]) -> Result<Value, NativeError> {
+  let name = match arguments[0].kind() {
+    ValueKind::Number(_) => "number",
+    ValueKind::Bool(_) => "bool",
+    ValueKind::Nil => "nil",
+    ValueKind::Object(object) => match heap.get(object) {
+      Object::String(_) => "string",
+      Object::Class(_) => "class",
+      Object::List(_) => "list",
+      Object::Map(_) => "map",
+      Object::Instance(instance) => match heap.get(instance.class) {
+        Object::Class(class) => return Ok(Value::object(class.name)),
+        object => unreachable!("instances always have classes, not {:?}", object),
+      },
+      Object::Upvalue(_) => unreachable!("upvalues are never values"),
+      _ => "function",
+    },
+  };
+
+  Ok(string(heap, String::from(name)))
+}
+
+// the arguments after the template fill in its placeholders the way print would show them,
+// or with a fixed number of decimals
+fn format(heap: &mut Heap, arguments: &[Value]) -> Result<Value, NativeError> {
+  let not_a_string = || String::from("First argument to format() must be a string.");
+  let (template, arguments) = arguments.split_first().ok_or_else(not_a_string)?;
+  let pieces = strings::parse_template(heap.as_string(*template).ok_or_else(not_a_string)?, arguments.len())?;
+
+  let mut text = String::new();
+
+  for piece in pieces {
+    match piece {
+      Piece::Text(piece) => text.push_str(&piece),
+      Piece::Argument { index, precision: None } => text.push_str(&heap.display(arguments[index]).to_string()),
+      Piece::Argument { index, precision: Some(precision) } => match arguments[index].kind() {
+        ValueKind::Number(value) => text.push_str(&strings::fixed(value, precision)),
+        _ => return Err(strings::precision_needs_a_number().into()),
+      },
+    }
+  }
+
+  Ok(string(heap, text))
+}
+
+// objects become maps and arrays lists, null is nil. Nothing is collected while a native runs,
+// so the elements are safe on the heap until the list or map holding them is
+fn json_parse(heap: &mut Heap, arguments: &[Value]) -> Result<Value, NativeError> {
+  let json = match heap.as_string(arguments[0]) {
+    Some(text) => Json::parse(text)?,
+    None => return Err(String::from("Argument to jsonParse() must be a string.").into()),
+  };
+
+  Ok(from_json(heap, json))
+}
+
+fn from_json(heap: &mut Heap, json: Json) -> Value {
+  match json {
+    Json::Null => Value::nil(),
+    Json::Bool(value) => Value::bool(value),
+    Json::Number(value) => Value::number(value),
+    Json::String(value) => string(heap, value),
+    Json::Array(items) => {
+      let elements = items.into_iter().map(|item| from_json(heap, item)).collect();
+
+      Value::object(heap.allocate(Object::List(elements)))
+    }
+    Json::Object(fields) => {
+      let mut map = MapObject::new();
+
+      for (key, value) in fields {
+        let key = intern(heap, key);
+        let value = from_json(heap, value);
+        map.set(MapKey::String(key), value);
+      }
+
+      Value::object(heap.allocate(Object::Map(map)))
+    }
+  }
+}
+
+// on one line, or indented over several when pretty is true
+fn json_stringify(heap: &mut Heap, arguments: &[Value]) -> Result<Value, NativeError> {
+  let pretty = match arguments {
+    [_] => false,
+    [_, pretty] => match pretty.kind() {
+      ValueKind::Bool(pretty) => pretty,
+      _ => return Err(String::from("Second argument to jsonStringify() must be a bool.").into()),
+    },
+    _ => return Err(format!("Expected 1 or 2 arguments but got {}.", arguments.len()).into()),
+  };
+
+  let json = to_json(heap, arguments[0], &mut vec![])?;
+
+  Ok(string(heap, if pretty { json.pretty() } else { json.to_string() }))
+}
+
+// number keys are written the way print shows them, as JSON's keys are strings. open holds the
+// lists and maps the value is inside of
+fn to_json(heap: &Heap, value: Value, open: &mut Vec<ObjRef>) -> Result<Json, String> {
+  let object = match value.kind() {
+    ValueKind::Nil => return Ok(Json::Null),
+    ValueKind::Bool(value) => return Ok(Json::Bool(value)),
+    ValueKind::Number(value) if !value.is_finite() => return Err(json::not_finite()),
+    ValueKind::Number(value) => return Ok(Json::Number(value)),
+    ValueKind::Object(object) => object,
+  };
+
+  if let Some(text) = heap.as_string(value) {
+    return Ok(Json::String(String::from(text)));
+  }
+
+  if open.contains(&object) {
+    return Err(json::contains_itself());
+  }
+
+  if open.len() == json::MAX_DEPTH {
+    return Err(json::nested_too_deeply());
+  }
+
+  open.push(object);
+
+  let json = match heap.get(object) {
+    Object::List(elements) => {
+      Json::Array(elements.iter().map(|element| to_json(heap, *element, open)).collect::<Result<_, _>>()?)
+    }
+    Object::Map(map) => Json::Object(
+      map
+        .entries()
+        .iter()
+        .map(|(key, value)| Ok((heap.display(Heap::from_key(*key)).to_string(), to_json(heap, *value, open)?)))
+        .collect::<Result<_, String>>()?,
+    ),
+    _ => return Err(json::unsupported()),
+  };
+
+  open.pop();
+
+  Ok(json)
+}
+
+// nil when the variable isn't set
+fn get_env(heap: &mut Heap, arguments: &[Value]) -> Result<Value, NativeError> {
+  let value = match heap.as_string(arguments[0]) {
+    Some(name) => system::get_env(name),
+    None => return Err(String::from("Argument to getEnv() must be a string.").into()),
+  };
+
+  Ok(value.map_or(Value::nil(), |value| string(heap, value)))
+}
+
+// the arguments after `--` on the command line, in a new list every call
+fn args(heap: &mut Heap, _: &[Value]) -> Result<Value, NativeError> {
+  let args = system::args().into_iter().map(|arg| string(heap, arg)).collect();
+
+  Ok(Value::object(heap.allocate(Object::List(args))))
+}
+
+fn platform(heap: &mut Heap, _: &[Value]) -> Result<Value, NativeError> {
+  Ok(string(heap, String::from(system::platform())))
+}
+
+// always an error, the call stops the script with the status when it's a valid one
+fn exit(_: &mut Heap, arguments: &[Value]) -> Result<Value, NativeError> {
+  match arguments[0].kind() {
+    ValueKind::Number(status) => Err(system::exit(status)),
+    _ => Err(system::invalid_status().into()),
+  }
+}
+
+// a falsey condition fails with the message, whatever kind of value it is
+fn assert(heap: &mut Heap, arguments: &[Value]) -> Result<Value, NativeError> {
+  match arguments[0].is_truthy() {
+    true => Ok(Value::nil()),
+    false => Err(format!("Assertion failed: {}", heap.display(arguments[1])).into()),
+  }
+}
+
+#[cfg(feature = "file-io")]
+fn read_file(heap: &mut Heap, arguments: &[Value]) -> Result<Value, NativeError> {
+  let contents = match heap.as_string(arguments[0]) {
+    Some(path) => files::read(path)?,
+    None => return Err(String::from("Argument to readFile() must be a string.").into()),
+  };
+
+  Ok(string(heap, contents))
+}
+
+#[cfg(feature = "file-io")]
+fn write_file(heap: &mut Heap, arguments: &[Value]) -> Result<Value, NativeError> {
+  match (heap.as_string(arguments[0]), heap.as_string(arguments[1])) {
+    (Some(path), Some(contents)) => files::write(path, contents).map(|_| Value::nil()).map_err(NativeError::from),
+    _ => Err(String::from("Arguments to writeFile() must be two strings.").into()),
+  }
+}
+
+#[cfg(feature = "file-io")]
+fn append_file(heap: &mut Heap, arguments: &[Value]) -> Result<Value, NativeError> {
+  match (heap.as_string(arguments[0]), heap.as_string(arguments[1])) {
+    (Some(path), Some(contents)) => files::append(path, contents).map(|_| Value::nil()).map_err(NativeError::from),
+    _ => Err(String::from("Arguments to appendFile() must be two strings.").into()),
+  }
+}
+
+// the numbers every script starts out with as globals
+pub(crate) const CONSTANTS: [(&str, f64); 2] = [("PI", consts::PI), ("E", consts::E)];
+
+// the natives every script starts out with, on the register vm as well
+pub(crate) fn natives() -> Vec<NativeFunction> {
+  vec![
+    NativeFunction {
+      name: "clock",
+      arity: 0,
+      function: clock,
+    },
+    NativeFunction {
+      name: "gcStats",
+      arity: 0,
+      function: gc_stats,
+    },
+    NativeFunction {
+      name: "len",
+      arity: 1,
+      function: len,
+    },
+    NativeFunction {
+      name: "substring",
+      arity: 3,
+      function: substring,
+    },
+    NativeFunction {
+      name: "indexOf",
+      arity: 2,
+      function: index_of,
+    },
+    NativeFunction {
+      name: "toUpper",
+      arity: 1,
+      function: to_upper,
+    },
+    NativeFunction {
+      name: "toLower",
+      arity: 1,
+      function: to_lower,
+    },
+    NativeFunction {
+      name: "split",
+      arity: 2,
+      function: split,
+    },
+    NativeFunction {
+      name: "push",
+      arity: 2,
+      function: push,
+    },
+    NativeFunction {
+      name: "pop",
+      arity: 1,
+      function: pop,
+    },
+    NativeFunction {
+      name: "insert",
+      arity: 3,
+      function: insert,
+    },
+    NativeFunction {
+      name: "remove",
+      arity: 2,
+      function: remove,
+    },
+    NativeFunction {
+      name: "keys",
+      arity: 1,
+      function: keys,
+    },
+    NativeFunction {
+      name: "values",
+      arity: 1,
+      function: values,
+    },
+    NativeFunction {
+      name: "has",
+      arity: 2,
+      function: has,
+    },
+    NativeFunction {
+      name: "delete",
+      arity: 2,
+      function: delete,
+    },
+    NativeFunction {
+      name: "sqrt",
+      arity: 1,
+      function: sqrt,
+    },
+    NativeFunction {
+      name: "abs",
+      arity: 1,
+      function: abs,
+    },
+    NativeFunction {
+      name: "floor",
+      arity: 1,
+      function: floor,
+    },
+    NativeFunction {
+      name: "ceil",
+      arity: 1,
+      function: ceil,
+    },
+    NativeFunction {
+      name: "pow",
+      arity: 2,
+      function: pow,
+    },
+    NativeFunction {
+      name: "min",
+      arity: 2,
+      function: min,
+    },
+    NativeFunction {
+      name: "max",
+      arity: 2,
+      function: max,
+    },
+    NativeFunction {
+      name: "random",
+      arity: 0,
+      function: random,
+    },
+    NativeFunction {
+      name: "randomInt",
+      arity: 2,
+      function: random_int,
+    },
+    NativeFunction {
+      name: "seedRandom",
+      arity: 1,
+      function: seed_random,
+    },
+    NativeFunction {
+      name: "readLine",
+      arity: 0,
+      function: read_line,
+    },
+    NativeFunction {
+      name: "readNumber",
+      arity: 0,
+      function: read_number,
+    },
+    NativeFunction {
+      name: "str",
+      arity: 1,
+      function: str,
+    },
+    NativeFunction {
+      name: "num",
+      arity: 1,
+      function: num,
+    },
+    NativeFunction {
+      name: "chr",
+      arity: 1,
+      function: chr,
+    },
+    NativeFunction {
+      name: "ord",
+      arity: 1,
+      function: ord,
+    },
+    NativeFunction {
+      name: "type",
+      arity: 1,
+      function: type_of,
+    },
+    NativeFunction {
+      name: "format",
+      arity: NativeFunction::VARIADIC,
+      function: format,
+    },
+    NativeFunction {
+      name: "jsonParse",
+      arity: 1,
+      function: json_parse,
+    },
+    NativeFunction {
+      name: "jsonStringify",
+      arity: NativeFunction::VARIADIC,
+      function: json_stringify,
+    },
+    NativeFunction {
+      name: "getEnv",
+      arity: 1,
+      function: get_env,
+    },
+    NativeFunction {
+      name: "args",
+      arity: 0,
+      function: args,
+    },
+    NativeFunction {
+      name: "platform",
+      arity: 0,
+      function: platform,
+    },
+    NativeFunction {
+      name: "exit",
+      arity: 1,
+      function: exit,
+    },
+    NativeFunction {
+      name: "assert",
+      arity: 2,
+      function: assert,
+    },
+    #[cfg(feature = "file-io")]
+    NativeFunction {
+      name: "readFile",
+      arity: 1,
+      function: read_file,
+    },
+    #[cfg(feature = "file-io")]
+    NativeFunction {
+      name: "writeFile",
+      arity: 2,
+      function: write_file,
+    },
+    #[cfg(feature = "file-io")]
+    NativeFunction {
+      name: "appendFile",
+      arity: 2,
+      function: append_file,
+    },
+  ]
+}
@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+use std::rc::Rc;
+
+use super::{Heap, InlineCache, ObjRef, Value};
+use crate::chunk;
+use crate::maps::{Key, Table};
+use crate::system::NativeError;
+
+// a compiled function loaded onto the heap, its constants turned into values the vm can use.
+// The code itself is shared with the chunk it came from, the caches are the function's own and
+// indexed by the offset of the instruction using them
+#[derive(Debug)]
+pub struct FunctionObject {
+  pub function: Rc<chunk::Function>,
+  pub constants: Vec<Value>,
+  pub caches: Vec<InlineCache>,
+}
+
+// a function together with the variables it captured from the functions around it
+#[derive(Debug)]
+pub struct ClosureObject {
+  pub function: ObjRef,
+  pub upvalues: Vec<ObjRef>,
+  // the number of the module whose globals the function's code uses, the script's is 0
+  pub module: usize,
+}
+
+// a captured variable. It stays in its slot on the stack while the function declaring it is
+// running, and moves into the upvalue once that slot goes away
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UpvalueObject {
+  Open(usize),
+  Closed(Value),
+}
+
+// methods are looked up by their interned name. A subclass starts out with a copy of its
+// superclass's methods, so finding one never walks the chain. Every field name ever set on an
+// instance of the class gets an index in the layout, shared by all of them, so a cache that
+// knows the class knows where the field is
+#[derive(Debug)]
+pub struct ClassObject {
+  pub name: ObjRef,
+  pub methods: HashMap<ObjRef, Value>,
+  pub layout: HashMap<ObjRef, usize>,
+}
+
+// fields are indexed by the class's layout, None for the ones this instance never set
+#[derive(Debug)]
+pub struct InstanceObject {
+  pub class: ObjRef,
+  pub fields: Vec<Option<Value>>,
+}
+
+impl InstanceObject {
+  pub fn field(&self, index: usize) -> Option<Value> {
+    self.fields.get(index).copied().flatten()
+  }
+
+  pub fn set_field(&mut self, index: usize, value: Value) {
+    if index >= self.fields.len() {
+      self.fields.resize(index + 1, None);
+    }
+
+    self.fields[index] = Some(value);
+  }
+}
+
+// a method taken off an instance, which remembers the instance to call it on
+#[derive(Debug)]
+pub struct BoundMethodObject {
+  pub receiver: Value,
+  pub method: ObjRef,
+}
+
+// string keys are the interned strings' handles
+pub type MapKey = Key<ObjRef>;
+pub type MapObject = Table<MapKey, Value>;
+
+// a function implemented in Rust, errors are the message to report. It gets the heap for
+// allocating its result, which nothing collects before the vm has it
+pub struct NativeFunction {
+  pub name: &'static str,
+  pub arity: usize,
+  pub function: fn(&mut Heap, &[Value]) -> Result<Value, NativeError>,
+}
+
+impl NativeFunction {
+  // the arity of a native that takes any number of arguments and checks them itself
+  pub const VARIADIC: usize = usize::MAX;
+}
+
+impl fmt::Debug for NativeFunction {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "NativeFunction({})", self.name)
+  }
+}
+
+// everything that lives on the heap rather than in a value
+#[derive(Debug)]
+pub enum Object {
+  // interned, the heap holds one string for each text
+  String(Rc<str>),
+  Function(FunctionObject),
+  Closure(ClosureObject),
+  Upvalue(UpvalueObject),
+  Native(NativeFunction),
+  Class(ClassObject),
+  Instance(InstanceObject),
+  BoundMethod(BoundMethodObject),
+  List(Vec<Value>),
+  Map(MapObject),
+  // a function compiled for the register vm, which shares everything else with this one
+  #[cfg(feature = "register-vm")]
+  RegisterFunction(crate::register::FunctionObject),
+}
+
+impl Object {
+  // roughly how much memory the object holds on to, what decides when to collect
+  pub fn size(&self) -> usize {
+    mem::size_of::<Self>()
+      + match self {
+        Object::String(value) => value.len(),
+        Object::Function(function) => {
+          function.constants.len() * mem::size_of::<Value>() + function.caches.len() * mem::size_of::<InlineCache>()
+        }
+        Object::Closure(closure) => closure.upvalues.len() * mem::size_of::<ObjRef>(),
+        Object::Class(class) => class.methods.len() * mem::size_of::<(ObjRef, Value)>(),
+        Object::Instance(instance) => instance.fields.len() * mem::size_of::<Option<Value>>(),
+        Object::List(elements) => elements.len() * mem::size_of::<Value>(),
+        Object::Map(map) => map.len() * mem::size_of::<(MapKey, Value, usize)>(),
+        #[cfg(feature = "register-vm")]
+        Object::RegisterFunction(function) => {
+          function.constants.len() * mem::size_of::<Value>()
+            + function.globals.len() * mem::size_of::<usize>()
+            + function.functions.len() * mem::size_of::<ObjRef>()
+        }
+        Object::Upvalue(_) | Object::Native(_) | Object::BoundMethod(_) => 0,
+      }
+  }
+}
@@ -0,0 +1,144 @@
+use super::ObjRef;
+
+// what a value is, whichever way values are represented. Matching on a value goes through this
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValueKind {
+  Number(f64),
+  Bool(bool),
+  Nil,
+  Object(ObjRef),
+}
+
+// what the vm's stack and globals hold. Anything bigger than a number lives on the heap, so
+// values are cheap to copy and the collector can find every object still in use through them.
+// Strings are interned, so objects are equal only when they're the same object
+#[cfg(not(feature = "nan-boxing"))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Value(ValueKind);
+
+#[cfg(not(feature = "nan-boxing"))]
+impl Value {
+  pub fn number(value: f64) -> Self {
+    Self(ValueKind::Number(value))
+  }
+
+  pub fn bool(value: bool) -> Self {
+    Self(ValueKind::Bool(value))
+  }
+
+  pub fn nil() -> Self {
+    Self(ValueKind::Nil)
+  }
+
+  pub fn object(object: ObjRef) -> Self {
+    Self(ValueKind::Object(object))
+  }
+
+  pub fn kind(self) -> ValueKind {
+    self.0
+  }
+}
+
+// with the nan-boxing feature a value is a single f64 sized word. Numbers are stored as they
+// are, everything else hides in the payload of a quiet NaN no arithmetic ever produces: nil,
+// false and true as small tags and objects as their heap index with the sign bit set
+#[cfg(feature = "nan-boxing")]
+#[derive(Clone, Copy)]
+pub struct Value(u64);
+
+#[cfg(feature = "nan-boxing")]
+const QUIET_NAN: u64 = 0x7ffc_0000_0000_0000;
+#[cfg(feature = "nan-boxing")]
+const SIGN_BIT: u64 = 0x8000_0000_0000_0000;
+#[cfg(feature = "nan-boxing")]
+const TAG_NIL: u64 = 1;
+#[cfg(feature = "nan-boxing")]
+const TAG_FALSE: u64 = 2;
+#[cfg(feature = "nan-boxing")]
+const TAG_TRUE: u64 = 3;
+
+#[cfg(feature = "nan-boxing")]
+impl Value {
+  pub fn number(value: f64) -> Self {
+    Self(value.to_bits())
+  }
+
+  pub fn bool(value: bool) -> Self {
+    Self(QUIET_NAN | if value { TAG_TRUE } else { TAG_FALSE })
+  }
+
+  pub fn nil() -> Self {
+    Self(QUIET_NAN | TAG_NIL)
+  }
+
+  pub fn object(object: ObjRef) -> Self {
+    Self(SIGN_BIT | QUIET_NAN | object.0 as u64)
+  }
+
+  pub fn kind(self) -> ValueKind {
+    if self.0 & QUIET_NAN != QUIET_NAN {
+      ValueKind::Number(f64::from_bits(self.0))
+    } else if self.0 & (SIGN_BIT | QUIET_NAN) == SIGN_BIT | QUIET_NAN {
+      ValueKind::Object(ObjRef((self.0 & !(SIGN_BIT | QUIET_NAN)) as usize))
+    } else {
+      match self.0 & !QUIET_NAN {
+        TAG_NIL => ValueKind::Nil,
+        TAG_FALSE => ValueKind::Bool(false),
+        TAG_TRUE => ValueKind::Bool(true),
+        // a NaN the arithmetic produced, which is still a number
+        _ => ValueKind::Number(f64::from_bits(self.0)),
+      }
+    }
+  }
+}
+
+// numbers compare as numbers so NaN still isn't equal to itself, everything else by its bits
+#[cfg(feature = "nan-boxing")]
+impl PartialEq for Value {
+  fn eq(&self, other: &Self) -> bool {
+    match (self.kind(), other.kind()) {
+      (ValueKind::Number(left), ValueKind::Number(right)) => left == right,
+      _ => self.0 == other.0,
+    }
+  }
+}
+
+#[cfg(feature = "nan-boxing")]
+impl std::fmt::Debug for Value {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(f, "{:?}", self.kind())
+  }
+}
+
+impl Value {
+  // false and nil are falsey, everything else is truthy
+  pub fn is_truthy(self) -> bool {
+    !matches!(self.kind(), ValueKind::Bool(false) | ValueKind::Nil)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn values_keep_what_they_were_made_from() {
+    let values = [
+      (Value::number(-1.5), ValueKind::Number(-1.5)),
+      (Value::number(f64::INFINITY), ValueKind::Number(f64::INFINITY)),
+      (Value::bool(true), ValueKind::Bool(true)),
+      (Value::bool(false), ValueKind::Bool(false)),
+      (Value::nil(), ValueKind::Nil),
+      (Value::object(ObjRef(0)), ValueKind::Object(ObjRef(0))),
+      (Value::object(ObjRef(123_456)), ValueKind::Object(ObjRef(123_456))),
+    ];
+
+    for (value, kind) in values {
+      assert_eq!(value.kind(), kind);
+    }
+
+    assert!(matches!(Value::number(f64::NAN).kind(), ValueKind::Number(value) if value.is_nan()));
+    assert_ne!(Value::number(f64::NAN), Value::number(f64::NAN));
+    assert_ne!(Value::number(0.0), Value::bool(false));
+  }
+}
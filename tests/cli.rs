@@ -0,0 +1,412 @@
+// runs the rslox binary the way a shell, an editor or a Lox test harness would, checking what it
+// prints and the status it exits with
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::process::{self, Command, Output, Stdio};
+
+// writes a script into a directory of the test's own, so tests running at the same time can't
+// see each other's files
+fn script(test: &str, name: &str, code: &str) -> String {
+  let dir = env::temp_dir().join(format!("rslox-cli-{}-{}", process::id(), test));
+  fs::create_dir_all(&dir).unwrap();
+
+  let path = dir.join(name);
+  fs::write(&path, code).unwrap();
+
+  path.to_str().unwrap().to_string()
+}
+
+fn rslox_with_stdin(args: &[&str], stdin: &str) -> Output {
+  let mut child = Command::new(env!("CARGO_BIN_EXE_rslox"))
+    .args(args)
+    .stdin(Stdio::piped())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()
+    .unwrap();
+
+  child.stdin.take().unwrap().write_all(stdin.as_bytes()).unwrap();
+  child.wait_with_output().unwrap()
+}
+
+fn rslox(args: &[&str]) -> Output {
+  rslox_with_stdin(args, "")
+}
+
+fn stdout(output: &Output) -> String {
+  String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+  String::from_utf8(output.stderr.clone()).unwrap()
+}
+
+#[test]
+fn emit_tokens() {
+  let path = script("emit_tokens", "tokens.lox", "x\t= 1.5");
+  let output = rslox(&["--emit-tokens", "--tab-width", "4", &path]);
+
+  assert_eq!(output.status.code(), Some(0));
+  assert_eq!(stderr(&output), "");
+  assert_eq!(
+    stdout(&output),
+    r#"[
+  {
+    "kind": "Identifier",
+    "lexeme": "x",
+    "span": {
+      "start": 0,
+      "end": 1
+    },
+    "line": 1,
+    "col": 1,
+    "value": "x"
+  },
+  {
+    "kind": "Assignment",
+    "lexeme": "=",
+    "span": {
+      "start": 2,
+      "end": 3
+    },
+    "line": 1,
+    "col": 5
+  },
+  {
+    "kind": "Number",
+    "lexeme": "1.5",
+    "span": {
+      "start": 4,
+      "end": 7
+    },
+    "line": 1,
+    "col": 7,
+    "value": 1.5
+  },
+  {
+    "kind": "Eof",
+    "lexeme": "",
+    "span": {
+      "start": 7,
+      "end": 7
+    },
+    "line": 1,
+    "col": 10
+  }
+]
+"#
+  );
+}
+
+#[test]
+fn emit_tokens_with_trivia() {
+  let path = script("emit_tokens_with_trivia", "tokens.lox", "// hi\nx");
+  let output = rslox(&["--emit-tokens", "--trivia", &path]);
+
+  assert_eq!(output.status.code(), Some(0));
+  assert_eq!(
+    stdout(&output),
+    r#"[
+  {
+    "kind": "Identifier",
+    "lexeme": "x",
+    "span": {
+      "start": 6,
+      "end": 7
+    },
+    "line": 2,
+    "col": 1,
+    "value": "x",
+    "leading": [
+      {
+        "kind": "LineComment",
+        "span": {
+          "start": 0,
+          "end": 5
+        }
+      },
+      {
+        "kind": "Newline",
+        "span": {
+          "start": 5,
+          "end": 6
+        }
+      }
+    ],
+    "trailing": []
+  },
+  {
+    "kind": "Eof",
+    "lexeme": "",
+    "span": {
+      "start": 7,
+      "end": 7
+    },
+    "line": 2,
+    "col": 2,
+    "leading": [],
+    "trailing": []
+  }
+]
+"#
+  );
+}
+
+#[test]
+fn emit_tokens_reports_lex_errors() {
+  let path = script("emit_tokens_reports_lex_errors", "tokens.lox", "x @ y");
+  let output = rslox(&["--emit-tokens", &path]);
+
+  assert_eq!(output.status.code(), Some(65));
+  assert_eq!(stdout(&output), "");
+  assert!(stderr(&output).starts_with("error: invalid token @\n"), "{}", stderr(&output));
+
+  assert_eq!(rslox(&["--emit-tokens", "--tab-width", "wide", &path]).status.code(), Some(64));
+  assert_eq!(rslox(&["--emit-tokens"]).status.code(), Some(64));
+}
+
+#[test]
+fn emit_ast() {
+  let path = script("emit_ast", "ast.lox", "print 1 + 2 * 3;\n");
+
+  for flag in ["--emit-ast", "--emit-ast=sexp", "--print-ast"] {
+    let output = rslox(&[flag, &path]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "(print (+ 1 (* 2 3)))\n", "{}", flag);
+  }
+
+  let output = rslox(&["--emit-ast=json", &path]);
+  let json = stdout(&output);
+  assert_eq!(output.status.code(), Some(0));
+  assert!(json.starts_with("[\n  {\n    \"type\": \"Print\",\n"), "{}", json);
+  assert!(json.contains("\"operator\": \"*\""), "{}", json);
+}
+
+#[test]
+fn emit_ast_errors() {
+  let path = script("emit_ast_errors", "ast.lox", "print (1;\n");
+  let output = rslox(&["--emit-ast", &path]);
+
+  assert_eq!(output.status.code(), Some(65));
+  assert_eq!(stdout(&output), "");
+  assert!(stderr(&output).starts_with("error: expected ')' after expression\n"), "{}", stderr(&output));
+
+  let output = rslox(&["--emit-ast=xml", &path]);
+  assert_eq!(output.status.code(), Some(64));
+  assert!(stderr(&output).starts_with("usage: rslox"), "{}", stderr(&output));
+
+  let missing = path.replace("ast.lox", "missing.lox");
+  assert_eq!(rslox(&["--emit-ast", &missing]).status.code(), Some(66));
+}
+
+#[test]
+fn usage_errors_exit_with_64() {
+  for args in [&["--bogus", "script.lox"][..], &["compile", "script.lox"], &["-e"], &["a.lox", "b.lox"]] {
+    let output = rslox(args);
+    assert_eq!(output.status.code(), Some(64), "{:?}", args);
+    assert!(stderr(&output).starts_with("usage: rslox"), "{:?}", args);
+  }
+}
+
+#[test]
+fn static_errors_exit_with_65() {
+  let lex = script("static_errors_exit_with_65", "lex.lox", "print @;");
+  let parse = script("static_errors_exit_with_65", "parse.lox", "print (1;");
+  let resolve = script("static_errors_exit_with_65", "resolve.lox", "{ var a = a; }");
+
+  for path in [&lex, &parse, &resolve] {
+    for backend in [&[][..], &["--vm"]] {
+      let output = rslox(&[backend, &[path.as_str()]].concat());
+      assert_eq!(output.status.code(), Some(65), "{} {:?}", path, backend);
+      assert!(stderr(&output).starts_with("error: "), "{}", stderr(&output));
+    }
+  }
+}
+
+#[test]
+fn missing_input_exits_with_66() {
+  let missing = env::temp_dir().join(format!("rslox-cli-{}-missing.lox", process::id()));
+  let missing = missing.to_str().unwrap();
+
+  for args in [&[missing][..], &["--vm", missing], &["check", missing], &["--emit-tokens", missing]] {
+    let output = rslox(args);
+    assert_eq!(output.status.code(), Some(66), "{:?}", args);
+    assert!(stderr(&output).starts_with(&format!("could not read {}: ", missing)), "{}", stderr(&output));
+  }
+}
+
+#[test]
+fn runtime_errors_exit_with_70() {
+  let path = script("runtime_errors_exit_with_70", "runtime.lox", "print 1;\nprint nil + 1;\nprint 2;");
+
+  for backend in [&[][..], &["--vm"]] {
+    let output = rslox(&[backend, &[path.as_str()]].concat());
+    assert_eq!(output.status.code(), Some(70), "{:?}", backend);
+    assert_eq!(stdout(&output), "1\n");
+    assert!(stderr(&output).starts_with("error: Operands must be two numbers or two strings.\n"));
+  }
+
+  // exit() picks the status itself
+  assert_eq!(rslox(&["-e", "exit(3);"]).status.code(), Some(3));
+  assert_eq!(rslox(&["--vm", "-e", "exit(3);"]).status.code(), Some(3));
+}
+
+#[test]
+fn unwritable_output_file_exits_with_73() {
+  let path = script("unwritable_output_file_exits_with_73", "script.lox", "print 1;");
+  let out = path.replace("script.lox", "missing/script.loxc");
+  let output = rslox(&["compile", &path, "-o", &out]);
+
+  assert_eq!(output.status.code(), Some(73));
+  assert!(stderr(&output).starts_with(&format!("could not write {}: ", out)), "{}", stderr(&output));
+}
+
+// /dev/full fails every write with "no space left on device"
+#[cfg(target_os = "linux")]
+#[test]
+fn failed_writes_to_stdout_exit_with_74() {
+  let path = script("failed_writes_to_stdout_exit_with_74", "script.lox", "print 1;");
+  let output = Command::new(env!("CARGO_BIN_EXE_rslox"))
+    .args(["--emit-ast", &path])
+    .stdout(fs::File::create("/dev/full").unwrap())
+    .stderr(Stdio::piped())
+    .output()
+    .unwrap();
+
+  assert_eq!(output.status.code(), Some(74));
+  assert!(stderr(&output).starts_with("could not write the output: "), "{}", stderr(&output));
+}
+
+#[test]
+fn eval() {
+  for backend in [&[][..], &["--vm"]] {
+    let output = rslox(&[backend, &["-e", "print 1 + 2;"]].concat());
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "3\n");
+  }
+
+  // errors are reported against <eval>
+  let output = rslox(&["-e", "print (;"]);
+  assert_eq!(output.status.code(), Some(65));
+  assert!(stderr(&output).contains(" --> <eval>:1:8\n"), "{}", stderr(&output));
+}
+
+#[test]
+fn stdin() {
+  for backend in [&[][..], &["--vm"]] {
+    let output = rslox_with_stdin(&[backend, &["-"]].concat(), "var a = 2;\nprint a * 3;\n");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "6\n");
+  }
+
+  let output = rslox_with_stdin(&["-"], "print 1;\nprint nil + 1;\n");
+  assert_eq!(output.status.code(), Some(70));
+  assert_eq!(stdout(&output), "1\n");
+  assert!(stderr(&output).contains(" --> <stdin>:2:7\n"), "{}", stderr(&output));
+}
+
+#[test]
+fn arguments_after_a_double_dash_go_to_the_script() {
+  let path = script("arguments_after_a_double_dash_go_to_the_script", "args.lox", "print args();");
+
+  assert_eq!(stdout(&rslox(&[&path])), "[]\n");
+  // flags after the script's -- are left alone, and only the first -- is a separator
+  assert_eq!(stdout(&rslox(&[&path, "--", "-e", "--vm", "--", "x"])), "[-e, --vm, --, x]\n");
+  assert_eq!(stdout(&rslox(&["--vm", &path, "--", "a"])), "[a]\n");
+  assert_eq!(stdout(&rslox(&["-e", "print args();", "--", "a", "b"])), "[a, b]\n");
+  assert_eq!(stdout(&rslox_with_stdin(&["-", "--", "a"], "print args();")), "[a]\n");
+  // the code after -e is never taken for the separator, here it's code that doesn't parse
+  let output = rslox(&["-e", "--", "--", "a"]);
+  assert_eq!(output.status.code(), Some(65));
+  assert!(stderr(&output).contains(" --> <eval>:1:1\n"), "{}", stderr(&output));
+}
+
+#[test]
+fn check_passes_good_scripts_without_running_them() {
+  let path = script("check_passes_good_scripts_without_running_them", "good.lox", "print 1;\nprint nil + 1;\n");
+  let output = rslox(&["check", &path]);
+
+  // the runtime error is left for running the script to find
+  assert_eq!(output.status.code(), Some(0));
+  assert_eq!(stdout(&output), "");
+  assert_eq!(stderr(&output), "");
+}
+
+#[test]
+fn check_reports_every_static_error() {
+  let path = script("check_reports_every_static_error", "bad.lox", "print 1;\n{ var a = a; }\nreturn 1;\n");
+  let output = rslox(&["check", &path]);
+  let errors = stderr(&output);
+
+  assert_eq!(output.status.code(), Some(65));
+  assert_eq!(stdout(&output), "");
+  assert!(errors.contains("error: can't read local variable 'a' in its own initializer\n"), "{}", errors);
+  assert!(errors.contains(&format!(" --> {}:2:11\n", path)), "{}", errors);
+  assert!(errors.contains("error: can't return from top-level code\n"), "{}", errors);
+  assert!(errors.contains(&format!(" --> {}:3:1\n", path)), "{}", errors);
+}
+
+#[test]
+fn check_reports_errors_in_imported_files() {
+  let module = script("check_reports_errors_in_imported_files", "module.lox", "var x = ;\n");
+  let path = script("check_reports_errors_in_imported_files", "main.lox", "import \"module.lox\";\n");
+  let output = rslox(&["check", &path]);
+
+  assert_eq!(output.status.code(), Some(65));
+  assert!(stderr(&output).contains(&format!(" --> {}:1:9\n", module)), "{}", stderr(&output));
+}
+
+// the stage names of a --time report, in the order they're listed
+fn timed_stages(report: &str) -> Vec<&str> {
+  report
+    .lines()
+    .filter(|line| line.ends_with("ms"))
+    .map(|line| line.split_whitespace().next().unwrap())
+    .collect()
+}
+
+#[test]
+fn time_reports_on_stderr() {
+  let output = rslox(&["--time", "-e", "print 1;"]);
+  assert_eq!(output.status.code(), Some(0));
+  assert_eq!(stdout(&output), "1\n");
+  assert_eq!(timed_stages(&stderr(&output)), vec!["lex", "parse", "import", "resolve", "execute", "total"]);
+
+  let output = rslox(&["--time", "--vm", "-O", "-e", "print 1;"]);
+  assert_eq!(stdout(&output), "1\n");
+  assert_eq!(
+    timed_stages(&stderr(&output)),
+    vec!["lex", "parse", "import", "resolve", "compile", "optimize", "execute", "total"]
+  );
+}
+
+#[test]
+fn time_leaves_out_stages_that_never_ran() {
+  let output = rslox(&["--time", "-e", "print (;"]);
+
+  assert_eq!(output.status.code(), Some(65));
+  assert_eq!(stdout(&output), "");
+  assert_eq!(timed_stages(&stderr(&output)), vec!["lex", "parse", "total"]);
+}
+
+#[test]
+fn failed_assertions_exit_with_70() {
+  let path = script(
+    "failed_assertions_exit_with_70",
+    "assert.lox",
+    "assert(true, \"unused\");\nprint 1;\nassert(1 + 1 == 3, \"math is broken\");\nprint 2;\n",
+  );
+
+  for backend in [&[][..], &["--vm"]] {
+    let output = rslox(&[backend, &[path.as_str()]].concat());
+    let errors = stderr(&output);
+
+    assert_eq!(output.status.code(), Some(70), "{:?}", backend);
+    assert_eq!(stdout(&output), "1\n");
+    assert!(errors.starts_with("error: Assertion failed: math is broken\n"), "{}", errors);
+    // the call that failed is shown
+    assert!(errors.contains("3 | assert(1 + 1 == 3, \"math is broken\");\n"), "{}", errors);
+  }
+}